@@ -0,0 +1,134 @@
+//! Text from small attachments (logs, notes, PDFs), so a pasted document reaches the
+//! model instead of looking like an empty message.
+
+use eyre::Result;
+use serenity::model::channel::Attachment;
+
+/// Attachments bigger than this aren't downloaded.
+const MAX_ATTACHMENT_BYTES: u64 = 512 * 1024;
+/// Only this many pages of a PDF are read.
+#[cfg(feature = "pdf")]
+const MAX_PDF_PAGES: usize = 10;
+/// How much of each attachment's text is passed on.
+const MAX_EXCERPT_CHARS: usize = 4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttachmentKind {
+    Text,
+    #[cfg(feature = "pdf")]
+    Pdf,
+}
+
+impl AttachmentKind {
+    /// The kind of an attachment, by its content type or else its extension. None for
+    /// anything the bot can't read.
+    fn of(filename: &str, content_type: Option<&str>) -> Option<Self> {
+        let content_type = content_type.map(|c| c.split(';').next().unwrap_or(c).trim());
+        let extension = filename
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_ascii_lowercase());
+        match (content_type, extension.as_deref()) {
+            (Some("text/plain" | "text/markdown"), _) => Some(AttachmentKind::Text),
+            (_, Some("txt" | "md" | "markdown" | "log")) => Some(AttachmentKind::Text),
+            #[cfg(feature = "pdf")]
+            (Some("application/pdf"), _) | (_, Some("pdf")) => Some(AttachmentKind::Pdf),
+            _ => None,
+        }
+    }
+
+    async fn extract(self, bytes: Vec<u8>) -> Result<String> {
+        match self {
+            AttachmentKind::Text => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+            // parsing is slow and the parser can panic on a broken file, so it's kept
+            // off the async threads and a panic only loses this attachment
+            #[cfg(feature = "pdf")]
+            AttachmentKind::Pdf => {
+                tokio::task::spawn_blocking(move || {
+                    std::panic::catch_unwind(|| pdf_text(&bytes))
+                        .unwrap_or_else(|_| Err(eyre::eyre!("the PDF reader gave up on it")))
+                })
+                .await?
+            }
+        }
+    }
+}
+
+/// The text of a PDF's first pages. The rest aren't read at all.
+#[cfg(feature = "pdf")]
+fn pdf_text(bytes: &[u8]) -> Result<String> {
+    let mut doc = pdf_extract::Document::load_mem(bytes)?;
+    if doc.is_encrypted() {
+        doc.decrypt("")?;
+    }
+    let mut pages = vec![];
+    for &page in doc.get_pages().keys().take(MAX_PDF_PAGES) {
+        let mut text = String::new();
+        {
+            let mut output = pdf_extract::PlainTextOutput::new(&mut text);
+            pdf_extract::output_doc_page(&doc, &mut output, page)?;
+        }
+        pages.push(text);
+    }
+    Ok(pages.join("\n"))
+}
+
+/// The readable attachments' text, each cut short and labelled with its filename.
+/// Attachments that are too big or fail to download are left out.
+pub async fn excerpts(attachments: &[Attachment]) -> Vec<String> {
+    let mut excerpts = vec![];
+    for attachment in attachments {
+        let Some(kind) =
+            AttachmentKind::of(&attachment.filename, attachment.content_type.as_deref())
+        else {
+            continue;
+        };
+        if attachment.size > MAX_ATTACHMENT_BYTES {
+            log::info!("Skipping {}, it's too big to read", attachment.filename);
+            continue;
+        }
+        let text = match attachment.download().await {
+            Ok(bytes) => kind.extract(bytes).await,
+            Err(e) => Err(e.into()),
+        };
+        match text {
+            Ok(text) => excerpts.push(excerpt(&attachment.filename, &text)),
+            Err(e) => log::warn!("Failed to read {}: {}", attachment.filename, e),
+        }
+    }
+    excerpts
+}
+
+fn excerpt(filename: &str, text: &str) -> String {
+    let text = text.trim();
+    match text.char_indices().nth(MAX_EXCERPT_CHARS) {
+        Some((end, _)) => format!("[Attached {filename}, cut short]\n{}\n[...]", &text[..end]),
+        None => format!("[Attached {filename}]\n{text}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind() {
+        assert_eq!(
+            AttachmentKind::of("notes.MD", None),
+            Some(AttachmentKind::Text)
+        );
+        assert_eq!(
+            AttachmentKind::of("crash", Some("text/plain; charset=utf-8")),
+            Some(AttachmentKind::Text)
+        );
+        assert_eq!(AttachmentKind::of("horse.png", Some("image/png")), None);
+    }
+
+    #[test]
+    fn test_excerpt() {
+        assert_eq!(excerpt("a.txt", " neigh\n"), "[Attached a.txt]\nneigh");
+        let long = "é".repeat(MAX_EXCERPT_CHARS + 1);
+        let cut = excerpt("b.log", &long);
+        assert!(cut.starts_with("[Attached b.log, cut short]\n"));
+        assert_eq!(cut.matches('é').count(), MAX_EXCERPT_CHARS);
+    }
+}
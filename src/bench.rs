@@ -0,0 +1,193 @@
+//! `horse-npc bench`: replay synthetic messages through the reply pipeline against a
+//! mock OpenAI, reporting latency percentiles for each stage so slowdowns in the hot
+//! path show up as numbers rather than hunches.
+
+use crate::{
+    chatbot::{self, ChatBot},
+    keys::{KeyAssignment, KeyPool},
+    load_tools,
+    mentions::MentionCache,
+    schema::Database,
+    Args, TestBot,
+};
+use async_openai::{config::OpenAIConfig, Client};
+use axum::{routing::post, Json, Router};
+use eyre::Result;
+use serde_json::{json, Map, Value};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How many members the synthetic messages mention between them.
+const MEMBERS: usize = 200;
+
+const MODERATION_CATEGORIES: [&str; 7] = [
+    "hate",
+    "hate/threatening",
+    "self-harm",
+    "sexual",
+    "sexual/minors",
+    "violence",
+    "violence/graphic",
+];
+
+struct Timings {
+    stage: &'static str,
+    samples: Vec<Duration>,
+}
+
+impl Timings {
+    fn new(stage: &'static str) -> Self {
+        Self {
+            stage,
+            samples: vec![],
+        }
+    }
+
+    fn time<T>(&mut self, started: Instant, value: T) -> T {
+        self.samples.push(started.elapsed());
+        value
+    }
+
+    fn summary(&mut self) -> String {
+        self.samples.sort();
+        let [p50, p95, p99] = [50, 95, 99].map(|p| percentile(&self.samples, p));
+        format!(
+            "{:<15} p50 {p50:>10.3?}  p95 {p95:>10.3?}  p99 {p99:>10.3?}",
+            self.stage
+        )
+    }
+}
+
+pub async fn bench(args: Args, messages: usize) -> Result<()> {
+    let config = OpenAIConfig::new()
+        .with_api_key("mock")
+        .with_api_base(serve_mock().await?);
+    let timeout = Duration::from_secs(args.openai_timeout);
+    let openai = KeyPool::from_clients(
+        vec![Client::with_config(config)],
+        KeyAssignment::default(),
+        timeout,
+    )?;
+    let bot = TestBot {
+        openai: Arc::new(openai),
+        database: Arc::new(Database::new(None).await?),
+        tools: Arc::new(load_tools(args.plugins.as_deref())?),
+    };
+    let conversation = bot.conversation(&(), &String::new()).await?;
+    let mut mentions = MentionCache::default();
+    for id in 0..MEMBERS {
+        mentions.insert(None, format!("<@{id}>"), format!("@member{id}"));
+    }
+
+    let mut decode = Timings::new("mention decode");
+    let mut read = Timings::new("db read");
+    let mut render = Timings::new("prompt render");
+    let mut reply = Timings::new("whole reply");
+    for i in 0..messages {
+        let message = format!(
+            "<@{}> what do you make of <@{}>? This is message {i}.",
+            i % MEMBERS,
+            i * 7 % MEMBERS
+        );
+
+        // each stage is timed on its own, then the reply runs them all again
+        let content = decode.time(Instant::now(), mentions.decode(None, &message));
+        let started = Instant::now();
+        read.time(started, bot.database.history(conversation).await?);
+        let vars = bot.prompt_vars(&(), &content).await?;
+        let started = Instant::now();
+        let prompt = chatbot::render_prompt(&bot.database, conversation, None, None, None, vars);
+        render.time(started, prompt.await?);
+        let started = Instant::now();
+        reply.time(started, chatbot::reply(bot.clone(), &(), &content).await?);
+    }
+
+    println!("{messages} messages");
+    for timings in [&mut decode, &mut read, &mut render, &mut reply] {
+        println!("{}", timings.summary());
+    }
+
+    Ok(())
+}
+
+/// The nearest-rank percentile of sorted samples.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p * sorted.len() + 99) / 100;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Answer chat completions with a canned reply and pass every message through
+/// moderation, on a local port. Returns the API base to point clients at.
+async fn serve_mock() -> Result<String> {
+    let app = Router::new()
+        .route(
+            "/v1/chat/completions",
+            post(|| async {
+                Json(json!({
+                    "id": "mock",
+                    "object": "chat.completion",
+                    "created": 0,
+                    "model": "mock",
+                    "choices": [{
+                        "index": 0,
+                        "message": {"role": "assistant", "content": "Neigh."},
+                        "finish_reason": "stop"
+                    }],
+                    "usage": {"prompt_tokens": 0, "completion_tokens": 0, "total_tokens": 0}
+                }))
+            }),
+        )
+        .route(
+            "/v1/moderations",
+            post(|| async {
+                Json(json!({
+                    "id": "mock",
+                    "model": "mock",
+                    "results": [{
+                        "flagged": false,
+                        "categories": moderation_categories(json!(false)),
+                        "category_scores": moderation_categories(json!(0.0)),
+                    }]
+                }))
+            }),
+        );
+    let server = axum::Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))?
+        .serve(app.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            log::error!("Mock OpenAI stopped: {:?}", e);
+        }
+    });
+
+    Ok(format!("http://{addr}/v1"))
+}
+
+fn moderation_categories(value: Value) -> Value {
+    MODERATION_CATEGORIES
+        .iter()
+        .map(|category| (category.to_string(), value.clone()))
+        .collect::<Map<_, _>>()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let samples = (1..=200).map(Duration::from_millis).collect::<Vec<_>>();
+        assert_eq!(percentile(&samples, 50), Duration::from_millis(100));
+        assert_eq!(percentile(&samples, 95), Duration::from_millis(190));
+        assert_eq!(percentile(&samples, 99), Duration::from_millis(198));
+        assert_eq!(percentile(&samples[..1], 99), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50), Duration::ZERO);
+    }
+}
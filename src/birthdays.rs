@@ -0,0 +1,113 @@
+//! Birthday greetings, in the channel each server's birthday_channel setting names.
+
+use crate::{
+    schema::{Birthday, Conversation, BIRTHDAY_CHANNEL},
+    DiscordBot,
+};
+use chrono::{Datelike, Local};
+use eyre::{eyre, Result};
+use serenity::{
+    model::id::{ChannelId, GuildId},
+    prelude as discord,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How often to look for birthdays that haven't been greeted yet.
+const CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Start greeting birthdays. Nothing is sent until the bot has connected.
+pub fn spawn(bot: Arc<DiscordBot>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let Some(context) = bot.gateway_context() else {
+                continue;
+            };
+            if let Err(e) = bot.greet_birthdays(&context).await {
+                log::error!("Failed to greet birthdays: {}", e);
+            }
+        }
+    });
+}
+
+impl DiscordBot {
+    async fn greet_birthdays(&self, context: &discord::Context) -> Result<()> {
+        let now = Local::now();
+        let today = now.date_naive();
+        for (guild_id, birthday) in self.database.due_birthdays(today).await? {
+            let guild_id = GuildId(guild_id);
+            // one server's missing channel shouldn't hold up the others
+            let channel_id = match self
+                .server_channel(context, guild_id, &BIRTHDAY_CHANNEL)
+                .await
+            {
+                Ok(Some(channel_id)) => channel_id,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::error!("Failed to find {}'s birthday channel: {}", guild_id, e);
+                    continue;
+                }
+            };
+            let conversation = match self.channel_conversation(context, channel_id).await {
+                Ok(conversation) => conversation,
+                Err(e) => {
+                    log::error!("Failed to find birthday channel {}: {}", channel_id, e);
+                    continue;
+                }
+            };
+            // try again later, the day isn't over
+            if self.database.get_quiet(conversation).await?.is_quiet(now) {
+                continue;
+            }
+
+            let greeted = self
+                .greet_in_character(context, guild_id, channel_id, conversation, &birthday)
+                .await;
+            if let Err(e) = greeted {
+                log::warn!("Sending a plain birthday greeting: {}", e);
+                let ping = self.pings(conversation, Some(guild_id)).await?;
+                let greeting = self
+                    .encode_user_mentions(
+                        Some(guild_id),
+                        format!("Happy birthday, @{}!", birthday.user_name),
+                        ping,
+                    )
+                    .await?;
+                self.database
+                    .queue_message(channel_id.0, Some(conversation), greeting)
+                    .await?;
+            }
+            self.database
+                .birthday_greeted(guild_id.0, birthday.user_id, today.year())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn greet_in_character(
+        &self,
+        context: &discord::Context,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        conversation: Conversation,
+        birthday: &Birthday,
+    ) -> Result<()> {
+        if !self.current_openai().serves(Some(guild_id.0)) {
+            return Err(eyre!("this server has no OpenAI key"));
+        }
+        if self.breaker.is_open(Instant::now()) {
+            return Err(eyre!("the horse is asleep"));
+        }
+        let instruction = format!(
+            "It's @{}'s birthday today! Wish them a happy birthday.",
+            birthday.user_name
+        );
+        self.remark(context, guild_id, channel_id, conversation, instruction)
+            .await
+    }
+}
@@ -0,0 +1,153 @@
+use crate::keys::KeyPool;
+use async_openai::error::{ApiError, OpenAIError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How many replies in a row must fail before the breaker opens.
+const FAILURE_THRESHOLD: usize = 3;
+
+/// How long to stop calling OpenAI once the breaker opens.
+const COOLDOWN: Duration = Duration::from_secs(5 * 60);
+
+/// What the bot says instead of replying while the breaker is open.
+pub const ASLEEP: &str = "*The horse is asleep in its stall. Try again in a few minutes.*";
+
+#[derive(Default)]
+struct State {
+    failures: usize,
+    open_until: Option<Instant>,
+}
+
+/// Stops sending requests to OpenAI for a while after repeated failures, so an
+/// outage gets a quick answer instead of a pile of doomed, slow calls.
+#[derive(Default)]
+pub struct CircuitBreaker {
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn is_open(&self, now: Instant) -> bool {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.open_until.map_or(false, |until| now < until)
+    }
+
+    /// Record how a call went. After the cooldown one call is let through, and if
+    /// that fails too the breaker opens again straight away.
+    pub fn record(&self, ok: bool, now: Instant) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if ok {
+            *state = State::default();
+            return;
+        }
+        state.failures += 1;
+        if state.failures >= FAILURE_THRESHOLD {
+            if state.failures == FAILURE_THRESHOLD {
+                log::warn!("OpenAI keeps failing, the horse is going to sleep");
+            }
+            state.open_until = Some(now + COOLDOWN);
+        }
+    }
+
+    /// Record how a reply went. Only failures that point at OpenAI count against it;
+    /// a Discord, template or database error says nothing about whether it's up.
+    pub fn record_reply<T>(&self, reply: &eyre::Result<T>, now: Instant) {
+        match reply {
+            Ok(_) => self.record(true, now),
+            Err(e) if is_openai_failure(e) => self.record(false, now),
+            Err(_) => {}
+        }
+    }
+
+    /// Record how a call made for `guild` went, unless it was made with the server's
+    /// own key, which only that server depends on.
+    pub fn record_call<T>(&self, openai: &KeyPool, guild: Option<u64>, result: &eyre::Result<T>) {
+        if !openai.has_own_key(guild) {
+            self.record_reply(result, Instant::now());
+        }
+    }
+}
+
+/// Whether an error says OpenAI is down or overloaded: a rate limit, a server error, or
+/// a request that timed out or couldn't connect. A bad request or a rejected key is
+/// the caller's problem, and shouldn't put the bot to sleep for everyone.
+fn is_openai_failure(error: &eyre::Report) -> bool {
+    error
+        .chain()
+        .any(|e| match e.downcast_ref::<OpenAIError>() {
+            Some(OpenAIError::ApiError(e)) => is_overloaded(e),
+            Some(OpenAIError::Reqwest(e)) => e.is_timeout() || e.is_connect(),
+            // a gateway error comes back as an HTML page rather than JSON
+            Some(OpenAIError::JSONDeserialize(_)) => true,
+            _ => false,
+        })
+}
+
+/// Whether an API error is a 429 or a 5xx. The API doesn't pass on the status, only
+/// the error's type and code.
+fn is_overloaded(error: &ApiError) -> bool {
+    let code = error.code.as_ref().and_then(|c| c.as_str());
+    matches!(code, Some("rate_limit_exceeded" | "insufficient_quota"))
+        || matches!(
+            error.r#type.as_deref(),
+            Some("server_error" | "requests" | "tokens" | "insufficient_quota")
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker() {
+        let breaker = CircuitBreaker::default();
+        let now = Instant::now();
+        for _ in 1..FAILURE_THRESHOLD {
+            breaker.record(false, now);
+            assert!(!breaker.is_open(now));
+        }
+        breaker.record(false, now);
+        assert!(breaker.is_open(now));
+
+        // half open after the cooldown, and one more failure opens it again
+        let later = now + COOLDOWN;
+        assert!(!breaker.is_open(later));
+        breaker.record(false, later);
+        assert!(breaker.is_open(later));
+
+        let much_later = later + COOLDOWN;
+        breaker.record(true, much_later);
+        breaker.record(false, much_later);
+        assert!(!breaker.is_open(much_later));
+    }
+
+    #[test]
+    fn test_record_reply() {
+        let breaker = CircuitBreaker::default();
+        let now = Instant::now();
+        for _ in 0..FAILURE_THRESHOLD {
+            breaker.record_reply::<()>(&Err(eyre::eyre!("database is locked")), now);
+        }
+        assert!(!breaker.is_open(now));
+
+        let invalid = OpenAIError::InvalidArgument("no messages".to_owned());
+        assert!(!is_openai_failure(&invalid.into()));
+        let bad_request = OpenAIError::ApiError(
+            serde_json::from_str(
+                r#"{"message": "bad key", "type": "invalid_request_error", "param": null, "code": "invalid_api_key"}"#,
+            )
+            .unwrap(),
+        );
+        assert!(!is_openai_failure(&bad_request.into()));
+        let overloaded = || {
+            let e = OpenAIError::ApiError(serde_json::from_str(
+                r#"{"message": "overloaded", "type": "server_error", "param": null, "code": null}"#,
+            )
+            .unwrap());
+            eyre::Report::from(e).wrap_err("reply")
+        };
+        for _ in 0..FAILURE_THRESHOLD {
+            breaker.record_reply::<()>(&Err(overloaded()), now);
+        }
+        assert!(breaker.is_open(now));
+    }
+}
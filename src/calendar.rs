@@ -0,0 +1,346 @@
+//! Holidays and server events for the prompt: a built-in table of holidays, plus a
+//! server's own calendar when its calendar_url setting points at an iCal feed.
+
+mod rrule;
+
+pub use rrule::Rule;
+
+use chrono::NaiveDate;
+use eyre::Result;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How far ahead `upcoming_events` looks.
+const UPCOMING_DAYS: i64 = 14;
+/// At most this many upcoming events are passed to the prompt.
+const MAX_UPCOMING: usize = 5;
+/// How long a fetched calendar (or a failure to fetch it) is reused.
+const CALENDAR_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Holidays on the same date every year, as (month, day, name).
+const HOLIDAYS: &[(u32, u32, &str)] = &[
+    (1, 1, "New Year's Day"),
+    (2, 14, "Valentine's Day"),
+    (3, 17, "St. Patrick's Day"),
+    (4, 1, "April Fools' Day"),
+    (10, 31, "Halloween"),
+    (12, 24, "Christmas Eve"),
+    (12, 25, "Christmas Day"),
+    (12, 31, "New Year's Eve"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub name: String,
+    pub date: NaiveDate,
+    /// How it repeats, if it does.
+    pub repeats: Option<Rule>,
+    /// Dates a repeating event is skipped on.
+    pub except: Vec<NaiveDate>,
+    /// A whole day, which counts for `today_is_holiday`, rather than an event at a time.
+    pub holiday: bool,
+}
+
+impl Event {
+    /// The next time this happens on or after `today`, if it does again.
+    fn next(&self, today: NaiveDate) -> Option<NaiveDate> {
+        match &self.repeats {
+            Some(rule) => rule
+                .dates(self.date, today)
+                .find(|date| !self.except.contains(date)),
+            None => Some(self.date).filter(|date| *date >= today),
+        }
+    }
+}
+
+/// An event as the prompt sees it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Upcoming {
+    pub name: String,
+    pub date: String,
+    /// 0 for today.
+    pub days_away: i64,
+}
+
+fn holidays() -> impl Iterator<Item = Event> {
+    HOLIDAYS.iter().filter_map(|&(month, day, name)| {
+        Some(Event {
+            name: name.to_owned(),
+            date: NaiveDate::from_ymd_opt(2000, month, day)?,
+            repeats: Some(Rule::yearly()),
+            except: vec![],
+            holiday: true,
+        })
+    })
+}
+
+/// An event being read from a feed.
+#[derive(Default)]
+struct Draft {
+    name: Option<String>,
+    date: Option<NaiveDate>,
+    repeats: Option<Rule>,
+    except: Vec<NaiveDate>,
+    holiday: bool,
+}
+
+/// The events in an iCal feed. Only the name, start date, repeats and skipped dates
+/// are read; anything else about an event is ignored, and so are events without a date.
+pub fn parse_ical(ical: &str) -> Vec<Event> {
+    // long lines are folded onto the next, which starts with a space or tab
+    let ical = ical
+        .replace("\r\n", "\n")
+        .replace("\n ", "")
+        .replace("\n\t", "");
+    let mut events = vec![];
+    let mut event: Option<Draft> = None;
+    for line in ical.lines() {
+        let Some((property, value)) = line.split_once(':') else {
+            continue;
+        };
+        let name = property.split(';').next().unwrap_or(property);
+        match (name, event.as_mut()) {
+            ("BEGIN", _) if value == "VEVENT" => event = Some(Draft::default()),
+            ("END", Some(_)) if value == "VEVENT" => {
+                let Some(draft) = event.take() else {
+                    continue;
+                };
+                if let (Some(name), Some(date)) = (draft.name, draft.date) {
+                    events.push(Event {
+                        name,
+                        date,
+                        repeats: draft.repeats,
+                        except: draft.except,
+                        holiday: draft.holiday,
+                    });
+                }
+            }
+            ("SUMMARY", Some(draft)) => draft.name = Some(unescape(value)),
+            ("DTSTART", Some(draft)) => {
+                draft.date = rrule::parse_date(value);
+                // a date with no time is the whole day
+                draft.holiday = value.len() == 8;
+            }
+            ("RRULE", Some(draft)) => draft.repeats = Rule::parse(value),
+            ("EXDATE", Some(draft)) => {
+                draft
+                    .except
+                    .extend(value.split(',').filter_map(rrule::parse_date));
+            }
+            _ => {}
+        }
+    }
+
+    events
+}
+
+/// Undoes iCal's escaping of text, in one pass so an escaped backslash before an n
+/// stays a backslash.
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push(' '),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+/// The events happening in the next couple of weeks, soonest first, and whether
+/// today is a holiday.
+pub fn upcoming<'a, I>(events: I, today: NaiveDate) -> (Vec<Upcoming>, bool)
+where
+    I: IntoIterator<Item = &'a Event>,
+{
+    let mut upcoming = events
+        .into_iter()
+        .filter_map(|event| Some((event.next(today)?, event)))
+        .filter(|(date, _)| (*date - today).num_days() < UPCOMING_DAYS)
+        .collect::<Vec<_>>();
+    upcoming.sort_by_key(|(date, _)| *date);
+    let holiday = upcoming
+        .iter()
+        .any(|(date, event)| *date == today && event.holiday);
+    let upcoming = upcoming
+        .into_iter()
+        .take(MAX_UPCOMING)
+        .map(|(date, event)| Upcoming {
+            name: event.name.clone(),
+            date: date.format("%A, the %e of %B").to_string(),
+            days_away: (date - today).num_days(),
+        })
+        .collect();
+
+    (upcoming, holiday)
+}
+
+/// Each calendar's events, by URL, and when they were fetched.
+type Feeds = HashMap<String, (Instant, Arc<Vec<Event>>)>;
+
+/// Fetches servers' calendars, keeping each for a while so a busy channel doesn't
+/// download one for every reply.
+pub struct Calendars {
+    http: reqwest::Client,
+    feeds: Arc<Mutex<Feeds>>,
+    holidays: Vec<Event>,
+}
+
+impl Calendars {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            http: reqwest::Client::builder().timeout(FETCH_TIMEOUT).build()?,
+            feeds: Arc::default(),
+            holidays: holidays().collect(),
+        })
+    }
+
+    /// The upcoming events and whether today is a holiday, from the built-in holidays
+    /// and the calendar at `url`, if any. A calendar that hasn't been fetched yet is
+    /// left out, and one that's gone stale is used while it's fetched again.
+    pub fn upcoming(&self, url: Option<&str>, today: NaiveDate) -> (Vec<Upcoming>, bool) {
+        let feed = match url {
+            Some(url) => self.feed(url),
+            None => Arc::default(),
+        };
+        upcoming(self.holidays.iter().chain(feed.iter()), today)
+    }
+
+    fn feed(&self, url: &str) -> Arc<Vec<Event>> {
+        let mut feeds = self.feeds.lock().unwrap_or_else(|e| e.into_inner());
+        let events = match feeds.get_mut(url) {
+            Some((fetched, events)) if fetched.elapsed() < CALENDAR_TTL => return events.clone(),
+            // marked fresh now so other replies don't fetch it too
+            Some((fetched, events)) => {
+                *fetched = Instant::now();
+                events.clone()
+            }
+            None => {
+                let events = Arc::<Vec<Event>>::default();
+                feeds.insert(url.to_owned(), (Instant::now(), events.clone()));
+                events
+            }
+        };
+        drop(feeds);
+
+        let http = self.http.clone();
+        let feeds = self.feeds.clone();
+        let url = url.to_owned();
+        tokio::spawn(async move {
+            match fetch(&http, &url).await {
+                Ok(events) => {
+                    feeds
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .insert(url, (Instant::now(), Arc::new(events)));
+                }
+                // the old events are kept until the next try
+                Err(e) => log::warn!("Failed to fetch calendar {url}: {e}"),
+            }
+        });
+        events
+    }
+}
+
+async fn fetch(http: &reqwest::Client, url: &str) -> Result<Vec<Event>> {
+    let ical = http
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Ok(parse_ical(&ical))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ical() {
+        let ical = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\n\
+            SUMMARY:Horse show\\, finals\r\n\
+            DTSTART:20231028T180000Z\r\n\
+            END:VEVENT\r\n\
+            BEGIN:VEVENT\r\n\
+            SUMMARY:Founding of the\r\n  ranch\r\n\
+            DTSTART;VALUE=DATE:20150301\r\n\
+            RRULE:FREQ=YEARLY\r\n\
+            END:VEVENT\r\n\
+            BEGIN:VEVENT\r\n\
+            SUMMARY:Riding lesson\r\n\
+            DTSTART;TZID=America/Los_Angeles:20231002T170000\r\n\
+            RRULE:FREQ=WEEKLY;BYDAY=MO\r\n\
+            EXDATE;TZID=America/Los_Angeles:20231009T170000\r\n\
+            END:VEVENT\r\n\
+            BEGIN:VEVENT\r\n\
+            SUMMARY:No date\r\n\
+            END:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+        let day = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let events = parse_ical(ical);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].name, "Horse show, finals");
+        assert!(events[0].repeats.is_none() && !events[0].holiday);
+        assert_eq!(events[1].name, "Founding of the ranch");
+        assert_eq!(events[1].repeats, Some(Rule::yearly()));
+        assert!(events[1].holiday);
+        assert_eq!(events[2].name, "Riding lesson");
+        assert_eq!(events[2].except, vec![day(2023, 10, 9)]);
+
+        let today = day(2023, 10, 4);
+        assert_eq!(events[2].next(today), Some(day(2023, 10, 16)));
+        assert_eq!(events[1].next(today), Some(day(2024, 3, 1)));
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("Oats\\, hay\\; apples"), "Oats, hay; apples");
+        assert_eq!(unescape("one\\ntwo"), "one two");
+        assert_eq!(unescape("C:\\\\new"), "C:\\new");
+        assert_eq!(unescape("trailing\\"), "trailing\\");
+    }
+
+    #[test]
+    fn test_upcoming() {
+        let day = |m, d| NaiveDate::from_ymd_opt(2023, m, d).unwrap();
+        let show = Event {
+            name: "Horse show".to_owned(),
+            date: day(10, 28),
+            repeats: None,
+            except: vec![],
+            holiday: false,
+        };
+        let events = holidays().chain([show]).collect::<Vec<_>>();
+
+        let (upcoming, holiday) = super::upcoming(&events, day(10, 27));
+        assert!(!holiday);
+        assert_eq!(
+            upcoming
+                .iter()
+                .map(|u| (u.name.as_str(), u.days_away))
+                .collect::<Vec<_>>(),
+            vec![("Horse show", 1), ("Halloween", 4)]
+        );
+        assert_eq!(upcoming[1].date, "Tuesday, the 31 of October");
+
+        let (upcoming, holiday) = super::upcoming(&events, day(12, 25));
+        assert!(holiday);
+        assert_eq!(upcoming[0].days_away, 0);
+        assert_eq!(upcoming[2].name, "New Year's Day");
+    }
+}
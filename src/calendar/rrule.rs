@@ -0,0 +1,384 @@
+//! The dates an iCal RRULE repeats on. Times are ignored, so a rule that repeats
+//! several times a day happens every day, and weeks always start on Monday.
+
+use chrono::{Datelike, Days, Months, NaiveDate, Weekday};
+
+/// Stops expanding a rule whose parts can never match, like the 30th of February.
+const MAX_PERIODS: u32 = 20_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    freq: Freq,
+    interval: u32,
+    until: Option<NaiveDate>,
+    count: Option<u32>,
+    by_month: Vec<u32>,
+    /// Days of the month, counting back from its end when negative.
+    by_month_day: Vec<i32>,
+    /// Weekdays, each maybe with a position like the 2 in 2TU or the -1 in -1FR.
+    by_day: Vec<(Option<i32>, Weekday)>,
+}
+
+impl Rule {
+    /// Every year on the start's month and day.
+    pub fn yearly() -> Self {
+        Self {
+            freq: Freq::Yearly,
+            interval: 1,
+            until: None,
+            count: None,
+            by_month: vec![],
+            by_month_day: vec![],
+            by_day: vec![],
+        }
+    }
+
+    /// A rule like `FREQ=MONTHLY;BYDAY=-1FR`, or None without a known FREQ. Parts
+    /// it doesn't understand are ignored.
+    pub fn parse(rule: &str) -> Option<Self> {
+        let mut freq = None;
+        let mut sub_daily = false;
+        let mut parsed = Self::yearly();
+        for part in rule.split(';') {
+            let Some((name, value)) = part.split_once('=') else {
+                continue;
+            };
+            match name {
+                "FREQ" => {
+                    freq = match value {
+                        "SECONDLY" | "MINUTELY" | "HOURLY" => {
+                            sub_daily = true;
+                            Some(Freq::Daily)
+                        }
+                        "DAILY" => Some(Freq::Daily),
+                        "WEEKLY" => Some(Freq::Weekly),
+                        "MONTHLY" => Some(Freq::Monthly),
+                        "YEARLY" => Some(Freq::Yearly),
+                        _ => None,
+                    }
+                }
+                "INTERVAL" => parsed.interval = value.parse().unwrap_or(1).max(1),
+                "UNTIL" => parsed.until = parse_date(value),
+                "COUNT" => parsed.count = value.parse().ok(),
+                "BYMONTH" => {
+                    parsed.by_month = value
+                        .split(',')
+                        .filter_map(|month| month.parse().ok())
+                        .filter(|month| (1..=12).contains(month))
+                        .collect()
+                }
+                "BYMONTHDAY" => {
+                    parsed.by_month_day = value
+                        .split(',')
+                        .filter_map(|day| day.parse::<i32>().ok())
+                        .filter(|day| (1..=31).contains(&day.abs()))
+                        .collect()
+                }
+                "BYDAY" => parsed.by_day = value.split(',').filter_map(parse_weekday).collect(),
+                _ => {}
+            }
+        }
+        // the interval of a rule repeating within a day is in hours or minutes, not days
+        if sub_daily {
+            parsed.interval = 1;
+        }
+
+        Some(Self {
+            freq: freq?,
+            ..parsed
+        })
+    }
+
+    /// The dates this repeats on from `start`, in order, leaving out any before `from`.
+    pub fn dates(&self, start: NaiveDate, from: NaiveDate) -> impl Iterator<Item = NaiveDate> + '_ {
+        // without a count, the periods before `from` can't matter
+        let skip = match self.count {
+            Some(_) => 0,
+            None => self.periods_between(start, from) / self.interval * self.interval,
+        };
+        (0..MAX_PERIODS)
+            .map(move |n| skip + n * self.interval)
+            .flat_map(move |k| self.period(start, k))
+            .filter(move |date| *date >= start)
+            .take_while(move |date| self.until.map_or(true, |until| *date <= until))
+            .take(self.count.map_or(usize::MAX, |count| count as usize))
+            .filter(move |date| *date >= from)
+    }
+
+    fn periods_between(&self, start: NaiveDate, end: NaiveDate) -> u32 {
+        let periods = match self.freq {
+            Freq::Daily => (end - start).num_days(),
+            Freq::Weekly => (monday(end) - monday(start)).num_days() / 7,
+            Freq::Monthly => {
+                i64::from(end.year() - start.year()) * 12 + i64::from(end.month())
+                    - i64::from(start.month())
+            }
+            Freq::Yearly => i64::from(end.year() - start.year()),
+        };
+        periods.clamp(0, i64::from(u32::MAX)) as u32
+    }
+
+    /// The dates in the `k`th day, week, month or year after the start's, in order.
+    fn period(&self, start: NaiveDate, k: u32) -> Vec<NaiveDate> {
+        let mut dates = match self.freq {
+            Freq::Daily => start
+                .checked_add_days(Days::new(k.into()))
+                .into_iter()
+                .filter(|date| self.in_month(*date) && self.on_month_day(*date))
+                .filter(|date| self.on_weekday(*date))
+                .collect(),
+            Freq::Weekly => {
+                let Some(week) = monday(start).checked_add_days(Days::new(u64::from(k) * 7)) else {
+                    return vec![];
+                };
+                let weekdays = if self.by_day.is_empty() {
+                    vec![start.weekday()]
+                } else {
+                    self.by_day.iter().map(|(_, weekday)| *weekday).collect()
+                };
+                weekdays
+                    .into_iter()
+                    .filter_map(|weekday| {
+                        week.checked_add_days(Days::new(weekday.num_days_from_monday().into()))
+                    })
+                    .filter(|date| self.in_month(*date))
+                    .collect()
+            }
+            Freq::Monthly => {
+                let Some(month) = first_of_month(start).checked_add_months(Months::new(k)) else {
+                    return vec![];
+                };
+                if self.in_month(month) {
+                    self.in_month_days(start, month)
+                } else {
+                    vec![]
+                }
+            }
+            Freq::Yearly => {
+                let year = i32::try_from(k)
+                    .ok()
+                    .and_then(|k| start.year().checked_add(k));
+                let Some(year) = year else {
+                    return vec![];
+                };
+                match (
+                    self.by_month.is_empty(),
+                    self.by_month_day.is_empty(),
+                    self.by_day.is_empty(),
+                ) {
+                    // a weekday's position counts through the whole year
+                    (true, true, false) => {
+                        let first = NaiveDate::from_ymd_opt(year, 1, 1);
+                        let last = NaiveDate::from_ymd_opt(year, 12, 31);
+                        match first.zip(last) {
+                            Some((first, last)) => self.weekdays_between(first, last),
+                            None => vec![],
+                        }
+                    }
+                    (true, true, true) => start.with_year(year).into_iter().collect(),
+                    _ => {
+                        let months = if self.by_month.is_empty() {
+                            vec![start.month()]
+                        } else {
+                            self.by_month.clone()
+                        };
+                        months
+                            .into_iter()
+                            .filter_map(|month| NaiveDate::from_ymd_opt(year, month, 1))
+                            .flat_map(|month| self.in_month_days(start, month))
+                            .collect()
+                    }
+                }
+            }
+        };
+        dates.sort();
+        dates.dedup();
+        dates
+    }
+
+    /// The days in the month starting on `month` that the rule picks.
+    fn in_month_days(&self, start: NaiveDate, month: NaiveDate) -> Vec<NaiveDate> {
+        let last = last_of_month(month);
+        if !self.by_month_day.is_empty() {
+            self.by_month_day
+                .iter()
+                .filter_map(|&day| {
+                    if day > 0 {
+                        month.with_day(day as u32)
+                    } else {
+                        last.checked_sub_days(Days::new(u64::from(day.unsigned_abs()) - 1))
+                    }
+                })
+                .filter(|date| date.month() == month.month() && self.on_weekday(*date))
+                .collect()
+        } else if !self.by_day.is_empty() {
+            self.weekdays_between(month, last)
+        } else {
+            month.with_day(start.day()).into_iter().collect()
+        }
+    }
+
+    /// The days from `first` to `last` on the rule's weekdays, at their positions if
+    /// they have one.
+    fn weekdays_between(&self, first: NaiveDate, last: NaiveDate) -> Vec<NaiveDate> {
+        let days = first.iter_days().take_while(|date| *date <= last);
+        self.by_day
+            .iter()
+            .flat_map(|&(position, weekday)| {
+                let matching = days
+                    .clone()
+                    .filter(|date| date.weekday() == weekday)
+                    .collect::<Vec<_>>();
+                match position {
+                    None => matching,
+                    Some(n) if n > 0 => matching.get(n as usize - 1).copied().into_iter().collect(),
+                    Some(n) => matching
+                        .len()
+                        .checked_sub(n.unsigned_abs() as usize)
+                        .and_then(|i| matching.get(i).copied())
+                        .into_iter()
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    fn in_month(&self, date: NaiveDate) -> bool {
+        self.by_month.is_empty() || self.by_month.contains(&date.month())
+    }
+
+    fn on_month_day(&self, date: NaiveDate) -> bool {
+        let from_end = date.day() as i32 - last_of_month(date).day() as i32 - 1;
+        self.by_month_day.is_empty()
+            || self.by_month_day.contains(&(date.day() as i32))
+            || self.by_month_day.contains(&from_end)
+    }
+
+    fn on_weekday(&self, date: NaiveDate) -> bool {
+        self.by_day.is_empty()
+            || self
+                .by_day
+                .iter()
+                .any(|(_, weekday)| *weekday == date.weekday())
+    }
+}
+
+/// The date at the start of an iCal date or date-time, like 20231031 or 20231031T180000Z.
+pub fn parse_date(value: &str) -> Option<NaiveDate> {
+    value
+        .get(..8)
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok())
+}
+
+/// A BYDAY entry like MO, 2TU or -1FR.
+fn parse_weekday(day: &str) -> Option<(Option<i32>, Weekday)> {
+    let (position, weekday) = day.split_at(day.len().checked_sub(2)?);
+    let weekday = match weekday {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    };
+    let position = match position {
+        "" => None,
+        position => Some(position.parse().ok().filter(|n| *n != 0)?),
+    };
+
+    Some((position, weekday))
+}
+
+fn monday(date: NaiveDate) -> NaiveDate {
+    date.checked_sub_days(Days::new(date.weekday().num_days_from_monday().into()))
+        .unwrap_or(date)
+}
+
+fn first_of_month(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap_or(date)
+}
+
+fn last_of_month(date: NaiveDate) -> NaiveDate {
+    first_of_month(date)
+        .checked_add_months(Months::new(1))
+        .and_then(|next| next.pred_opt())
+        .unwrap_or(date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn dates(rule: &str, start: NaiveDate, from: NaiveDate, n: usize) -> Vec<NaiveDate> {
+        let rule = Rule::parse(rule).unwrap();
+        rule.dates(start, from).take(n).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(Rule::parse("INTERVAL=2").is_none());
+        assert!(Rule::parse("FREQ=FORTNIGHTLY").is_none());
+        assert_eq!(parse_weekday("-1FR"), Some((Some(-1), Weekday::Fri)));
+        assert_eq!(parse_weekday("TU"), Some((None, Weekday::Tue)));
+        assert_eq!(parse_weekday("0TU"), None);
+        assert_eq!(parse_weekday("X"), None);
+    }
+
+    #[test]
+    fn test_dates() {
+        // a Monday
+        let start = day(2023, 10, 2);
+        assert_eq!(
+            dates("FREQ=WEEKLY;BYDAY=MO,TH", start, day(2023, 10, 4), 3),
+            vec![day(2023, 10, 5), day(2023, 10, 9), day(2023, 10, 12)]
+        );
+        assert_eq!(
+            dates("FREQ=WEEKLY;INTERVAL=2", start, day(2024, 1, 1), 2),
+            vec![day(2024, 1, 8), day(2024, 1, 22)]
+        );
+        assert_eq!(
+            dates("FREQ=DAILY;COUNT=3", start, start, 5),
+            vec![day(2023, 10, 2), day(2023, 10, 3), day(2023, 10, 4)]
+        );
+        assert_eq!(
+            dates("FREQ=DAILY;UNTIL=20231003T120000Z", start, start, 5),
+            vec![day(2023, 10, 2), day(2023, 10, 3)]
+        );
+        assert_eq!(
+            dates("FREQ=MONTHLY;BYDAY=-1FR", start, start, 2),
+            vec![day(2023, 10, 27), day(2023, 11, 24)]
+        );
+        assert_eq!(
+            dates("FREQ=MONTHLY;BYMONTHDAY=-1", start, day(2024, 2, 1), 1),
+            vec![day(2024, 2, 29)]
+        );
+        assert_eq!(
+            dates("FREQ=MONTHLY", day(2023, 1, 31), day(2023, 2, 1), 2),
+            vec![day(2023, 3, 31), day(2023, 5, 31)]
+        );
+        // thanksgiving
+        assert_eq!(
+            dates("FREQ=YEARLY;BYMONTH=11;BYDAY=4TH", start, start, 2),
+            vec![day(2023, 11, 23), day(2024, 11, 28)]
+        );
+        assert_eq!(
+            dates("FREQ=YEARLY", day(2020, 2, 29), day(2021, 1, 1), 1),
+            vec![day(2024, 2, 29)]
+        );
+        assert!(dates("FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=30", start, start, 1).is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+//! Fixed answers a server sets up for things it wants answered the same way every
+//! time, like where the rules are, without the model getting a say.
+
+use crate::{schema::CannedResponse, text};
+use eyre::{eyre, Result};
+use once_cell::sync::Lazy;
+use rand::seq::SliceRandom;
+use regex::{Regex, RegexBuilder};
+use std::{collections::HashMap, sync::Mutex};
+
+/// Patterns already compiled, by their source (None if it doesn't compile), so each
+/// message doesn't compile every one of a server's patterns again.
+static COMPILED: Lazy<Mutex<HashMap<String, Option<Regex>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// When there are more compiled patterns than this, they're all dropped and
+/// compiled again as they're needed, so removed patterns don't pile up.
+const MAX_COMPILED: usize = 1000;
+
+/// The most characters of a response `/canned list` shows, so each fits on a line
+/// and a long list is split between responses rather than in the middle of one.
+const MAX_LISTED_CHARS: usize = 200;
+
+/// Compile a canned response's pattern, which matches anywhere in a message,
+/// ignoring case.
+pub fn pattern(pattern: &str) -> Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(1 << 16)
+        .build()
+        .map_err(|e| eyre!("{pattern} isn't a pattern I understand: {e}"))
+}
+
+/// A pattern from the cache, compiling it the first time it's seen.
+fn compiled(source: &str) -> Option<Regex> {
+    let mut compiled = COMPILED.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(regex) = compiled.get(source) {
+        return regex.clone();
+    }
+    if compiled.len() >= MAX_COMPILED {
+        compiled.clear();
+    }
+    let regex = pattern(source).ok();
+    compiled.insert(source.to_owned(), regex.clone());
+    regex
+}
+
+/// The answer to a message, if any pattern matches it: one of the responses for the
+/// oldest matching pattern, picked by weight.
+pub fn pick<'a>(responses: &'a [CannedResponse], content: &str) -> Option<&'a str> {
+    let matched = responses.iter().find(|r| {
+        compiled(&r.pattern)
+            .map(|p| p.is_match(content))
+            .unwrap_or(false)
+    })?;
+    let choices = responses
+        .iter()
+        .filter(|r| r.pattern == matched.pattern)
+        .collect::<Vec<_>>();
+    let mut rng = rand::thread_rng();
+    choices
+        .choose_weighted(&mut rng, |r| r.weight)
+        .map(|r| r.response.as_str())
+        .ok()
+}
+
+/// A server's canned responses as `/canned list` shows them, one to a line.
+pub fn list(responses: &[CannedResponse]) -> String {
+    responses
+        .iter()
+        .map(|r| {
+            let response = r.response.split_whitespace().collect::<Vec<_>>().join(" ");
+            let shown = match text::truncate(&response, MAX_LISTED_CHARS) {
+                t if t.len() < response.len() => format!("{t}..."),
+                t => t.to_owned(),
+            };
+            format!("{}. `{}` ({}): {shown}", r.id, r.pattern, r.weight)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick() {
+        let canned = |id, pattern: &str, response: &str, weight| CannedResponse {
+            id,
+            pattern: pattern.to_owned(),
+            response: response.to_owned(),
+            weight,
+        };
+        let responses = vec![
+            canned(1, r"\brules\b", "See #rules", 1),
+            canned(2, r"member role", "React in #welcome", 1),
+            canned(3, r"\brules\b", "Never shown", 0),
+        ];
+        assert_eq!(pick(&responses, "where are the RULES?"), Some("See #rules"));
+        assert_eq!(
+            pick(&responses, "how do I get the member role"),
+            Some("React in #welcome")
+        );
+        assert_eq!(pick(&responses, "hello"), None);
+        assert!(pattern("(unclosed").is_err());
+        let broken = vec![canned(4, "(unclosed", "Never shown", 1)];
+        assert_eq!(pick(&broken, "(unclosed"), None);
+        assert_eq!(pick(&broken, "(unclosed"), None);
+    }
+
+    #[test]
+    fn test_list() {
+        let responses = vec![
+            CannedResponse {
+                id: 1,
+                pattern: "rules".to_owned(),
+                response: "See\n#rules".to_owned(),
+                weight: 2,
+            },
+            CannedResponse {
+                id: 2,
+                pattern: "long".to_owned(),
+                response: "word ".repeat(500),
+                weight: 1,
+            },
+        ];
+        let listed = list(&responses);
+        let lines = listed.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "1. `rules` (2): See #rules");
+        assert!(lines[1].ends_with("..."));
+        assert!(lines[1].chars().count() < 300);
+    }
+}
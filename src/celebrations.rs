@@ -0,0 +1,271 @@
+//! Celebrations of server boosts, member count milestones and server anniversaries, in
+//! the channel each server's celebration_channel setting names.
+
+use crate::{
+    schema::{SettingsResolver, CELEBRATION_CHANNEL, MEMBER_MILESTONES},
+    templates::ServerTemplates,
+    DiscordBot,
+};
+use chrono::{Datelike, Local, NaiveDate, TimeZone, Utc};
+use eyre::{eyre, Result};
+use minijinja::{context, value::Value};
+use serenity::{
+    model::{
+        channel::{Message, MessageType},
+        id::{ChannelId, GuildId},
+    },
+    prelude as discord,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How often to check servers' member counts and anniversaries.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The server template the bot's instruction comes from, rendered with `occasion`
+/// (boost, members or anniversary) and its details. Without one, a built-in
+/// instruction is used.
+const CELEBRATION_TEMPLATE: &str = "celebration";
+
+/// Start checking for milestones. Nothing is sent until the bot has connected.
+pub fn spawn(bot: Arc<DiscordBot>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let Some(context) = bot.gateway_context() else {
+                continue;
+            };
+            for guild_id in context.cache.guilds() {
+                if let Err(e) = bot.check_milestones(&context, guild_id).await {
+                    log::error!("Failed to check milestones in {}: {}", guild_id, e);
+                }
+            }
+        }
+    });
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Occasion {
+    Boost { user_nick: String, tier: Option<u8> },
+    Members(u64),
+    Anniversary(i32),
+}
+
+impl Occasion {
+    fn vars(&self, server_name: Option<String>) -> Value {
+        match self {
+            Occasion::Boost { user_nick, tier } => {
+                context! { occasion => "boost", user_nick, tier, server_name }
+            }
+            Occasion::Members(member_count) => {
+                context! { occasion => "members", member_count, server_name }
+            }
+            Occasion::Anniversary(years) => {
+                context! { occasion => "anniversary", years, server_name }
+            }
+        }
+    }
+
+    /// The instruction for servers without a celebration template.
+    fn instruction(&self) -> String {
+        match self {
+            Occasion::Boost { user_nick, .. } => {
+                format!("{user_nick} just boosted the server! Thank them in a sentence or two.")
+            }
+            Occasion::Members(count) => {
+                format!("The server just reached {count} members! Celebrate in a sentence or two.")
+            }
+            Occasion::Anniversary(years) => {
+                format!("The server is {years} years old today! Celebrate in a sentence or two.")
+            }
+        }
+    }
+}
+
+/// The member counts to celebrate, from a comma separated list like "100, 500".
+fn parse_milestones(value: &str) -> Result<Vec<u64>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            v.parse()
+                .map_err(|_| eyre!("{v:?} isn't a number of members"))
+        })
+        .collect()
+}
+
+/// The biggest milestone above `celebrated` that `count` members reaches, if any.
+fn crossed_milestone(milestones: &[u64], celebrated: u64, count: u64) -> Option<u64> {
+    milestones
+        .iter()
+        .copied()
+        .filter(|m| celebrated < *m && *m <= count)
+        .max()
+}
+
+/// How many years old a server created on `created` is on `today`, if it's its
+/// anniversary. Servers created on a leap day celebrate on the 28th of February.
+fn anniversary(created: NaiveDate, today: NaiveDate) -> Option<i32> {
+    let years = today.year() - created.year();
+    let day = created
+        .with_year(today.year())
+        .or_else(|| NaiveDate::from_ymd_opt(today.year(), 2, 28))?;
+    Some(years).filter(|years| *years > 0 && day == today)
+}
+
+impl DiscordBot {
+    async fn check_milestones(&self, context: &discord::Context, guild_id: GuildId) -> Result<()> {
+        let Some(channel_id) = self
+            .server_channel(context, guild_id, &CELEBRATION_CHANNEL)
+            .await?
+        else {
+            return Ok(());
+        };
+        let settings = SettingsResolver::guild(&self.database, guild_id.0);
+        let milestones = parse_milestones(&settings.get(&MEMBER_MILESTONES).await?)?;
+        let guild = context.http.get_guild_with_counts(guild_id.0).await?;
+        if let Some(count) = guild.approximate_member_count {
+            match self.database.members_celebrated(guild_id.0).await? {
+                // the first count is only remembered, so milestones from before don't go off
+                None => {
+                    self.database
+                        .set_members_celebrated(guild_id.0, count)
+                        .await?
+                }
+                Some(celebrated) => {
+                    if let Some(milestone) = crossed_milestone(&milestones, celebrated, count) {
+                        let occasion = Occasion::Members(milestone);
+                        // otherwise it's tried again at the next check
+                        if self
+                            .celebrate(context, guild_id, channel_id, occasion)
+                            .await?
+                        {
+                            self.database
+                                .set_members_celebrated(guild_id.0, milestone)
+                                .await?;
+                        }
+                    }
+                }
+            }
+        }
+
+        let today = Local::now().date_naive();
+        let created = Utc
+            .timestamp_opt(guild_id.created_at().unix_timestamp(), 0)
+            .single()
+            .map(|t| t.with_timezone(&Local).date_naive());
+        if let Some(years) = created.and_then(|created| anniversary(created, today)) {
+            let last = self.database.last_anniversary(guild_id.0).await?;
+            if last < Some(today.year()) {
+                let occasion = Occasion::Anniversary(years);
+                if self
+                    .celebrate(context, guild_id, channel_id, occasion)
+                    .await?
+                {
+                    self.database
+                        .set_last_anniversary(guild_id.0, today.year())
+                        .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Celebrate a boost, given its system message. Returns false for other messages.
+    pub async fn boost_hook(&self, context: &discord::Context, msg: &Message) -> Result<bool> {
+        let tier = match msg.kind {
+            MessageType::NitroBoost => None,
+            MessageType::NitroTier1 => Some(1),
+            MessageType::NitroTier2 => Some(2),
+            MessageType::NitroTier3 => Some(3),
+            _ => return Ok(false),
+        };
+        let Some(guild_id) = msg.guild_id else {
+            return Ok(true);
+        };
+        if self.claim(context, msg).await?.is_none() {
+            return Ok(true);
+        }
+        let Some(channel_id) = self
+            .server_channel(context, guild_id, &CELEBRATION_CHANNEL)
+            .await?
+        else {
+            return Ok(true);
+        };
+        let name = msg
+            .author_nick(context)
+            .await
+            .unwrap_or_else(|| msg.author.name.clone());
+        let occasion = Occasion::Boost {
+            user_nick: format!("@{name}"),
+            tier,
+        };
+        self.celebrate(context, guild_id, channel_id, occasion)
+            .await?;
+
+        Ok(true)
+    }
+
+    async fn celebrate(
+        &self,
+        context: &discord::Context,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        occasion: Occasion,
+    ) -> Result<bool> {
+        let conversation = self.channel_conversation(context, channel_id).await?;
+        if self
+            .database
+            .get_quiet(conversation)
+            .await?
+            .is_quiet(Local::now())
+        {
+            return Ok(false);
+        }
+        if !self.current_openai().serves(Some(guild_id.0)) {
+            return Ok(false);
+        }
+        if self.breaker.is_open(Instant::now()) {
+            log::info!("Not celebrating while the horse is asleep");
+            return Ok(false);
+        }
+
+        let templates = ServerTemplates::load(&self.database, Some(guild_id.0)).await?;
+        let instruction = match templates.get(CELEBRATION_TEMPLATE) {
+            Some(template) => templates.render(template, occasion.vars(guild_id.name(context)))?,
+            None => occasion.instruction(),
+        };
+        self.remark(context, guild_id, channel_id, conversation, instruction)
+            .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_milestones() {
+        let milestones = parse_milestones("100, 500,1000").unwrap();
+        assert_eq!(crossed_milestone(&milestones, 90, 120), Some(100));
+        assert_eq!(crossed_milestone(&milestones, 90, 1200), Some(1000));
+        assert_eq!(crossed_milestone(&milestones, 100, 120), None);
+        assert!(parse_milestones("lots").is_err());
+        assert!(parse_milestones("").unwrap().is_empty());
+
+        let day = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(anniversary(day(2020, 5, 1), day(2023, 5, 1)), Some(3));
+        assert_eq!(anniversary(day(2023, 5, 1), day(2023, 5, 1)), None);
+        assert_eq!(anniversary(day(2020, 2, 29), day(2023, 2, 28)), Some(3));
+        assert_eq!(
+            Occasion::Anniversary(2).instruction(),
+            "The server is 2 years old today! Celebrate in a sentence or two."
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! The kinds of Discord channel the horse can be talked to in, so each gets its own
+//! conversation name and operators can turn some of them off.
+
+use eyre::{eyre, Result};
+use serenity::model::channel::ChannelType;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelKind {
+    /// A text or announcement channel.
+    Text,
+    /// A thread in a text channel.
+    Thread,
+    /// A post in a forum channel.
+    Forum,
+    /// The text chat of a voice channel.
+    Voice,
+    /// The text chat of a stage channel.
+    Stage,
+    /// A direct message.
+    Dm,
+}
+
+impl ChannelKind {
+    /// The kind of a server channel, given the kind of its parent for threads.
+    /// None for channels nobody can send messages to, like categories.
+    pub fn of_guild_channel(kind: ChannelType, parent: Option<ChannelType>) -> Option<Self> {
+        match kind {
+            ChannelType::Text | ChannelType::News => Some(ChannelKind::Text),
+            ChannelType::PublicThread | ChannelType::PrivateThread | ChannelType::NewsThread => {
+                match parent {
+                    Some(ChannelType::Forum) => Some(ChannelKind::Forum),
+                    _ => Some(ChannelKind::Thread),
+                }
+            }
+            ChannelType::Voice => Some(ChannelKind::Voice),
+            ChannelType::Stage => Some(ChannelKind::Stage),
+            _ => None,
+        }
+    }
+
+    /// The conversation name for a channel of this kind called `name`. `parent` is
+    /// the name of the channel a thread is in.
+    pub fn conversation_name(&self, name: &str, parent: Option<&str>) -> String {
+        match (self, parent) {
+            (ChannelKind::Thread, Some(parent)) => format!("#{parent}:{name}"),
+            (ChannelKind::Text | ChannelKind::Thread, _) => format!("#{name}"),
+            (ChannelKind::Forum, _) => format!("forum:{name}"),
+            (ChannelKind::Voice, _) => format!("voice:{name}"),
+            (ChannelKind::Stage, _) => format!("stage:{name}"),
+            (ChannelKind::Dm, _) => name.to_owned(),
+        }
+    }
+}
+
+impl FromStr for ChannelKind {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(ChannelKind::Text),
+            "thread" => Ok(ChannelKind::Thread),
+            "forum" => Ok(ChannelKind::Forum),
+            "voice" => Ok(ChannelKind::Voice),
+            "stage" => Ok(ChannelKind::Stage),
+            "dm" => Ok(ChannelKind::Dm),
+            _ => Err(eyre!(
+                "unknown channel kind {s}, use text, thread, forum, voice, stage or dm"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversation_name() {
+        let kind = |kind, parent| ChannelKind::of_guild_channel(kind, parent).unwrap();
+
+        let thread = kind(ChannelType::PublicThread, Some(ChannelType::Text));
+        assert_eq!(
+            thread.conversation_name("ideas", Some("general")),
+            "#general:ideas"
+        );
+        let post = kind(ChannelType::PublicThread, Some(ChannelType::Forum));
+        assert_eq!(
+            post.conversation_name("Saddle help", Some("help")),
+            "forum:Saddle help"
+        );
+        let stage = kind(ChannelType::Stage, None);
+        assert_eq!(
+            stage.conversation_name("town-hall", None),
+            "stage:town-hall"
+        );
+        let voice = kind(ChannelType::Voice, None);
+        assert_eq!(voice.conversation_name("stable", None), "voice:stable");
+        assert_eq!(
+            ChannelKind::of_guild_channel(ChannelType::Category, None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("forum".parse::<ChannelKind>().unwrap(), ChannelKind::Forum);
+        assert!("category".parse::<ChannelKind>().is_err());
+    }
+}
@@ -1,29 +1,88 @@
 use crate::{
+    breaker::CircuitBreaker,
+    canned, faq,
+    function_calls::FunctionCallAssembler,
     helpers::OpenAIHelpers,
-    schema::{Conversation, Database, Message, Role},
+    karma,
+    keys::KeyPool,
+    latency, leaks, providers,
+    router::{self, Route},
+    schema::{
+        Conversation, Database, Flag, Flags, Message, ModerationPolicy, PrivacyMode, ReplyMetadata,
+        Role, SettingsResolver, AUDIT, FAQ_REPHRASE_MODEL, FAST_MODEL, FUNCTIONS, LATENCY_TARGET,
+        MODEL, MODERATION_BYPASS, ROUTER, ROUTER_MODEL, SHADOW, TEMPERATURE,
+    },
+    scripting::Hooks,
+    shadow,
+    tags::Tags,
+    templates::ServerTemplates,
+    text,
+    tools::{relationships, ToolContext, ToolRegistry},
+    transforms,
+};
+use async_openai::types::{
+    ChatCompletionFunctions, CreateChatCompletionRequest, CreateChatCompletionRequestArgs,
+    CreateChatCompletionResponse,
 };
-use async_openai::{config::OpenAIConfig, types::CreateChatCompletionRequestArgs};
 use async_trait::async_trait;
-use eyre::{ContextCompat, Result};
-use minijinja::value::Value;
+use chrono::Local;
+use eyre::{eyre, ContextCompat, Result};
+use minijinja::{context, value::Value};
 
-use async_openai::types::ChatCompletionFunctions;
-use std::sync::Arc;
+use std::{fmt, sync::Arc, time::Instant};
 
 const DEFAULT_PROMPT: &str = include_str!("default_prompt.jinja");
 
-pub(crate) fn functions() -> Vec<ChatCompletionFunctions> {
-    let functions = include_str!("functions.json");
-    serde_json::from_str(functions).expect("Failed to parse functions.json")
+/// The server template holding its base persona, which channel prompts can extend.
+pub const BASE_TEMPLATE: &str = "base";
+
+/// How many function calls the model may chain before we stop asking it.
+const MAX_FUNCTION_ROUNDS: usize = 5;
+
+/// Longer messages from users are cut off at this many tokens.
+const MAX_MESSAGE_TOKENS: usize = 1000;
+
+/// Recorded with each reply's metadata.
+const PROVIDER: &str = "openai";
+
+const DESCRIPTION_INSTRUCTION: &str = "Describe the conversation so far in one short sentence \
+    of at most 15 words, giving its topic and mood, for someone deciding which conversation to \
+    read. Don't name anyone.";
+
+/// The most tokens a description may use.
+const MAX_DESCRIPTION_TOKENS: u16 = 60;
+
+const SUMMARY_INSTRUCTION: &str = "Summarize the conversation so far in a few sentences, \
+    keeping only what you need to carry it on. Leave out names, contact details and anything \
+    else personal.";
+
+/// The person who sent a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Speaker {
+    /// A stable identifier, such as a discord user id.
+    pub id: String,
+    /// The name the bot knows them by, without any leading @.
+    pub name: String,
 }
 
 #[async_trait]
-pub trait ChatBot {
-    type Message;
-    type Context;
+pub trait ChatBot: Send + Sync {
+    type Message: Send + Sync;
+    type Context: Send + Sync;
 
-    fn openai(&self) -> Arc<async_openai::Client<OpenAIConfig>>;
+    fn openai(&self) -> Arc<KeyPool>;
     fn database(&self) -> Arc<Database>;
+    fn tools(&self) -> Arc<ToolRegistry>;
+
+    /// The breaker calls made besides the reply itself are recorded to, if any.
+    fn breaker(&self) -> Option<Arc<CircuitBreaker>> {
+        None
+    }
+
+    /// The server a message came from, if any, for picking an API key.
+    fn guild_id(&self, _context: &Self::Context, _message: &Self::Message) -> Option<u64> {
+        None
+    }
 
     async fn conversation(
         &self,
@@ -33,69 +92,780 @@ pub trait ChatBot {
 
     async fn message_content(&self, context: &Self::Context, message: &Self::Message) -> Result<String>;
 
+    async fn speaker(&self, context: &Self::Context, message: &Self::Message) -> Result<Speaker>;
+
+    /// The name the bot goes by where the message was sent, without any leading @.
+    async fn bot_name(&self, context: &Self::Context, message: &Self::Message) -> Result<String>;
+
     async fn prompt_vars(&self, context: &Self::Context, message: &Self::Message) -> Result<Value>;
+
+    /// The ids of the roles the message's author has where it was sent, if any.
+    async fn speaker_roles(
+        &self,
+        _context: &Self::Context,
+        _message: &Self::Message,
+    ) -> Result<Vec<u64>> {
+        Ok(vec![])
+    }
+
+    /// Whether the message was sent somewhere marked as not safe for work.
+    async fn is_nsfw(&self, _context: &Self::Context, _message: &Self::Message) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Handle a function that isn't in the tool registry, such as the platform specific
+    /// ones from functions.json. Returns None if the function is unknown.
+    async fn call_function(
+        &self,
+        _context: &Self::Context,
+        _message: &Self::Message,
+        _name: &str,
+        _arguments: &serde_json::Value,
+    ) -> Result<Option<String>> {
+        Ok(None)
+    }
 }
 
+/// The bot's reply to a message, or None when it lets the message pass without one.
 #[allow(unused_variables, dead_code)]
-pub async fn reply<B>(bot: B, context: &B::Context, message: &B::Message) -> Result<String>
+pub async fn reply<B>(bot: B, context: &B::Context, message: &B::Message) -> Result<Option<String>>
 where
     B: ChatBot,
 {
     let openai = bot.openai();
+    let guild = bot.guild_id(context, message);
     let db = bot.database();
     let conversation = bot.conversation(context, message).await?;
     let content = bot.message_content(context, message).await?;
+    // before anything else sees it, moderation and plugins included
+    let content = leaks::redact(&content).into_owned();
+    let hooks = Hooks::load(&db, conversation).await?;
+    let content = match &hooks {
+        Some(hooks) => hooks.pre_moderation(content)?,
+        None => content,
+    };
 
-    if openai.must_moderate(content.clone()).await? {
-        return Ok(random_moderation_response());
+    let nsfw = if bot.is_nsfw(context, message).await? {
+        Some(db.get_nsfw_settings(conversation).await?)
+    } else {
+        None
+    };
+    let settings = SettingsResolver::new(&db, conversation, guild);
+    let flags = Flags::new(&db, conversation, guild);
+    let memory = flags.enabled(Flag::Memory).await?;
+    let bypass_roles = settings.get(&MODERATION_BYPASS).await?;
+    let roles = bot.speaker_roles(context, message).await?;
+    // staff configuring the bot shouldn't keep getting deflected
+    let policy = if bypasses_moderation(&bypass_roles, &roles) {
+        ModerationPolicy::Off
+    } else {
+        nsfw.as_ref().map(|n| n.moderation).unwrap_or_default()
+    };
+    let speaker = bot.speaker(context, message).await?;
+
+    if openai
+        .client(guild)?
+        .must_moderate(content.clone(), policy)
+        .await?
+    {
+        if memory {
+            db.adjust_karma(conversation, speaker.id, karma::FLAGGED)
+                .await?;
+            db.nudge_mood(conversation, -1, Local::now()).await?;
+        }
+        // in the persona's own voice, if it has one
+        let deflections = db.deflections(conversation).await?;
+        let templates = ServerTemplates::load(&db, guild).await?;
+        let deflection = random_moderation_response(&deflections);
+        let bot_name = bot.bot_name(context, message).await?;
+        let vars = context! {
+            user_nick => format!("@{}", speaker.name),
+            bot_nick => format!("@{}", bot_name),
+        };
+        // a broken deflection still deflects, as written
+        let deflection = templates.render(deflection, vars).unwrap_or_else(|e| {
+            log::warn!("Failed to render a deflection: {}", e);
+            deflection.to_owned()
+        });
+        return Ok(Some(deflection));
+    }
+    // one look at the message for karma, mood and the router alike
+    let tags = Tags::of(&content);
+    // before the prompt, which shows how the bot feels about them and the day
+    if memory {
+        db.adjust_karma(conversation, speaker.id, tags.sentiment)
+            .await?;
+        let swing = tags.sentiment.signum() + random_mood_swing();
+        db.nudge_mood(conversation, swing, Local::now()).await?;
     }
 
-    db.add_user_message(conversation, content).await?;
+    let content = text::trim_to_tokens(&content, MAX_MESSAGE_TOKENS).to_owned();
+    // held until the reply is saved, so a second message waits for this answer
+    let _lock = db.lock_conversation(conversation).await;
+    let history_id = db.add_user_message(conversation, &content).await?;
+    tags.save(&db, history_id).await?;
     let mut messages = db.history(conversation).await?;
 
-    let env = minijinja::Environment::new();
-    let prompt = db
-        .get_prompt(conversation)
-        .await?
-        .unwrap_or_else(|| DEFAULT_PROMPT.to_owned());
-    let prompt = env.render_str(&prompt, bot.prompt_vars(context, message).await?)?;
-    messages.insert(0, Message::new(Role::System, prompt));
+    // the server's fixed answers come before anything the model might say
+    let responses = db.canned_responses(guild).await?;
+    if let Some(response) = canned::pick(&responses, &content) {
+        let reply = Message::new(Role::Assistant, response);
+        db.add_messages(conversation, vec![reply.clone()]).await?;
+        return Ok(Some(reply.content()));
+    }
+
+    let vars = bot.prompt_vars(context, message).await?;
+    let nsfw_prompt = nsfw.and_then(|n| n.prompt);
+    // a FAQ that can't be searched shouldn't stop the bot answering the usual way
+    let entry = faq::find(&openai, &db, &settings, guild, &content)
+        .await
+        .unwrap_or_else(|e| {
+            log::error!("Failed to search the FAQ: {}", e);
+            None
+        });
+    if let Some(entry) = entry {
+        let rephrase_model = settings.get(&FAQ_REPHRASE_MODEL).await?;
+        let answer = if rephrase_model.is_empty() {
+            entry.answer.clone()
+        } else {
+            let hooks = hooks.as_ref();
+            let persona = render_prompt(&db, conversation, guild, nsfw_prompt, hooks, vars).await?;
+            faq::rephrase(&openai, guild, &rephrase_model, persona, &content, &entry).await?
+        };
+        let reply = Message::new(Role::Assistant, faq::cite(&answer, &entry));
+        db.add_messages(conversation, vec![reply.clone()]).await?;
+        return Ok(Some(reply.content()));
+    }
+    if settings.get(&ROUTER).await? {
+        let router_model = settings.get(&ROUTER_MODEL).await?;
+        let asked = tags.intent.is_none() && !router_model.is_empty();
+        let route = router::route(&openai, guild, &router_model, &content, tags.intent).await;
+        if let (true, Some(breaker)) = (asked, bot.breaker()) {
+            breaker.record_call(&openai, guild, &route);
+        }
+        let route = match route {
+            Ok(route) => {
+                if asked {
+                    db.set_message_intent(history_id, route.as_str()).await?;
+                }
+                route
+            }
+            // like the FAQ, a router that can't be asked shouldn't stop the usual answer
+            Err(e) => {
+                log::error!("Failed to route a message: {}", e);
+                Route::Reply
+            }
+        };
+        match route {
+            Route::Reply => {}
+            // still in the history, for the next reply's context
+            Route::Ignore => return Ok(None),
+            route => {
+                let templates = ServerTemplates::load(&db, guild).await?;
+                let reply = router::canned(&templates, route, vars)?;
+                let reply = Message::new(Role::Assistant, reply);
+                db.add_messages(conversation, vec![reply.clone()]).await?;
+                return Ok(Some(reply.content()));
+            }
+        }
+    }
+    let prompt = render_prompt(&db, conversation, guild, nsfw_prompt, hooks.as_ref(), vars).await?;
+    let prompt = Message::new(Role::System, prompt);
+
+    let tools = bot.tools();
+    let ceiling = db.max_tokens(conversation).await?;
+    let mut model = settings.get(&MODEL).await?;
+    let target = settings.get(&LATENCY_TARGET).await?;
+    let fast = latency::fast_mode(&db, conversation, target).await?;
+    if fast {
+        let fast_model = settings.get(&FAST_MODEL).await?;
+        if !fast_model.is_empty() {
+            model = fast_model;
+        }
+    }
+    let temperature = settings.get(&TEMPERATURE).await?;
+    let audit = settings.get(&AUDIT).await? && keeps_everything(&db, conversation).await?;
+    let window = text::context_window(&model);
+    let functions = if flags.enabled(Flag::Tools).await? {
+        let mut functions = tools.allowed_functions(&settings.get(&FUNCTIONS).await?);
+        if !memory {
+            functions.retain(|f| !relationships::MEMORY_FUNCTIONS.contains(&f.name.as_str()));
+        }
+        functions
+    } else {
+        Vec::new()
+    };
+    let function_tokens = text::count_function_tokens(&functions)?;
+    let prompt_tokens = text::count_message_tokens(std::slice::from_ref(&prompt));
+
+    // leave room for the longest reply we allow
+    let budget = window.saturating_sub(prompt_tokens + function_tokens + ceiling as usize);
+    // a shorter history is read (and written about) faster
+    let budget = if fast { budget / 2 } else { budget };
+    let before = messages.len();
+    truncate_history(&mut messages, budget);
+    let mut metadata = ReplyMetadata {
+        provider: PROVIDER.to_owned(),
+        model: model.clone(),
+        moderated: policy != ModerationPolicy::Off,
+        truncated: before - messages.len(),
+        fast,
+        ..Default::default()
+    };
+    messages.insert(0, prompt);
+
+    for _ in 0..MAX_FUNCTION_ROUNDS {
+        let used = text::count_message_tokens(&messages) + function_tokens;
+        let max_tokens = text::completion_budget(window, ceiling, used)
+            .ok_or_else(|| eyre!("the conversation doesn't fit in {model}'s context window"))?;
+        let mut request = CreateChatCompletionRequestArgs::default();
+        request
+            .max_tokens(max_tokens)
+            .model(&model)
+            .temperature(temperature)
+            .messages(providers::openai::request_messages(&messages));
+        // the API rejects an empty list, so leave it out entirely
+        if !functions.is_empty() {
+            request.functions(functions.clone());
+        }
+        let request = request.build()?;
+
+        let started = Instant::now();
+        let response = audited_chat(&openai, guild, &db, conversation, audit, request).await?;
+        metadata.latency += started.elapsed();
+        if let Some(usage) = &response.usage {
+            metadata.prompt_tokens += usage.prompt_tokens;
+            metadata.completion_tokens += usage.completion_tokens;
+        }
+        let choice = response
+            .choices
+            .into_iter()
+            .next()
+            .wrap_err("No response")?;
+        let response = providers::openai::response_message(choice.message.clone())?;
+        metadata.finish_reason = choice.finish_reason.clone();
+
+        let (fn_name, fn_args) = match &response {
+            Message::Function {
+                fn_name, fn_args, ..
+            } => (fn_name, fn_args),
+            _ => {
+                db.add_reply(conversation, response.clone(), metadata)
+                    .await?;
+                let content = match &hooks {
+                    Some(hooks) => hooks.post_reply(response.content())?,
+                    None => response.content(),
+                };
+                if settings.get(&SHADOW).await? && keeps_everything(&db, conversation).await? {
+                    shadow::spawn(
+                        openai.clone(),
+                        db.clone(),
+                        bot.breaker(),
+                        guild,
+                        conversation,
+                        messages,
+                    );
+                }
+                let pipeline = db.get_transforms(conversation).await?;
+                return transforms::apply_all(&pipeline, content).map(Some);
+            }
+        };
+        metadata.tools.push(fn_name.clone());
+        // a call that isn't streamed arrives whole, but gets the same checks
+        let mut call = FunctionCallAssembler::default();
+        call.push(Some(fn_name), Some(fn_args));
+        let result = match call.finish() {
+            Ok((name, arguments)) => {
+                call_function(
+                    &bot,
+                    context,
+                    message,
+                    conversation,
+                    &functions,
+                    &name,
+                    arguments,
+                )
+                .await
+            }
+            Err(e) => format!("error: {e}"),
+        };
+        let result = Message::function_result(fn_name, result);
+        // together, so the history never has a call without its result
+        db.add_messages(conversation, vec![response.clone(), result.clone()])
+            .await?;
+        messages.push(response);
+        messages.push(result);
+    }
+
+    Err(eyre!("Too many function calls"))
+}
+
+/// Have the bot speak up unprompted, following `instruction`, for events like a
+/// channel topic change. The reply is added to the history like any other.
+pub async fn comment(
+    openai: &KeyPool,
+    guild: Option<u64>,
+    db: &Database,
+    conversation: Conversation,
+    nsfw_prompt: Option<String>,
+    vars: Value,
+    instruction: String,
+) -> Result<String> {
+    let _lock = db.lock_conversation(conversation).await;
+    let hooks = Hooks::load(db, conversation).await?;
+    let prompt = render_prompt(db, conversation, guild, nsfw_prompt, hooks.as_ref(), vars).await?;
+    let prompt = Message::new(Role::System, prompt);
+    let instruction = Message::new(Role::System, instruction);
+
+    let ceiling = db.max_tokens(conversation).await?;
+    let settings = SettingsResolver::new(db, conversation, guild);
+    let model = settings.get(&MODEL).await?;
+    let temperature = settings.get(&TEMPERATURE).await?;
+    let audit = settings.get(&AUDIT).await? && keeps_everything(db, conversation).await?;
+    let window = text::context_window(&model);
+    let fixed = text::count_message_tokens(&[prompt.clone(), instruction.clone()]);
+    let mut messages = db.history(conversation).await?;
+    let before = messages.len();
+    truncate_history(
+        &mut messages,
+        window.saturating_sub(fixed + ceiling as usize),
+    );
+    let truncated = before - messages.len();
+    messages.insert(0, prompt);
+    messages.push(instruction);
+
+    let used = text::count_message_tokens(&messages);
+    let max_tokens = text::completion_budget(window, ceiling, used)
+        .ok_or_else(|| eyre!("the conversation doesn't fit in {model}'s context window"))?;
+    let request = CreateChatCompletionRequestArgs::default()
+        .max_tokens(max_tokens)
+        .model(&model)
+        .temperature(temperature)
+        .messages(providers::openai::request_messages(&messages))
+        .build()?;
+
+    let started = Instant::now();
+    let response = audited_chat(openai, guild, db, conversation, audit, request).await?;
+    let latency = started.elapsed();
+    let usage = response.usage.clone();
+    let choice = response
+        .choices
+        .into_iter()
+        .next()
+        .wrap_err("No response")?;
+    let finish_reason = choice.finish_reason.clone();
+    let response = providers::openai::response_message(choice.message)?;
+    let content = response.content();
+    let metadata = ReplyMetadata {
+        provider: PROVIDER.to_owned(),
+        model,
+        finish_reason,
+        prompt_tokens: usage.as_ref().map_or(0, |u| u.prompt_tokens),
+        completion_tokens: usage.as_ref().map_or(0, |u| u.completion_tokens),
+        latency,
+        truncated,
+        ..Default::default()
+    };
+    db.add_reply(
+        conversation,
+        Message::new(Role::Assistant, content.clone()),
+        metadata,
+    )
+    .await?;
+
+    let content = match &hooks {
+        Some(hooks) => hooks.post_reply(content)?,
+        None => content,
+    };
+    let pipeline = db.get_transforms(conversation).await?;
+    transforms::apply_all(&pipeline, content)
+}
+
+/// Whether the conversation keeps everything said in it: channels always do, DMs only
+/// with `/privacy full`. Logs that outlive the history are only kept when it does.
+async fn keeps_everything(db: &Database, conversation: Conversation) -> Result<bool> {
+    let privacy = db.get_privacy(conversation).await?;
+    Ok(matches!(privacy, None | Some(PrivacyMode::Full)))
+}
+
+/// Send a chat request, logging it (rendered prompt and all) and the raw response when
+/// the conversation has the audit setting on.
+async fn audited_chat(
+    openai: &KeyPool,
+    guild: Option<u64>,
+    db: &Database,
+    conversation: Conversation,
+    audit: bool,
+    request: CreateChatCompletionRequest,
+) -> Result<CreateChatCompletionResponse> {
+    let logged = audit.then(|| serde_json::to_string(&request)).transpose()?;
+    let response = openai.chat(guild, request).await?;
+    if let Some(request) = logged {
+        let raw = serde_json::to_string(&response)?;
+        if let Err(e) = db.add_audit_entry(conversation, request, raw).await {
+            log::error!("Failed to write the audit log: {}", e);
+        }
+    }
+    Ok(response)
+}
+
+/// Replace a conversation's history with a short summary of it, for private
+/// conversations that asked for only a summary to be kept.
+pub async fn summarize(openai: &KeyPool, db: &Database, conversation: Conversation) -> Result<()> {
+    let _lock = db.lock_conversation(conversation).await;
+    // private conversations are DMs, outside any server
+    let summary = write_summary(openai, None, db, conversation).await?;
+    let summary = Message::new(
+        Role::System,
+        format!("Summary of the conversation so far: {summary}"),
+    );
+    db.replace_history(conversation, vec![summary]).await
+}
+
+/// Ask the model for a short summary of a conversation's history, leaving it as it is.
+pub async fn write_summary(
+    openai: &KeyPool,
+    guild: Option<u64>,
+    db: &Database,
+    conversation: Conversation,
+) -> Result<String> {
+    let instruction = Message::new(Role::System, SUMMARY_INSTRUCTION);
+    let ceiling = db.max_tokens(conversation).await?;
+    let model = SettingsResolver::new(db, conversation, guild)
+        .get(&MODEL)
+        .await?;
+    let window = text::context_window(&model);
+    let fixed = text::count_message_tokens(std::slice::from_ref(&instruction));
+    let mut messages = db.history(conversation).await?;
+    truncate_history(
+        &mut messages,
+        window.saturating_sub(fixed + ceiling as usize),
+    );
+    messages.push(instruction);
 
+    let used = text::count_message_tokens(&messages);
+    let max_tokens = text::completion_budget(window, ceiling, used)
+        .ok_or_else(|| eyre!("the conversation doesn't fit in {model}'s context window"))?;
     let request = CreateChatCompletionRequestArgs::default()
-        .max_tokens(db.max_tokens(conversation).await?)
-        .model(db.model(conversation).await?)
-        .temperature(0.5)
-        .functions(functions())
-        .messages(
-            messages
-                .iter()
-                .map(|m| m.try_into())
-                .collect::<Result<Vec<_>, _>>()?,
-        )
+        .max_tokens(max_tokens)
+        .model(&model)
+        .temperature(0.0)
+        .messages(providers::openai::request_messages(&messages))
         .build()?;
 
-    let response = openai.chat().create(request).await?;
+    let response = openai.chat(guild, request).await?;
     let choice = response
         .choices
         .into_iter()
         .next()
         .wrap_err("No response")?;
-    let message: Message = choice.message.clone().try_into()?;
-    let content = message.content();
-    
-    db.add_message(conversation, message).await?;
+    let summary = providers::openai::response_message(choice.message)?;
+    Ok(summary.content())
+}
+
+/// Ask the model for a line on what a conversation is about and its mood, to tell
+/// conversations apart in listings. The history is left as it is.
+pub async fn describe(
+    openai: &KeyPool,
+    db: &Database,
+    conversation: Conversation,
+    guild: Option<u64>,
+) -> Result<String> {
+    let instruction = Message::new(Role::System, DESCRIPTION_INSTRUCTION);
+    let model = SettingsResolver::new(db, conversation, guild)
+        .get(&MODEL)
+        .await?;
+    let window = text::context_window(&model);
+    let fixed = text::count_message_tokens(std::slice::from_ref(&instruction));
+    let mut messages = db.history(conversation).await?;
+    truncate_history(
+        &mut messages,
+        window.saturating_sub(fixed + MAX_DESCRIPTION_TOKENS as usize),
+    );
+    messages.push(instruction);
+
+    let request = CreateChatCompletionRequestArgs::default()
+        .max_tokens(MAX_DESCRIPTION_TOKENS)
+        .model(&model)
+        .temperature(0.0)
+        .messages(providers::openai::request_messages(&messages))
+        .build()?;
+
+    let response = openai.chat(guild, request).await?;
+    let choice = response
+        .choices
+        .into_iter()
+        .next()
+        .wrap_err("No response")?;
+    let description = providers::openai::response_message(choice.message)?;
+    Ok(description.content().trim().to_owned())
+}
+
+/// Render the system prompt exactly as the model will see it. In a server with a base
+/// persona, the prompt is an overlay that can `{% extends "base" %}` (or include it, or
+/// any other server template), and with no prompt of its own the conversation gets the
+/// base persona as is.
+pub async fn render_prompt(
+    db: &Database,
+    conversation: Conversation,
+    guild: Option<u64>,
+    nsfw_prompt: Option<String>,
+    hooks: Option<&Hooks>,
+    vars: Value,
+) -> Result<String> {
+    let templates = ServerTemplates::load(db, guild).await?;
+    let prompt = match nsfw_prompt {
+        Some(prompt) => Some(prompt),
+        None => db.current_prompt(conversation, Local::now()).await?,
+    };
+    let mut prompt = compose_prompt(prompt.as_deref(), &templates, vars)?;
+    // what the persona knows about its world, as given rather than as a template
+    if let Some(lore) = db.lore(conversation).await? {
+        prompt = format!("{prompt}\n\n{lore}");
+    }
+    match hooks {
+        Some(hooks) => hooks.pre_prompt(prompt),
+        None => Ok(prompt),
+    }
+}
+
+fn compose_prompt(
+    prompt: Option<&str>,
+    templates: &ServerTemplates,
+    vars: Value,
+) -> Result<String> {
+    let prompt = prompt
+        .or(templates.get(BASE_TEMPLATE))
+        .unwrap_or(DEFAULT_PROMPT);
+    templates.render(prompt, vars)
+}
+
+/// Drop the oldest messages until the history fits in `budget` tokens,
+/// always keeping the newest one.
+fn truncate_history(messages: &mut Vec<Message>, budget: usize) {
+    let tokens = messages
+        .iter()
+        .map(|m| text::count_message_tokens(std::slice::from_ref(m)))
+        .collect::<Vec<_>>();
+    let mut total: usize = tokens.iter().sum();
+    let mut start = 0;
+    while total > budget && start + 1 < messages.len() {
+        total -= tokens[start];
+        start += 1;
+    }
+    // a function result makes no sense without the call before it
+    while start + 1 < messages.len() && matches!(messages[start], Message::FunctionResult { .. }) {
+        start += 1;
+    }
+    messages.drain(..start);
+}
+
+/// Where a conversation stands against its model's context window.
+#[derive(Debug)]
+pub struct TokenReport {
+    pub model: String,
+    pub window: usize,
+    /// The prompt template before rendering, so slightly smaller than what is sent.
+    pub prompt: usize,
+    pub functions: usize,
+    pub history: usize,
+    pub history_messages: usize,
+    /// The most tokens a reply may use.
+    pub reply: u16,
+}
+
+impl TokenReport {
+    /// Tokens left before the oldest history starts being dropped. Negative once it is.
+    pub fn headroom(&self) -> i64 {
+        self.window as i64
+            - (self.prompt + self.functions + self.history + self.reply as usize) as i64
+    }
+}
+
+impl fmt::Display for TokenReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Model: {} ({} token window)", self.model, self.window)?;
+        writeln!(f, "Prompt: {} tokens", self.prompt)?;
+        writeln!(f, "Functions: {} tokens", self.functions)?;
+        writeln!(
+            f,
+            "History: {} tokens in {} messages",
+            self.history, self.history_messages
+        )?;
+        writeln!(f, "Reply: up to {} tokens", self.reply)?;
+        match self.headroom() {
+            h if h >= 0 => write!(f, "Headroom: {h} tokens"),
+            h => write!(f, "Over by {} tokens, dropping old history", -h),
+        }
+    }
+}
+
+pub async fn token_report(
+    db: &Database,
+    tools: &ToolRegistry,
+    conversation: Conversation,
+    guild: Option<u64>,
+) -> Result<TokenReport> {
+    let model = SettingsResolver::new(db, conversation, guild)
+        .get(&MODEL)
+        .await?;
+    let prompt = db
+        .current_prompt(conversation, Local::now())
+        .await?
+        .unwrap_or_else(|| DEFAULT_PROMPT.to_owned());
+    let history = db.history(conversation).await?;
+
+    Ok(TokenReport {
+        window: text::context_window(&model),
+        model,
+        prompt: text::count_message_tokens(&[Message::new(Role::System, prompt)]),
+        functions: text::count_function_tokens(&tools.functions())?,
+        history: text::count_message_tokens(&history),
+        history_messages: history.len(),
+        reply: db.max_tokens(conversation).await?,
+    })
+}
+
+async fn call_function<B>(
+    bot: &B,
+    context: &B::Context,
+    message: &B::Message,
+    conversation: Conversation,
+    offered: &[ChatCompletionFunctions],
+    name: &str,
+    arguments: serde_json::Value,
+) -> String
+where
+    B: ChatBot,
+{
+    dispatch_function(
+        bot,
+        context,
+        message,
+        conversation,
+        offered,
+        name,
+        arguments,
+    )
+    .await
+    .unwrap_or_else(|e| {
+        log::error!("function {name} failed: {e}");
+        format!("error: {e}")
+    })
+}
 
-    Ok(content)
+/// Run a function the model called, as long as it was one of those `offered`. A model
+/// can name any function it likes, including ones left out by settings or flags.
+async fn dispatch_function<B>(
+    bot: &B,
+    context: &B::Context,
+    message: &B::Message,
+    conversation: Conversation,
+    offered: &[ChatCompletionFunctions],
+    name: &str,
+    arguments: serde_json::Value,
+) -> Result<String>
+where
+    B: ChatBot,
+{
+    if !offered.iter().any(|f| f.name == name) {
+        return Err(eyre!("unknown function {name}"));
+    }
+    match bot.tools().get(name) {
+        Some(tool) => {
+            let tool_context = ToolContext {
+                database: bot.database(),
+                conversation,
+                speaker: bot.speaker(context, message).await?,
+            };
+            tool.call(&tool_context, arguments).await
+        }
+        None => bot
+            .call_function(context, message, name, &arguments)
+            .await?
+            .wrap_err_with(|| format!("unknown function {name}")),
+    }
 }
 
+/// One reply in this many (each way) comes with a random change of mood.
+const MOOD_SWING_ODDS: u32 = 40;
+
 const HORSE_MODERATION_RESPONSES: &str = include_str!("../moderation_responses.txt");
 
-fn random_moderation_response() -> String {
-    use rand::prelude::IteratorRandom;
+/// One of a conversation's deflection templates, or of the horse ones if it has none.
+fn random_moderation_response(deflections: &[String]) -> &str {
+    use rand::prelude::{IteratorRandom, SliceRandom};
     let mut rng = rand::thread_rng();
-    HORSE_MODERATION_RESPONSES
-        .lines()
-        .choose(&mut rng)
-        .unwrap_or("Crikey, I'm not sure what to say")
-        .to_owned()
+    match deflections.choose(&mut rng) {
+        Some(deflection) => deflection,
+        None => HORSE_MODERATION_RESPONSES
+            .lines()
+            .choose(&mut rng)
+            .unwrap_or("Crikey, I'm not sure what to say"),
+    }
+}
+
+/// Whether someone with `roles` skips input moderation, given the comma separated
+/// role ids of the moderation_bypass setting.
+fn bypasses_moderation(bypass_roles: &str, roles: &[u64]) -> bool {
+    bypass_roles
+        .split(',')
+        .filter_map(|role| role.trim().parse::<u64>().ok())
+        .any(|role| roles.contains(&role))
+}
+
+/// Now and then the bot's mood changes for no reason at all, like anyone's.
+fn random_mood_swing() -> i64 {
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+    match rng.gen_range(0..MOOD_SWING_ODDS) {
+        0 => -1,
+        1 => 1,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bypasses_moderation() {
+        assert!(bypasses_moderation("12, 34", &[7, 34]));
+        assert!(!bypasses_moderation("12", &[7, 34]));
+        assert!(!bypasses_moderation("", &[7]));
+    }
+
+    #[test]
+    fn test_random_moderation_response() {
+        let deflections = vec!["Beep.".to_owned()];
+        assert_eq!(random_moderation_response(&deflections), "Beep.");
+        assert!(HORSE_MODERATION_RESPONSES
+            .lines()
+            .any(|line| line == random_moderation_response(&[])));
+    }
+
+    #[test]
+    fn test_compose_prompt() {
+        let base = "You are a horse. {% block flavor %}Be nice.{% endblock %}";
+        let overlay =
+            r#"{% extends "base" %}{% block flavor %}Talk about {{ topic }}.{% endblock %}"#;
+        let vars = || context! { topic => "hay" };
+        let templates = ServerTemplates::new([(BASE_TEMPLATE.to_owned(), base.to_owned())].into());
+        let none = ServerTemplates::default();
+
+        assert_eq!(
+            compose_prompt(Some(overlay), &templates, vars()).unwrap(),
+            "You are a horse. Talk about hay."
+        );
+        assert_eq!(
+            compose_prompt(None, &templates, vars()).unwrap(),
+            "You are a horse. Be nice."
+        );
+        assert_eq!(
+            compose_prompt(Some("Just {{ topic }}"), &none, vars()).unwrap(),
+            "Just hay"
+        );
+        assert!(compose_prompt(Some(overlay), &none, vars()).is_err());
+    }
 }
@@ -0,0 +1,159 @@
+//! `horse-npc check`: validate the configuration without connecting to anything,
+//! reporting every problem found rather than stopping at the first.
+
+use crate::{
+    load_tools,
+    schema::{Database, SourceKind, StoredSource},
+    scripting::Hooks,
+    secrets::Secrets,
+    transforms::Transform,
+    Args,
+};
+use async_openai::types::ChatCompletionFunctions;
+use eyre::Result;
+
+pub async fn check(args: Args) -> Result<()> {
+    let mut problems = vec![];
+
+    for var in ["OPENAI_KEY", "DISCORD_TOKEN"] {
+        if std::env::var(var).map_or(true, |v| v.trim().is_empty()) {
+            problems.push(format!("{var} is not set"));
+        }
+    }
+    if let Ok(token) = std::env::var("DISCORD_TOKEN") {
+        if serenity::utils::validate_token(&token).is_err() {
+            problems.push("DISCORD_TOKEN doesn't look like a bot token".to_owned());
+        }
+    }
+    if let Err(e) = Secrets::from_env() {
+        problems.push(e.to_string());
+    }
+
+    // checking shouldn't create the data directory or the database
+    match args.database_location() {
+        None => problems.push("--ephemeral given, so nothing would be saved".to_owned()),
+        Some(path) if !path.exists() => {
+            problems.push(format!("database {} doesn't exist", path.display()))
+        }
+        Some(path) => match Database::new(Some(path.clone())).await {
+            Ok(database) => {
+                problems.extend(
+                    database
+                        .integrity_check()
+                        .await?
+                        .into_iter()
+                        .map(|p| format!("database: {p}")),
+                );
+                for source in database.stored_sources().await? {
+                    problems.extend(source_problem(&source));
+                }
+            }
+            Err(e) => problems.push(format!("can't open database {}: {e}", path.display())),
+        },
+    }
+
+    match load_tools(args.plugins.as_deref()) {
+        Ok(tools) => {
+            for function in tools.functions() {
+                problems.extend(function_problems(&function));
+            }
+        }
+        Err(e) => problems.push(format!("can't load tools: {e}")),
+    }
+
+    if problems.is_empty() {
+        println!("Everything looks fine");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{problem}");
+    }
+    Err(eyre::eyre!("found {} problem(s)", problems.len()))
+}
+
+fn source_problem(stored: &StoredSource) -> Option<String> {
+    let env = minijinja::Environment::new();
+    let template = |s: &str| env.template_from_str(s).err().map(|e| e.to_string());
+    let (what, error) = match stored.kind {
+        SourceKind::Prompt => ("prompt", template(&stored.source)),
+        SourceKind::NsfwPrompt => ("NSFW prompt", template(&stored.source)),
+        SourceKind::TopicTemplate => ("topic template", template(&stored.source)),
+        SourceKind::Template => ("template", template(&stored.source)),
+        SourceKind::Script => (
+            "script",
+            Hooks::compile(&stored.source).err().map(|e| e.to_string()),
+        ),
+        SourceKind::Transforms => (
+            "transforms",
+            serde_json::from_str::<Vec<Transform>>(&stored.source)
+                .err()
+                .map(|e| e.to_string()),
+        ),
+    };
+    error.map(|e| format!("{}: {what}: {e}", stored.conversation))
+}
+
+/// OpenAI rejects requests whose function definitions aren't JSON schema objects.
+fn function_problems(function: &ChatCompletionFunctions) -> Vec<String> {
+    let name = &function.name;
+    let mut problems = vec![];
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        problems.push(format!(
+            "function {name:?}: names may only use letters, digits, _ and -"
+        ));
+    }
+    if function.description.as_deref().map_or(true, str::is_empty) {
+        problems.push(format!("function {name}: missing description"));
+    }
+    let schema_type = function
+        .parameters
+        .as_ref()
+        .and_then(|p| p.get("type"))
+        .and_then(|t| t.as_str());
+    if schema_type != Some("object") {
+        problems.push(format!(
+            "function {name}: parameters must be a JSON schema with \"type\": \"object\""
+        ));
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_function_problems() {
+        let mut function = ChatCompletionFunctions {
+            name: "roll_dice".to_owned(),
+            description: Some("Roll some dice".to_owned()),
+            parameters: Some(serde_json::json!({"type": "object", "properties": {}})),
+        };
+        assert!(function_problems(&function).is_empty());
+
+        function.name = "roll dice".to_owned();
+        function.parameters = Some(serde_json::json!({"properties": {}}));
+        assert_eq!(function_problems(&function).len(), 2);
+    }
+
+    #[test]
+    fn test_source_problem() {
+        let mut stored = StoredSource {
+            conversation: "#general".to_owned(),
+            kind: SourceKind::Prompt,
+            source: "Hello {{ user_nick }}".to_owned(),
+        };
+        assert_eq!(source_problem(&stored), None);
+        stored.source = "Hello {{ user_nick".to_owned();
+        assert!(source_problem(&stored)
+            .unwrap()
+            .starts_with("#general: prompt: "));
+        stored.kind = SourceKind::Transforms;
+        stored.source = r#"[{"type": "horse_speak"}]"#.to_owned();
+        assert_eq!(source_problem(&stored), None);
+    }
+}
@@ -0,0 +1,1469 @@
+mod bang;
+mod context_menu;
+mod report;
+
+use crate::{
+    canned,
+    chatbot::{render_prompt, token_report, ChatBot, Speaker},
+    embeddings,
+    helpers::{from_now, parse_duration},
+    keys::new_client,
+    presets,
+    schema::{
+        parse_flag_state, Conversation, Flag, Mood, PrivacyMode, SettingScope, SettingsResolver,
+        EMBEDDING_MODEL,
+    },
+    scripting::Hooks,
+    text,
+    tools::{
+        economy::{describe_wallet, CURRENCY},
+        quests::describe_quests,
+    },
+    DiscordBot, MAX_MESSAGE_LENGTH,
+};
+use chrono::NaiveTime;
+use eyre::{eyre, Context, Result};
+use serenity::{
+    builder::CreateApplicationCommands,
+    model::{
+        application::{
+            command::CommandOptionType,
+            interaction::{
+                application_command::{
+                    ApplicationCommandInteraction, CommandDataOption, CommandDataOptionValue,
+                },
+                autocomplete::AutocompleteInteraction,
+                Interaction, InteractionResponseType,
+            },
+        },
+        channel::{Channel, Message},
+        id::{ChannelId, GuildId, UserId},
+        user::User,
+        Permissions,
+    },
+    prelude as discord,
+};
+
+/// A command users can give the bot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BotCommand {
+    /// Set (or with no value, remove) a field on your character sheet.
+    SheetSet {
+        key: String,
+        value: Option<String>,
+    },
+    /// Show a character sheet, by default your own.
+    SheetShow {
+        user: Option<String>,
+    },
+    /// Claim your daily allowance.
+    Daily,
+    /// Show your balance and inventory.
+    Balance,
+    /// Show the quest log.
+    Quests {
+        all: bool,
+    },
+    /// Ignore messages in this channel for a while.
+    Mute {
+        duration: chrono::Duration,
+    },
+    Unmute,
+    /// Set (or with None, clear) a daily do-not-disturb window.
+    Dnd {
+        window: Option<(NaiveTime, NaiveTime)>,
+    },
+    /// Stop a user from triggering the bot in this server.
+    Block {
+        user: UserId,
+    },
+    Unblock {
+        user: UserId,
+    },
+    /// Show how much of the context window this channel's conversation uses.
+    Tokens,
+    /// Share another channel's conversation history.
+    Link {
+        channel: ChannelId,
+    },
+    /// Move another channel's conversation into this one.
+    Merge {
+        channel: ChannelId,
+    },
+    /// Show the system prompt as the model would see it here.
+    PromptPreview,
+    /// Re-read the bot's configuration without reconnecting.
+    Reload,
+    /// Show (or with None, stop showing) an activity instead of the configured ones.
+    Status {
+        text: Option<String>,
+    },
+    /// Replace what a stored message in this channel's conversation said.
+    Redact {
+        id: i64,
+    },
+    /// Show (or with a mood, set) how the bot feels in this channel's conversation.
+    Mood {
+        mood: Option<Mood>,
+    },
+    /// Turn an optional behavior on or off (or with None, back to inherit) in this
+    /// channel's conversation, or with `server`, the whole server.
+    Flag {
+        flag: Flag,
+        enabled: Option<bool>,
+        server: bool,
+    },
+    /// Show what went into the bot's latest reply in this channel.
+    DebugLast,
+    /// Show (or with a mode, change) how much of a DM conversation is kept.
+    Privacy {
+        mode: Option<PrivacyMode>,
+    },
+    /// Let the bot give its opinion of you when someone asks, or not.
+    Opinions {
+        allowed: bool,
+    },
+    /// Share (or with None, forget) your birthday in this server, as a month and day.
+    Birthday {
+        date: Option<(u32, u32)>,
+    },
+    /// Answer messages matching a pattern with a fixed response, picked by weight among
+    /// those with the same pattern.
+    CannedAdd {
+        pattern: String,
+        response: String,
+        weight: u32,
+    },
+    /// List this server's canned responses.
+    CannedList,
+    /// Remove one of this server's canned responses, by its id.
+    CannedRemove {
+        id: i64,
+    },
+    /// Answer messages asking something like `question` with `answer`.
+    FaqAdd {
+        question: String,
+        answer: String,
+    },
+    /// List this server's FAQ.
+    FaqList,
+    /// Remove a question from this server's FAQ, by its id.
+    FaqRemove {
+        id: i64,
+    },
+    /// Switch this channel's conversation to a preset's model, temperature, reply
+    /// length and prompt.
+    PresetUse {
+        name: String,
+    },
+    /// List the presets.
+    PresetList,
+    /// Register (or with None, remove) this server's own OpenAI key.
+    OpenaiKey {
+        key: Option<String>,
+    },
+}
+
+/// How much currency `/daily` pays out.
+const DAILY_AMOUNT: i64 = 10;
+
+/// Discord shows at most this many autocomplete suggestions.
+const MAX_SUGGESTIONS: usize = 25;
+
+/// Who ran a command, and where.
+pub struct Invocation {
+    pub user: Speaker,
+    pub channel_id: ChannelId,
+    pub guild_id: Option<GuildId>,
+}
+
+/// Define the slash commands for [`BotCommand`].
+pub fn create_commands(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
+    commands.create_application_command(|command| {
+        command
+            .name("sheet")
+            .description("Your character sheet")
+            .create_option(|option| {
+                option
+                    .name("set")
+                    .description(
+                        "Set a field on your character sheet (leave out the value to remove it)",
+                    )
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("key")
+                            .description("The field, like class or hp")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                            .set_autocomplete(true)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("value")
+                            .description("The value")
+                            .kind(CommandOptionType::String)
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("show")
+                    .description("Show a character sheet")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("user")
+                            .description("Whose sheet, by default yours")
+                            .kind(CommandOptionType::User)
+                    })
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("daily")
+            .description(format!("Claim your daily {CURRENCY}"))
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("balance")
+            .description(format!("Show your {CURRENCY} and inventory"))
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("quests")
+            .description("Show the quest log")
+            .create_option(|o| {
+                o.name("all")
+                    .description("Include completed quests")
+                    .kind(CommandOptionType::Boolean)
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("mute")
+            .description("Make the bot ignore this channel for a while")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+            .create_option(|o| {
+                o.name("duration")
+                    .description("How long, like 30m, 1h or 2d (default 1h)")
+                    .kind(CommandOptionType::String)
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("unmute")
+            .description("Let the bot talk in this channel again")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("dnd")
+            .description("Set daily quiet hours for this channel (leave out both to clear them)")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+            .create_option(|o| {
+                o.name("start")
+                    .description("Start time, like 22:00")
+                    .kind(CommandOptionType::String)
+            })
+            .create_option(|o| {
+                o.name("end")
+                    .description("End time, like 07:00")
+                    .kind(CommandOptionType::String)
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("block")
+            .description("Stop someone from talking to the bot in this server")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+            .dm_permission(false)
+            .create_option(|o| {
+                o.name("user")
+                    .description("Who to block")
+                    .kind(CommandOptionType::User)
+                    .required(true)
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("unblock")
+            .description("Let someone talk to the bot again")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+            .dm_permission(false)
+            .create_option(|o| {
+                o.name("user")
+                    .description("Who to unblock")
+                    .kind(CommandOptionType::User)
+                    .required(true)
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("tokens")
+            .description("Show how much of the context window this channel uses")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("link")
+            .description("Make this channel share another channel's conversation")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+            .dm_permission(false)
+            .create_option(|o| {
+                o.name("channel")
+                    .description("The channel whose conversation to share")
+                    .kind(CommandOptionType::Channel)
+                    .required(true)
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("merge")
+            .description("Move another channel's conversation, history and all, into this one")
+            .default_member_permissions(Permissions::MANAGE_CHANNELS)
+            .dm_permission(false)
+            .create_option(|o| {
+                o.name("channel")
+                    .description("The channel whose conversation to merge")
+                    .kind(CommandOptionType::Channel)
+                    .required(true)
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("prompt")
+            .description("This channel's system prompt")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+            .create_option(|option| {
+                option
+                    .name("preview")
+                    .description("Show the prompt as the model would see it, rendered for you")
+                    .kind(CommandOptionType::SubCommand)
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("debug")
+            .description("Look into how the bot replied")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+            .create_option(|option| {
+                option
+                    .name("last")
+                    .description("Model, tokens, latency and tools for the latest reply here")
+                    .kind(CommandOptionType::SubCommand)
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("privacy")
+            .description("Show or change how much of our DMs I keep")
+            .create_option(|o| {
+                o.name("mode")
+                    .description("full keeps everything, summary a short summary, off nothing")
+                    .kind(CommandOptionType::String)
+                    .add_string_choice("full", "full")
+                    .add_string_choice("summary", "summary")
+                    .add_string_choice("off", "off")
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("opinions")
+            .description("Whether I'll give my opinion of you when someone asks")
+            .create_option(|o| {
+                o.name("allow")
+                    .description("Let people ask for my opinion of you")
+                    .kind(CommandOptionType::Boolean)
+                    .required(true)
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("birthday")
+            .description("Your birthday, so I can wish you a happy one")
+            .dm_permission(false)
+            .create_option(|option| {
+                option
+                    .name("set")
+                    .description("Share your birthday with this server")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("month")
+                            .description("From 1 to 12")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(1)
+                            .max_int_value(12)
+                            .required(true)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("day")
+                            .description("From 1 to 31")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(1)
+                            .max_int_value(31)
+                            .required(true)
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("remove")
+                    .description("Forget your birthday")
+                    .kind(CommandOptionType::SubCommand)
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("openai-key")
+            .description("Have this server's requests billed to its own OpenAI key")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .dm_permission(false)
+            .create_option(|option| {
+                option
+                    .name("set")
+                    .description("Use this key for everything the bot does here")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("key")
+                            .description("An OpenAI API key, stored encrypted")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("remove")
+                    .description("Forget this server's key")
+                    .kind(CommandOptionType::SubCommand)
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("canned")
+            .description("Fixed answers for messages matching a pattern, without the model")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .dm_permission(false)
+            .create_option(|option| {
+                option
+                    .name("add")
+                    .description("Answer messages matching a pattern with this response")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("pattern")
+                            .description("A regular expression, like \\brules\\b, ignoring case")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("response")
+                            .description("What to say")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("weight")
+                            .description("How often it's picked over others for the pattern")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(1)
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("list")
+                    .description("Show this server's canned responses")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_option(|option| {
+                option
+                    .name("remove")
+                    .description("Stop giving a canned response")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("id")
+                            .description("The response's id, from /canned list")
+                            .kind(CommandOptionType::Integer)
+                            .required(true)
+                    })
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("faq")
+            .description("Questions the bot answers the same way every time")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .dm_permission(false)
+            .create_option(|option| {
+                option
+                    .name("add")
+                    .description("Answer messages asking this question with this answer")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("question")
+                            .description("Like: How do I get the member role?")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("answer")
+                            .description("The answer, given as written or in character")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("list")
+                    .description("Show this server's FAQ")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_option(|option| {
+                option
+                    .name("remove")
+                    .description("Stop answering a question")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("id")
+                            .description("The question's id, from /faq list")
+                            .kind(CommandOptionType::Integer)
+                            .required(true)
+                    })
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("preset")
+            .description("Switch styles: model, temperature, reply length and prompt at once")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .create_option(|option| {
+                option
+                    .name("use")
+                    .description("Switch this channel to a preset")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("name")
+                            .description("Like storyteller, concise helper or chaos horse")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                            .set_autocomplete(true)
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("list")
+                    .description("Show the presets")
+                    .kind(CommandOptionType::SubCommand)
+            })
+    });
+    commands.create_application_command(|command| {
+        command
+            .name("admin")
+            .description("Manage the bot itself")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .dm_permission(false)
+            .create_option(|option| {
+                option
+                    .name("reload")
+                    .description("Re-read the configuration and plugins without reconnecting")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_option(|option| {
+                option
+                    .name("status")
+                    .description("Show an activity of your own, everywhere, until it's cleared (bot owner only)")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("text")
+                            .description("What I'm playing. Leave out to go back to the usual")
+                            .kind(CommandOptionType::String)
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("redact")
+                    .description("Blank out a stored message in this channel's conversation")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("id")
+                            .description("The message's id, from the transcript")
+                            .kind(CommandOptionType::Integer)
+                            .required(true)
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("mood")
+                    .description("Show or set my mood here, which wears off over a few hours")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("mood")
+                            .description("Leave out to see how I feel")
+                            .kind(CommandOptionType::String)
+                            .add_string_choice("gloomy", "gloomy")
+                            .add_string_choice("grumpy", "grumpy")
+                            .add_string_choice("content", "content")
+                            .add_string_choice("cheerful", "cheerful")
+                            .add_string_choice("giddy", "giddy")
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("flag")
+                    .description("Turn tools, ambient context or memory on or off here")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("flag")
+                            .description("The behavior to change")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                            .add_string_choice("tools", "tools")
+                            .add_string_choice("ambient_context", "ambient_context")
+                            .add_string_choice("memory", "memory")
+                    })
+                    .create_sub_option(|o| {
+                        o.name("state")
+                            .description("inherit goes back to the server's (or the bot's) choice")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                            .add_string_choice("on", "on")
+                            .add_string_choice("off", "off")
+                            .add_string_choice("inherit", "inherit")
+                    })
+                    .create_sub_option(|o| {
+                        o.name("server")
+                            .description("For the whole server instead of this channel")
+                            .kind(CommandOptionType::Boolean)
+                    })
+            })
+    });
+    context_menu::create_context_menus(commands)
+}
+
+impl BotCommand {
+    pub fn from_interaction(interaction: &ApplicationCommandInteraction) -> Result<Self> {
+        let data = &interaction.data;
+        let sub = subcommand(&data.options);
+        let command = match (data.name.as_str(), sub) {
+            ("sheet", Some(("set", options))) => BotCommand::SheetSet {
+                key: string_option(options, "key").ok_or_else(|| eyre!("key is required"))?,
+                value: string_option(options, "value"),
+            },
+            ("sheet", Some(("show", options))) => BotCommand::SheetShow {
+                user: user_option(options, "user").map(|u| u.id.to_string()),
+            },
+            ("daily", None) => BotCommand::Daily,
+            ("balance", None) => BotCommand::Balance,
+            ("quests", None) => BotCommand::Quests {
+                all: bool_option(&data.options, "all").unwrap_or(false),
+            },
+            ("mute", None) => BotCommand::Mute {
+                duration: parse_duration(
+                    &string_option(&data.options, "duration").unwrap_or_else(|| "1h".to_owned()),
+                )?,
+            },
+            ("unmute", None) => BotCommand::Unmute,
+            ("dnd", None) => BotCommand::Dnd {
+                window: parse_window(
+                    string_option(&data.options, "start"),
+                    string_option(&data.options, "end"),
+                )?,
+            },
+            ("block", None) => BotCommand::Block {
+                user: user_option(&data.options, "user")
+                    .ok_or_else(|| eyre!("user is required"))?
+                    .id,
+            },
+            ("unblock", None) => BotCommand::Unblock {
+                user: user_option(&data.options, "user")
+                    .ok_or_else(|| eyre!("user is required"))?
+                    .id,
+            },
+            ("tokens", None) => BotCommand::Tokens,
+            ("link", None) => BotCommand::Link {
+                channel: channel_option(&data.options, "channel")
+                    .ok_or_else(|| eyre!("channel is required"))?,
+            },
+            ("prompt", Some(("preview", _))) => BotCommand::PromptPreview,
+            ("admin", Some(("reload", _))) => BotCommand::Reload,
+            ("admin", Some(("status", options))) => BotCommand::Status {
+                text: string_option(options, "text"),
+            },
+            ("admin", Some(("redact", options))) => BotCommand::Redact {
+                id: integer_option(options, "id").ok_or_else(|| eyre!("id is required"))?,
+            },
+            ("admin", Some(("mood", options))) => BotCommand::Mood {
+                mood: string_option(options, "mood")
+                    .map(|m| m.parse())
+                    .transpose()?,
+            },
+            ("admin", Some(("flag", options))) => BotCommand::Flag {
+                flag: string_option(options, "flag")
+                    .ok_or_else(|| eyre!("flag is required"))?
+                    .parse()?,
+                enabled: parse_flag_state(
+                    &string_option(options, "state").ok_or_else(|| eyre!("state is required"))?,
+                )?,
+                server: bool_option(options, "server").unwrap_or_default(),
+            },
+            ("debug", Some(("last", _))) => BotCommand::DebugLast,
+            ("privacy", None) => BotCommand::Privacy {
+                mode: string_option(&data.options, "mode")
+                    .map(|m| m.parse())
+                    .transpose()?,
+            },
+            ("opinions", None) => BotCommand::Opinions {
+                allowed: bool_option(&data.options, "allow")
+                    .ok_or_else(|| eyre!("allow is required"))?,
+            },
+            ("birthday", Some(("set", options))) => {
+                let month =
+                    integer_option(options, "month").ok_or_else(|| eyre!("month is required"))?;
+                let day = integer_option(options, "day").ok_or_else(|| eyre!("day is required"))?;
+                BotCommand::Birthday {
+                    date: Some((u32::try_from(month)?, u32::try_from(day)?)),
+                }
+            }
+            ("birthday", Some(("remove", _))) => BotCommand::Birthday { date: None },
+            ("openai-key", Some(("set", options))) => BotCommand::OpenaiKey {
+                key: Some(string_option(options, "key").ok_or_else(|| eyre!("key is required"))?),
+            },
+            ("openai-key", Some(("remove", _))) => BotCommand::OpenaiKey { key: None },
+            ("canned", Some(("add", options))) => BotCommand::CannedAdd {
+                pattern: string_option(options, "pattern")
+                    .ok_or_else(|| eyre!("pattern is required"))?,
+                response: string_option(options, "response")
+                    .ok_or_else(|| eyre!("response is required"))?,
+                weight: integer_option(options, "weight")
+                    .map(u32::try_from)
+                    .transpose()?
+                    .unwrap_or(1),
+            },
+            ("canned", Some(("list", _))) => BotCommand::CannedList,
+            ("canned", Some(("remove", options))) => BotCommand::CannedRemove {
+                id: integer_option(options, "id").ok_or_else(|| eyre!("id is required"))?,
+            },
+            ("faq", Some(("add", options))) => BotCommand::FaqAdd {
+                question: string_option(options, "question")
+                    .ok_or_else(|| eyre!("question is required"))?,
+                answer: string_option(options, "answer")
+                    .ok_or_else(|| eyre!("answer is required"))?,
+            },
+            ("faq", Some(("list", _))) => BotCommand::FaqList,
+            ("faq", Some(("remove", options))) => BotCommand::FaqRemove {
+                id: integer_option(options, "id").ok_or_else(|| eyre!("id is required"))?,
+            },
+            ("preset", Some(("use", options))) => BotCommand::PresetUse {
+                name: string_option(options, "name").ok_or_else(|| eyre!("name is required"))?,
+            },
+            ("preset", Some(("list", _))) => BotCommand::PresetList,
+            ("merge", None) => BotCommand::Merge {
+                channel: channel_option(&data.options, "channel")
+                    .ok_or_else(|| eyre!("channel is required"))?,
+            },
+            (name, _) => return Err(eyre!("unknown command {name}")),
+        };
+
+        Ok(command)
+    }
+
+    /// The permissions needed to run a command, matching its slash command.
+    pub fn permissions(&self) -> Permissions {
+        match self {
+            BotCommand::Mute { .. }
+            | BotCommand::Unmute
+            | BotCommand::Dnd { .. }
+            | BotCommand::Block { .. }
+            | BotCommand::Unblock { .. }
+            | BotCommand::Tokens
+            | BotCommand::Link { .. }
+            | BotCommand::PromptPreview
+            | BotCommand::DebugLast => Permissions::MANAGE_MESSAGES,
+            BotCommand::Merge { .. } => Permissions::MANAGE_CHANNELS,
+            BotCommand::CannedAdd { .. }
+            | BotCommand::CannedList
+            | BotCommand::CannedRemove { .. }
+            | BotCommand::FaqAdd { .. }
+            | BotCommand::FaqList
+            | BotCommand::FaqRemove { .. }
+            | BotCommand::PresetUse { .. }
+            | BotCommand::PresetList => Permissions::MANAGE_GUILD,
+            BotCommand::Reload
+            | BotCommand::Status { .. }
+            | BotCommand::Redact { .. }
+            | BotCommand::Mood { .. }
+            | BotCommand::Flag { .. }
+            | BotCommand::OpenaiKey { .. } => Permissions::ADMINISTRATOR,
+            _ => Permissions::empty(),
+        }
+    }
+
+    /// Whether a command reaches past the conversation it's run in, to a whole server or
+    /// the whole bot, so it needs a server to check the permissions of whoever ran it.
+    pub fn needs_guild(&self) -> bool {
+        matches!(
+            self,
+            BotCommand::Reload
+                | BotCommand::Status { .. }
+                | BotCommand::OpenaiKey { .. }
+                | BotCommand::Flag { server: true, .. }
+        )
+    }
+}
+
+fn parse_window(
+    start: Option<String>,
+    end: Option<String>,
+) -> Result<Option<(NaiveTime, NaiveTime)>> {
+    match (start, end) {
+        (Some(start), Some(end)) => Ok(Some((
+            NaiveTime::parse_from_str(&start, "%H:%M")?,
+            NaiveTime::parse_from_str(&end, "%H:%M")?,
+        ))),
+        (None, None) => Ok(None),
+        _ => Err(eyre!("give both a start and an end time")),
+    }
+}
+
+fn subcommand(options: &[CommandDataOption]) -> Option<(&str, &[CommandDataOption])> {
+    options
+        .first()
+        .filter(|o| o.kind == CommandOptionType::SubCommand)
+        .map(|o| (o.name.as_str(), o.options.as_slice()))
+}
+
+fn string_option(options: &[CommandDataOption], name: &str) -> Option<String> {
+    options
+        .iter()
+        .find(|o| o.name == name)
+        .and_then(|o| o.value.as_ref())
+        .and_then(|v| v.as_str())
+        .map(str::to_owned)
+}
+
+fn bool_option(options: &[CommandDataOption], name: &str) -> Option<bool> {
+    options
+        .iter()
+        .find(|o| o.name == name)
+        .and_then(|o| o.value.as_ref())
+        .and_then(|v| v.as_bool())
+}
+
+fn integer_option(options: &[CommandDataOption], name: &str) -> Option<i64> {
+    options
+        .iter()
+        .find(|o| o.name == name)
+        .and_then(|o| o.value.as_ref())
+        .and_then(|v| v.as_i64())
+}
+
+fn channel_option(options: &[CommandDataOption], name: &str) -> Option<ChannelId> {
+    options
+        .iter()
+        .find(|o| o.name == name)
+        .and_then(|o| match &o.resolved {
+            Some(CommandDataOptionValue::Channel(channel)) => Some(channel.id),
+            _ => None,
+        })
+}
+
+fn user_option(options: &[CommandDataOption], name: &str) -> Option<User> {
+    options
+        .iter()
+        .find(|o| o.name == name)
+        .and_then(|o| match &o.resolved {
+            Some(CommandDataOptionValue::User(user, _)) => Some(user.clone()),
+            _ => None,
+        })
+}
+
+impl DiscordBot {
+    pub async fn run_command(
+        &self,
+        context: &discord::Context,
+        invocation: &Invocation,
+        command: BotCommand,
+    ) -> Result<String> {
+        let conversation = self
+            .channel_conversation(context, invocation.channel_id)
+            .await?;
+
+        match command {
+            BotCommand::SheetSet { key, value } => {
+                let reply = match &value {
+                    Some(value) => format!("Set {key} to {value}."),
+                    None => format!("Removed {key}."),
+                };
+                self.database
+                    .set_sheet_value(
+                        conversation,
+                        &invocation.user.id,
+                        &invocation.user.name,
+                        &key,
+                        value.as_ref(),
+                    )
+                    .await?;
+                Ok(reply)
+            }
+            BotCommand::SheetShow { user } => {
+                let user = user.unwrap_or_else(|| invocation.user.id.clone());
+                let sheet = self.database.get_sheet(conversation, &user).await?;
+                if sheet.is_empty() {
+                    return Ok("No character sheet yet. Fill one in with /sheet set.".to_owned());
+                }
+                Ok(sheet
+                    .into_iter()
+                    .map(|(key, value)| format!("**{key}**: {value}"))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+            BotCommand::Daily => {
+                self.ensure_wallet(conversation, &invocation.user).await?;
+                let today = chrono::Local::now().date_naive();
+                let balance = self
+                    .database
+                    .claim_daily(conversation, &invocation.user.id, today, DAILY_AMOUNT)
+                    .await?;
+                Ok(match balance {
+                    Some(balance) => {
+                        format!("Here are {DAILY_AMOUNT} {CURRENCY}. You now have {balance}.")
+                    }
+                    None => "You already claimed today's allowance. Come back tomorrow!".to_owned(),
+                })
+            }
+            BotCommand::Balance => {
+                self.ensure_wallet(conversation, &invocation.user).await?;
+                let wallet = self
+                    .database
+                    .wallet(conversation, &invocation.user.id)
+                    .await?;
+                Ok(describe_wallet(&wallet))
+            }
+            BotCommand::Quests { all } => {
+                let quests = self.database.list_quests(conversation, all).await?;
+                Ok(describe_quests(&quests))
+            }
+            BotCommand::Mute { duration } => {
+                let until = from_now(duration)?;
+                self.database
+                    .set_muted_until(conversation, Some(until))
+                    .await?;
+                Ok(format!("Muted until {}.", until.format("%A %I:%M %p")))
+            }
+            BotCommand::Unmute => {
+                self.database.set_muted_until(conversation, None).await?;
+                Ok("Unmuted.".to_owned())
+            }
+            BotCommand::Dnd { window } => {
+                self.database.set_dnd(conversation, window).await?;
+                Ok(match window {
+                    Some((start, end)) => format!(
+                        "Quiet hours are {} to {}.",
+                        start.format("%H:%M"),
+                        end.format("%H:%M")
+                    ),
+                    None => "Quiet hours cleared.".to_owned(),
+                })
+            }
+            BotCommand::Block { user } => {
+                // every DM shares the one list, so a DM can't change it
+                let Some(guild_id) = invocation.guild_id else {
+                    return Err(eyre!("people are blocked per server"));
+                };
+                self.database.block_user(Some(guild_id.0), user.0).await?;
+                Ok(format!("Blocked <@{}>.", user.0))
+            }
+            BotCommand::Unblock { user } => {
+                let Some(guild_id) = invocation.guild_id else {
+                    return Err(eyre!("people are blocked per server"));
+                };
+                if self.database.unblock_user(Some(guild_id.0), user.0).await? {
+                    Ok(format!("Unblocked <@{}>.", user.0))
+                } else {
+                    Ok(format!("<@{}> wasn't blocked.", user.0))
+                }
+            }
+            BotCommand::Tokens => {
+                let guild = invocation.guild_id.map(|g| g.0);
+                let report =
+                    token_report(&self.database, &self.current_tools(), conversation, guild)
+                        .await?;
+                Ok(format!("```\n{report}\n```"))
+            }
+            BotCommand::Link { channel } => {
+                let Some(guild_id) = invocation.guild_id else {
+                    return Err(eyre!("channels are linked within a server"));
+                };
+                if channel == invocation.channel_id {
+                    return Err(eyre!("a channel can't link to itself"));
+                }
+                self.ensure_in_guild(context, channel, guild_id).await?;
+                let target = self.channel_conversation(context, channel).await?;
+                self.database
+                    .link_channel(invocation.channel_id.to_string(), target)
+                    .await?;
+                let name = self.database.conversation_name(target).await?;
+                Ok(format!("This channel now shares the {name} conversation."))
+            }
+            BotCommand::Merge { channel } => {
+                let Some(guild_id) = invocation.guild_id else {
+                    return Err(eyre!("conversations are merged within a server"));
+                };
+                self.ensure_in_guild(context, channel, guild_id).await?;
+                let src = self.channel_conversation(context, channel).await?;
+                let name = self.database.conversation_name(src).await?;
+                self.database.merge_conversations(src, conversation).await?;
+                self.vectors.reassign(src, conversation).await?;
+                Ok(format!("Merged {name} into this channel's conversation."))
+            }
+            BotCommand::PromptPreview => {
+                let guild = invocation.guild_id.and_then(|g| g.to_guild_cached(context));
+                let user_id = UserId(invocation.user.id.parse()?);
+                let vars = self
+                    .channel_prompt_vars(
+                        context,
+                        guild.as_ref(),
+                        invocation.guild_id,
+                        invocation.channel_id,
+                        user_id,
+                    )
+                    .await?;
+                let nsfw_prompt = if self.channel_is_nsfw(context, invocation.channel_id).await? {
+                    self.database.get_nsfw_settings(conversation).await?.prompt
+                } else {
+                    None
+                };
+                let hooks = Hooks::load(&self.database, conversation).await?;
+                render_prompt(
+                    &self.database,
+                    conversation,
+                    invocation.guild_id.map(|g| g.0),
+                    nsfw_prompt,
+                    hooks.as_ref(),
+                    vars,
+                )
+                .await
+            }
+            BotCommand::Reload => {
+                self.reload().await?;
+                Ok("Reloaded configuration.".to_owned())
+            }
+            BotCommand::Status { text } => {
+                // the activity shows in every server, so only whoever runs the bot sets it
+                if !self.is_operator(context, &invocation.user.id).await? {
+                    return Err(eyre!("only the bot's owner can change its status"));
+                }
+                let reply = match &text {
+                    Some(text) => format!("Now playing {text}."),
+                    None => "Back to the usual activity.".to_owned(),
+                };
+                self.presence.set_override(text);
+                self.presence.update(context, false).await;
+                Ok(reply)
+            }
+            BotCommand::Redact { id } => {
+                if !self.database.redact_message(Some(conversation), id).await? {
+                    return Err(eyre!("no message {id} in this channel's conversation"));
+                }
+                self.vectors.remove(&[id]).await?;
+                Ok(format!("Redacted message {id}."))
+            }
+            BotCommand::Mood { mood } => {
+                let now = chrono::Local::now();
+                let mood = match mood {
+                    Some(mood) => {
+                        self.database.set_mood(conversation, mood, now).await?;
+                        mood
+                    }
+                    None => self.database.mood(conversation, now).await?,
+                };
+                Ok(format!("I'm feeling {} here.", mood.as_str()))
+            }
+            BotCommand::Flag {
+                flag,
+                enabled,
+                server,
+            } => {
+                let scope = match (server, invocation.guild_id) {
+                    (true, Some(guild_id)) => SettingScope::Guild(guild_id.0),
+                    (true, None) => return Err(eyre!("server flags are for servers")),
+                    (false, _) => SettingScope::Conversation(conversation),
+                };
+                self.database.set_flag(scope, flag, enabled).await?;
+                let state = match enabled {
+                    Some(true) => "on",
+                    Some(false) => "off",
+                    None => "back to inheriting",
+                };
+                let place = if server { "this server" } else { "here" };
+                Ok(format!("{} is now {state} for {place}.", flag.as_str()))
+            }
+            BotCommand::Privacy { mode } => {
+                if invocation.guild_id.is_some() {
+                    return Err(eyre!("privacy settings are for DMs with me"));
+                }
+                let mode = match mode {
+                    Some(mode) => {
+                        self.database.set_privacy(conversation, mode).await?;
+                        mode
+                    }
+                    None => self
+                        .database
+                        .get_privacy(conversation)
+                        .await?
+                        .unwrap_or_default(),
+                };
+                Ok(match mode {
+                    PrivacyMode::Full => "I keep everything we say here.",
+                    PrivacyMode::Summary => "I keep only a short summary of what we say here.",
+                    PrivacyMode::Off => "I forget what we say here once I've answered.",
+                }
+                .to_owned())
+            }
+            BotCommand::Opinions { allowed } => {
+                let user_id = invocation.user.id.parse()?;
+                self.database.set_opinions_allowed(user_id, allowed).await?;
+                Ok(if allowed {
+                    "I'll tell anyone who asks what I make of you."
+                } else {
+                    "I'll keep my opinions of you to myself."
+                }
+                .to_owned())
+            }
+            BotCommand::Birthday { date } => {
+                let Some(guild_id) = invocation.guild_id else {
+                    return Err(eyre!("birthdays are shared with a server"));
+                };
+                self.database
+                    .set_birthday(
+                        guild_id.0,
+                        invocation.user.id.clone(),
+                        invocation.user.name.clone(),
+                        date,
+                    )
+                    .await?;
+                Ok(match date {
+                    Some((month, day)) => {
+                        let date = chrono::NaiveDate::from_ymd_opt(2000, month, day)
+                            .ok_or_else(|| eyre!("no such day"))?;
+                        format!("I'll remember your birthday, {}.", date.format("%B %-d"))
+                    }
+                    None => "I've forgotten your birthday.".to_owned(),
+                })
+            }
+            BotCommand::CannedAdd {
+                pattern,
+                response,
+                weight,
+            } => {
+                canned::pattern(&pattern)?;
+                if weight == 0 {
+                    return Err(eyre!("the weight must be at least 1"));
+                }
+                // every DM shares the ones without a server, so a DM can't change them
+                let Some(guild_id) = invocation.guild_id else {
+                    return Err(eyre!("canned responses are kept per server"));
+                };
+                let id = self
+                    .database
+                    .add_canned_response(Some(guild_id.0), pattern.clone(), response, weight)
+                    .await?;
+                Ok(format!("Added canned response {id} for `{pattern}`."))
+            }
+            BotCommand::CannedList => {
+                let guild_id = invocation.guild_id.map(|g| g.0);
+                let responses = self.database.canned_responses(guild_id).await?;
+                if responses.is_empty() {
+                    return Ok("No canned responses yet.".to_owned());
+                }
+                // split into messages between responses, never in the middle of one
+                Ok(canned::list(&responses))
+            }
+            BotCommand::CannedRemove { id } => {
+                let Some(guild_id) = invocation.guild_id else {
+                    return Err(eyre!("canned responses are kept per server"));
+                };
+                if self
+                    .database
+                    .remove_canned_response(Some(guild_id.0), id)
+                    .await?
+                {
+                    Ok(format!("Removed canned response {id}."))
+                } else {
+                    Err(eyre!("no canned response {id} here"))
+                }
+            }
+            BotCommand::FaqAdd { question, answer } => {
+                // every DM shares the entries without a server, so a DM can't change them
+                let Some(guild) = invocation.guild_id else {
+                    return Err(eyre!("the FAQ is kept per server"));
+                };
+                let guild_id = Some(guild.0);
+                let model = SettingsResolver::new(&self.database, conversation, guild_id)
+                    .get(&EMBEDDING_MODEL)
+                    .await?;
+                let embedding =
+                    embeddings::embed_one(&self.current_openai(), guild_id, &model, &question)
+                        .await?;
+                let id = self
+                    .database
+                    .add_faq(guild_id, question, answer, &embedding, model)
+                    .await?;
+                Ok(format!("Added FAQ {id}."))
+            }
+            BotCommand::FaqList => {
+                let guild_id = invocation.guild_id.map(|g| g.0);
+                let faqs = self.database.faqs(guild_id).await?;
+                if faqs.is_empty() {
+                    return Ok("No FAQ yet.".to_owned());
+                }
+                Ok(faqs
+                    .iter()
+                    .map(|f| format!("{}. {}\n> {}", f.id, f.question, f.answer))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+            BotCommand::FaqRemove { id } => {
+                let Some(guild_id) = invocation.guild_id else {
+                    return Err(eyre!("the FAQ is kept per server"));
+                };
+                if self.database.remove_faq(Some(guild_id.0), id).await? {
+                    Ok(format!("Removed FAQ {id}."))
+                } else {
+                    Err(eyre!("no FAQ {id} here"))
+                }
+            }
+            BotCommand::PresetUse { name } => {
+                let preset = presets::find(&self.database, &name).await?;
+                presets::apply(&self.database, conversation, &preset).await?;
+                Ok(format!("Switched to {}.", preset.name))
+            }
+            BotCommand::PresetList => Ok(presets::all(&self.database)
+                .await?
+                .iter()
+                .map(|p| format!("**{}**: {}", p.name, p.description))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            BotCommand::OpenaiKey { key } => {
+                let Some(guild_id) = invocation.guild_id else {
+                    return Err(eyre!("OpenAI keys are registered per server"));
+                };
+                let Some(secrets) = self.secrets()? else {
+                    return Err(eyre!("this bot isn't set up to use servers' own keys"));
+                };
+                let sealed = match &key {
+                    Some(key) => {
+                        // make sure OpenAI takes it before anything is billed to it
+                        new_client(key.clone(), self.openai_timeout)?
+                            .models()
+                            .list()
+                            .await
+                            .wrap_err("OpenAI didn't accept that key")?;
+                        Some(secrets.seal(key)?)
+                    }
+                    None => None,
+                };
+                self.database.set_guild_key(guild_id.0, sealed).await?;
+                let registered = key.is_some();
+                self.current_openai().set_guild_key(guild_id.0, key)?;
+                Ok(if registered {
+                    "This server's requests now use its own OpenAI key."
+                } else if self.guild_keys_required {
+                    "Forgot this server's key. I won't reply here until another is registered."
+                } else {
+                    "Forgot this server's key. Requests here use the bot's own keys again."
+                }
+                .to_owned())
+            }
+            BotCommand::DebugLast => match self.database.last_reply_metadata(conversation).await? {
+                Some(metadata) => Ok(format!("```\n{metadata}\n```")),
+                None => Ok("I haven't replied here yet.".to_owned()),
+            },
+        }
+    }
+
+    /// Make sure a channel named in a command belongs to the server it was run in, so
+    /// one server's moderators can't reach into another server's conversations.
+    async fn ensure_in_guild(
+        &self,
+        context: &discord::Context,
+        channel: ChannelId,
+        guild_id: GuildId,
+    ) -> Result<()> {
+        match channel.to_channel(context).await? {
+            Channel::Guild(c) if c.guild_id == guild_id => Ok(()),
+            _ => Err(eyre!("<#{}> isn't a channel in this server", channel.0)),
+        }
+    }
+
+    async fn ensure_wallet(&self, conversation: Conversation, user: &Speaker) -> Result<()> {
+        self.database
+            .ensure_wallet(conversation, &user.id, &user.name)
+            .await
+    }
+
+    /// Run a bang command if the message is one. Returns whether it was.
+    pub async fn bang_hook(&self, context: &discord::Context, msg: &Message) -> Result<bool> {
+        let Some(command) = bang::parse(&msg.content) else {
+            return Ok(false);
+        };
+        let guild_id = msg.guild_id.map(|g| g.0);
+        if self.database.is_blocked(guild_id, msg.author.id.0).await? {
+            log::info!("Ignoring command from blocked user {}", msg.author.id);
+            return Ok(true);
+        }
+        if self.claim(context, msg).await?.is_none() {
+            return Ok(true);
+        }
+        let reply = match command {
+            Ok(command) => self.run_bang_command(context, msg, command).await,
+            Err(e) => Err(e),
+        };
+        let content = reply.unwrap_or_else(|e| {
+            log::error!("Command failed: {}", e);
+            format!("Something went wrong: {}", e)
+        });
+        for chunk in text::split_message(&content, MAX_MESSAGE_LENGTH) {
+            msg.reply(context, chunk).await?;
+        }
+
+        Ok(true)
+    }
+
+    async fn run_bang_command(
+        &self,
+        context: &discord::Context,
+        msg: &Message,
+        command: BotCommand,
+    ) -> Result<String> {
+        // a DM has no permissions to check, so these can't be trusted there
+        if command.needs_guild() && msg.guild_id.is_none() {
+            return Err(eyre!("that command only works in a server"));
+        }
+        let needed = command.permissions();
+        if !needed.is_empty() && msg.guild_id.is_some() {
+            let member = msg.member(context).await?;
+            if !member.permissions(context)?.contains(needed) {
+                return Err(eyre!("that command is for moderators"));
+            }
+        }
+        let invocation = Invocation {
+            user: self.speaker(context, msg).await?,
+            channel_id: msg.channel_id,
+            guild_id: msg.guild_id,
+        };
+        self.run_command(context, &invocation, command).await
+    }
+
+    // this is called by EventHandler::interaction_create, but it can return a Result.
+    pub async fn interaction_hook(
+        &self,
+        context: discord::Context,
+        interaction: Interaction,
+    ) -> Result<()> {
+        let interaction = match interaction {
+            Interaction::ApplicationCommand(interaction) => interaction,
+            Interaction::Autocomplete(autocomplete) => {
+                return self.autocomplete_hook(&context, autocomplete).await
+            }
+            Interaction::ModalSubmit(form) => return self.report_submitted(&context, &form).await,
+            _ => return Ok(()),
+        };
+        if self.context_menu_hook(&context, &interaction).await? {
+            return Ok(());
+        }
+        let user = &interaction.user;
+        let invocation = Invocation {
+            user: Speaker {
+                id: user.id.to_string(),
+                name: interaction
+                    .member
+                    .as_ref()
+                    .and_then(|m| m.nick.clone())
+                    .unwrap_or_else(|| user.name.clone()),
+            },
+            channel_id: interaction.channel_id,
+            guild_id: interaction.guild_id,
+        };
+        let reply = match BotCommand::from_interaction(&interaction) {
+            Ok(command) => self.run_command(&context, &invocation, command).await,
+            Err(e) => Err(e),
+        };
+        let content = reply.unwrap_or_else(|e| {
+            log::error!("Command failed: {}", e);
+            format!("Something went wrong: {}", e)
+        });
+
+        let mut chunks = text::split_message(&content, MAX_MESSAGE_LENGTH).into_iter();
+        let first = chunks.next().unwrap_or_default();
+        interaction
+            .create_interaction_response(&context.http, |response| {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|message| message.content(first).ephemeral(true))
+            })
+            .await?;
+        for chunk in chunks {
+            interaction
+                .create_followup_message(&context.http, |message| {
+                    message.content(chunk).ephemeral(true)
+                })
+                .await?;
+        }
+
+        Ok(())
+    }
+    /// Whether a user owns the bot's Discord application, or is on the team that does.
+    async fn is_operator(&self, context: &discord::Context, user_id: &str) -> Result<bool> {
+        let app = context.http.get_current_application_info().await?;
+        let team = app
+            .team
+            .iter()
+            .flat_map(|t| t.members.iter().map(|m| m.user.id));
+        let mut owners = std::iter::once(app.owner.id).chain(team);
+        Ok(owners.any(|id| id.to_string() == user_id))
+    }
+
+    /// Suggest values for an option as it is typed. `/sheet set` suggests the field
+    /// names already used in the channel, and `/preset use` the presets, built-in and
+    /// stored, so people can find them without asking or listing them first.
+    async fn autocomplete_hook(
+        &self,
+        context: &discord::Context,
+        autocomplete: AutocompleteInteraction,
+    ) -> Result<()> {
+        let data = &autocomplete.data;
+        let suggestions = match (data.name.as_str(), subcommand(&data.options)) {
+            ("sheet", Some(("set", options))) => {
+                let typed = string_option(options, "key").unwrap_or_default();
+                let conversation = self
+                    .channel_conversation(context, autocomplete.channel_id)
+                    .await?;
+                let guild_id = autocomplete.guild_id.map(|g| g.0);
+                let keys = self.database.sheet_keys(conversation, guild_id).await?;
+                text::fuzzy_matches(&typed, &keys)
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect()
+            }
+            ("preset", Some(("use", options))) => {
+                let typed = string_option(options, "name").unwrap_or_default();
+                let names = presets::all(&self.database)
+                    .await?
+                    .into_iter()
+                    .map(|p| p.name)
+                    .collect::<Vec<_>>();
+                text::fuzzy_matches(&typed, &names)
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect()
+            }
+            _ => vec![],
+        };
+
+        autocomplete
+            .create_autocomplete_response(&context.http, |response| {
+                for suggestion in suggestions.iter().take(MAX_SUGGESTIONS) {
+                    response.add_string_choice(suggestion, suggestion);
+                }
+                response
+            })
+            .await?;
+
+        Ok(())
+    }
+}
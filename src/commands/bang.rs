@@ -0,0 +1,492 @@
+//! Prefix commands like `!horse mute 30m`, for servers where slash commands are unavailable.
+//! They are parsed with clap into the same [`BotCommand`]s the slash commands use.
+
+use super::{parse_window, BotCommand};
+use crate::helpers::parse_duration;
+use clap::{Parser, Subcommand};
+use eyre::{eyre, Result};
+use serenity::model::id::{ChannelId, UserId};
+
+pub const PREFIX: &str = "!horse";
+
+#[derive(Parser)]
+#[command(name = PREFIX, no_binary_name = true, disable_version_flag = true)]
+struct BangArgs {
+    #[command(subcommand)]
+    command: BangCommand,
+}
+
+#[derive(Subcommand)]
+enum BangCommand {
+    /// Your character sheet
+    Sheet {
+        #[command(subcommand)]
+        action: SheetAction,
+    },
+    /// Claim your daily allowance
+    Daily,
+    /// Show your balance and inventory
+    Balance,
+    /// Show the quest log
+    Quests {
+        /// Include completed quests
+        #[arg(long)]
+        all: bool,
+    },
+    /// Make the bot ignore this channel for a while
+    Mute {
+        /// How long, like 30m, 1h or 2d
+        #[arg(default_value = "1h")]
+        duration: String,
+    },
+    /// Let the bot talk in this channel again
+    Unmute,
+    /// Set daily quiet hours for this channel (leave out both to clear them)
+    Dnd {
+        /// Start time, like 22:00
+        #[arg(requires = "end")]
+        start: Option<String>,
+        /// End time, like 07:00
+        end: Option<String>,
+    },
+    /// Stop someone from talking to the bot in this server
+    Block { user: String },
+    /// Let someone talk to the bot again
+    Unblock { user: String },
+    /// Show how much of the context window this channel uses
+    Tokens,
+    /// Make this channel share another channel's conversation
+    Link { channel: String },
+    /// Move another channel's conversation, history and all, into this one
+    Merge { channel: String },
+    /// This channel's system prompt
+    Prompt {
+        #[command(subcommand)]
+        action: PromptAction,
+    },
+    /// Show or change how much of our DMs the bot keeps
+    Privacy {
+        /// full, summary or off
+        mode: Option<String>,
+    },
+    /// Whether the bot gives its opinion of you when someone asks
+    Opinions {
+        #[arg(value_parser = ["on", "off"])]
+        setting: String,
+    },
+    /// Share (or forget) your birthday in this server
+    Birthday {
+        #[command(subcommand)]
+        action: BirthdayAction,
+    },
+    /// Fixed answers for messages matching a pattern
+    Canned {
+        #[command(subcommand)]
+        action: CannedAction,
+    },
+    /// Questions the bot answers the same way every time
+    Faq {
+        #[command(subcommand)]
+        action: FaqAction,
+    },
+    /// Switch styles: model, temperature, reply length and prompt at once
+    Preset {
+        #[command(subcommand)]
+        action: PresetAction,
+    },
+    /// Look into how the bot replied
+    Debug {
+        #[command(subcommand)]
+        action: DebugAction,
+    },
+    /// Manage the bot itself
+    Admin {
+        #[command(subcommand)]
+        action: AdminAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum BirthdayAction {
+    /// Share your birthday, like `set 3 14` for the 14th of March
+    Set { month: u32, day: u32 },
+    /// Forget your birthday
+    Remove,
+}
+
+#[derive(Subcommand)]
+enum DebugAction {
+    /// Model, tokens, latency and tools for the latest reply here
+    Last,
+}
+
+#[derive(Subcommand)]
+enum AdminAction {
+    /// Re-read the configuration and plugins without reconnecting
+    Reload,
+    /// Show an activity of your own, everywhere (leave it out to go back to the usual)
+    Status { text: Vec<String> },
+    /// Blank out a stored message in this channel's conversation, by its transcript id
+    Redact { id: i64 },
+    /// Show or set the bot's mood here: gloomy, grumpy, content, cheerful or giddy
+    Mood { mood: Option<String> },
+    /// Turn tools, ambient_context or memory on, off or back to inherit here
+    Flag {
+        flag: String,
+        state: String,
+        /// For the whole server instead of this channel
+        #[arg(long)]
+        server: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum CannedAction {
+    /// Answer messages matching a pattern (a regular expression, ignoring case)
+    Add {
+        pattern: String,
+        response: Vec<String>,
+        /// How often it's picked over other responses for the same pattern
+        #[arg(long, default_value_t = 1)]
+        weight: u32,
+    },
+    /// Show this server's canned responses
+    List,
+    /// Stop giving a canned response, by its id
+    Remove { id: i64 },
+}
+
+#[derive(Subcommand)]
+enum FaqAction {
+    /// Answer messages asking a ("quoted") question with an answer
+    Add {
+        question: String,
+        answer: Vec<String>,
+    },
+    /// Show this server's FAQ
+    List,
+    /// Stop answering a question, by its id
+    Remove { id: i64 },
+}
+
+#[derive(Subcommand)]
+enum PresetAction {
+    /// Switch this channel to a preset, like storyteller or chaos horse
+    Use { name: Vec<String> },
+    /// Show the presets
+    List,
+}
+
+#[derive(Subcommand)]
+enum PromptAction {
+    /// Show the prompt as the model would see it, rendered for you
+    Preview,
+}
+
+#[derive(Subcommand)]
+enum SheetAction {
+    /// Set a field on your character sheet (leave out the value to remove it)
+    Set { key: String, value: Vec<String> },
+    /// Show a character sheet, by default your own
+    Show { user: Option<String> },
+}
+
+/// Parse a message as a bang command. Returns None if it isn't one.
+/// Errors (including `!horse help`) carry clap's usage text.
+pub fn parse(content: &str) -> Option<Result<BotCommand>> {
+    let rest = content.trim().strip_prefix(PREFIX)?;
+    if !(rest.is_empty() || rest.starts_with(char::is_whitespace)) {
+        return None;
+    }
+    let args = match BangArgs::try_parse_from(split_args(rest)) {
+        Ok(args) => args,
+        Err(e) => return Some(Err(eyre!("```\n{}\n```", e.render()))),
+    };
+    Some(to_command(args.command))
+}
+
+fn to_command(command: BangCommand) -> Result<BotCommand> {
+    let command = match command {
+        BangCommand::Sheet {
+            action: SheetAction::Set { key, value },
+        } => BotCommand::SheetSet {
+            key,
+            value: Some(value.join(" ")).filter(|v| !v.is_empty()),
+        },
+        BangCommand::Sheet {
+            action: SheetAction::Show { user },
+        } => BotCommand::SheetShow {
+            user: user.map(|u| match parse_user(&u) {
+                Ok(id) => id.to_string(),
+                Err(_) => u,
+            }),
+        },
+        BangCommand::Daily => BotCommand::Daily,
+        BangCommand::Balance => BotCommand::Balance,
+        BangCommand::Quests { all } => BotCommand::Quests { all },
+        BangCommand::Mute { duration } => BotCommand::Mute {
+            duration: parse_duration(&duration)?,
+        },
+        BangCommand::Unmute => BotCommand::Unmute,
+        BangCommand::Dnd { start, end } => BotCommand::Dnd {
+            window: parse_window(start, end)?,
+        },
+        BangCommand::Block { user } => BotCommand::Block {
+            user: parse_user(&user)?,
+        },
+        BangCommand::Unblock { user } => BotCommand::Unblock {
+            user: parse_user(&user)?,
+        },
+        BangCommand::Tokens => BotCommand::Tokens,
+        BangCommand::Link { channel } => BotCommand::Link {
+            channel: parse_channel(&channel)?,
+        },
+        BangCommand::Merge { channel } => BotCommand::Merge {
+            channel: parse_channel(&channel)?,
+        },
+        BangCommand::Prompt {
+            action: PromptAction::Preview,
+        } => BotCommand::PromptPreview,
+        BangCommand::Privacy { mode } => BotCommand::Privacy {
+            mode: mode.map(|m| m.parse()).transpose()?,
+        },
+        BangCommand::Opinions { setting } => BotCommand::Opinions {
+            allowed: setting == "on",
+        },
+        BangCommand::Birthday {
+            action: BirthdayAction::Set { month, day },
+        } => BotCommand::Birthday {
+            date: Some((month, day)),
+        },
+        BangCommand::Birthday {
+            action: BirthdayAction::Remove,
+        } => BotCommand::Birthday { date: None },
+        BangCommand::Canned {
+            action:
+                CannedAction::Add {
+                    pattern,
+                    response,
+                    weight,
+                },
+        } => BotCommand::CannedAdd {
+            pattern,
+            response: response.join(" "),
+            weight,
+        },
+        BangCommand::Canned {
+            action: CannedAction::List,
+        } => BotCommand::CannedList,
+        BangCommand::Canned {
+            action: CannedAction::Remove { id },
+        } => BotCommand::CannedRemove { id },
+        BangCommand::Faq {
+            action: FaqAction::Add { question, answer },
+        } => BotCommand::FaqAdd {
+            question,
+            answer: answer.join(" "),
+        },
+        BangCommand::Faq {
+            action: FaqAction::List,
+        } => BotCommand::FaqList,
+        BangCommand::Faq {
+            action: FaqAction::Remove { id },
+        } => BotCommand::FaqRemove { id },
+        BangCommand::Preset {
+            action: PresetAction::Use { name },
+        } => BotCommand::PresetUse {
+            name: name.join(" "),
+        },
+        BangCommand::Preset {
+            action: PresetAction::List,
+        } => BotCommand::PresetList,
+        BangCommand::Debug {
+            action: DebugAction::Last,
+        } => BotCommand::DebugLast,
+        BangCommand::Admin {
+            action: AdminAction::Reload,
+        } => BotCommand::Reload,
+        BangCommand::Admin {
+            action: AdminAction::Status { text },
+        } => BotCommand::Status {
+            text: Some(text.join(" ")).filter(|t| !t.is_empty()),
+        },
+        BangCommand::Admin {
+            action: AdminAction::Redact { id },
+        } => BotCommand::Redact { id },
+        BangCommand::Admin {
+            action: AdminAction::Mood { mood },
+        } => BotCommand::Mood {
+            mood: mood.map(|m| m.parse()).transpose()?,
+        },
+        BangCommand::Admin {
+            action:
+                AdminAction::Flag {
+                    flag,
+                    state,
+                    server,
+                },
+        } => BotCommand::Flag {
+            flag: flag.parse()?,
+            enabled: crate::schema::parse_flag_state(&state)?,
+            server,
+        },
+    };
+
+    Ok(command)
+}
+
+/// Accept a mention (`<@123>` or `<@!123>`) or a bare user id.
+fn parse_user(s: &str) -> Result<UserId> {
+    let id = s
+        .strip_prefix("<@")
+        .and_then(|s| s.strip_suffix('>'))
+        .map(|s| s.trim_start_matches('!'))
+        .unwrap_or(s);
+    let id = id
+        .parse::<u64>()
+        .map_err(|_| eyre!("{s} is not a user mention"))?;
+    Ok(UserId(id))
+}
+
+/// Accept a channel mention (`<#123>`) or a bare channel id.
+fn parse_channel(s: &str) -> Result<ChannelId> {
+    let id = s
+        .strip_prefix("<#")
+        .and_then(|s| s.strip_suffix('>'))
+        .unwrap_or(s);
+    let id = id
+        .parse::<u64>()
+        .map_err(|_| eyre!("{s} is not a channel mention"))?;
+    Ok(ChannelId(id))
+}
+
+/// Split on whitespace, keeping "double quoted" words together.
+fn split_args(s: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut in_arg = false;
+    for c in s.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert!(parse("hello horse").is_none());
+        assert!(parse("!horsey daily").is_none());
+        assert_eq!(parse("!horse daily").unwrap().unwrap(), BotCommand::Daily);
+        assert_eq!(
+            parse(r#"!horse sheet set class "Wizard Knight""#)
+                .unwrap()
+                .unwrap(),
+            BotCommand::SheetSet {
+                key: "class".to_owned(),
+                value: Some("Wizard Knight".to_owned())
+            }
+        );
+        assert_eq!(
+            parse("!horse block <@!42>").unwrap().unwrap(),
+            BotCommand::Block { user: UserId(42) }
+        );
+        assert_eq!(
+            parse("!horse mute 30m").unwrap().unwrap(),
+            BotCommand::Mute {
+                duration: chrono::Duration::minutes(30)
+            }
+        );
+        assert_eq!(
+            parse("!horse link <#7>").unwrap().unwrap(),
+            BotCommand::Link {
+                channel: ChannelId(7)
+            }
+        );
+        assert_eq!(
+            parse("!horse privacy off").unwrap().unwrap(),
+            BotCommand::Privacy {
+                mode: Some(crate::schema::PrivacyMode::Off)
+            }
+        );
+        assert!(parse("!horse privacy everything").unwrap().is_err());
+        assert_eq!(
+            parse("!horse opinions off").unwrap().unwrap(),
+            BotCommand::Opinions { allowed: false }
+        );
+        assert_eq!(
+            parse("!horse admin status Out to pasture")
+                .unwrap()
+                .unwrap(),
+            BotCommand::Status {
+                text: Some("Out to pasture".to_owned())
+            }
+        );
+        assert_eq!(
+            parse("!horse birthday set 3 14").unwrap().unwrap(),
+            BotCommand::Birthday {
+                date: Some((3, 14))
+            }
+        );
+        assert_eq!(
+            parse("!horse admin redact 12").unwrap().unwrap(),
+            BotCommand::Redact { id: 12 }
+        );
+        assert_eq!(
+            parse("!horse admin mood grumpy").unwrap().unwrap(),
+            BotCommand::Mood {
+                mood: Some(crate::schema::Mood::Grumpy)
+            }
+        );
+        assert!(parse("!horse admin mood sleepy").unwrap().is_err());
+        assert_eq!(
+            parse(r#"!horse canned add "member role" React in #welcome"#)
+                .unwrap()
+                .unwrap(),
+            BotCommand::CannedAdd {
+                pattern: "member role".to_owned(),
+                response: "React in #welcome".to_owned(),
+                weight: 1
+            }
+        );
+        assert_eq!(
+            parse("!horse admin flag memory off --server")
+                .unwrap()
+                .unwrap(),
+            BotCommand::Flag {
+                flag: crate::schema::Flag::Memory,
+                enabled: Some(false),
+                server: true
+            }
+        );
+        assert_eq!(
+            parse("!horse preset use chaos horse").unwrap().unwrap(),
+            BotCommand::PresetUse {
+                name: "chaos horse".to_owned()
+            }
+        );
+        assert!(parse("!horse dnd 22:00").unwrap().is_err());
+        assert!(parse("!horse").unwrap().is_err());
+    }
+}
@@ -0,0 +1,318 @@
+//! Right-click commands on messages and users, which answer with the model rather
+//! than a canned [`BotCommand`](super::BotCommand) reply, and the one for reporting
+//! the bot's messages.
+
+use super::report::REPORT_REPLY;
+use crate::{
+    breaker, chatbot, mentions, text, tools::economy::describe_wallet, DiscordBot,
+    MAX_MESSAGE_LENGTH,
+};
+use eyre::{eyre, Result};
+use serenity::{
+    builder::CreateApplicationCommands,
+    model::{
+        application::{
+            command::CommandType,
+            interaction::{
+                application_command::{ApplicationCommandInteraction, ResolvedTarget},
+                InteractionResponseType,
+            },
+        },
+        channel::Message,
+    },
+    prelude as discord,
+};
+use std::time::Instant;
+
+pub const ASK_ABOUT_MESSAGE: &str = "Ask the horse about this";
+pub const OPINION_OF_USER: &str = "Horse's opinion of this user";
+
+/// The answer to being asked about a message the bot lets pass, like a lone "lol".
+const NOTHING_TO_SAY: &str = "*The horse flicks an ear, but has nothing to say about that.*";
+
+pub fn create_context_menus(
+    commands: &mut CreateApplicationCommands,
+) -> &mut CreateApplicationCommands {
+    commands.create_application_command(|command| {
+        command
+            .name(ASK_ABOUT_MESSAGE)
+            .kind(CommandType::Message)
+            .dm_permission(false)
+    });
+    commands.create_application_command(|command| {
+        command
+            .name(OPINION_OF_USER)
+            .kind(CommandType::User)
+            .dm_permission(false)
+    });
+    commands.create_application_command(|command| {
+        command
+            .name(REPORT_REPLY)
+            .kind(CommandType::Message)
+            .dm_permission(false)
+    })
+}
+
+impl DiscordBot {
+    /// Handle a context menu command. Returns false if the interaction isn't one.
+    pub(super) async fn context_menu_hook(
+        &self,
+        context: &discord::Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<bool> {
+        match (interaction.data.kind, interaction.data.name.as_str()) {
+            (CommandType::Message, ASK_ABOUT_MESSAGE) => {
+                // the reply goes to the message itself, so this only says what went wrong
+                defer(context, interaction, true).await?;
+                match self.ask_about_message(context, interaction).await {
+                    Ok(true) => {
+                        interaction
+                            .delete_original_interaction_response(&context.http)
+                            .await?
+                    }
+                    // asked outright, so saying nothing would look like it broke
+                    Ok(false) => respond(context, interaction, NOTHING_TO_SAY.to_owned()).await?,
+                    Err(e) => respond(context, interaction, failure(e)).await?,
+                }
+            }
+            (CommandType::User, OPINION_OF_USER) => {
+                defer(context, interaction, false).await?;
+                let opinion = self
+                    .opinion_of_user(context, interaction)
+                    .await
+                    .unwrap_or_else(failure);
+                respond(context, interaction, opinion).await?;
+            }
+            // the form asking for a reason has to be the first response, so no deferring
+            (CommandType::Message, REPORT_REPLY) => {
+                if let Err(e) = self.report_form(context, interaction).await {
+                    log::error!("Command failed: {}", e);
+                }
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Reply to the right-clicked message as if the user had quoted it to the bot.
+    /// Returns false if the bot let it pass without a reply.
+    async fn ask_about_message(
+        &self,
+        context: &discord::Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<bool> {
+        let Some(ResolvedTarget::Message(target)) = interaction.data.target() else {
+            return Err(eyre!("no message to ask about"));
+        };
+        self.check_can_answer(interaction).await?;
+
+        // resolved messages come without a guild id, but the question is still asked in
+        // the server, with its key, settings and nicknames
+        let question = Message {
+            author: interaction.user.clone(),
+            member: None,
+            content: quote(target.author.id.0, &target.content),
+            guild_id: interaction.guild_id,
+            ..target.clone()
+        };
+        let reply = chatbot::reply(self, context, &question).await;
+        self.record_reply(question.guild_id.map(|g| g.0), &reply);
+        let conversation = self
+            .channel_conversation(context, target.channel_id)
+            .await?;
+        let Some(reply) = reply? else {
+            return Ok(false);
+        };
+        let ping = self.pings(conversation, question.guild_id).await?;
+        let reply = self
+            .encode_user_mentions(question.guild_id, reply, ping)
+            .await?;
+        for chunk in text::split_message(&reply, MAX_MESSAGE_LENGTH) {
+            target
+                .channel_id
+                .send_message(context, |m| {
+                    m.content(chunk)
+                        .reference_message(&target)
+                        .allowed_mentions(|a| mentions::allowed(ping, a))
+                })
+                .await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Have the bot say, in character, what it makes of the right-clicked user.
+    async fn opinion_of_user(
+        &self,
+        context: &discord::Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<String> {
+        let Some(ResolvedTarget::User(user, _)) = interaction.data.target() else {
+            return Err(eyre!("no user to give an opinion of"));
+        };
+        if !self.database.opinions_allowed(user.id.0).await? {
+            return Ok(format!(
+                "{} would rather I kept my opinions to myself.",
+                user.name
+            ));
+        }
+        self.check_can_answer(interaction).await?;
+
+        let conversation = self
+            .channel_conversation(context, interaction.channel_id)
+            .await?;
+        let user_id = user.id.to_string();
+        let sheet = self.database.get_sheet(conversation, &user_id).await?;
+        let wallet = match self.database.find_wallet(conversation, &user_id).await? {
+            Some(user_id) => Some(self.database.wallet(conversation, user_id).await?),
+            None => None,
+        };
+        let mut facts = sheet
+            .into_iter()
+            .map(|(key, value)| format!("- {key}: {value}"))
+            .collect::<Vec<_>>();
+        facts.extend(wallet.map(|w| format!("- {}", describe_wallet(&w))));
+
+        let guild = interaction
+            .guild_id
+            .and_then(|g| g.to_guild_cached(context));
+        let vars = self
+            .channel_prompt_vars(
+                context,
+                guild.as_ref(),
+                interaction.guild_id,
+                interaction.channel_id,
+                interaction.user.id,
+            )
+            .await?;
+        let nsfw_prompt = if self
+            .channel_is_nsfw(context, interaction.channel_id)
+            .await?
+        {
+            self.database.get_nsfw_settings(conversation).await?.prompt
+        } else {
+            None
+        };
+        let opinion = chatbot::comment(
+            &self.current_openai(),
+            interaction.guild_id.map(|g| g.0),
+            &self.database,
+            conversation,
+            nsfw_prompt,
+            vars,
+            opinion_instruction(&user.name, user.id.0, &facts),
+        )
+        .await;
+        self.record_reply(interaction.guild_id.map(|g| g.0), &opinion);
+        let ping = self.pings(conversation, interaction.guild_id).await?;
+        self.encode_user_mentions(interaction.guild_id, opinion?, ping)
+            .await
+    }
+
+    /// The same checks a mention goes through before the bot answers.
+    async fn check_can_answer(&self, interaction: &ApplicationCommandInteraction) -> Result<()> {
+        let guild_id = interaction.guild_id.map(|g| g.0);
+        if self
+            .database
+            .is_blocked(guild_id, interaction.user.id.0)
+            .await?
+        {
+            return Err(eyre!("you can't talk to me here"));
+        }
+        if !self.current_openai().serves(guild_id) {
+            return Err(eyre!("this server hasn't registered an OpenAI key"));
+        }
+        if self.breaker.is_open(Instant::now()) {
+            return Err(eyre!(breaker::ASLEEP));
+        }
+
+        Ok(())
+    }
+}
+
+/// Acknowledge the interaction now, since the model takes longer than discord waits.
+async fn defer(
+    context: &discord::Context,
+    interaction: &ApplicationCommandInteraction,
+    ephemeral: bool,
+) -> Result<()> {
+    interaction
+        .create_interaction_response(&context.http, |response| {
+            response
+                .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                .interaction_response_data(|message| message.ephemeral(ephemeral))
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Fill in a deferred response, following up with the rest of a long one.
+async fn respond(
+    context: &discord::Context,
+    interaction: &ApplicationCommandInteraction,
+    content: String,
+) -> Result<()> {
+    let mut chunks = text::split_message(&content, MAX_MESSAGE_LENGTH).into_iter();
+    let first = chunks.next().unwrap_or_default();
+    interaction
+        .edit_original_interaction_response(&context.http, |message| message.content(first))
+        .await?;
+    for chunk in chunks {
+        interaction
+            .create_followup_message(&context.http, |message| message.content(chunk))
+            .await?;
+    }
+
+    Ok(())
+}
+
+fn failure(e: eyre::Report) -> String {
+    log::error!("Command failed: {}", e);
+    format!("Something went wrong: {}", e)
+}
+
+fn opinion_instruction(name: &str, user_id: u64, facts: &[String]) -> String {
+    let known = if facts.is_empty() {
+        "You know nothing about them beyond this conversation.".to_owned()
+    } else {
+        format!("What you know about them:\n{}", facts.join("\n"))
+    };
+    format!(
+        "Someone asked for your honest opinion of {} (<@{}>). Say what you make of them in a \
+        short paragraph, in character, drawing on what they've said here.\n{known}",
+        name, user_id
+    )
+}
+
+/// The message, quoted to the bot by whoever right-clicked it.
+fn quote(author_id: u64, content: &str) -> String {
+    let quoted = content
+        .lines()
+        .map(|line| format!("> {line}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("<@{author_id}> said:\n{quoted}\nWhat do you make of that?")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(
+            quote(42, "hay is\noverrated"),
+            "<@42> said:\n> hay is\n> overrated\nWhat do you make of that?"
+        );
+    }
+
+    #[test]
+    fn test_opinion_instruction() {
+        let instruction = opinion_instruction("dylan", 42, &["- class: Wizard".to_owned()]);
+        assert!(instruction.contains("opinion of dylan"));
+        assert!(instruction.ends_with("What you know about them:\n- class: Wizard"));
+        assert!(opinion_instruction("dylan", 42, &[]).ends_with("beyond this conversation."));
+    }
+}
@@ -0,0 +1,192 @@
+//! Reporting something the bot said, so a community has somewhere to take it when the
+//! horse says something it shouldn't. Reports are kept in the database and, when the
+//! report_channel setting names one, passed on to the staff there.
+
+use crate::{
+    mentions,
+    schema::{SettingsResolver, REPORT_CHANNEL},
+    DiscordBot,
+};
+use eyre::Result;
+use serenity::{
+    model::{
+        application::{
+            component::{ActionRowComponent, InputTextStyle},
+            interaction::{
+                application_command::{ApplicationCommandInteraction, ResolvedTarget},
+                modal::ModalSubmitInteraction,
+                InteractionResponseType,
+            },
+        },
+        id::{ChannelId, MessageId},
+    },
+    prelude as discord,
+};
+
+pub const REPORT_REPLY: &str = "Report this reply";
+
+/// The reason field of the report form, and the form's id, which ends with the id of
+/// the message being reported.
+const REASON: &str = "reason";
+const REPORT_FORM: &str = "report:";
+const MAX_REASON_CHARS: u64 = 500;
+
+impl DiscordBot {
+    /// Ask why the right-clicked message is being reported. Only the bot's own
+    /// messages can be.
+    pub(super) async fn report_form(
+        &self,
+        context: &discord::Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        let target = match interaction.data.target() {
+            Some(ResolvedTarget::Message(target))
+                if target.author.id == context.cache.current_user_id() =>
+            {
+                target
+            }
+            _ => {
+                interaction
+                    .create_interaction_response(&context.http, |response| {
+                        response
+                            .kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|message| {
+                                message
+                                    .content("Only my own messages can be reported.")
+                                    .ephemeral(true)
+                            })
+                    })
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        interaction
+            .create_interaction_response(&context.http, |response| {
+                response
+                    .kind(InteractionResponseType::Modal)
+                    .interaction_response_data(|form| {
+                        form.custom_id(format!("{REPORT_FORM}{}", target.id))
+                            .title(REPORT_REPLY)
+                            .components(|components| {
+                                components.create_action_row(|row| {
+                                    row.create_input_text(|input| {
+                                        input
+                                            .custom_id(REASON)
+                                            .label("What's wrong with it?")
+                                            .style(InputTextStyle::Paragraph)
+                                            .max_length(MAX_REASON_CHARS)
+                                            .required(true)
+                                    })
+                                })
+                            })
+                    })
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Record a report once its form comes back, and tell the staff if there's a
+    /// channel for it. The form is always answered, or Discord tells whoever sent it
+    /// that something went wrong without saying what.
+    pub(super) async fn report_submitted(
+        &self,
+        context: &discord::Context,
+        form: &ModalSubmitInteraction,
+    ) -> Result<()> {
+        let Some(message_id) = form.data.custom_id.strip_prefix(REPORT_FORM) else {
+            return Ok(());
+        };
+        let reply = match self.record_report(context, form, message_id).await {
+            Ok(()) => "Thanks, the report has been recorded.".to_owned(),
+            Err(e) => {
+                log::error!("Failed to record a report: {}", e);
+                format!("Something went wrong, the report wasn't recorded: {}", e)
+            }
+        };
+        form.create_interaction_response(&context.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| message.content(reply).ephemeral(true))
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    async fn record_report(
+        &self,
+        context: &discord::Context,
+        form: &ModalSubmitInteraction,
+        message_id: &str,
+    ) -> Result<()> {
+        let message_id = MessageId(message_id.parse()?);
+        let reason = form
+            .data
+            .components
+            .iter()
+            .flat_map(|row| &row.components)
+            .find_map(|component| match component {
+                ActionRowComponent::InputText(input) if input.custom_id == REASON => {
+                    Some(input.value.clone())
+                }
+                _ => None,
+            })
+            .unwrap_or_default();
+        let message = form.channel_id.message(context, message_id).await?;
+        let conversation = self.channel_conversation(context, form.channel_id).await?;
+        self.database
+            .add_report(
+                conversation,
+                form.channel_id.0,
+                message_id.0,
+                form.user.id.0,
+                reason.clone(),
+                message.content.clone(),
+            )
+            .await?;
+        log::info!("{} reported message {}", form.user.id, message_id);
+
+        let staff = SettingsResolver::new(&self.database, conversation, form.guild_id.map(|g| g.0))
+            .get(&REPORT_CHANNEL)
+            .await?;
+        if let Ok(staff) = staff.parse() {
+            let notice = report_notice(&form.user.name, &reason, &message.content, &message.link());
+            let sent = ChannelId(staff)
+                .send_message(context, |m| {
+                    m.content(notice)
+                        .allowed_mentions(|a| mentions::allowed(false, a))
+                })
+                .await;
+            if let Err(e) = sent {
+                log::error!("Failed to pass a report on to {}: {}", staff, e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What the staff channel is told about a report.
+fn report_notice(reporter: &str, reason: &str, content: &str, link: &str) -> String {
+    let quoted = content
+        .lines()
+        .map(|line| format!("> {line}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("{reporter} reported {link}\n{quoted}\nReason: {reason}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_notice() {
+        assert_eq!(
+            report_notice("dylan", "rude", "neigh\nhmph", "https://discord.com/x"),
+            "dylan reported https://discord.com/x\n> neigh\n> hmph\nReason: rude"
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! `horse-npc conversations`: list conversations, or show one, without opening the
+//! database by hand.
+
+use crate::{
+    schema::{ConversationInfo, Database, SettingsResolver, MODEL},
+    text, Args,
+};
+use eyre::{eyre, Result};
+use serde::Serialize;
+
+#[derive(Debug, clap::Subcommand)]
+pub enum ConversationsCommand {
+    /// List every conversation with its size, last activity, model and prompt
+    List {
+        #[clap(long)]
+        json: bool,
+    },
+    /// Show one conversation, with its whole prompt
+    Show {
+        conversation: String,
+        #[clap(long)]
+        json: bool,
+    },
+}
+
+/// How much of each prompt the table shows.
+const PROMPT_PREVIEW_CHARS: usize = 40;
+
+#[derive(Serialize)]
+struct Row {
+    #[serde(flatten)]
+    info: ConversationInfo,
+    model: String,
+}
+
+pub async fn list(args: &Args, json: bool) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let rows = rows(&database, None).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+    } else {
+        print!("{}", table(&rows));
+    }
+
+    Ok(())
+}
+
+pub async fn show(args: &Args, conversation: &str, json: bool) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let row = rows(&database, Some(conversation.to_owned()))
+        .await?
+        .pop()
+        .ok_or_else(|| eyre!("no conversation named {conversation}"))?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&row)?);
+        return Ok(());
+    }
+
+    println!("id:          {}", row.info.id);
+    println!("name:        {}", row.info.name);
+    println!("messages:    {}", row.info.messages);
+    println!(
+        "last active: {}",
+        row.info.last_active.as_deref().unwrap_or("never")
+    );
+    println!("model:       {}", row.model);
+    println!(
+        "description: {}",
+        row.info.description.as_deref().unwrap_or("none yet")
+    );
+    match &row.info.prompt {
+        Some(prompt) => println!("prompt:\n{prompt}"),
+        None => println!("prompt:      default"),
+    }
+
+    Ok(())
+}
+
+async fn rows(database: &Database, name: Option<String>) -> Result<Vec<Row>> {
+    let mut rows = vec![];
+    for info in database.conversation_infos(name).await? {
+        let model = SettingsResolver::new(database, info.conversation, info.guild_id)
+            .get(&MODEL)
+            .await?;
+        rows.push(Row { info, model });
+    }
+
+    Ok(rows)
+}
+
+fn table(rows: &[Row]) -> String {
+    let lines = rows
+        .iter()
+        .map(|row| {
+            let prompt = row
+                .info
+                .prompt
+                .as_deref()
+                .map_or("default".to_owned(), |p| {
+                    let p = p.split_whitespace().collect::<Vec<_>>().join(" ");
+                    match text::truncate(&p, PROMPT_PREVIEW_CHARS) {
+                        t if t.len() < p.len() => format!("{t}..."),
+                        t => t.to_owned(),
+                    }
+                });
+            [
+                row.info.id.to_string(),
+                row.info.name.clone(),
+                row.info.messages.to_string(),
+                row.info
+                    .last_active
+                    .clone()
+                    .unwrap_or_else(|| "never".to_owned()),
+                row.model.clone(),
+                prompt,
+                row.info.description.clone().unwrap_or_default(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = [
+        "ID",
+        "NAME",
+        "MESSAGES",
+        "LAST ACTIVE",
+        "MODEL",
+        "PROMPT",
+        "DESCRIPTION",
+    ]
+    .map(str::to_owned);
+    let mut widths = header.clone().map(|h| h.chars().count());
+    for line in &lines {
+        for (width, cell) in widths.iter_mut().zip(line) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    std::iter::once(&header)
+        .chain(&lines)
+        .map(|cells| {
+            let line = cells
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{cell:<width$}"))
+                .collect::<Vec<_>>()
+                .join("  ");
+            format!("{}\n", line.trim_end())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_table() {
+        let database = Database::new(None).await.unwrap();
+        let general = database.find_conversation("#general").await.unwrap();
+        database
+            .set_prompt(
+                general,
+                "You are a horse.\nYou live in a stable and you love apples very much.",
+            )
+            .await
+            .unwrap();
+        database
+            .set_description(general, "Horse talk.".to_owned())
+            .await
+            .unwrap();
+        let rows = rows(&database, None).await.unwrap();
+        assert_eq!(
+            table(&rows),
+            "ID  NAME      MESSAGES  LAST ACTIVE  MODEL          PROMPT                                       DESCRIPTION\n\
+             1   #general  0         never        gpt-3.5-turbo  You are a horse. You live in a stable an...  Horse talk.\n"
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! The `--data-dir` layout, giving a bare binary one place for everything it keeps:
+//!
+//! ```text
+//! <data-dir>/horse.db
+//! <data-dir>/backups/
+//! <data-dir>/prompts/
+//! <data-dir>/cache/
+//! ```
+
+use eyre::{eyre, Context, Result};
+use std::path::{Path, PathBuf};
+
+const DATABASE: &str = "horse.db";
+const BACKUPS: &str = "backups";
+const PROMPTS: &str = "prompts";
+const CACHE: &str = "cache";
+const SUBDIRS: &[&str] = &[BACKUPS, PROMPTS, CACHE];
+
+pub struct DataDir(PathBuf);
+
+impl DataDir {
+    /// Create whatever is missing from the layout and make sure it can be written to.
+    /// A new data directory is only readable by its owner, since it holds DMs.
+    pub fn open(root: &Path) -> Result<Self> {
+        let created = !root.exists();
+        for dir in std::iter::once(root.to_owned()).chain(SUBDIRS.iter().map(|d| root.join(d))) {
+            std::fs::create_dir_all(&dir)
+                .wrap_err_with(|| format!("creating {}", dir.display()))?;
+            check_writable(&dir)?;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if created {
+                std::fs::set_permissions(root, std::fs::Permissions::from_mode(0o700))?;
+            } else if std::fs::metadata(root)?.permissions().mode() & 0o077 != 0 {
+                log::warn!(
+                    "{} can be read by other users, but holds private conversations",
+                    root.display()
+                );
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = created;
+
+        Ok(Self(root.to_owned()))
+    }
+
+    pub fn database(&self) -> PathBuf {
+        Self::database_in(&self.0)
+    }
+
+    /// Where the database is in a data directory that may not exist yet.
+    pub fn database_in(root: &Path) -> PathBuf {
+        root.join(DATABASE)
+    }
+
+    /// Copies of the database.
+    #[allow(dead_code)]
+    pub fn backups(&self) -> PathBuf {
+        self.0.join(BACKUPS)
+    }
+
+    /// Prompt and template files kept alongside the database.
+    #[allow(dead_code)]
+    pub fn prompts(&self) -> PathBuf {
+        self.0.join(PROMPTS)
+    }
+
+    /// Anything that can be fetched or worked out again, safe to delete.
+    #[allow(dead_code)]
+    pub fn cache(&self) -> PathBuf {
+        self.0.join(CACHE)
+    }
+}
+
+fn check_writable(dir: &Path) -> Result<()> {
+    let probe = dir.join(".horse-npc-write-test");
+    std::fs::write(&probe, b"")
+        .and_then(|_| std::fs::remove_file(&probe))
+        .map_err(|e| eyre!("{} isn't writable: {e}", dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        let root = std::env::temp_dir().join(format!("horse-npc-data-{}", std::process::id()));
+        assert_eq!(DataDir::database_in(&root), root.join("horse.db"));
+        assert!(!root.exists());
+        let data_dir = DataDir::open(&root).unwrap();
+        assert_eq!(data_dir.database(), root.join("horse.db"));
+        for dir in [data_dir.backups(), data_dir.prompts(), data_dir.cache()] {
+            assert!(dir.is_dir());
+        }
+        // opening it again is fine
+        DataDir::open(&root).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
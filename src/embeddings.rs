@@ -0,0 +1,98 @@
+//! Embeddings: text turned into vectors by the model, so messages can be compared by
+//! meaning rather than wording. They're stored as little-endian f32 blobs, along with
+//! the model that made them, since vectors from different models can't be compared.
+
+use crate::keys::KeyPool;
+use async_openai::types::CreateEmbeddingRequestArgs;
+use eyre::{eyre, Result};
+
+/// Embed each of `texts`, in order.
+pub async fn embed(
+    openai: &KeyPool,
+    guild: Option<u64>,
+    model: &str,
+    texts: Vec<String>,
+) -> Result<Vec<Vec<f32>>> {
+    let count = texts.len();
+    let request = CreateEmbeddingRequestArgs::default()
+        .model(model)
+        .input(texts)
+        .build()?;
+    let mut response = openai.client(guild)?.embeddings().create(request).await?;
+    if response.data.len() != count {
+        return Err(eyre!(
+            "asked {model} for {count} embeddings, got {}",
+            response.data.len()
+        ));
+    }
+    response.data.sort_by_key(|e| e.index);
+    Ok(response.data.into_iter().map(|e| e.embedding).collect())
+}
+
+/// Embed a single text.
+pub async fn embed_one(
+    openai: &KeyPool,
+    guild: Option<u64>,
+    model: &str,
+    text: &str,
+) -> Result<Vec<f32>> {
+    let mut embeddings = embed(openai, guild, model, vec![text.to_owned()]).await?;
+    embeddings
+        .pop()
+        .ok_or_else(|| eyre!("{model} returned no embedding"))
+}
+
+/// How alike two embeddings are, from -1 to 1.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+/// `embedding` scaled to unit length and then to -127..=127, so the dot product of two
+/// divided by 127² is roughly their cosine similarity.
+pub fn quantize(embedding: &[f32]) -> Vec<i8> {
+    let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return vec![0; embedding.len()];
+    }
+    embedding
+        .iter()
+        .map(|x| (x / norm * 127.0).round() as i8)
+        .collect()
+}
+
+pub fn to_blob(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+pub fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embeddings() {
+        let a = [1.0, 0.0, 2.0];
+        assert_eq!(from_blob(&to_blob(&a)), a);
+        assert!((cosine_similarity(&a, &[2.0, 0.0, 4.0]) - 1.0).abs() < 1e-6);
+        assert!(cosine_similarity(&a, &[0.0, 1.0, 0.0]).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&a, &[1.0]), 0.0);
+        assert_eq!(cosine_similarity(&a, &[0.0; 3]), 0.0);
+        assert_eq!(quantize(&a), [57, 0, 114]);
+        assert_eq!(quantize(&[0.0, 0.0]), [0, 0]);
+    }
+}
@@ -0,0 +1,118 @@
+//! Answering the questions a server has written answers for, like how to get a role,
+//! the same way every time. Messages are compared to the FAQ by embedding, so they
+//! needn't be worded the same, and a close enough match is answered from it.
+
+use crate::{
+    embeddings,
+    keys::KeyPool,
+    providers,
+    schema::{Database, Faq, Message, Role, SettingsResolver, EMBEDDING_MODEL, FAQ_THRESHOLD},
+};
+use async_openai::types::CreateChatCompletionRequestArgs;
+use eyre::{ContextCompat, Result};
+
+/// The FAQ closest to `embedding` from the same model, if any is at least `threshold`
+/// alike.
+pub fn closest<'a>(
+    faqs: &'a [Faq],
+    model: &str,
+    embedding: &[f32],
+    threshold: f32,
+) -> Option<&'a Faq> {
+    faqs.iter()
+        .filter(|faq| faq.embedding_model == model)
+        .map(|faq| {
+            (
+                faq,
+                embeddings::cosine_similarity(&faq.embedding, embedding),
+            )
+        })
+        .filter(|(_, similarity)| *similarity >= threshold)
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(faq, _)| faq)
+}
+
+/// The server's FAQ a message asks, if it has any.
+pub async fn find(
+    openai: &KeyPool,
+    db: &Database,
+    settings: &SettingsResolver<'_>,
+    guild: Option<u64>,
+    content: &str,
+) -> Result<Option<Faq>> {
+    let faqs = db.faqs(guild).await?;
+    if faqs.is_empty() {
+        return Ok(None);
+    }
+    let model = settings.get(&EMBEDDING_MODEL).await?;
+    let threshold = settings.get(&FAQ_THRESHOLD).await?;
+    let embedding = embeddings::embed_one(openai, guild, &model, content).await?;
+    Ok(closest(&faqs, &model, &embedding, threshold).cloned())
+}
+
+/// Have `model` put the FAQ's answer in the persona's words, without changing what it
+/// says.
+pub async fn rephrase(
+    openai: &KeyPool,
+    guild: Option<u64>,
+    model: &str,
+    persona: String,
+    content: &str,
+    faq: &Faq,
+) -> Result<String> {
+    let instruction = format!(
+        "Answer the message below in character, using this answer to \"{}\", keeping \
+        every fact, name and link exactly as written:\n\n{}",
+        faq.question, faq.answer
+    );
+    let messages = [
+        Message::new(Role::System, persona),
+        Message::new(Role::System, instruction),
+        Message::new(Role::User, content),
+    ];
+    let request = CreateChatCompletionRequestArgs::default()
+        .model(model)
+        .temperature(0.3)
+        .messages(providers::openai::request_messages(&messages))
+        .build()?;
+
+    let response = openai.chat(guild, request).await?;
+    let choice = response
+        .choices
+        .into_iter()
+        .next()
+        .wrap_err("No response")?;
+    Ok(providers::openai::response_message(choice.message)?.content())
+}
+
+/// An answer with a note of the FAQ entry it came from.
+pub fn cite(answer: &str, faq: &Faq) -> String {
+    format!("{answer}\n\n_From the FAQ ({}): {}_", faq.id, faq.question)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closest() {
+        let faq = |id, embedding: Vec<f32>, model: &str| Faq {
+            id,
+            question: format!("question {id}"),
+            answer: format!("answer {id}"),
+            embedding,
+            embedding_model: model.to_owned(),
+        };
+        let faqs = vec![
+            faq(1, vec![1.0, 0.0], "ada"),
+            faq(2, vec![0.8, 0.6], "ada"),
+            faq(3, vec![0.0, 1.0], "other"),
+        ];
+        let id = |faq: Option<&Faq>| faq.map(|f| f.id);
+        assert_eq!(id(closest(&faqs, "ada", &[0.9, 0.1], 0.9)), Some(1));
+        assert_eq!(id(closest(&faqs, "ada", &[0.7, 0.7], 0.9)), Some(2));
+        assert_eq!(id(closest(&faqs, "ada", &[0.0, 1.0], 0.9)), None);
+        assert_eq!(id(closest(&faqs, "other", &[0.0, 1.0], 0.9)), Some(3));
+        assert!(cite("Yes.", &faqs[0]).ends_with("_From the FAQ (1): question 1_"));
+    }
+}
@@ -0,0 +1,69 @@
+//! Puts a function call back together from the pieces a streamed reply sends it in,
+//! so a tool is only called once its arguments have all arrived and make sense.
+
+use eyre::{eyre, Result};
+use serde_json::Value;
+
+#[derive(Debug, Default)]
+pub struct FunctionCallAssembler {
+    name: String,
+    arguments: String,
+}
+
+impl FunctionCallAssembler {
+    /// Add one chunk's piece of the call. The name usually comes whole in the first
+    /// chunk and the arguments a few characters at a time after it.
+    pub fn push(&mut self, name: Option<&str>, arguments: Option<&str>) {
+        self.name.push_str(name.unwrap_or_default());
+        self.arguments.push_str(arguments.unwrap_or_default());
+    }
+
+    /// The finished call, once the reply is over: its name, and its arguments, which
+    /// must be a JSON object. No arguments at all count as an empty one.
+    pub fn finish(self) -> Result<(String, Value)> {
+        let name = self.name.trim().to_owned();
+        if name.is_empty() {
+            return Err(eyre!("the function call has no name"));
+        }
+        let arguments = match self.arguments.trim() {
+            "" => Value::Object(Default::default()),
+            arguments => serde_json::from_str(arguments)
+                .map_err(|e| eyre!("the arguments to {name} aren't valid JSON: {e}"))?,
+        };
+        if !arguments.is_object() {
+            return Err(eyre!("the arguments to {name} aren't a JSON object"));
+        }
+        Ok((name, arguments))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_assemble() {
+        let mut call = FunctionCallAssembler::default();
+        call.push(Some("roll"), None);
+        for piece in ["{\"di", "ce\": ", "\"2d6\"", "}"] {
+            call.push(None, Some(piece));
+        }
+        let (name, arguments) = call.finish().unwrap();
+        assert_eq!(name, "roll");
+        assert_eq!(arguments, json!({"dice": "2d6"}));
+
+        let mut call = FunctionCallAssembler::default();
+        call.push(Some("shrug"), None);
+        assert_eq!(call.finish().unwrap().1, json!({}));
+
+        // cut off before the arguments were finished
+        let mut call = FunctionCallAssembler::default();
+        call.push(Some("roll"), Some("{\"dice\": \"2d"));
+        assert!(call.finish().is_err());
+
+        let mut call = FunctionCallAssembler::default();
+        call.push(Some("roll"), Some("[1, 2]"));
+        assert!(call.finish().is_err());
+    }
+}
@@ -1,16 +1,82 @@
-use async_openai::{types::CreateModerationRequestArgs, Client, config::OpenAIConfig};
+use crate::schema::ModerationPolicy;
+use async_openai::{
+    config::OpenAIConfig,
+    types::{ContentModerationResult, CreateModerationRequestArgs},
+    Client,
+};
 use async_trait::async_trait;
 use eyre::{eyre, Result};
-use serenity::model::prelude::{Guild, Message};
+use once_cell::sync::Lazy;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Category score above which [`ModerationPolicy::Strict`] deflects.
+const STRICT_THRESHOLD: f32 = 0.2;
+
+/// How long a moderation verdict is reused for identical messages.
+const MODERATION_TTL: Duration = Duration::from_secs(60 * 60);
+const MAX_MODERATION_VERDICTS: usize = 10_000;
+
+static MODERATION_CACHE: Lazy<Mutex<ModerationCache>> =
+    Lazy::new(|| Mutex::new(ModerationCache::default()));
+
+/// Recent moderation verdicts, keyed by a hash of the message and the policy, so
+/// chatter like "lol" isn't sent to the moderation endpoint again and again.
+#[derive(Default)]
+struct ModerationCache {
+    verdicts: HashMap<u64, (bool, Instant)>,
+}
+
+impl ModerationCache {
+    fn key(message: &str, policy: ModerationPolicy) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        message.trim().hash(&mut hasher);
+        policy.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn get(&self, key: u64, now: Instant) -> Option<bool> {
+        self.verdicts
+            .get(&key)
+            .filter(|(_, checked)| now.duration_since(*checked) < MODERATION_TTL)
+            .map(|(flagged, _)| *flagged)
+    }
+
+    fn insert(&mut self, key: u64, flagged: bool, now: Instant) {
+        if self.verdicts.len() >= MAX_MODERATION_VERDICTS {
+            self.verdicts
+                .retain(|_, (_, checked)| now.duration_since(*checked) < MODERATION_TTL);
+        }
+        if self.verdicts.len() >= MAX_MODERATION_VERDICTS {
+            self.verdicts.clear();
+        }
+        self.verdicts.insert(key, (flagged, now));
+    }
+}
 
 #[async_trait]
 pub trait OpenAIHelpers {
-    async fn must_moderate(&self, message: String) -> Result<bool>;
+    async fn must_moderate(&self, message: String, policy: ModerationPolicy) -> Result<bool>;
 }
 
 #[async_trait]
 impl OpenAIHelpers for Client<OpenAIConfig> {
-    async fn must_moderate(&self, message: String) -> Result<bool> {
+    async fn must_moderate(&self, message: String, policy: ModerationPolicy) -> Result<bool> {
+        if policy == ModerationPolicy::Off {
+            return Ok(false);
+        }
+        let key = ModerationCache::key(&message, policy);
+        let cached = MODERATION_CACHE
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(key, Instant::now());
+        if let Some(flagged) = cached {
+            return Ok(flagged);
+        }
         let response = self
             .moderations()
             .create(
@@ -20,36 +86,113 @@ impl OpenAIHelpers for Client<OpenAIConfig> {
             )
             .await?;
         log::info!("Moderation response: {:?}", response);
-        Ok(response.results.iter().any(|r| r.flagged))
+        let flagged = response.results.iter().any(|r| is_flagged(r, policy));
+        MODERATION_CACHE
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key, flagged, Instant::now());
+        Ok(flagged)
     }
 }
 
+fn is_flagged(result: &ContentModerationResult, policy: ModerationPolicy) -> bool {
+    let categories = &result.categories;
+    let scores = &result.category_scores;
+    match policy {
+        ModerationPolicy::Off => false,
+        ModerationPolicy::Default => result.flagged,
+        ModerationPolicy::Relaxed => {
+            categories.sexual_minors
+                || categories.hate_threatening
+                || categories.self_harm
+                || categories.violence_graphic
+        }
+        ModerationPolicy::Strict => {
+            result.flagged
+                || [
+                    scores.hate,
+                    scores.hate_threatening,
+                    scores.self_harm,
+                    scores.sexual,
+                    scores.sexual_minors,
+                    scores.violence,
+                    scores.violence_graphic,
+                ]
+                .iter()
+                .any(|score| *score > STRICT_THRESHOLD)
+        }
+    }
+}
 
-#[async_trait]
-pub trait DiscordContextHelpers {
-        async fn get_guild(&self,  message: Option<&Message>) -> Result<Guild>;
+/// Parse a duration like "90s", "30m", "1h", "2d" or "1w".
+pub fn parse_duration(s: &str) -> Result<chrono::Duration> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| eyre!("duration {s:?} needs a unit (s, m, h, d or w)"))?;
+    let (amount, unit) = s.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| eyre!("invalid duration {s:?}"))?;
+    let duration = match unit.trim() {
+        "s" => chrono::Duration::try_seconds(amount),
+        "m" => chrono::Duration::try_minutes(amount),
+        "h" => chrono::Duration::try_hours(amount),
+        "d" => chrono::Duration::try_days(amount),
+        "w" => chrono::Duration::try_weeks(amount),
+        _ => return Err(eyre!("unknown duration unit in {s:?}")),
+    };
+    duration.ok_or_else(|| eyre!("duration {s:?} is too long"))
 }
 
+/// The time `duration` from now, or an error if that's further off than a date can be.
+pub fn from_now(duration: chrono::Duration) -> Result<chrono::DateTime<chrono::Local>> {
+    chrono::Local::now()
+        .checked_add_signed(duration)
+        .ok_or_else(|| eyre!("that's too far in the future"))
+}
 
-#[async_trait]
-impl DiscordContextHelpers for serenity::prelude::Context {
-    async fn get_guild(&self,  message: Option<&Message>) -> Result<Guild> {
-        if let Some(message) = message {
-            if let Some(guild) = message.guild(self) {
-                return Ok(guild);
-            }
-        }
-        let guilds = self.http.get_guilds(None, Some(1)).await?;
-        if guilds.is_empty() {
-            return Err(eyre!("No guilds found"));
-        }
-        let guild = guilds[0]
-            .id
-            .to_guild_cached(self)
-            .ok_or_else(|| eyre!("Guild not found"))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Ok(guild)
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("1h").unwrap(), chrono::Duration::hours(1));
+        assert_eq!(parse_duration("90d").unwrap(), chrono::Duration::days(90));
+        assert_eq!(
+            parse_duration(" 30 m").unwrap(),
+            chrono::Duration::minutes(30)
+        );
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("3y").is_err());
+        assert!(parse_duration("9999999999999999h").is_err());
     }
-}
 
+    #[test]
+    fn test_from_now() {
+        let hour = from_now(chrono::Duration::hours(1)).unwrap();
+        assert!(hour > chrono::Local::now());
+        // what /mute 999999999999h asks for
+        let huge = parse_duration("999999999999h").unwrap();
+        assert!(from_now(huge).is_err());
+    }
 
+    #[test]
+    fn test_moderation_cache() {
+        let mut cache = ModerationCache::default();
+        let now = Instant::now();
+        let key = ModerationCache::key("lol", ModerationPolicy::Default);
+        assert_eq!(
+            key,
+            ModerationCache::key(" lol\n", ModerationPolicy::Default)
+        );
+        assert_ne!(key, ModerationCache::key("lol", ModerationPolicy::Strict));
+
+        assert_eq!(cache.get(key, now), None);
+        cache.insert(key, true, now);
+        assert_eq!(cache.get(key, now), Some(true));
+        assert_eq!(cache.get(key, now + MODERATION_TTL), None);
+    }
+}
@@ -0,0 +1,152 @@
+//! Idle chatter: a remark now and then in a server conversation that has gone quiet,
+//! where its idle_chatter setting asks for one.
+
+use crate::{
+    schema::{IdleChannel, SettingsResolver, IDLE_CHATTER},
+    DiscordBot,
+};
+use chrono::{DateTime, Duration, Local, Utc};
+use eyre::Result;
+use serenity::{
+    model::id::{ChannelId, GuildId},
+    prelude as discord,
+};
+use std::{sync::Arc, time::Instant};
+
+/// How often to look for conversations that have gone quiet.
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+const INSTRUCTION: &str = "Nobody has said anything here for a while. Say something short, in \
+    character, to liven things up, without asking anyone in particular to answer.";
+
+/// The least time between two idle remarks in a conversation.
+fn min_gap() -> Duration {
+    Duration::hours(12)
+}
+
+/// Conversations quiet for longer than this are left alone, rather than woken up.
+fn max_quiet() -> Duration {
+    Duration::days(2)
+}
+
+/// Start looking for quiet conversations. Nothing is sent until the bot has connected.
+pub fn spawn(bot: Arc<DiscordBot>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let Some(context) = bot.gateway_context() else {
+                continue;
+            };
+            if let Err(e) = bot.chatter(&context).await {
+                log::error!("Failed to chatter in quiet channels: {}", e);
+            }
+        }
+    });
+}
+
+/// Whether the bot should speak up: someone else had the last word at least `after`
+/// ago (but not so long ago that the channel is dead), and it hasn't spoken up there
+/// for a while.
+fn is_due(
+    channel: &IdleChannel,
+    after: Duration,
+    last_chatter: Option<DateTime<Local>>,
+    now: DateTime<Local>,
+) -> bool {
+    let quiet = now.with_timezone(&Utc) - channel.last_activity;
+    channel.awaiting_bot
+        && after <= quiet
+        && quiet < max_quiet()
+        && last_chatter.map_or(true, |last| now - last >= min_gap())
+}
+
+impl DiscordBot {
+    async fn chatter(&self, context: &discord::Context) -> Result<()> {
+        let now = Local::now();
+        for channel in self.database.idle_channels().await? {
+            let settings =
+                SettingsResolver::new(&self.database, channel.conversation, Some(channel.guild_id));
+            let after = settings.get(&IDLE_CHATTER).await?;
+            if after == 0 {
+                continue;
+            }
+            let last_chatter = self
+                .database
+                .last_idle_chatter(channel.conversation)
+                .await?;
+            if !is_due(&channel, Duration::minutes(after.into()), last_chatter, now) {
+                continue;
+            }
+            let channel_id = ChannelId(channel.channel_id);
+            match self.ignores_channel(context, channel_id).await {
+                Ok(false) => {}
+                Ok(true) => continue,
+                Err(e) => {
+                    log::warn!("Failed to look up channel {}: {}", channel.channel_id, e);
+                    continue;
+                }
+            }
+            if self
+                .database
+                .get_quiet(channel.conversation)
+                .await?
+                .is_quiet(now)
+            {
+                continue;
+            }
+            if !self.current_openai().serves(Some(channel.guild_id))
+                || self.breaker.is_open(Instant::now())
+            {
+                continue;
+            }
+
+            // counted even if it fails, so a broken channel isn't retried every few minutes
+            self.database
+                .idle_chattered(channel.conversation, now)
+                .await?;
+            let remark = self
+                .remark(
+                    context,
+                    GuildId(channel.guild_id),
+                    channel_id,
+                    channel.conversation,
+                    INSTRUCTION.to_owned(),
+                )
+                .await;
+            if let Err(e) = remark {
+                log::warn!("Failed to chatter in channel {}: {}", channel.channel_id, e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::Database;
+
+    #[tokio::test]
+    async fn test_is_due() {
+        let db = Database::new(None).await.expect("failed to create db");
+        db.find_channel_conversation("7", Some((1, "Ranch")), "general")
+            .await
+            .unwrap();
+        db.channel_active(7, false).await.unwrap();
+        let channel = db.idle_channels().await.unwrap().remove(0);
+        let hour = Duration::hours(1);
+        let later = |d| channel.last_activity.with_timezone(&Local) + d;
+
+        assert!(!is_due(&channel, hour, None, later(Duration::minutes(30))));
+        assert!(is_due(&channel, hour, None, later(hour * 2)));
+        assert!(!is_due(&channel, hour, None, later(Duration::days(3))));
+        assert!(!is_due(&channel, hour, Some(later(hour)), later(hour * 2)));
+        let answered = IdleChannel {
+            awaiting_bot: false,
+            ..channel.clone()
+        };
+        assert!(!is_due(&answered, hour, None, later(hour * 2)));
+    }
+}
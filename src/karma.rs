@@ -0,0 +1,67 @@
+//! A rough sense of how each person treats the bot, so it can warm up to friendly
+//! people and stay wary of rude ones. Scored from cheap word matching on what they
+//! say and the reactions they leave on its messages, never a model call.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// What a message moderation flags counts for.
+pub const FLAGGED: i64 = -3;
+
+static FRIENDLY: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)\b(thanks|thank you|thx|ty|please|pls|love you|good (horse|boy|girl|pony)|well done|awesome|amazing|you'?re (great|the best)|nice one|have an apple)\b",
+    )
+    .expect("valid friendly regex")
+});
+
+static HOSTILE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)\b(stupid|idiot|dumb|useless|shut up|hate you|glue factory|worthless|garbage|trash|moron|go away)\b",
+    )
+    .expect("valid hostile regex")
+});
+
+/// How a message changes its author's standing: up for kindness, down (more) for
+/// rudeness, nothing for most.
+pub fn sentiment(content: &str) -> i64 {
+    let friendly = FRIENDLY.find_iter(content).count().min(2) as i64;
+    let hostile = HOSTILE.find_iter(content).count().min(2) as i64;
+    friendly - 2 * hostile
+}
+
+/// How a reaction on one of the bot's messages changes its author's standing.
+pub fn reaction_sentiment(emoji: &str) -> i64 {
+    match emoji {
+        "👍" | "❤️" | "😂" | "🥰" | "😍" | "🐴" | "🍎" | "🥕" | "⭐" | "🎉" => {
+            1
+        }
+        "👎" | "😡" | "🤬" | "💩" | "🖕" => -1,
+        _ => 0,
+    }
+}
+
+/// A word for a standing, for prompts that would rather not do arithmetic.
+pub fn standing(karma: i64) -> &'static str {
+    match karma {
+        i64::MIN..=-5 => "wary",
+        5..=i64::MAX => "friendly",
+        _ => "neutral",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sentiment() {
+        assert_eq!(sentiment("what's the weather like?"), 0);
+        assert_eq!(sentiment("Thanks, good horse!"), 2);
+        assert_eq!(sentiment("shut up, you stupid nag"), -4);
+        assert_eq!(sentiment("thx thx thx thx"), 2);
+        assert_eq!(reaction_sentiment("👎"), -1);
+        assert_eq!(standing(-7), "wary");
+        assert_eq!(standing(0), "neutral");
+    }
+}
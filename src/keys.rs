@@ -0,0 +1,295 @@
+//! Several OpenAI API keys (say, from different organizations) shared between servers,
+//! so one busy server doesn't use up a single organization's rate limit. Servers can
+//! also bring their own key, so their usage is billed to them.
+
+use async_openai::{
+    config::OpenAIConfig,
+    error::OpenAIError,
+    types::{CreateChatCompletionRequest, CreateChatCompletionResponse},
+    Client,
+};
+use eyre::{eyre, Result};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// How long to leave a key alone after it hits a rate limit.
+const RATE_LIMITED_FOR: Duration = Duration::from_secs(60);
+
+/// How long to leave a key alone after OpenAI says it is invalid or out of quota.
+const DISABLED_FOR: Duration = Duration::from_secs(60 * 60);
+
+/// Which key a request tries first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyAssignment {
+    /// Take turns.
+    #[default]
+    RoundRobin,
+    /// Each server always starts with the same key. DMs take turns.
+    Guild,
+}
+
+impl FromStr for KeyAssignment {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "round-robin" => Ok(KeyAssignment::RoundRobin),
+            "guild" => Ok(KeyAssignment::Guild),
+            _ => Err(eyre!(
+                "unknown key assignment {s}, use round-robin or guild"
+            )),
+        }
+    }
+}
+
+struct PooledKey {
+    client: Client<OpenAIConfig>,
+    benched_until: Mutex<Option<Instant>>,
+}
+
+pub struct KeyPool {
+    keys: Vec<PooledKey>,
+    assignment: KeyAssignment,
+    next: AtomicUsize,
+    timeout: Duration,
+    /// Keys servers registered for themselves, used instead of the pool.
+    guild_keys: Mutex<HashMap<u64, Client<OpenAIConfig>>>,
+    /// Whether servers without their own key go unanswered.
+    guild_keys_required: bool,
+}
+
+impl KeyPool {
+    /// A client for each key. Requests give up after `timeout` instead of hanging.
+    pub fn new(keys: Vec<String>, assignment: KeyAssignment, timeout: Duration) -> Result<Self> {
+        let clients = keys
+            .into_iter()
+            .map(|key| new_client(key, timeout))
+            .collect::<Result<Vec<_>>>()?;
+        Self::from_clients(clients, assignment, timeout)
+    }
+
+    /// A pool of clients that were already configured, say to talk to something other
+    /// than api.openai.com. `timeout` is for the keys servers register later.
+    pub fn from_clients(
+        clients: Vec<Client<OpenAIConfig>>,
+        assignment: KeyAssignment,
+        timeout: Duration,
+    ) -> Result<Self> {
+        if clients.is_empty() {
+            return Err(eyre!("no OpenAI API keys given"));
+        }
+        let keys = clients
+            .into_iter()
+            .map(|client| PooledKey {
+                client,
+                benched_until: Mutex::new(None),
+            })
+            .collect();
+
+        Ok(Self {
+            keys,
+            assignment,
+            next: AtomicUsize::new(0),
+            timeout,
+            guild_keys: Mutex::new(HashMap::new()),
+            guild_keys_required: false,
+        })
+    }
+
+    /// Only answer servers that registered their own key, keeping the pool for DMs.
+    pub fn require_guild_keys(mut self, required: bool) -> Self {
+        self.guild_keys_required = required;
+        self
+    }
+
+    /// Use (or with None, stop using) a server's own key for its requests.
+    pub fn set_guild_key(&self, guild: u64, key: Option<String>) -> Result<()> {
+        let mut guild_keys = self.guild_keys.lock().unwrap_or_else(|e| e.into_inner());
+        match key {
+            Some(key) => {
+                guild_keys.insert(guild, new_client(key, self.timeout)?);
+            }
+            None => {
+                guild_keys.remove(&guild);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether requests from `guild` have a key to use.
+    pub fn serves(&self, guild: Option<u64>) -> bool {
+        self.guild_client(guild).is_some() || !(self.guild_keys_required && guild.is_some())
+    }
+
+    /// Whether requests from `guild` use a key the server registered for itself.
+    pub fn has_own_key(&self, guild: Option<u64>) -> bool {
+        self.guild_client(guild).is_some()
+    }
+
+    fn guild_client(&self, guild: Option<u64>) -> Option<Client<OpenAIConfig>> {
+        let guild_keys = self.guild_keys.lock().unwrap_or_else(|e| e.into_inner());
+        guild_keys.get(&guild?).cloned()
+    }
+
+    /// The client a request from `guild` should use.
+    pub fn client(&self, guild: Option<u64>) -> Result<Client<OpenAIConfig>> {
+        if let Some(client) = self.guild_client(guild) {
+            return Ok(client);
+        }
+        if !self.serves(guild) {
+            return Err(eyre!("this server hasn't registered an OpenAI key"));
+        }
+        let first = self.order(guild, Instant::now())[0];
+        Ok(self.keys[first].client.clone())
+    }
+
+    /// Every key's client, for checking them all.
+    pub fn clients(&self) -> impl Iterator<Item = &Client<OpenAIConfig>> {
+        self.keys.iter().map(|k| &k.client)
+    }
+
+    /// Create a chat completion, moving on to the next key when one is rate limited
+    /// or disabled. A server's own key is used alone, without failing over.
+    pub async fn chat(
+        &self,
+        guild: Option<u64>,
+        request: CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse> {
+        if let Some(client) = self.guild_client(guild) {
+            return Ok(client.chat().create(request).await?);
+        }
+        if !self.serves(guild) {
+            return Err(eyre!("this server hasn't registered an OpenAI key"));
+        }
+
+        let mut last_error = None;
+        for index in self.order(guild, Instant::now()) {
+            let key = &self.keys[index];
+            match key.client.chat().create(request.clone()).await {
+                Ok(response) => return Ok(response),
+                Err(e) => match bench_for(&e) {
+                    Some(duration) => {
+                        log::warn!("OpenAI key {} failed, trying another: {}", index + 1, e);
+                        self.bench(index, Instant::now() + duration);
+                        last_error = Some(e);
+                    }
+                    None => return Err(e.into()),
+                },
+            }
+        }
+        Err(last_error.map_or_else(|| eyre!("no OpenAI API keys given"), Into::into))
+    }
+
+    fn bench(&self, index: usize, until: Instant) {
+        *self.keys[index]
+            .benched_until
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(until);
+    }
+
+    /// Key indexes to try, starting with the assigned one and skipping benched keys.
+    /// When every key is benched they are all tried anyway.
+    fn order(&self, guild: Option<u64>, now: Instant) -> Vec<usize> {
+        let count = self.keys.len();
+        let start = match (self.assignment, guild) {
+            (KeyAssignment::Guild, Some(guild)) => (guild % count as u64) as usize,
+            _ => self.next.fetch_add(1, Ordering::Relaxed) % count,
+        };
+        let all = (0..count).map(|i| (start + i) % count).collect::<Vec<_>>();
+        let available = all
+            .iter()
+            .copied()
+            .filter(|&i| {
+                let benched = self.keys[i]
+                    .benched_until
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner());
+                benched.map_or(true, |until| now >= until)
+            })
+            .collect::<Vec<_>>();
+        if available.is_empty() {
+            all
+        } else {
+            available
+        }
+    }
+}
+
+/// A client for one key, whose requests give up after `timeout`.
+pub fn new_client(key: String, timeout: Duration) -> Result<Client<OpenAIConfig>> {
+    let config = OpenAIConfig::new().with_api_key(key);
+    let http = reqwest::Client::builder().timeout(timeout).build()?;
+    Ok(Client::with_config(config).with_http_client(http))
+}
+
+/// How long to stop using a key after this error, if it's the key's fault.
+fn bench_for(error: &OpenAIError) -> Option<Duration> {
+    let OpenAIError::ApiError(e) = error else {
+        return None;
+    };
+    let code = e.code.as_ref().and_then(|c| c.as_str());
+    match (code, e.r#type.as_deref()) {
+        (Some("rate_limit_exceeded"), _) => Some(RATE_LIMITED_FOR),
+        (Some("invalid_api_key" | "insufficient_quota"), _) | (_, Some("insufficient_quota")) => {
+            Some(DISABLED_FOR)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(assignment: KeyAssignment) -> KeyPool {
+        let keys = vec!["a".to_owned(), "b".to_owned(), "c".to_owned()];
+        KeyPool::new(keys, assignment, Duration::from_secs(1)).unwrap()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let pool = pool(KeyAssignment::RoundRobin);
+        let now = Instant::now();
+        assert_eq!(pool.order(Some(7), now), vec![0, 1, 2]);
+        assert_eq!(pool.order(Some(7), now), vec![1, 2, 0]);
+
+        pool.bench(0, now + RATE_LIMITED_FOR);
+        assert_eq!(pool.order(None, now), vec![2, 1]);
+        assert_eq!(pool.order(None, now + RATE_LIMITED_FOR), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_guild_assignment() {
+        let pool = pool(KeyAssignment::Guild);
+        let now = Instant::now();
+        assert_eq!(pool.order(Some(4), now), vec![1, 2, 0]);
+        assert_eq!(pool.order(Some(4), now), vec![1, 2, 0]);
+
+        // everything benched: try them all rather than fail outright
+        for i in 0..3 {
+            pool.bench(i, now + DISABLED_FOR);
+        }
+        assert_eq!(pool.order(Some(4), now), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn test_guild_keys() {
+        let pool = pool(KeyAssignment::RoundRobin).require_guild_keys(true);
+        assert!(pool.serves(None));
+        assert!(!pool.serves(Some(4)));
+        assert!(pool.client(Some(4)).is_err());
+
+        pool.set_guild_key(4, Some("d".to_owned())).unwrap();
+        assert!(pool.serves(Some(4)));
+        pool.set_guild_key(4, None).unwrap();
+        assert!(!pool.serves(Some(4)));
+    }
+}
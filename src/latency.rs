@@ -0,0 +1,96 @@
+//! Keeping replies quick on busy servers. When a conversation's recent replies take
+//! longer than its `latency_target`, it switches to fast mode, a faster model and a
+//! shorter history, until the model speeds up again.
+
+use crate::schema::{Conversation, Database};
+use eyre::Result;
+use std::time::Duration;
+
+/// How many of the latest replies the p95 is taken over.
+const WINDOW: usize = 20;
+
+/// Too few replies to say anything about the tail.
+const MIN_SAMPLES: usize = 5;
+
+/// Fast mode ends once the p95 is back under this much of the target, so a
+/// conversation near the target doesn't flip back and forth.
+const RECOVERY: f64 = 0.6;
+
+/// The latency 95% of the replies came in under.
+pub fn p95(latencies: &[Duration]) -> Option<Duration> {
+    if latencies.is_empty() {
+        return None;
+    }
+    let mut sorted = latencies.to_vec();
+    sorted.sort();
+    let rank = (sorted.len() as f64 * 0.95).ceil() as usize;
+    Some(sorted[rank.max(1) - 1])
+}
+
+/// Whether a conversation should be in fast mode, given whether it is and its p95.
+pub fn should_be_fast(fast: bool, p95: Duration, target: Duration) -> bool {
+    if fast {
+        p95.as_secs_f64() >= target.as_secs_f64() * RECOVERY
+    } else {
+        p95 > target
+    }
+}
+
+/// Decide whether this reply is made in fast mode, recording any change. A target of
+/// 0 seconds turns it off.
+pub async fn fast_mode(db: &Database, conversation: Conversation, target: f32) -> Result<bool> {
+    let fast = db.latency_mode(conversation).await?;
+    let target = Duration::from_secs_f32(target.max(0.0));
+    if target.is_zero() {
+        if fast {
+            db.set_latency_mode(conversation, false).await?;
+        }
+        return Ok(false);
+    }
+
+    let latencies = db.recent_latencies(conversation, WINDOW).await?;
+    let Some(p95) = p95(&latencies).filter(|_| latencies.len() >= MIN_SAMPLES) else {
+        return Ok(fast);
+    };
+    let now_fast = should_be_fast(fast, p95, target);
+    if now_fast != fast {
+        log::info!(
+            "{} fast mode in {:?}: p95 of {:.2}s against a target of {:.2}s",
+            if now_fast { "Entering" } else { "Leaving" },
+            conversation,
+            p95.as_secs_f64(),
+            target.as_secs_f64()
+        );
+        db.set_latency_mode(conversation, now_fast).await?;
+    }
+
+    Ok(now_fast)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_p95() {
+        let ms = |ms: &[u64]| {
+            ms.iter()
+                .map(|&ms| Duration::from_millis(ms))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(p95(&[]), None);
+        assert_eq!(p95(&ms(&[300])), Some(Duration::from_millis(300)));
+        let mut latencies = ms(&[100; 19]);
+        latencies.push(Duration::from_secs(9));
+        assert_eq!(p95(&latencies), Some(Duration::from_millis(100)));
+        latencies.push(Duration::from_secs(9));
+        assert_eq!(p95(&latencies), Some(Duration::from_secs(9)));
+
+        let target = Duration::from_secs(5);
+        let secs = Duration::from_secs;
+        assert!(!should_be_fast(false, secs(5), target));
+        assert!(should_be_fast(false, secs(6), target));
+        assert!(should_be_fast(true, secs(4), target));
+        assert!(!should_be_fast(true, secs(2), target));
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// How many suspicious rounds in a row before the bot stops replying.
+const MAX_LOOP_ROUNDS: usize = 3;
+
+/// How many of the bot's own replies to remember per channel.
+const RECENT_REPLIES: usize = 5;
+
+/// Word overlap above which a message counts as an echo of the bot's own output.
+const ECHO_SIMILARITY: f64 = 0.8;
+
+pub const LOOP_REFUSAL: &str =
+    "I won't talk to myself. That's how a horse ends up going around in circles. Neigh.";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Reply,
+    /// Too many rounds: say so once, in character.
+    Refuse,
+    /// Already refused, stay quiet until the loop is broken.
+    Ignore,
+}
+
+#[derive(Default)]
+struct ChannelState {
+    recent: VecDeque<String>,
+    rounds: usize,
+}
+
+/// Detects the bot ping-ponging with webhooks or other bots that get past the
+/// author-is-bot check, by watching for messages that echo its own recent replies.
+#[derive(Default)]
+pub struct LoopGuard {
+    channels: HashMap<u64, ChannelState>,
+}
+
+impl LoopGuard {
+    pub fn check(&mut self, channel: u64, content: &str, from_webhook: bool) -> Verdict {
+        let state = self.channels.entry(channel).or_default();
+        let echo = state
+            .recent
+            .iter()
+            .any(|reply| similarity(reply, content) >= ECHO_SIMILARITY);
+
+        if !(echo || from_webhook) {
+            state.rounds = 0;
+            return Verdict::Reply;
+        }
+        state.rounds += 1;
+        match state.rounds {
+            r if r < MAX_LOOP_ROUNDS => Verdict::Reply,
+            r if r == MAX_LOOP_ROUNDS => Verdict::Refuse,
+            _ => Verdict::Ignore,
+        }
+    }
+
+    pub fn record_reply(&mut self, channel: u64, reply: &str) {
+        let state = self.channels.entry(channel).or_default();
+        state.recent.push_back(reply.to_owned());
+        while state.recent.len() > RECENT_REPLIES {
+            state.recent.pop_front();
+        }
+    }
+}
+
+/// Jaccard similarity of the lowercased word sets.
+fn similarity(a: &str, b: &str) -> f64 {
+    let words = |s: &str| -> HashSet<String> {
+        s.split_whitespace()
+            .map(|w| {
+                w.trim_matches(|c: char| !c.is_alphanumeric())
+                    .to_lowercase()
+            })
+            .filter(|w| !w.is_empty())
+            .collect()
+    };
+    let (a, b) = (words(a), words(b));
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_echo_loop() {
+        let mut guard = LoopGuard::default();
+        let reply = "Hay there, what a stable conversation!";
+        guard.record_reply(1, reply);
+        assert_eq!(
+            guard.check(1, "hay there what a stable conversation", false),
+            Verdict::Reply
+        );
+        assert_eq!(guard.check(1, reply, false), Verdict::Reply);
+        assert_eq!(guard.check(1, reply, false), Verdict::Refuse);
+        assert_eq!(guard.check(1, reply, false), Verdict::Ignore);
+        assert_eq!(guard.check(2, reply, false), Verdict::Reply);
+        assert_eq!(
+            guard.check(1, "something else entirely", false),
+            Verdict::Reply
+        );
+    }
+
+    #[test]
+    fn test_webhook_loop() {
+        let mut guard = LoopGuard::default();
+        for _ in 1..MAX_LOOP_ROUNDS {
+            assert_eq!(guard.check(1, "hello", true), Verdict::Reply);
+        }
+        assert_eq!(guard.check(1, "hello", true), Verdict::Refuse);
+    }
+}
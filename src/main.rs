@@ -1,32 +1,103 @@
 extern crate core;
 
+mod attachments;
+mod bench;
+mod birthdays;
+mod breaker;
+mod calendar;
+mod canned;
+mod celebrations;
+mod channels;
 mod chatbot;
+mod check;
+mod commands;
+mod conversations;
+mod datadir;
+mod embeddings;
+mod faq;
+mod function_calls;
 mod helpers;
+mod idle;
+mod karma;
+mod keys;
+mod latency;
+mod leaks;
+mod loops;
+mod members;
+mod mentions;
+mod outbox;
+mod persona;
+mod presence;
+mod presets;
+mod providers;
+mod router;
+mod schedules;
 mod schema;
+mod scripting;
+mod secrets;
+mod service;
+mod shadow;
+mod smoke;
+mod tags;
+mod templates;
+mod text;
+mod tools;
+mod transforms;
+mod vectors;
+mod web;
 
-use async_openai::config::OpenAIConfig;
 use async_trait::async_trait;
-use bimap::BiMap;
-use chatbot::ChatBot;
+use breaker::CircuitBreaker;
+use calendar::Calendars;
+use channels::ChannelKind;
+use chatbot::{ChatBot, Speaker};
 use clap::Parser;
+use conversations::ConversationsCommand;
+use datadir::DataDir;
 use eyre::{Context, Result};
+use itertools::Itertools;
+use keys::{KeyAssignment, KeyPool};
 
-use helpers::DiscordContextHelpers;
-use itertools::intersperse;
+use leaks::LEAK_WARNING;
+use loops::{LoopGuard, Verdict, LOOP_REFUSAL};
+use members::MemberEvent;
+use mentions::{MentionCache, USER_MENTION};
 use minijinja::{context, value::Value};
-use schema::{Conversation, Database};
+use persona::PersonaCommand;
+use presence::Presence;
+use presets::PresetCommand;
+use schedules::ScheduleCommand;
+use schema::{
+    Conversation, Database, Flag, Flags, ModerationPolicy, NsfwSettings, PrivacyMode, Role,
+    Setting, SettingScope, SettingsResolver, CALENDAR_URL, EMBEDDING_MODEL, PACING, PING, REPLY,
+};
+use secrets::Secrets;
 use serenity::{
     model::{
-        prelude::{Channel, Guild, Message, Ready},
+        application::{command::Command as SlashCommand, interaction::Interaction},
+        event::GuildMembersChunkEvent,
+        prelude::{
+            Channel, ChannelId, Guild, GuildId, Member, Message, MessageId, Reaction, ReactionType,
+            Ready, UserId,
+        },
         user::User,
     },
     prelude::{self as discord},
 };
-use std::{path::PathBuf, sync::Arc};
-use tokio::sync::Mutex;
-use unicase::UniCase;
-
-// use tiktoken_rs::async_openai::get_chat_completion_max_tokens;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+use templates::ServerTemplates;
+use tokio::sync::{Mutex, Notify};
+use tools::ToolRegistry;
+use vectors::{sqlite::SqliteStore, Entry, VectorStore};
 
 #[derive(Debug, clap::Parser)]
 #[command(author, version, about, long_about = None)]
@@ -34,20 +105,368 @@ struct Args {
     #[clap(short, long)]
     database: Option<PathBuf>,
 
+    /// Keep the database, backups, prompts and cache here, creating them as needed.
+    /// --database still takes precedence for the database.
+    #[clap(long)]
+    data_dir: Option<PathBuf>,
+
+    /// Keep everything in memory, forgetting it all on exit, instead of using horse.db
+    #[clap(long, conflicts_with_all = ["database", "data_dir"])]
+    ephemeral: bool,
+
+    /// Directory of WASM tool plugins to load (requires the wasm feature)
+    #[clap(long)]
+    plugins: Option<PathBuf>,
+
+    /// Seconds to wait for each OpenAI request before giving up
+    #[clap(long, default_value = "60")]
+    openai_timeout: u64,
+
+    /// How to spread requests over the comma separated keys in OPENAI_KEY: round-robin,
+    /// or guild to start each server on its own key
+    #[clap(long, default_value = "round-robin")]
+    key_assignment: KeyAssignment,
+
+    /// Only reply in servers that registered their own OpenAI key with /openai-key.
+    /// The keys in OPENAI_KEY are then used just for DMs.
+    #[clap(long)]
+    guild_keys_required: bool,
+
+    /// Notice members joining and leaving servers, for welcome_channel. Needs the Server
+    /// Members intent turned on for the bot in the Discord developer portal
+    #[clap(long)]
+    member_events: bool,
+
+    /// Comma separated kinds of channel not to answer mentions in: text, thread, forum,
+    /// voice, stage or dm
+    #[clap(long, value_delimiter = ',')]
+    ignore_channels: Vec<ChannelKind>,
+
+    /// What the bot is shown playing, like "Grazing in {{ servers }} servers"
+    #[clap(long)]
+    activity: Option<String>,
+
+    /// A file of in-character activities, one per line, to show in turn instead of --activity
+    #[clap(long)]
+    activities: Option<PathBuf>,
+
+    /// Minutes to show each of --activities for
+    #[clap(long, default_value = "15")]
+    activity_minutes: u64,
+
+    /// Whether the bot shows as online, idle, dnd or invisible
+    #[clap(long, default_value = "online", value_parser = presence::parse_status)]
+    status: serenity::model::user::OnlineStatus,
+
+    /// Serve read-only conversation transcripts on this address, like 127.0.0.1:8080.
+    /// Requests must send the HTTP_TOKEN environment variable as a bearer token.
+    #[clap(long)]
+    http: Option<SocketAddr>,
+
+    /// Log to this file instead of stderr, moving it aside as horse.log.1 and so on
+    /// when it gets too big
+    #[clap(long)]
+    log_file: Option<PathBuf>,
+
+    /// How many megabytes the log file can grow to before it is rotated
+    #[clap(long, default_value = "10")]
+    log_max_size: u64,
+
+    /// Write the process id to this file while running
+    #[clap(long)]
+    pid_file: Option<PathBuf>,
+
+    /// Search embeddings with the Qdrant server at this URL, like http://localhost:6333,
+    /// instead of the database (requires the qdrant feature)
+    #[clap(long)]
+    qdrant: Option<String>,
+
+    /// Prefix for the Qdrant collections, which are named after it and the embedding model
+    #[clap(long, default_value = "horse-npc")]
+    qdrant_collection: String,
+
     #[clap(subcommand)]
     command: Command,
 }
 
+impl Args {
+    /// The database from --database, or else the one in --data-dir, or else horse.db in
+    /// the current directory. None (an in-memory database) only with --ephemeral.
+    fn database_path(&self) -> Result<Option<PathBuf>> {
+        if self.ephemeral {
+            log::warn!("Using an in-memory database, everything will be forgotten on exit");
+            return Ok(None);
+        }
+        match (&self.database, &self.data_dir) {
+            (Some(path), _) => Ok(Some(path.clone())),
+            (None, Some(dir)) => Ok(Some(DataDir::open(dir)?.database())),
+            (None, None) => Ok(Some(PathBuf::from(DEFAULT_DATABASE))),
+        }
+    }
+
+    /// Where the database would be, without creating the data directory on the way.
+    /// None with --ephemeral.
+    fn database_location(&self) -> Option<PathBuf> {
+        if self.ephemeral {
+            return None;
+        }
+        match (&self.database, &self.data_dir) {
+            (Some(path), _) => Some(path.clone()),
+            (None, Some(dir)) => Some(DataDir::database_in(dir)),
+            (None, None) => Some(PathBuf::from(DEFAULT_DATABASE)),
+        }
+    }
+}
+
+/// Where the database goes when neither --database nor --data-dir is given.
+const DEFAULT_DATABASE: &str = "horse.db";
+
 #[derive(Debug, clap::Parser)]
 enum Command {
     Run,
     Test,
+    /// Check the environment, database, stored templates and scripts, and tool
+    /// definitions, reporting every problem found
+    Check,
+    /// Set (or with no file, remove) the hook script for a conversation
+    Script {
+        conversation: String,
+        file: Option<PathBuf>,
+    },
+    /// Set the output transforms for a conversation from a JSON file (or with no file, remove them)
+    Transforms {
+        conversation: String,
+        file: Option<PathBuf>,
+    },
+    /// Set what a conversation says to flagged messages, one template per line (or with
+    /// no file, go back to the built-in horse ones)
+    Deflections {
+        conversation: String,
+        file: Option<PathBuf>,
+    },
+    /// Configure how a conversation behaves when its channel is marked NSFW
+    Nsfw {
+        conversation: String,
+        /// A prompt template file to use instead of the conversation's prompt
+        #[clap(long)]
+        prompt: Option<PathBuf>,
+        /// One of strict, default, relaxed or off
+        #[clap(long, default_value = "default")]
+        moderation: ModerationPolicy,
+    },
+    /// Inspect conversations
+    Conversations {
+        #[clap(subcommand)]
+        command: ConversationsCommand,
+    },
+    /// Manage prompts that take over a conversation on a schedule
+    Schedule {
+        #[clap(subcommand)]
+        command: ScheduleCommand,
+    },
+    /// Manage presets of model, temperature, reply length and prompt, and share them
+    /// as TOML
+    Preset {
+        #[clap(subcommand)]
+        command: PresetCommand,
+    },
+    /// Share a whole persona (prompt, parameters, lore, functions and canned responses)
+    /// as one TOML file
+    Persona {
+        #[clap(subcommand)]
+        command: PersonaCommand,
+    },
+    /// Move everything from one conversation into another, deleting the first
+    Merge {
+        src: String,
+        dst: String,
+    },
+    /// Rename a conversation
+    Rename {
+        conversation: String,
+        name: String,
+    },
+    /// Set (or with no file, remove) the template for commenting when the channel topic
+    /// changes. It is rendered with `topic` and `channel_name`.
+    TopicTemplate {
+        conversation: String,
+        file: Option<PathBuf>,
+    },
+    /// Set (or with no file, remove) a template shared by a server's conversations.
+    /// The one named base is the server's persona, which channel prompts can extend.
+    Template {
+        guild: u64,
+        name: String,
+        file: Option<PathBuf>,
+    },
+    /// Hold a short scripted conversation with OpenAI (a greeting, a tool call and a
+    /// message moderation should flag), reporting whether each stage passed
+    Smoke,
+    /// Replay synthetic messages through the reply pipeline against a mock OpenAI,
+    /// reporting latency percentiles for each stage
+    Bench {
+        #[clap(long, default_value_t = 1000)]
+        messages: usize,
+    },
+    /// Set (or with no value, remove) a setting, such as temperature or model, for a
+    /// conversation, a server, or with neither, every conversation
+    Setting {
+        #[clap(long, conflicts_with = "guild")]
+        conversation: Option<String>,
+        #[clap(long)]
+        guild: Option<u64>,
+        key: String,
+        value: Option<String>,
+    },
+    /// Turn an optional behavior (tools, ambient_context or memory) on, off, or back to
+    /// inherit, for a conversation, a server, or with neither, every conversation
+    Flag {
+        #[clap(long, conflicts_with = "guild")]
+        conversation: Option<String>,
+        #[clap(long)]
+        guild: Option<u64>,
+        flag: Flag,
+        /// on, off or inherit
+        state: String,
+    },
+    /// Have the model describe each conversation (or just one) in a line, for listings
+    /// and the transcript viewer
+    Describe {
+        #[clap(long)]
+        conversation: Option<String>,
+    },
+    /// Delete history older than a duration like 90d, in one conversation or all of them
+    Prune {
+        #[clap(long)]
+        older_than: String,
+        #[clap(long)]
+        conversation: Option<String>,
+        /// Only report what would be deleted
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Rewrite history stored in an older format in the current one. It is upgraded
+    /// as it is read anyway, so this is only needed before dropping support for one
+    MigrateMessages,
+    /// Compute embeddings for the FAQ and history that lack one from the embedding
+    /// model, say after changing it. Stopped partway, it picks up where it left off
+    Reindex {
+        /// Instead of the embedding_model setting
+        #[clap(long)]
+        model: Option<String>,
+        /// How many texts to send the model at once
+        #[clap(long, default_value_t = 100)]
+        batch: usize,
+    },
+    /// Find the messages of a conversation closest in meaning to QUERY, among those
+    /// embedded by reindex
+    Search {
+        conversation: String,
+        query: String,
+        #[clap(long, default_value_t = 5)]
+        limit: usize,
+    },
+    /// Break down what people say to the bot, in one conversation or all of them: what
+    /// their messages needed and how friendly they were
+    Stats {
+        #[clap(long)]
+        conversation: Option<String>,
+    },
+    /// List reports of the bot's messages, in one conversation or all of them
+    Reports {
+        #[clap(long)]
+        conversation: Option<String>,
+    },
+    /// Show a conversation's latest logged requests (see the audit setting), or with
+    /// --redact, delete its log
+    Audit {
+        conversation: String,
+        #[clap(long, default_value_t = 10)]
+        limit: usize,
+        #[clap(long)]
+        redact: bool,
+    },
+    /// Show what a conversation's stages on trial would have done (see the shadow setting)
+    Shadow {
+        conversation: String,
+        #[clap(long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Show a conversation's latest messages with their ids, for redact
+    History {
+        conversation: String,
+        #[clap(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Replace what a stored message said with a placeholder, keeping its place in the
+    /// history. Ids are shown by history and in transcripts
+    Redact {
+        #[clap(long)]
+        message_id: i64,
+    },
+    /// Show how much of its model's context window a conversation uses
+    Tokens {
+        #[clap(long)]
+        conversation: String,
+    },
+    /// Seal every stored secret again with SECRETS_KEY, after the old key was moved
+    /// to SECRETS_OLD_KEYS
+    RotateSecrets,
 }
 
+/// How many custom emoji and active members to list in the prompt variables.
+const MAX_GUILD_EMOJI: usize = 50;
+const MAX_ACTIVE_MEMBERS: usize = 20;
+
+/// Sent the first time someone DMs the bot.
+const PRIVACY_NOTICE: &str = "Before we chat: I keep what we say here so I can remember it, \
+    and whoever runs me can read it. Use `/privacy summary` to have me keep only a short summary, \
+    or `/privacy off` to have me forget each exchange once I've answered.";
+
+/// Discord rejects messages longer than this many characters.
+pub const MAX_MESSAGE_LENGTH: usize = 2000;
+
+/// On connecting, look for missed mentions in conversations active this recently,
+/// at most this many messages per channel.
+const CATCH_UP_DAYS: i64 = 1;
+const CATCH_UP_LIMIT: u64 = 50;
+
+/// How fast the bot "types" the follow-ups of a paced reply, and how long it takes
+/// over each one at least and at most.
+const TYPING_CHARS_PER_SECOND: u64 = 30;
+const MIN_TYPING_DELAY: Duration = Duration::from_secs(1);
+const MAX_TYPING_DELAY: Duration = Duration::from_secs(4);
+
 struct DiscordBot {
     database: Arc<Database>,
-    openai: Arc<async_openai::Client<OpenAIConfig>>,
-    mentions: Arc<Mutex<BiMap<String, UniCase<String>>>>,
+    openai: RwLock<Arc<KeyPool>>,
+    tools: RwLock<Arc<ToolRegistry>>,
+    mentions: Arc<Mutex<MentionCache>>,
+    loops: Arc<Mutex<LoopGuard>>,
+    breaker: Arc<CircuitBreaker>,
+    calendars: Calendars,
+    /// Where embeddings are searched, told when messages are deleted or moved.
+    vectors: Arc<dyn VectorStore>,
+    /// The latest context from the gateway, for jobs that run outside of events.
+    gateway: std::sync::Mutex<Option<discord::Context>>,
+    /// Set once the outbox delivery loop has been started.
+    outbox_started: AtomicBool,
+    /// Woken on every connection, to catch up on what was missed while disconnected.
+    connected: Notify,
+    presence: Arc<Presence>,
+    /// Set once the activity rotation has been started.
+    presence_started: AtomicBool,
+    /// Kept for rebuilding the OpenAI keys and tools on reload.
+    openai_timeout: Duration,
+    key_assignment: KeyAssignment,
+    guild_keys_required: bool,
+    plugins: Option<PathBuf>,
+    /// Kinds of channel whose mentions go unanswered.
+    ignored_channels: Vec<ChannelKind>,
+    /// Variables read from `.env` by the last reload, which win over the environment.
+    /// They're kept here because the environment isn't safe to change while other
+    /// threads may be reading it.
+    reloaded_env: RwLock<HashMap<String, String>>,
 }
 
 #[async_trait]
@@ -55,98 +474,516 @@ impl ChatBot for &DiscordBot {
     type Message = Message;
     type Context = discord::Context;
 
-    fn openai(&self) -> Arc<async_openai::Client<OpenAIConfig>> {
-        self.openai.clone()
+    fn openai(&self) -> Arc<KeyPool> {
+        self.current_openai()
     }
 
     fn database(&self) -> Arc<Database> {
         self.database.clone()
     }
 
+    fn tools(&self) -> Arc<ToolRegistry> {
+        self.current_tools()
+    }
+
+    fn breaker(&self) -> Option<Arc<CircuitBreaker>> {
+        Some(self.breaker.clone())
+    }
+
+    fn guild_id(&self, _context: &Self::Context, message: &Self::Message) -> Option<u64> {
+        message.guild_id.map(|g| g.0)
+    }
+
     async fn message_content(
         &self,
         context: &Self::Context,
         message: &Self::Message,
     ) -> Result<String> {
         let content = message.content.clone();
-        let content = self
+        let mut content = self
             .decode_user_mentions(context, Some(message), content)
             .await?;
+        for excerpt in attachments::excerpts(&message.attachments).await {
+            content.push_str("\n\n");
+            content.push_str(&excerpt);
+        }
 
         Ok(content)
     }
 
+    async fn speaker(&self, context: &Self::Context, message: &Self::Message) -> Result<Speaker> {
+        let name = message
+            .author_nick(context)
+            .await
+            .unwrap_or_else(|| message.author.name.clone());
+        Ok(Speaker {
+            id: message.author.id.to_string(),
+            name,
+        })
+    }
+
+    async fn bot_name(&self, context: &Self::Context, message: &Self::Message) -> Result<String> {
+        let bot = context.cache.current_user();
+        let nick = message
+            .guild_id
+            .and_then(|guild_id| context.cache.member(guild_id, bot.id))
+            .and_then(|member| member.nick);
+        Ok(nick.unwrap_or(bot.name))
+    }
+
     async fn conversation(
         &self,
         context: &Self::Context,
         message: &Self::Message,
     ) -> Result<Conversation> {
-        let channel = message.channel_id.to_channel(&context).await?;
-        let name = match channel {
-            Channel::Guild(g) => {
-                // determine if thread or regular channel
-                if let Some(parent) = g.parent_id {
-                    let parent = parent.to_channel(&context).await?;
-                    if let Channel::Guild(parent) = parent {
-                        format!("#{}:{}", parent.name, g.name)
-                    } else {
-                        format!("#{}", g.name)
-                    }
-                } else {
-                    format!("#{}", g.name)
-                }
-            }
-            Channel::Private(p) => p.recipient.name.to_string(),
-            _ => "unknown".to_string(),
-        };
-        self.database().find_conversation(name).await
+        self.channel_conversation(context, message.channel_id).await
     }
 
     async fn prompt_vars(&self, context: &Self::Context, message: &Self::Message) -> Result<Value> {
+        // DMs have no server, and so no nicknames or emoji
+        let guild = message.guild(context);
+        self.channel_prompt_vars(
+            context,
+            guild.as_ref(),
+            message.guild_id,
+            message.channel_id,
+            message.author.id,
+        )
+        .await
+    }
+
+    async fn speaker_roles(
+        &self,
+        _context: &Self::Context,
+        message: &Self::Message,
+    ) -> Result<Vec<u64>> {
+        let roles = message.member.as_ref().map(|m| &m.roles);
+        Ok(roles.into_iter().flatten().map(|role| role.0).collect())
+    }
+
+    async fn is_nsfw(&self, context: &Self::Context, message: &Self::Message) -> Result<bool> {
+        self.channel_is_nsfw(context, message.channel_id).await
+    }
+
+    async fn call_function(
+        &self,
+        context: &Self::Context,
+        message: &Self::Message,
+        name: &str,
+        arguments: &serde_json::Value,
+    ) -> Result<Option<String>> {
+        match name {
+            "react" => {
+                let reaction_name = arguments["reaction_name"]
+                    .as_str()
+                    .ok_or_else(|| eyre::eyre!("missing reaction_name"))?;
+                let reaction = reaction_type(context, message, reaction_name)?;
+                message.react(context, reaction).await?;
+                Ok(Some(format!("reacted with {reaction_name}")))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Find a guild emoji by name, or treat the name as a unicode emoji.
+fn reaction_type(
+    context: &discord::Context,
+    message: &Message,
+    name: &str,
+) -> Result<ReactionType> {
+    let name = name.trim_matches(':');
+    let emoji = message
+        .guild(context)
+        .and_then(|g| g.emojis.values().find(|e| e.name == name).cloned());
+    match emoji {
+        Some(emoji) => Ok(emoji.into()),
+        None if !name.is_ascii() => Ok(ReactionType::Unicode(name.to_owned())),
+        None => Err(eyre::eyre!("unknown emoji {name}")),
+    }
+}
+
+/// How long to show the bot typing before sending `message` as part of a paced reply.
+fn typing_delay(message: &str) -> Duration {
+    let millis = message.chars().count() as u64 * 1000 / TYPING_CHARS_PER_SECOND;
+    Duration::from_millis(millis).clamp(MIN_TYPING_DELAY, MAX_TYPING_DELAY)
+}
+
+/// The guild's custom emoji, like `:horse_smile:`.
+fn guild_emoji(guild: &Guild) -> Vec<String> {
+    let mut names = guild
+        .emojis
+        .values()
+        .filter(|e| e.available)
+        .map(|e| format!(":{}:", e.name))
+        .collect::<Vec<_>>();
+    names.sort();
+    names.truncate(MAX_GUILD_EMOJI);
+    names
+}
+
+/// Who has spoken in the channel recently, most recent first, leaving out the `blocked`.
+/// This only looks at the message cache, so it never costs an API call.
+fn active_members(
+    context: &discord::Context,
+    channel_id: ChannelId,
+    blocked: &[u64],
+) -> Vec<String> {
+    let mut messages = context
+        .cache
+        .channel_messages(channel_id)
+        .unwrap_or_default();
+    messages.sort_by_key(|m| std::cmp::Reverse(m.id));
+    messages
+        .into_iter()
+        .filter(|m| !m.author.bot && !blocked.contains(&m.author.id.0))
+        .map(|m| {
+            let nick = m.member.and_then(|member| member.nick);
+            format!("@{}", nick.unwrap_or(m.author.name))
+        })
+        .unique()
+        .take(MAX_ACTIVE_MEMBERS)
+        .collect()
+}
+
+/// The user's nickname in the guild, or their username outside of one or when they
+/// can't be found in it (say, because they left).
+async fn get_nickname(context: &discord::Context, guild: Option<&Guild>, user: &User) -> String {
+    let Some(guild) = guild else {
+        return user.name.clone();
+    };
+    match guild.member(context, user.id).await {
+        Ok(member) => member.nick.unwrap_or_else(|| user.name.clone()),
+        Err(e) => {
+            log::warn!("Failed to look up {} in {}: {}", user.name, guild.name, e);
+            user.name.clone()
+        }
+    }
+}
+
+impl DiscordBot {
+    /// The template variables for a prompt in a channel, addressed to a user.
+    async fn channel_prompt_vars(
+        &self,
+        context: &discord::Context,
+        guild: Option<&Guild>,
+        guild_id: Option<GuildId>,
+        channel_id: ChannelId,
+        user_id: UserId,
+    ) -> Result<Value> {
         let now = chrono::Local::now();
         let date = now
             .format("Today is %A, the %e of %B, %Y. The time is %I:%M %p")
             .to_string();
-        let guild = context.get_guild(Some(message)).await?;
-        let user = message.author.id.to_user(&context).await?;
+        let user = user_id.to_user(&context).await?;
         let bot = context.cache.current_user_id().to_user(&context).await?;
-        let user_nick = get_nickname(context, &guild, &user).await?;
-        let bot_nick = get_nickname(context, &guild, &bot).await?;
-        let channel = message.channel_id.to_channel(&context).await?;
-        let server_name = message.guild_id.and_then(|g| g.name(context));
+        let user_nick = get_nickname(context, guild, &user).await;
+        let bot_nick = get_nickname(context, guild, &bot).await;
+        let channel = channel_id.to_channel(&context).await?;
+        let server_name = guild_id.and_then(|g| g.name(context));
         let (channel_name, channel_topic) = match channel {
             Channel::Guild(g) => (Some(g.name), g.topic),
             _ => (None, None),
         };
+        let channel_nsfw = self.channel_is_nsfw(context, channel_id).await?;
+        let conversation = self.channel_conversation(context, channel_id).await?;
+        let topic_changed = self
+            .database
+            .topic_changed_at(conversation)
+            .await?
+            .map(|t| t.format("%A, the %e of %B").to_string());
+        let settings = SettingsResolver::new(&self.database, conversation, guild_id.map(|g| g.0));
+        let flags = Flags::new(&self.database, conversation, guild_id.map(|g| g.0));
+        let ambient = flags.enabled(Flag::AmbientContext).await?;
+        let active_members = if ambient {
+            let blocked = self.database.blocked_users(guild_id.map(|g| g.0)).await?;
+            active_members(context, channel_id, &blocked)
+        } else {
+            vec![]
+        };
+        let calendar_url = settings.get(&CALENDAR_URL).await?;
+        let calendar_url = Some(calendar_url).filter(|url| ambient && !url.is_empty());
+        let (upcoming_events, today_is_holiday) = self
+            .calendars
+            .upcoming(calendar_url.as_deref(), now.date_naive());
+        let user_karma = self
+            .database
+            .karma(conversation, user_id.to_string())
+            .await?;
+        let mood = self.database.mood(conversation, now).await?;
 
         Ok(context! {
+            guild_emoji => guild.filter(|_| ambient).map(guild_emoji).unwrap_or_default(),
+            active_members,
             user_nick => format!("@{}", user_nick),
             bot_nick => format!("@{}", bot_nick),
             date,
             server_name,
             channel_name,
             channel_topic,
+            topic_changed,
+            channel_nsfw,
+            upcoming_events,
+            today_is_holiday,
+            user_karma,
+            user_standing => karma::standing(user_karma),
+            mood => mood.as_str(),
         })
     }
-}
 
-async fn get_nickname(context: &discord::Context, guild: &Guild, user: &User) -> Result<String> {
-    let member = guild.member(context, user.id).await?;
-    Ok(member.nick.unwrap_or(user.clone().name).to_owned())
-}
+    /// Threads don't carry the NSFW flag themselves, so check their parent channel.
+    async fn channel_is_nsfw(
+        &self,
+        context: &discord::Context,
+        channel_id: ChannelId,
+    ) -> Result<bool> {
+        let Channel::Guild(channel) = channel_id.to_channel(&context).await? else {
+            return Ok(false);
+        };
+        if channel.nsfw {
+            return Ok(true);
+        }
+        match channel.parent_id {
+            Some(parent) if channel.thread_metadata.is_some() => {
+                match parent.to_channel(&context).await? {
+                    Channel::Guild(parent) => Ok(parent.nsfw),
+                    _ => Ok(false),
+                }
+            }
+            _ => Ok(false),
+        }
+    }
 
-impl DiscordBot {
-    async fn new(db_path: Option<PathBuf>) -> Result<Self> {
+    async fn channel_conversation(
+        &self,
+        context: &discord::Context,
+        channel_id: ChannelId,
+    ) -> Result<Conversation> {
+        let channel = channel_id.to_channel(&context).await?;
+        let (kind, name, parent) = self.channel_kind(context, &channel).await?;
+        let guild = match &channel {
+            Channel::Guild(g) => {
+                let name = g
+                    .guild_id
+                    .name(&context)
+                    .unwrap_or_else(|| g.guild_id.to_string());
+                Some((g.guild_id.0, name))
+            }
+            _ => None,
+        };
+        let name = kind.conversation_name(&name, parent.as_deref());
+        self.database
+            .find_channel_conversation(channel_id.to_string(), guild, name)
+            .await
+    }
+
+    /// The channel one of a server's channel settings names, if it's set and the
+    /// channel is in that server.
+    async fn server_channel(
+        &self,
+        context: &discord::Context,
+        guild_id: GuildId,
+        setting: &Setting<String>,
+    ) -> Result<Option<ChannelId>> {
+        let channel = SettingsResolver::guild(&self.database, guild_id.0)
+            .get(setting)
+            .await?;
+        let Ok(channel_id) = channel.parse() else {
+            return Ok(None);
+        };
+        let channel_id = ChannelId(channel_id);
+        match channel_id.to_channel(&context).await? {
+            Channel::Guild(channel) if channel.guild_id == guild_id => Ok(Some(channel_id)),
+            _ => {
+                log::warn!(
+                    "{} names {}, which isn't a channel in server {}",
+                    setting.key,
+                    channel_id,
+                    guild_id
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// What kind of channel this is, with its name and, for threads, its parent's name.
+    async fn channel_kind(
+        &self,
+        context: &discord::Context,
+        channel: &Channel,
+    ) -> Result<(ChannelKind, String, Option<String>)> {
+        match channel {
+            Channel::Guild(g) => {
+                // channels in a category have it as their parent, which isn't a Guild one
+                let parent = match g.parent_id {
+                    Some(parent) => match parent.to_channel(&context).await? {
+                        Channel::Guild(parent) => Some(parent),
+                        _ => None,
+                    },
+                    None => None,
+                };
+                let kind = ChannelKind::of_guild_channel(g.kind, parent.as_ref().map(|p| p.kind))
+                    .ok_or_else(|| eyre::eyre!("can't talk in a {:?} channel", g.kind))?;
+                Ok((kind, g.name.clone(), parent.map(|p| p.name)))
+            }
+            Channel::Private(p) => Ok((ChannelKind::Dm, p.recipient.name.clone(), None)),
+            _ => Err(eyre::eyre!("can't talk in channel {}", channel.id())),
+        }
+    }
+
+    async fn new(
+        db_path: Option<PathBuf>,
+        plugins: Option<PathBuf>,
+        openai_timeout: Duration,
+        key_assignment: KeyAssignment,
+        guild_keys_required: bool,
+        ignored_channels: Vec<ChannelKind>,
+        presence: Presence,
+    ) -> Result<Self> {
         let schema = Arc::new(Database::new(db_path).await?);
-        let config = OpenAIConfig::new().with_api_key(get_openai_key()?);
-        let openai = Arc::new(async_openai::Client::with_config(config));
-        let mentions = Arc::new(Mutex::new(BiMap::new()));
+        let openai = openai_keys(key_assignment, openai_timeout)?;
+        let openai = RwLock::new(Arc::new(openai.require_guild_keys(guild_keys_required)));
+        let tools = RwLock::new(Arc::new(load_tools(plugins.as_deref())?));
+        let mentions = Arc::new(Mutex::new(MentionCache::default()));
+        let loops = Arc::new(Mutex::new(LoopGuard::default()));
+        let breaker = Arc::new(CircuitBreaker::default());
+        let calendars = Calendars::new()?;
+        let vectors: Arc<dyn VectorStore> = Arc::new(SqliteStore::new(schema.clone()));
 
-        Ok(Self {
+        let bot = Self {
             database: schema,
             openai,
+            tools,
             mentions,
-        })
+            loops,
+            breaker,
+            calendars,
+            vectors,
+            gateway: std::sync::Mutex::new(None),
+            outbox_started: AtomicBool::new(false),
+            connected: Notify::new(),
+            presence: Arc::new(presence),
+            presence_started: AtomicBool::new(false),
+            openai_timeout,
+            key_assignment,
+            guild_keys_required,
+            plugins,
+            ignored_channels,
+            reloaded_env: RwLock::new(HashMap::new()),
+        };
+        bot.load_guild_keys(&bot.current_openai()).await?;
+
+        Ok(bot)
+    }
+
+    /// Keep embeddings somewhere other than the database.
+    fn with_vectors(mut self, vectors: Arc<dyn VectorStore>) -> Self {
+        self.vectors = vectors;
+        self
+    }
+
+    /// Give the pool the keys servers registered for themselves.
+    async fn load_guild_keys(&self, pool: &KeyPool) -> Result<()> {
+        let sealed = self.database.guild_keys().await?;
+        if sealed.is_empty() {
+            return Ok(());
+        }
+        let Some(secrets) = self.secrets()? else {
+            log::warn!("SECRETS_KEY isn't set, so servers' own OpenAI keys are ignored");
+            return Ok(());
+        };
+        // one bad key shouldn't keep the bot from starting for everyone else
+        for (guild, sealed) in sealed {
+            let key = match secrets.open(&sealed) {
+                Ok(key) => key,
+                Err(e) => {
+                    log::error!("Skipping the OpenAI key of server {}: {}", guild, e);
+                    continue;
+                }
+            };
+            if let Err(e) = pool.set_guild_key(guild, Some(key)) {
+                log::error!("Skipping the OpenAI key of server {}: {}", guild, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn current_openai(&self) -> Arc<KeyPool> {
+        self.openai
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Tell the breaker how a reply went, unless it was made with a server's own key,
+    /// which only that server depends on.
+    fn record_reply<T>(&self, guild: Option<u64>, reply: &Result<T>) {
+        self.breaker
+            .record_call(&self.current_openai(), guild, reply);
+    }
+
+    fn current_tools(&self) -> Arc<ToolRegistry> {
+        self.tools.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// A variable from `.env` as of the last reload, or else from the environment.
+    fn env_var(&self, name: &str) -> Option<String> {
+        let reloaded = self
+            .reloaded_env
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(name)
+            .cloned();
+        reloaded.or_else(|| std::env::var(name).ok())
+    }
+
+    /// The master keys for sealing servers' own OpenAI keys, if SECRETS_KEY is set.
+    pub fn secrets(&self) -> Result<Option<Secrets>> {
+        Secrets::from_vars(
+            self.env_var("SECRETS_KEY").as_deref(),
+            self.env_var("SECRETS_OLD_KEYS").as_deref(),
+        )
+    }
+
+    /// Re-read `.env`, then rebuild the OpenAI client, reload plugins and forget the
+    /// cached server templates, all without dropping the Discord connection or the
+    /// mention cache. A plain level in RUST_LOG (like `warn`) is applied too, though it
+    /// can't be more verbose than at startup.
+    async fn reload(&self) -> Result<()> {
+        let mut reloaded = HashMap::new();
+        if let Ok(vars) = dotenv::dotenv_iter() {
+            for var in vars {
+                let (key, value) = var?;
+                reloaded.insert(key, value);
+            }
+        }
+        *self.reloaded_env.write().unwrap_or_else(|e| e.into_inner()) = reloaded;
+        if let Some(level) = self
+            .env_var("RUST_LOG")
+            .and_then(|l| l.parse::<log::LevelFilter>().ok())
+        {
+            log::set_max_level(level);
+        }
+
+        let keys = self
+            .env_var("OPENAI_KEY")
+            .ok_or_else(|| eyre::eyre!("OPENAI_KEY is not set"))?;
+        let openai = KeyPool::new(
+            parse_openai_keys(&keys),
+            self.key_assignment,
+            self.openai_timeout,
+        )?
+        .require_guild_keys(self.guild_keys_required);
+        self.load_guild_keys(&openai).await?;
+        let openai = Arc::new(openai);
+        let tools = Arc::new(load_tools(self.plugins.as_deref())?);
+        *self.openai.write().unwrap_or_else(|e| e.into_inner()) = openai;
+        *self.tools.write().unwrap_or_else(|e| e.into_inner()) = tools;
+        ServerTemplates::clear_cache();
+        log::info!("Reloaded configuration");
+
+        Ok(())
     }
 
     async fn decode_user_mentions<S>(
@@ -158,63 +995,124 @@ impl DiscordBot {
     where
         S: AsRef<str>,
     {
-        let re = regex::Regex::new(r"<@(\d+)>")?;
+        let re = &*USER_MENTION;
+        let guild_id = message.and_then(|m| m.guild_id).map(|g| g.0);
         let mut mentions = self.mentions.lock().await;
 
-        // iterate over all regex matches
-        for caps in re.captures_iter(content.as_ref()) {
-            // get the user id
-            let Some(user_id) = caps.get(1).map(|m| m.as_str()) else { continue };
-            let user_id = user_id.parse::<u64>()?;
-            let mention = format!("<@{}>", user_id);
-            if mentions.contains_left(&mention) {
-                continue;
+        // ids in code or links stay as they are, so they needn't be looked up
+        let unseen = text::segments(content.as_ref())
+            .into_iter()
+            .filter_map(|segment| match segment {
+                text::Segment::Prose(prose) => Some(prose),
+                text::Segment::Verbatim(_) => None,
+            })
+            .flat_map(|prose| re.captures_iter(prose))
+            .filter_map(|caps| caps.get(1)?.as_str().parse::<u64>().ok())
+            .unique()
+            .filter(|user_id| !mentions.contains(guild_id, &format!("<@{}>", user_id)))
+            .map(UserId)
+            .collect::<Vec<_>>();
+
+        if unseen.is_empty() {
+            return Ok(mentions.decode(guild_id, content.as_ref()));
+        }
+        match message.and_then(|m| m.guild(context)) {
+            Some(guild) => {
+                // the guild's member cache covers most mentions, and the rest are fetched
+                // together rather than one request after another
+                let (cached, misses): (Vec<_>, Vec<_>) = unseen
+                    .into_iter()
+                    .partition(|user_id| guild.members.contains_key(user_id));
+                let fetched = futures::future::join_all(
+                    misses
+                        .iter()
+                        .map(|user_id| guild.id.member(context, *user_id)),
+                )
+                .await;
+                let members = cached
+                    .iter()
+                    .filter_map(|user_id| guild.members.get(user_id).cloned())
+                    .chain(fetched.into_iter().filter_map(|member| match member {
+                        Ok(member) => Some(member),
+                        Err(e) => {
+                            log::warn!("Failed to look up a mentioned member: {}", e);
+                            None
+                        }
+                    }));
+                for member in members {
+                    mentions.insert(
+                        guild_id,
+                        format!("<@{}>", member.user.id),
+                        format!("@{}", member.display_name()),
+                    );
+                }
             }
-            let user = context.http.get_user(user_id).await?;
-            let guild = context.get_guild(message).await?;
-            let member = guild.member(context, user.id).await?;
-            let nickname = format!("@{}", member.nick.unwrap_or(user.name).to_owned());
-            mentions.insert(mention, UniCase::new(nickname));
-        }
-
-        let result = re.replace_all(content.as_ref(), |caps: &regex::Captures| {
-            let user_id = caps.get(1).map(|m| m.as_str()).unwrap_or("").to_owned();
-            let user_id = user_id.parse::<u64>().unwrap_or(0);
-            let mention = format!("<@{}>", user_id);
-
-            mentions
-                .get_by_left(&mention)
-                .cloned()
-                .map(|s| s.to_string())
-                .unwrap_or(mention.clone())
-        });
+            None => {
+                // in a DM there are no nicknames, only usernames
+                let users = futures::future::join_all(
+                    unseen.iter().map(|user_id| user_id.to_user(context)),
+                )
+                .await;
+                for user in users {
+                    match user {
+                        Ok(user) => mentions.insert(
+                            guild_id,
+                            format!("<@{}>", user.id),
+                            format!("@{}", user.name),
+                        ),
+                        Err(e) => log::warn!("Failed to look up a mentioned user: {}", e),
+                    }
+                }
+            }
+        }
 
-        Ok(result.to_string())
+        Ok(mentions.decode(guild_id, content.as_ref()))
+    }
+
+    /// Remember the nicknames in a chunk of guild members, so mentions of them
+    /// don't need to be looked up later.
+    async fn prime_mentions(&self, chunk: GuildMembersChunkEvent) {
+        let mut mentions = self.mentions.lock().await;
+        for member in chunk.members.values() {
+            mentions.insert(
+                Some(chunk.guild_id.0),
+                format!("<@{}>", member.user.id),
+                format!("@{}", member.display_name()),
+            );
+        }
     }
 
-    async fn encode_user_mentions<S>(&self, content: S) -> Result<String>
+    /// The gateway's context, once connected, for work done outside of an event.
+    fn gateway_context(&self) -> Option<discord::Context> {
+        self.gateway
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Whether the bot's messages in a conversation ping the people they mention.
+    async fn pings(&self, conversation: Conversation, guild_id: Option<GuildId>) -> Result<bool> {
+        SettingsResolver::new(&self.database, conversation, guild_id.map(|g| g.0))
+            .get(&PING)
+            .await
+    }
+
+    /// Turn the model's `@nicknames` back into discord mentions, or with `ping` unset,
+    /// leave them as plain text so nobody is notified.
+    async fn encode_user_mentions<S>(
+        &self,
+        guild_id: Option<GuildId>,
+        content: S,
+        ping: bool,
+    ) -> Result<String>
     where
         S: AsRef<str>,
     {
-        let mentions = self.mentions.lock().await;
-
-        let pattern = intersperse(
-            mentions.right_values().map(|s| regex::escape(s)),
-            "|".to_owned(),
-        )
-        .collect::<String>();
-
-        let re = regex::Regex::new(&pattern)?;
-        let result = re.replace_all(content.as_ref(), |caps: &regex::Captures| {
-            let nickname_string = caps.get(0).map(|m| m.as_str()).unwrap_or("").to_owned();
-            let nickname = UniCase::new(nickname_string.clone());
-            mentions
-                .get_by_right(&nickname)
-                .cloned()
-                .unwrap_or(nickname_string)
-                .to_string()
-        });
-        Ok(result.to_string())
+        if !ping {
+            return Ok(content.as_ref().to_owned());
+        }
+        let mut mentions = self.mentions.lock().await;
+        mentions.encode(guild_id.map(|g| g.0), content.as_ref())
     }
 
     #[allow(dead_code, unused_variables)]
@@ -229,25 +1127,434 @@ impl DiscordBot {
         Ok(messages)
     }
 
+    // this is called by EventHandler::reaction_add and reaction_remove, but it can
+    // return a Result.
+    async fn reaction_hook(
+        &self,
+        context: discord::Context,
+        reaction: Reaction,
+        added: bool,
+    ) -> Result<()> {
+        let Some(user_id) = reaction.user_id else {
+            return Ok(());
+        };
+        let ReactionType::Unicode(emoji) = &reaction.emoji else {
+            return Ok(());
+        };
+        let delta = karma::reaction_sentiment(emoji);
+        if delta == 0 || user_id == context.cache.current_user_id() {
+            return Ok(());
+        }
+        if !added {
+            // only reactions that were counted are in there, so no need to look
+            let message_id = reaction.message_id.0;
+            return self
+                .database
+                .remove_reaction_karma(user_id.to_string(), message_id, emoji.clone())
+                .await;
+        }
+        // only reactions to the bot's own messages say anything about how it's treated
+        let author = match context
+            .cache
+            .message(reaction.channel_id, reaction.message_id)
+        {
+            Some(message) => message.author.id,
+            None => reaction.message(&context).await?.author.id,
+        };
+        if author != context.cache.current_user_id() {
+            return Ok(());
+        }
+        let conversation = self
+            .channel_conversation(&context, reaction.channel_id)
+            .await?;
+        let flags = Flags::new(&self.database, conversation, reaction.guild_id.map(|g| g.0));
+        if !flags.enabled(Flag::Memory).await? {
+            return Ok(());
+        }
+        self.database
+            .add_reaction_karma(
+                conversation,
+                user_id.to_string(),
+                reaction.message_id.0,
+                emoji.clone(),
+                delta,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    // this is called by EventHandler::channel_update, but it can return a Result.
+    async fn channel_update_hook(&self, context: discord::Context, channel: Channel) -> Result<()> {
+        let Channel::Guild(channel) = channel else {
+            return Ok(());
+        };
+        if ChannelKind::of_guild_channel(channel.kind, None).is_none() {
+            return Ok(());
+        }
+        let conversation = self.channel_conversation(&context, channel.id).await?;
+        let now = chrono::Local::now();
+        if !self
+            .database
+            .update_topic(conversation, channel.topic.clone(), now)
+            .await?
+        {
+            return Ok(());
+        }
+        log::info!("Topic of #{} changed to {:?}", channel.name, channel.topic);
+
+        // only comment on a new topic, not a cleared one
+        let (Some(topic), Some(template)) = (
+            channel.topic.clone(),
+            self.database.get_topic_template(conversation).await?,
+        ) else {
+            return Ok(());
+        };
+        if self.database.get_quiet(conversation).await?.is_quiet(now) {
+            return Ok(());
+        }
+        if !self.current_openai().serves(Some(channel.guild_id.0)) {
+            return Ok(());
+        }
+        if self.breaker.is_open(Instant::now()) {
+            log::info!("Not commenting on the topic while the horse is asleep");
+            return Ok(());
+        }
+
+        let instruction = ServerTemplates::load(&self.database, Some(channel.guild_id.0))
+            .await?
+            .render(
+                &template,
+                context! { topic, channel_name => channel.name.clone() },
+            )?;
+        self.remark(
+            &context,
+            channel.guild_id,
+            channel.id,
+            conversation,
+            instruction,
+        )
+        .await
+    }
+
+    /// Have the bot speak up unprompted in a server channel, following `instruction`.
+    /// The remark goes through the outbox, since it isn't a reply to anyone.
+    async fn remark(
+        &self,
+        context: &discord::Context,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        conversation: Conversation,
+        instruction: String,
+    ) -> Result<()> {
+        let guild = guild_id.to_guild_cached(context);
+        let bot_id = context.cache.current_user_id();
+        let vars = self
+            .channel_prompt_vars(context, guild.as_ref(), Some(guild_id), channel_id, bot_id)
+            .await?;
+        let nsfw_prompt = if self.channel_is_nsfw(context, channel_id).await? {
+            self.database.get_nsfw_settings(conversation).await?.prompt
+        } else {
+            None
+        };
+        let reply = chatbot::comment(
+            &self.current_openai(),
+            Some(guild_id.0),
+            &self.database,
+            conversation,
+            nsfw_prompt,
+            vars,
+            instruction,
+        )
+        .await;
+        self.record_reply(Some(guild_id.0), &reply);
+        let reply = reply?;
+        let ping = self.pings(conversation, Some(guild_id)).await?;
+        let reply = self
+            .encode_user_mentions(Some(guild_id), reply, ping)
+            .await?;
+        // comment() has already added it to the history, along with what went into it
+        for chunk in text::split_message(&reply, MAX_MESSAGE_LENGTH) {
+            self.database
+                .queue_message(channel_id.0, None, chunk)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Answer mentions (and DMs) that arrived while the bot was disconnected, oldest first.
+    async fn catch_up(&self, context: &discord::Context) -> Result<()> {
+        let bot_id = context.cache.current_user_id();
+        let channels = self
+            .database
+            .catch_up_channels(chrono::Duration::days(CATCH_UP_DAYS))
+            .await?;
+        for (channel_id, cursor) in channels {
+            let channel_id = ChannelId(channel_id);
+            // messages fetched over REST don't say which server they're in, so the
+            // channel decides what's a DM
+            let channel = match channel_id.to_channel(context).await {
+                Ok(channel) => channel,
+                Err(e) => {
+                    log::warn!("Failed to catch up on channel {}: {}", channel_id, e);
+                    continue;
+                }
+            };
+            let guild_id = match &channel {
+                Channel::Guild(c) => Some(c.guild_id),
+                _ => None,
+            };
+            let dm = matches!(channel, Channel::Private(_));
+            let messages = channel_id
+                .messages(&context, |m| {
+                    m.after(MessageId(cursor)).limit(CATCH_UP_LIMIT)
+                })
+                .await;
+            let mut messages = match messages {
+                Ok(messages) => messages,
+                Err(e) => {
+                    log::warn!("Failed to catch up on channel {}: {}", channel_id, e);
+                    continue;
+                }
+            };
+            messages.sort_by_key(|m| m.id);
+            for mut msg in messages {
+                if msg.author.bot || !(msg.mentions_user_id(bot_id) || dm) {
+                    continue;
+                }
+                msg.guild_id = guild_id;
+                log::info!("Catching up on a missed message in {}", channel_id);
+                if let Err(e) = self.message_hook(context.clone(), msg).await {
+                    log::error!("Error: {}", e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send something the model didn't write, like a notice, and add it to the history
+    /// too, so the model knows what the channel saw.
+    async fn say(
+        &self,
+        context: &discord::Context,
+        conversation: Conversation,
+        channel_id: ChannelId,
+        content: &str,
+    ) -> Result<()> {
+        channel_id.say(context, content).await?;
+        self.database.record_sent(conversation, content).await
+    }
+
+    /// Claim a message before acting on it, so one that's seen twice, as when catching
+    /// up after a reconnect, is only acted on once. Returns the channel's conversation,
+    /// or None if the message was already claimed.
+    async fn claim(
+        &self,
+        context: &discord::Context,
+        msg: &Message,
+    ) -> Result<Option<Conversation>> {
+        let conversation = self.channel_conversation(context, msg.channel_id).await?;
+        if !self.database.claim_message(conversation, msg.id.0).await? {
+            log::info!("Ignoring a message that was already handled: {}", msg.id);
+            return Ok(None);
+        }
+        Ok(Some(conversation))
+    }
+
+    /// Note that a server channel was talked in, answered or not, so idle chatter knows
+    /// what's quiet. Only people the bot would answer count, and the bot itself.
+    async fn note_activity(&self, context: &discord::Context, msg: &Message) -> Result<()> {
+        let Some(guild_id) = msg.guild_id else {
+            return Ok(());
+        };
+        let by_bot = msg.author.id == context.cache.current_user_id();
+        if !by_bot {
+            // other bots (and webhooks) aren't waiting on an answer
+            if msg.author.bot {
+                return Ok(());
+            }
+            let blocked = self
+                .database
+                .is_blocked(Some(guild_id.0), msg.author.id.0)
+                .await?;
+            if blocked {
+                return Ok(());
+            }
+        }
+        if self.ignores_channel(context, msg.channel_id).await? {
+            return Ok(());
+        }
+        self.database.channel_active(msg.channel_id.0, by_bot).await
+    }
+
+    /// Whether a channel is of a kind the bot was told to ignore.
+    async fn ignores_channel(
+        &self,
+        context: &discord::Context,
+        channel_id: ChannelId,
+    ) -> Result<bool> {
+        let channel = channel_id.to_channel(context).await?;
+        let (kind, ..) = self.channel_kind(context, &channel).await?;
+        Ok(self.ignored_channels.contains(&kind))
+    }
+
     // this is called by EventHandler::message, but it can return a Result.
     // any errors will be reported to the user.
     async fn message_hook(&self, context: discord::Context, msg: Message) -> Result<()> {
+        if self.boost_hook(&context, &msg).await? {
+            return Ok(());
+        }
+        if self.bang_hook(&context, &msg).await? {
+            return Ok(());
+        }
+
         let mentioned = msg.mentions_me(&context).await.unwrap_or(false);
         let dm = msg.is_private();
 
         if mentioned || dm {
+            let guild_id = msg.guild_id.map(|g| g.0);
+            if self.database.is_blocked(guild_id, msg.author.id.0).await? {
+                log::info!("Ignoring message from blocked user {}", msg.author.id);
+                return Ok(());
+            }
+            if !self.current_openai().serves(guild_id) {
+                log::info!("Ignoring message from a server without its own OpenAI key");
+                return Ok(());
+            }
+            let channel = msg.channel_id.to_channel(&context).await?;
+            let (kind, ..) = self.channel_kind(&context, &channel).await?;
+            if self.ignored_channels.contains(&kind) {
+                log::info!("Ignoring message in an ignored kind of channel: {:?}", kind);
+                return Ok(());
+            }
+
+            let Some(conversation) = self.claim(&context, &msg).await? else {
+                return Ok(());
+            };
+            let privacy = if dm {
+                let privacy = self.database.get_privacy(conversation).await?;
+                if privacy.is_none() {
+                    self.say(&context, conversation, msg.channel_id, PRIVACY_NOTICE)
+                        .await?;
+                    self.database
+                        .set_privacy(conversation, PrivacyMode::default())
+                        .await?;
+                }
+                privacy.unwrap_or_default()
+            } else {
+                PrivacyMode::Full
+            };
+            let quiet = self.database.get_quiet(conversation).await?;
+            if quiet.is_quiet(chrono::Local::now()) {
+                log::info!("Ignoring message in a muted conversation");
+                return Ok(());
+            }
+
+            let verdict = self.loops.lock().await.check(
+                msg.channel_id.0,
+                &msg.content,
+                msg.webhook_id.is_some(),
+            );
+            match verdict {
+                Verdict::Reply => {}
+                Verdict::Refuse => {
+                    log::info!("Refusing to keep talking in a loop");
+                    self.say(&context, conversation, msg.channel_id, LOOP_REFUSAL)
+                        .await?;
+                    return Ok(());
+                }
+                Verdict::Ignore => return Ok(()),
+            }
+
+            if self.breaker.is_open(Instant::now()) {
+                self.say(&context, conversation, msg.channel_id, breaker::ASLEEP)
+                    .await?;
+                return Ok(());
+            }
+
             if let Ok(typing) = msg.channel_id.start_typing(&context.http) {
-                let reply = chatbot::reply(self, &context, &msg).await?;
+                let reply = chatbot::reply(self, &context, &msg).await;
+                self.record_reply(msg.guild_id.map(|g| g.0), &reply);
+                // the reply only saw it redacted, but it's still sitting in the channel
+                if leaks::has_secret(&msg.content) {
+                    self.say(&context, conversation, msg.channel_id, LEAK_WARNING)
+                        .await?;
+                }
+                let Some(reply) = reply? else {
+                    log::info!("Not replying to chatter");
+                    let _ = typing.stop();
+                    // it's in the history all the same
+                    return self.forget(conversation, privacy).await;
+                };
+                let settings = SettingsResolver::new(&self.database, conversation, guild_id);
+                let ping = settings.get(&PING).await?;
+                let as_reply = settings.get(&REPLY).await?;
+                let paced = settings.get(&PACING).await?;
                 let reply = self
-                    .encode_user_mentions(reply)
+                    .encode_user_mentions(msg.guild_id, reply, ping)
                     .await
                     .wrap_err("encode_user_mentions")?;
                 log::info!("HorseNPC: {}", reply);
+                self.loops
+                    .lock()
+                    .await
+                    .record_reply(msg.channel_id.0, &reply);
                 let _ = typing.stop();
-                match msg.channel_id.say(&context, reply).await {
-                    Ok(_) => log::info!("Sent horse"),
-                    Err(e) => log::error!("Failed to send horse: {}", e),
+                let messages = if paced {
+                    text::pace(&reply)
+                } else {
+                    vec![reply.clone()]
+                };
+                let mut first = true;
+                for (i, message) in messages.iter().enumerate() {
+                    if i > 0 {
+                        // type the next one out, like someone who hit enter early
+                        let typing = msg.channel_id.start_typing(&context.http);
+                        tokio::time::sleep(typing_delay(message)).await;
+                        if let Ok(typing) = typing {
+                            let _ = typing.stop();
+                        }
+                    }
+                    for chunk in text::split_message(message, MAX_MESSAGE_LENGTH) {
+                        let sent = msg
+                            .channel_id
+                            .send_message(&context, |m| {
+                                // only the first part points back at the question
+                                if as_reply && first {
+                                    m.reference_message(&msg);
+                                }
+                                m.content(chunk)
+                                    .allowed_mentions(|a| mentions::allowed(ping, a))
+                            })
+                            .await;
+                        first = false;
+                        match sent {
+                            Ok(_) => log::info!("Sent horse"),
+                            Err(e) => log::error!("Failed to send horse: {}", e),
+                        }
+                    }
                 }
+
+                self.forget(conversation, privacy).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Keep only as much of a DM as its privacy mode allows, once it's been answered.
+    async fn forget(&self, conversation: Conversation, privacy: PrivacyMode) -> Result<()> {
+        match privacy {
+            PrivacyMode::Full => {}
+            PrivacyMode::Summary => {
+                chatbot::summarize(&self.current_openai(), &self.database, conversation).await?;
+                self.vectors.remove_conversation(conversation).await?;
+            }
+            PrivacyMode::Off => {
+                self.database.replace_history(conversation, vec![]).await?;
+                self.vectors.remove_conversation(conversation).await?;
             }
         }
 
@@ -258,7 +1565,12 @@ impl DiscordBot {
 #[serenity::async_trait]
 impl discord::EventHandler for DiscordBot {
     async fn message(&self, context: discord::Context, msg: Message) {
-        if msg.author.bot {
+        if let Err(e) = self.note_activity(&context, &msg).await {
+            log::error!("Failed to note channel activity: {}", e);
+        }
+
+        // webhooks look like bots, but are left to the loop guard
+        if msg.author.bot && msg.webhook_id.is_none() {
             return;
         }
 
@@ -267,47 +1579,828 @@ impl discord::EventHandler for DiscordBot {
         }
     }
 
-    async fn ready(&self, _: discord::Context, ready: Ready) {
+    async fn guild_member_addition(&self, context: discord::Context, member: Member) {
+        let Member {
+            guild_id,
+            user,
+            nick,
+            ..
+        } = member;
+        let hooked = self
+            .member_hook(context, guild_id, user, nick, MemberEvent::Joined)
+            .await;
+        if let Err(e) = hooked {
+            log::error!("Error: {}", e);
+        }
+    }
+
+    async fn guild_member_removal(
+        &self,
+        context: discord::Context,
+        guild_id: GuildId,
+        user: User,
+        member: Option<Member>,
+    ) {
+        let nick = member.and_then(|m| m.nick);
+        let hooked = self
+            .member_hook(context, guild_id, user, nick, MemberEvent::Left)
+            .await;
+        if let Err(e) = hooked {
+            log::error!("Error: {}", e);
+        }
+    }
+
+    async fn guild_members_chunk(&self, _context: discord::Context, chunk: GuildMembersChunkEvent) {
+        self.prime_mentions(chunk).await;
+    }
+
+    async fn channel_update(&self, context: discord::Context, _old: Option<Channel>, new: Channel) {
+        if let Err(e) = self.channel_update_hook(context, new).await {
+            log::error!("Error: {}", e);
+        }
+    }
+
+    async fn reaction_add(&self, context: discord::Context, reaction: Reaction) {
+        if let Err(e) = self.reaction_hook(context, reaction, true).await {
+            log::error!("Error: {}", e);
+        }
+    }
+
+    async fn reaction_remove(&self, context: discord::Context, reaction: Reaction) {
+        if let Err(e) = self.reaction_hook(context, reaction, false).await {
+            log::error!("Error: {}", e);
+        }
+    }
+
+    async fn interaction_create(&self, context: discord::Context, interaction: Interaction) {
+        if let Err(e) = self.interaction_hook(context, interaction).await {
+            log::error!("Error: {}", e);
+        }
+    }
+
+    async fn ready(&self, context: discord::Context, ready: Ready) {
         log::info!("{} is connected!", ready.user.name);
+        *self.gateway.lock().unwrap_or_else(|e| e.into_inner()) = Some(context.clone());
+        service::notify_ready();
+        if !self.outbox_started.swap(true, Ordering::SeqCst) {
+            outbox::spawn(self.database.clone(), context.http.clone());
+        }
+        self.connected.notify_one();
+        if self.presence_started.swap(true, Ordering::SeqCst) {
+            // a reconnect, which clears the presence
+            self.presence.update(&context, false).await;
+        } else {
+            self.presence.clone().spawn(context.clone());
+        }
+
+        if let Err(e) = SlashCommand::set_global_application_commands(&context.http, |commands| {
+            commands::create_commands(commands)
+        })
+        .await
+        {
+            log::error!("Failed to register commands: {}", e);
+        }
     }
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() -> Result<()> {
     dotenv::dotenv().ok();
-    env_logger::init();
-
     let args: Args = Args::parse();
+
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(path) = &args.log_file {
+        let file = service::RotatingFile::open(path, args.log_max_size * 1024 * 1024)?;
+        logger.target(env_logger::Target::Pipe(Box::new(file)));
+    }
+    logger.init();
+
     match args.command {
         Command::Run => run(args).await,
         Command::Test => test(args).await,
+        Command::Check => check::check(args).await,
+        Command::Smoke => smoke::smoke(args).await,
+        Command::Bench { messages } => bench::bench(args, messages).await,
+        Command::Script {
+            ref conversation,
+            ref file,
+        } => script(&args, conversation, file.as_ref()).await,
+        Command::Transforms {
+            ref conversation,
+            ref file,
+        } => set_transforms(&args, conversation, file.as_ref()).await,
+        Command::Deflections {
+            ref conversation,
+            ref file,
+        } => set_deflections(&args, conversation, file.as_ref()).await,
+        Command::Nsfw {
+            ref conversation,
+            ref prompt,
+            moderation,
+        } => set_nsfw(&args, conversation, prompt.as_ref(), moderation).await,
+        Command::Conversations { ref command } => match command {
+            ConversationsCommand::List { json } => conversations::list(&args, *json).await,
+            ConversationsCommand::Show { conversation, json } => {
+                conversations::show(&args, conversation, *json).await
+            }
+        },
+        Command::Schedule { ref command } => schedules::run(&args, command).await,
+        Command::Preset { ref command } => presets::run(&args, command).await,
+        Command::Persona { ref command } => persona::run(&args, command).await,
+        Command::Merge { ref src, ref dst } => merge(&args, src, dst).await,
+        Command::Rename {
+            ref conversation,
+            ref name,
+        } => rename(&args, conversation, name).await,
+        Command::TopicTemplate {
+            ref conversation,
+            ref file,
+        } => topic_template(&args, conversation, file.as_ref()).await,
+        Command::Template {
+            guild,
+            ref name,
+            ref file,
+        } => template(&args, guild, name, file.as_ref()).await,
+        Command::Setting {
+            ref conversation,
+            guild,
+            ref key,
+            ref value,
+        } => setting(&args, conversation.as_deref(), guild, key, value.clone()).await,
+        Command::Flag {
+            ref conversation,
+            guild,
+            flag,
+            ref state,
+        } => set_flag(&args, conversation.as_deref(), guild, flag, state).await,
+        Command::Describe { ref conversation } => describe(&args, conversation.clone()).await,
+        Command::Prune {
+            ref older_than,
+            ref conversation,
+            dry_run,
+        } => prune(&args, older_than, conversation.as_deref(), dry_run).await,
+        Command::MigrateMessages => migrate_messages(&args).await,
+        Command::Reindex { ref model, batch } => reindex(&args, model.clone(), batch).await,
+        Command::Search {
+            ref conversation,
+            ref query,
+            limit,
+        } => search(&args, conversation, query, limit).await,
+        Command::Stats { ref conversation } => stats(&args, conversation.as_deref()).await,
+        Command::Reports { ref conversation } => reports(&args, conversation.as_deref()).await,
+        Command::Audit {
+            ref conversation,
+            limit,
+            redact,
+        } => audit(&args, conversation, limit, redact).await,
+        Command::Shadow {
+            ref conversation,
+            limit,
+        } => shadow_log(&args, conversation, limit).await,
+        Command::History {
+            ref conversation,
+            limit,
+        } => history(&args, conversation, limit).await,
+        Command::Redact { message_id } => redact(&args, message_id).await,
+        Command::Tokens { ref conversation } => tokens(&args, conversation).await,
+        Command::RotateSecrets => rotate_secrets(&args).await,
+    }
+}
+
+async fn rotate_secrets(args: &Args) -> Result<()> {
+    let secrets = Secrets::from_env()?.ok_or_else(|| eyre::eyre!("SECRETS_KEY is not set"))?;
+    let database = Database::new(args.database_path()?).await?;
+    let mut rotated = 0;
+    for (guild, sealed) in database.guild_keys().await? {
+        if secrets.is_stale(&sealed)? {
+            let resealed = secrets.seal(&secrets.open(&sealed)?)?;
+            database.set_guild_key(guild, Some(resealed)).await?;
+            rotated += 1;
+        }
+    }
+    println!("Sealed {rotated} secrets with the new key");
+
+    Ok(())
+}
+
+async fn script(args: &Args, conversation: &str, file: Option<&PathBuf>) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let conversation = database.find_conversation(conversation).await?;
+    let source = file.map(std::fs::read_to_string).transpose()?;
+    if let Some(source) = &source {
+        // fail early rather than on the next message
+        scripting::Hooks::compile(source)?;
+    }
+    database.set_script(conversation, source).await
+}
+
+async fn merge(args: &Args, src: &str, dst: &str) -> Result<()> {
+    let database = Arc::new(Database::new(args.database_path()?).await?);
+    let store = vectors::open(
+        database.clone(),
+        args.qdrant.as_deref(),
+        &args.qdrant_collection,
+    )
+    .await?;
+    let src = database.find_conversation(src).await?;
+    let dst = database.find_conversation(dst).await?;
+    database.merge_conversations(src, dst).await?;
+    store.reassign(src, dst).await
+}
+
+async fn rename(args: &Args, conversation: &str, name: &str) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let conversation = database.find_conversation(conversation).await?;
+    database.rename_conversation(conversation, name).await
+}
+
+async fn topic_template(args: &Args, conversation: &str, file: Option<&PathBuf>) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let conversation = database.find_conversation(conversation).await?;
+    let template = file.map(std::fs::read_to_string).transpose()?;
+    database.set_topic_template(conversation, template).await
+}
+
+async fn template(args: &Args, guild: u64, name: &str, file: Option<&PathBuf>) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let source = file.map(std::fs::read_to_string).transpose()?;
+    if let Some(source) = &source {
+        // fail early rather than on the next message
+        minijinja::Environment::new().template_from_str(source)?;
+    }
+    database.set_template(guild, name, source).await
+}
+
+async fn setting(
+    args: &Args,
+    conversation: Option<&str>,
+    guild: Option<u64>,
+    key: &str,
+    value: Option<String>,
+) -> Result<()> {
+    if let Some(value) = &value {
+        schema::check_setting(key, value)?;
+    }
+    let database = Database::new(args.database_path()?).await?;
+    let scope = match (conversation, guild) {
+        (Some(conversation), _) => {
+            SettingScope::Conversation(database.find_conversation(conversation).await?)
+        }
+        (None, Some(guild)) => SettingScope::Guild(guild),
+        (None, None) => SettingScope::Global,
+    };
+    schema::check_setting_scope(key, scope)?;
+    database.set_setting(scope, key, value).await
+}
+
+async fn set_flag(
+    args: &Args,
+    conversation: Option<&str>,
+    guild: Option<u64>,
+    flag: Flag,
+    state: &str,
+) -> Result<()> {
+    let enabled = schema::parse_flag_state(state)?;
+    let database = Database::new(args.database_path()?).await?;
+    let scope = match (conversation, guild) {
+        (Some(conversation), _) => {
+            SettingScope::Conversation(database.find_conversation(conversation).await?)
+        }
+        (None, Some(guild)) => SettingScope::Guild(guild),
+        (None, None) => SettingScope::Global,
+    };
+    database.set_flag(scope, flag, enabled).await
+}
+
+async fn describe(args: &Args, conversation: Option<String>) -> Result<()> {
+    let timeout = Duration::from_secs(args.openai_timeout);
+    let openai = openai_keys(args.key_assignment, timeout)?;
+    let database = Database::new(args.database_path()?).await?;
+    let mut failed = 0;
+    for info in database.conversation_infos(conversation).await? {
+        if info.messages == 0 {
+            continue;
+        }
+        let description =
+            match chatbot::describe(&openai, &database, info.conversation, info.guild_id).await {
+                Ok(description) => description,
+                Err(e) => {
+                    eprintln!("{}: failed to describe it: {}", info.name, e);
+                    failed += 1;
+                    continue;
+                }
+            };
+        println!("{}: {description}", info.name);
+        database
+            .set_description(info.conversation, description)
+            .await?;
+    }
+
+    if failed > 0 {
+        return Err(eyre::eyre!("failed to describe {failed} conversations"));
+    }
+    Ok(())
+}
+
+async fn prune(
+    args: &Args,
+    older_than: &str,
+    conversation: Option<&str>,
+    dry_run: bool,
+) -> Result<()> {
+    let age = helpers::parse_duration(older_than)?;
+    let database = Arc::new(Database::new(args.database_path()?).await?);
+    let store = vectors::open(
+        database.clone(),
+        args.qdrant.as_deref(),
+        &args.qdrant_collection,
+    )
+    .await?;
+    let conversation = match conversation {
+        Some(name) => Some(
+            database
+                .lookup_conversation(name)
+                .await?
+                .ok_or_else(|| eyre::eyre!("no conversation named {name}"))?,
+        ),
+        None => None,
+    };
+
+    let old = database.old_history(age, conversation).await?;
+    for (name, count) in &old {
+        println!("{name}: {count} messages older than {older_than}");
+    }
+    let total = old.iter().map(|(_, count)| count).sum::<usize>();
+    if dry_run {
+        println!("Would delete {total} messages");
+    } else {
+        let deleted = database.prune_history(age, conversation).await?;
+        store.remove(&deleted).await?;
+        println!("Deleted {} messages", deleted.len());
+    }
+
+    Ok(())
+}
+
+async fn migrate_messages(args: &Args) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let migrated = database.migrate_messages().await?;
+    println!("Rewrote {migrated} messages in the current format");
+    Ok(())
+}
+
+async fn reindex(args: &Args, model: Option<String>, batch: usize) -> Result<()> {
+    let timeout = Duration::from_secs(args.openai_timeout);
+    let openai = openai_keys(args.key_assignment, timeout)?;
+    let database = Arc::new(Database::new(args.database_path()?).await?);
+    let store = vectors::open(
+        database.clone(),
+        args.qdrant.as_deref(),
+        &args.qdrant_collection,
+    )
+    .await?;
+    let settings = SettingsResolver::global(&database);
+    let model = match model {
+        Some(model) => model,
+        None => settings.get(&EMBEDDING_MODEL).await?,
+    };
+    let batch = batch.max(1);
+
+    let faqs = database.unembedded_faqs(model.clone()).await?;
+    let mut done = 0;
+    for chunk in faqs.chunks(batch) {
+        let questions = chunk.iter().map(|(_, q)| q.clone()).collect();
+        let vectors = embeddings::embed(&openai, None, &model, questions).await?;
+        for ((id, _), vector) in chunk.iter().zip(vectors) {
+            database
+                .set_faq_embedding(*id, &vector, model.clone())
+                .await?;
+        }
+        done += chunk.len();
+        println!("FAQ: {done}/{}", faqs.len());
+    }
+
+    // each batch is saved as it goes, so a rerun skips what's done
+    let total = database.count_unembedded_history(model.clone()).await?;
+    let (mut after, mut scanned, mut embedded) = (0, 0, 0);
+    loop {
+        let next = database
+            .unembedded_history(after, model.clone(), batch)
+            .await?;
+        let Some(last_id) = next.last_id else {
+            break;
+        };
+        scanned += next.scanned;
+        embedded += next.messages.len();
+        if !next.messages.is_empty() {
+            let texts = next.messages.iter().map(|(_, _, t)| t.clone()).collect();
+            let embedded = embeddings::embed(&openai, None, &model, texts).await?;
+            let entries: Vec<_> = next
+                .messages
+                .into_iter()
+                .zip(embedded)
+                .map(|((history_id, conversation, _), embedding)| Entry {
+                    history_id,
+                    conversation,
+                    embedding,
+                })
+                .collect();
+            database
+                .set_message_embeddings(
+                    model.clone(),
+                    entries
+                        .iter()
+                        .map(|e| (e.history_id, e.embedding.clone()))
+                        .collect(),
+                )
+                .await?;
+            store.index(&model, &entries).await?;
+        }
+        after = last_id;
+        println!("History: {scanned}/{total} messages, {embedded} embedded");
+    }
+    let quantized = database.quantize_missing_embeddings().await?;
+    if quantized > 0 {
+        println!("Quantized {quantized} embeddings saved before quantizing for search");
+    }
+    println!("Embeddings are up to date with {model}");
+
+    Ok(())
+}
+
+async fn search(args: &Args, conversation: &str, query: &str, limit: usize) -> Result<()> {
+    let timeout = Duration::from_secs(args.openai_timeout);
+    let openai = openai_keys(args.key_assignment, timeout)?;
+    let database = Arc::new(Database::new(args.database_path()?).await?);
+    let store = vectors::open(
+        database.clone(),
+        args.qdrant.as_deref(),
+        &args.qdrant_collection,
+    )
+    .await?;
+    let conversation = database
+        .lookup_conversation(conversation)
+        .await?
+        .ok_or_else(|| eyre::eyre!("no conversation named {conversation}"))?;
+    let model = SettingsResolver::global(&database)
+        .get(&EMBEDDING_MODEL)
+        .await?;
+    let query = embeddings::embed_one(&openai, None, &model, query).await?;
+
+    let hits = store.search(conversation, &model, &query, limit).await?;
+    let ids = hits.iter().map(|h| h.history_id).collect();
+    // a store other than the database may still have deleted or redacted messages
+    let messages: HashMap<i64, String> =
+        database.embedded_messages(ids).await?.into_iter().collect();
+    for hit in hits {
+        if let Some(content) = messages.get(&hit.history_id) {
+            println!("{} ({:.3}): {content}", hit.history_id, hit.score);
+        }
+    }
+
+    Ok(())
+}
+
+async fn stats(args: &Args, conversation: Option<&str>) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let conversation = match conversation {
+        Some(name) => Some(
+            database
+                .lookup_conversation(name)
+                .await?
+                .ok_or_else(|| eyre::eyre!("no conversation named {name}"))?,
+        ),
+        None => None,
+    };
+    let stats = database.message_stats(conversation).await?;
+    let total = stats.positive + stats.neutral + stats.negative;
+    println!("{total} messages tagged");
+    let intents = stats
+        .intents
+        .iter()
+        .map(|(intent, n)| format!("{} {n}", intent.as_deref().unwrap_or("unsorted")))
+        .join(", ");
+    println!("Needed: {intents}");
+    println!(
+        "Sentiment: {} friendly, {} neutral, {} rude",
+        stats.positive, stats.neutral, stats.negative
+    );
+    Ok(())
+}
+
+async fn reports(args: &Args, conversation: Option<&str>) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let conversation = match conversation {
+        Some(name) => Some(
+            database
+                .lookup_conversation(name)
+                .await?
+                .ok_or_else(|| eyre::eyre!("no conversation named {name}"))?,
+        ),
+        None => None,
+    };
+    for report in database.reports(conversation).await? {
+        println!(
+            "{} {}: <@{}> reported message {} in channel {}: {}\n{}\n",
+            report.created_at,
+            report.conversation,
+            report.reporter_id,
+            report.message_id,
+            report.channel_id,
+            report.reason,
+            report.content
+        );
+    }
+    Ok(())
+}
+
+async fn redact(args: &Args, id: i64) -> Result<()> {
+    let database = Arc::new(Database::new(args.database_path()?).await?);
+    let store = vectors::open(
+        database.clone(),
+        args.qdrant.as_deref(),
+        &args.qdrant_collection,
+    )
+    .await?;
+    if !database.redact_message(None, id).await? {
+        return Err(eyre::eyre!("no message {id}"));
+    }
+    store.remove(&[id]).await?;
+    println!("Redacted message {id}");
+    Ok(())
+}
+
+async fn audit(args: &Args, conversation: &str, limit: usize, redact: bool) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let conversation = database
+        .lookup_conversation(conversation)
+        .await?
+        .ok_or_else(|| eyre::eyre!("no conversation named {conversation}"))?;
+    if redact {
+        let deleted = database.redact_audit_log(conversation).await?;
+        println!("Deleted {deleted} audit log entries");
+        return Ok(());
+    }
+    for entry in database.audit_entries(conversation, limit).await? {
+        println!(
+            "{}\nrequest: {}\nresponse: {}\n",
+            entry.created_at, entry.request, entry.response
+        );
     }
+    Ok(())
+}
+
+async fn shadow_log(args: &Args, conversation: &str, limit: usize) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let conversation = database
+        .lookup_conversation(conversation)
+        .await?
+        .ok_or_else(|| eyre::eyre!("no conversation named {conversation}"))?;
+    for entry in database.shadow_entries(conversation, limit).await? {
+        println!("{} {}\n{}\n", entry.created_at, entry.stage, entry.output);
+    }
+    Ok(())
+}
+
+async fn history(args: &Args, conversation: &str, limit: usize) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let conversation = database
+        .lookup_conversation(conversation)
+        .await?
+        .ok_or_else(|| eyre::eyre!("no conversation named {conversation}"))?;
+    let transcript = database.transcript(conversation).await?;
+    let latest = &transcript[transcript.len().saturating_sub(limit)..];
+    for entry in latest {
+        let who = match entry.message.role() {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "horse",
+            Role::Function => "function",
+        };
+        let when = entry.created_at.as_deref().unwrap_or("-");
+        println!("{} {when} {who}: {}", entry.id, entry.message.content());
+    }
+    Ok(())
+}
+
+async fn tokens(args: &Args, conversation: &str) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let tools = load_tools(args.plugins.as_deref())?;
+    let conversation = database.find_conversation(conversation).await?;
+    let report = chatbot::token_report(&database, &tools, conversation, None).await?;
+    println!("{report}");
+    Ok(())
+}
+
+async fn set_transforms(args: &Args, conversation: &str, file: Option<&PathBuf>) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let conversation = database.find_conversation(conversation).await?;
+    let transforms: Vec<transforms::Transform> = match file {
+        Some(file) => serde_json::from_str(&std::fs::read_to_string(file)?)?,
+        None => vec![],
+    };
+    database.set_transforms(conversation, &transforms).await
 }
 
-async fn run(_args: Args) -> Result<()> {
+async fn set_deflections(args: &Args, conversation: &str, file: Option<&PathBuf>) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let conversation = database.find_conversation(conversation).await?;
+    let deflections: Vec<String> = match file {
+        Some(file) => std::fs::read_to_string(file)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(str::to_owned)
+            .collect(),
+        None => vec![],
+    };
+    for deflection in &deflections {
+        minijinja::Environment::new().template_from_str(deflection)?;
+    }
+    database.set_deflections(conversation, &deflections).await
+}
+
+async fn set_nsfw(
+    args: &Args,
+    conversation: &str,
+    prompt: Option<&PathBuf>,
+    moderation: ModerationPolicy,
+) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    let conversation = database.find_conversation(conversation).await?;
+    let prompt = prompt.map(std::fs::read_to_string).transpose()?;
+    database
+        .set_nsfw_settings(conversation, NsfwSettings { prompt, moderation })
+        .await
+}
+
+async fn run(args: Args) -> Result<()> {
     log::info!("Starting up...");
-    let bot = DiscordBot::new(None).await?;
+    let _pid_file = args
+        .pid_file
+        .as_deref()
+        .map(service::PidFile::create)
+        .transpose()?;
+    let timeout = Duration::from_secs(args.openai_timeout);
+    let mut presence = Presence::new(
+        args.activity,
+        args.status,
+        Duration::from_secs(args.activity_minutes.max(1) * 60),
+    );
+    if let Some(path) = &args.activities {
+        presence = presence.with_rotation(path)?;
+    }
+    let bot = DiscordBot::new(
+        args.database_path()?,
+        args.plugins,
+        timeout,
+        args.key_assignment,
+        args.guild_keys_required,
+        args.ignore_channels,
+        presence,
+    )
+    .await?;
+    let vectors = vectors::open(
+        bot.database.clone(),
+        args.qdrant.as_deref(),
+        &args.qdrant_collection,
+    )
+    .await?;
+    let bot = Arc::new(bot.with_vectors(vectors));
+    let token = get_discord_token()?;
+    preflight(&bot, &token).await?;
+    #[cfg(unix)]
+    reload_on_hangup(bot.clone())?;
+    spawn_catch_up(bot.clone());
+    birthdays::spawn(bot.clone());
+    idle::spawn(bot.clone());
+    celebrations::spawn(bot.clone());
+    if let Some(addr) = args.http {
+        let token = std::env::var("HTTP_TOKEN")
+            .ok()
+            .filter(|t| !t.trim().is_empty())
+            .ok_or_else(|| eyre::eyre!("--http needs HTTP_TOKEN to be set"))?;
+        let database = bot.database.clone();
+        tokio::spawn(async move {
+            if let Err(e) = web::serve(addr, database, token).await {
+                log::error!("HTTP server stopped: {:?}", e);
+            }
+        });
+    }
 
-    let intents = discord::GatewayIntents::GUILD_MESSAGES
+    let mut intents = discord::GatewayIntents::GUILD_MESSAGES
         | discord::GatewayIntents::DIRECT_MESSAGES
         | discord::GatewayIntents::MESSAGE_CONTENT
-        | discord::GatewayIntents::GUILDS;
+        | discord::GatewayIntents::GUILDS
+        | discord::GatewayIntents::GUILD_MESSAGE_REACTIONS
+        | discord::GatewayIntents::DIRECT_MESSAGE_REACTIONS;
+    if args.member_events {
+        intents |= discord::GatewayIntents::GUILD_MEMBERS;
+    }
 
-    let mut client = discord::Client::builder(&get_discord_token()?, intents)
-        .event_handler(bot)
+    let mut client = discord::Client::builder(&token, intents)
+        .event_handler_arc(bot)
         .await?;
 
     log::info!("Starting client...");
 
     client.cache_and_http.cache.set_max_messages(2000);
+    service::spawn_watchdog();
     client.start().await?;
 
     Ok(())
 }
 
+/// Answer what was missed after each connection, without holding up the ready event
+/// while the replies are written.
+fn spawn_catch_up(bot: Arc<DiscordBot>) {
+    tokio::spawn(async move {
+        loop {
+            bot.connected.notified().await;
+            let Some(context) = bot.gateway_context() else {
+                continue;
+            };
+            if let Err(e) = bot.catch_up(&context).await {
+                log::error!("Failed to catch up on missed messages: {}", e);
+            }
+        }
+    });
+}
+
+/// Check the OpenAI key, the models conversations use and the Discord token before
+/// connecting, so a misconfigured bot fails at startup instead of on the first message.
+async fn preflight(bot: &DiscordBot, token: &str) -> Result<()> {
+    let models_in_use = bot.database.models_in_use().await?;
+    for (n, client) in bot.current_openai().clients().enumerate() {
+        let key = n + 1;
+        let models = client.models().list().await.wrap_err_with(|| {
+            format!("OpenAI rejected key {key} in OPENAI_KEY, check that it is a valid API key")
+        })?;
+        for model in &models_in_use {
+            if !models.data.iter().any(|m| &m.id == model) {
+                return Err(eyre::eyre!(
+                    "a conversation uses the model {model}, which key {key} in OPENAI_KEY can't use"
+                ));
+            }
+        }
+    }
+
+    serenity::utils::validate_token(token)
+        .map_err(|_| eyre::eyre!("DISCORD_TOKEN doesn't look like a bot token"))?;
+    let app = serenity::http::Http::new(token)
+        .get_current_application_info()
+        .await
+        .wrap_err("Discord rejected DISCORD_TOKEN, check that it is the bot's token")?;
+    log::info!("Pre-flight checks passed for {} ({})", app.name, app.id);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn reload_on_hangup(bot: Arc<DiscordBot>) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            log::info!("Got SIGHUP, reloading");
+            if let Err(e) = bot.reload().await {
+                log::error!("Failed to reload: {:?}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn load_tools(plugins: Option<&Path>) -> Result<ToolRegistry> {
+    let mut tools = ToolRegistry::new();
+    tools::games::register(&mut tools)?;
+    tools::combat::register(&mut tools)?;
+    tools::sheets::register(&mut tools)?;
+    tools::economy::register(&mut tools)?;
+    tools::quests::register(&mut tools)?;
+    tools::birthdays::register(&mut tools)?;
+    tools::relationships::register(&mut tools)?;
+    #[cfg(feature = "run-code")]
+    tools::run_code::register(&mut tools)?;
+    if let Some(plugins) = plugins {
+        #[cfg(feature = "wasm")]
+        tools.load_plugins(plugins)?;
+        #[cfg(not(feature = "wasm"))]
+        log::warn!(
+            "Ignoring plugins in {}: built without the wasm feature",
+            plugins.display()
+        );
+    }
+
+    Ok(tools)
+}
+
+#[derive(Clone)]
 struct TestBot {
-    openai: Arc<async_openai::Client<OpenAIConfig>>,
+    openai: Arc<KeyPool>,
     database: Arc<Database>,
+    tools: Arc<ToolRegistry>,
 }
 
 #[async_trait]
@@ -315,7 +2408,7 @@ impl ChatBot for TestBot {
     type Message = String;
     type Context = ();
 
-    fn openai(&self) -> Arc<async_openai::Client<OpenAIConfig>> {
+    fn openai(&self) -> Arc<KeyPool> {
         self.openai.clone()
     }
 
@@ -323,6 +2416,10 @@ impl ChatBot for TestBot {
         self.database.clone()
     }
 
+    fn tools(&self) -> Arc<ToolRegistry> {
+        self.tools.clone()
+    }
+
     async fn message_content(
         &self,
         _context: &Self::Context,
@@ -331,6 +2428,17 @@ impl ChatBot for TestBot {
         Ok(message.to_owned())
     }
 
+    async fn speaker(&self, _context: &Self::Context, _message: &Self::Message) -> Result<Speaker> {
+        Ok(Speaker {
+            id: "test".to_owned(),
+            name: "dylan".to_owned(),
+        })
+    }
+
+    async fn bot_name(&self, _context: &Self::Context, _message: &Self::Message) -> Result<String> {
+        Ok("HorseNPC".to_owned())
+    }
+
     async fn conversation(
         &self,
         _context: &Self::Context,
@@ -351,25 +2459,46 @@ impl ChatBot for TestBot {
             server_name => "Test Server",
             channel_name => "#test",
             channel_topic => "This is a test channel",
+            channel_nsfw => false,
         })
     }
 }
 
-async fn test(_args: Args) -> Result<()> {
-    let config = OpenAIConfig::new().with_api_key(get_openai_key()?);
-    let openai = Arc::new(async_openai::Client::with_config(config));
+async fn test(args: Args) -> Result<()> {
+    let timeout = Duration::from_secs(args.openai_timeout);
+    let openai = Arc::new(openai_keys(args.key_assignment, timeout)?);
     let database = Arc::new(Database::new(None).await?);
-    let bot = TestBot { openai, database };
+    let tools = Arc::new(load_tools(args.plugins.as_deref())?);
+    let bot = TestBot {
+        openai,
+        database,
+        tools,
+    };
     let message = "Hello, world!".to_owned();
-    let reply = chatbot::reply(bot, &(), &message).await?;
-    println!("{}", reply);
+    if let Some(reply) = chatbot::reply(bot, &(), &message).await? {
+        println!("{}", reply);
+    }
 
     Ok(())
 }
 
-fn get_openai_key() -> Result<String> {
-    let key = std::env::var("OPENAI_KEY")?;
-    Ok(key)
+/// The keys from OPENAI_KEY, whose requests give up after `timeout` instead of hanging.
+fn openai_keys(assignment: KeyAssignment, timeout: Duration) -> Result<KeyPool> {
+    KeyPool::new(get_openai_keys()?, assignment, timeout)
+}
+
+/// OPENAI_KEY may hold several comma separated keys.
+fn get_openai_keys() -> Result<Vec<String>> {
+    let keys = std::env::var("OPENAI_KEY")?;
+    Ok(parse_openai_keys(&keys))
+}
+
+fn parse_openai_keys(keys: &str) -> Vec<String> {
+    keys.split(',')
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .map(str::to_owned)
+        .collect()
 }
 
 fn get_discord_token() -> Result<String> {
@@ -0,0 +1,109 @@
+//! Welcomes and farewells, in the channel each server's welcome_channel setting names,
+//! when members join or leave (with --member-events).
+
+use crate::{
+    schema::{Message, Role, WELCOME_CHANNEL},
+    templates::ServerTemplates,
+    DiscordBot,
+};
+use eyre::Result;
+use minijinja::context;
+use serenity::{
+    model::{id::GuildId, user::User},
+    prelude as discord,
+};
+use std::time::Instant;
+
+/// The server templates the bot's instructions come from, rendered with `user_nick`
+/// and `server_name`. Without one, the event is only noted in the history.
+const WELCOME_TEMPLATE: &str = "welcome";
+const FAREWELL_TEMPLATE: &str = "farewell";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberEvent {
+    Joined,
+    Left,
+}
+
+impl MemberEvent {
+    fn template(self) -> &'static str {
+        match self {
+            MemberEvent::Joined => WELCOME_TEMPLATE,
+            MemberEvent::Left => FAREWELL_TEMPLATE,
+        }
+    }
+
+    /// What goes in the history, so the bot knows what happened when it's asked later.
+    fn note(self, user_nick: &str) -> String {
+        match self {
+            MemberEvent::Joined => format!("{user_nick} joined the server."),
+            MemberEvent::Left => format!("{user_nick} left the server."),
+        }
+    }
+}
+
+impl DiscordBot {
+    // this is called by EventHandler::guild_member_addition and guild_member_removal,
+    // but it can return a Result.
+    pub async fn member_hook(
+        &self,
+        context: discord::Context,
+        guild_id: GuildId,
+        user: User,
+        nick: Option<String>,
+        event: MemberEvent,
+    ) -> Result<()> {
+        if user.bot {
+            return Ok(());
+        }
+        let Some(channel_id) = self
+            .server_channel(&context, guild_id, &WELCOME_CHANNEL)
+            .await?
+        else {
+            return Ok(());
+        };
+        let conversation = self.channel_conversation(&context, channel_id).await?;
+        let user_nick = format!("@{}", nick.unwrap_or(user.name));
+        self.database
+            .add_message(
+                conversation,
+                Message::new(Role::System, event.note(&user_nick)),
+            )
+            .await?;
+
+        let templates = ServerTemplates::load(&self.database, Some(guild_id.0)).await?;
+        let Some(template) = templates.get(event.template()) else {
+            return Ok(());
+        };
+        let now = chrono::Local::now();
+        if self.database.get_quiet(conversation).await?.is_quiet(now) {
+            return Ok(());
+        }
+        if !self.current_openai().serves(Some(guild_id.0)) {
+            return Ok(());
+        }
+        if self.breaker.is_open(Instant::now()) {
+            log::info!("Not greeting {user_nick} while the horse is asleep");
+            return Ok(());
+        }
+
+        let server_name = guild_id.name(&context);
+        let instruction = templates.render(template, context! { user_nick, server_name })?;
+        self.remark(&context, guild_id, channel_id, conversation, instruction)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_note() {
+        assert_eq!(
+            MemberEvent::Joined.note("@Filly"),
+            "@Filly joined the server."
+        );
+        assert_eq!(MemberEvent::Left.template(), "farewell");
+    }
+}
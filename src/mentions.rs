@@ -0,0 +1,185 @@
+use crate::text::map_prose;
+use bimap::BiMap;
+use eyre::Result;
+use itertools::intersperse;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serenity::builder::{CreateAllowedMentions, ParseValue};
+use std::collections::{HashMap, VecDeque};
+use unicase::UniCase;
+
+/// How many mentions to remember per guild before evicting the least recently used.
+const MAX_MENTIONS_PER_GUILD: usize = 500;
+
+pub static USER_MENTION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"<@(\d+)>").expect("valid mention regex"));
+
+/// Which mentions in an outgoing message notify anyone: the users it names, and the
+/// author of the message it replies to, when `ping` is set, otherwise nobody.
+/// `@everyone` and roles never do, whatever the model writes.
+pub fn allowed(ping: bool, allowed: &mut CreateAllowedMentions) -> &mut CreateAllowedMentions {
+    allowed.empty_parse().replied_user(ping);
+    if ping {
+        allowed.parse(ParseValue::Users);
+    }
+    allowed
+}
+
+/// Maps discord mentions (`<@123>`) to the `@nicknames` shown to the model and back,
+/// partitioned by guild since nicknames are per guild. DMs use the `None` partition.
+#[derive(Default)]
+pub struct MentionCache {
+    guilds: HashMap<Option<u64>, GuildMentions>,
+}
+
+#[derive(Default)]
+struct GuildMentions {
+    mentions: BiMap<String, UniCase<String>>,
+    /// Mentions, least recently used first.
+    order: VecDeque<String>,
+    /// Alternation of every known nickname, rebuilt lazily after the map changes.
+    encoder: Option<Regex>,
+}
+
+impl GuildMentions {
+    fn touch(&mut self, mention: &str) {
+        if let Some(pos) = self.order.iter().position(|m| m == mention) {
+            if let Some(m) = self.order.remove(pos) {
+                self.order.push_back(m);
+            }
+        }
+    }
+
+    fn encoder(&mut self) -> Result<Option<&Regex>> {
+        if self.encoder.is_none() && !self.mentions.is_empty() {
+            // longest first, so one nickname that is a prefix of another doesn't shadow it
+            let mut nicknames = self.mentions.right_values().collect::<Vec<_>>();
+            nicknames.sort_by_key(|s| std::cmp::Reverse(s.len()));
+            let pattern = intersperse(
+                nicknames.into_iter().map(|s| regex::escape(s)),
+                "|".to_owned(),
+            )
+            .collect::<String>();
+            self.encoder = Some(Regex::new(&pattern)?);
+        }
+        Ok(self.encoder.as_ref())
+    }
+}
+
+impl MentionCache {
+    pub fn contains(&mut self, guild: Option<u64>, mention: &str) -> bool {
+        match self.guilds.get_mut(&guild) {
+            Some(g) if g.mentions.contains_left(mention) => {
+                g.touch(mention);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn insert(&mut self, guild: Option<u64>, mention: String, nickname: String) {
+        let g = self.guilds.entry(guild).or_default();
+        g.mentions.insert(mention.clone(), UniCase::new(nickname));
+        // inserting can overwrite another pair that shared the nickname
+        g.order
+            .retain(|m| m != &mention && g.mentions.contains_left(m));
+        g.order.push_back(mention);
+        while g.order.len() > MAX_MENTIONS_PER_GUILD {
+            if let Some(evicted) = g.order.pop_front() {
+                g.mentions.remove_by_left(&evicted);
+            }
+        }
+        g.encoder = None;
+    }
+
+    /// The nickname for a mention, if known.
+    pub fn nickname(&self, guild: Option<u64>, mention: &str) -> Option<String> {
+        self.guilds
+            .get(&guild)?
+            .mentions
+            .get_by_left(mention)
+            .map(|s| s.to_string())
+    }
+
+    /// Replace every mention in `content` with its nickname, leaving unknown ones (and
+    /// anything in code, links or escaped) as is.
+    pub fn decode(&self, guild: Option<u64>, content: &str) -> String {
+        map_prose(content, |text| {
+            let result = USER_MENTION.replace_all(text, |caps: &regex::Captures| {
+                let user_id = caps.get(1).map(|m| m.as_str()).unwrap_or("").to_owned();
+                let user_id = user_id.parse::<u64>().unwrap_or(0);
+                let mention = format!("<@{}>", user_id);
+
+                self.nickname(guild, &mention).unwrap_or(mention)
+            });
+            result.to_string()
+        })
+    }
+
+    /// Replace every known nickname in `content` with its mention, except in code, links
+    /// and escapes.
+    pub fn encode(&mut self, guild: Option<u64>, content: &str) -> Result<String> {
+        let Some(g) = self.guilds.get_mut(&guild) else {
+            return Ok(content.to_owned());
+        };
+        let Some(re) = g.encoder()?.cloned() else {
+            return Ok(content.to_owned());
+        };
+        Ok(map_prose(content, |text| {
+            let result = re.replace_all(text, |caps: &regex::Captures| {
+                let nickname = caps.get(0).map(|m| m.as_str()).unwrap_or("").to_owned();
+                g.mentions
+                    .get_by_right(&UniCase::new(nickname.clone()))
+                    .cloned()
+                    .unwrap_or(nickname)
+            });
+            result.to_string()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let mut cache = MentionCache::default();
+        cache.insert(Some(1), "<@1>".to_owned(), "@dyl".to_owned());
+        cache.insert(Some(1), "<@2>".to_owned(), "@dylan".to_owned());
+        assert_eq!(
+            cache.encode(Some(1), "hi @dylan and @dyl").unwrap(),
+            "hi <@2> and <@1>"
+        );
+        assert_eq!(cache.encode(Some(2), "hi @dylan").unwrap(), "hi @dylan");
+        assert_eq!(cache.nickname(Some(1), "<@2>"), Some("@dylan".to_owned()));
+        assert_eq!(cache.nickname(None, "<@2>"), None);
+        assert_eq!(
+            cache.decode(Some(1), "hi <@2> and <@3>"),
+            "hi @dylan and <@3>"
+        );
+        assert_eq!(
+            cache.decode(Some(1), "<@2> wrote `<@2>`"),
+            "@dylan wrote `<@2>`"
+        );
+        assert_eq!(
+            cache
+                .encode(Some(1), "@dylan:\n```\nping @dylan\n```")
+                .unwrap(),
+            "<@2>:\n```\nping @dylan\n```"
+        );
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut cache = MentionCache::default();
+        for i in 0..MAX_MENTIONS_PER_GUILD {
+            cache.insert(None, format!("<@{i}>"), format!("@user{i}"));
+        }
+        assert!(cache.contains(None, "<@0>"));
+        cache.insert(None, "<@new>".to_owned(), "@new".to_owned());
+        assert!(cache.contains(None, "<@0>"));
+        assert!(!cache.contains(None, "<@1>"));
+        assert_eq!(cache.encode(None, "@user1 @new").unwrap(), "@user1 <@new>");
+    }
+}
@@ -0,0 +1,108 @@
+//! Delivers queued messages (topic comments and the like) in the background, retrying
+//! with backoff, so nothing is lost to a reconnect or a restart. Messages queued with a
+//! conversation are added to its history as they are delivered.
+
+use crate::{
+    mentions,
+    schema::{Database, OutgoingMessage},
+};
+use eyre::Result;
+use serenity::{http::Http, model::id::ChannelId};
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+/// How often to look for due messages.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How many messages to send per poll.
+const BATCH_SIZE: usize = 20;
+/// Give up on a message after this many failures.
+const MAX_ATTEMPTS: u32 = 10;
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// Start delivering queued messages. Call this once the gateway is connected.
+pub fn spawn(database: Arc<Database>, http: Arc<Http>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = deliver(&database, &http).await {
+                log::error!("Failed to deliver queued messages: {}", e);
+            }
+        }
+    });
+}
+
+async fn deliver(database: &Database, http: &Http) -> Result<()> {
+    // keep each channel's messages in order: once one fails, the rest wait too, in this
+    // batch here and in later ones because due_messages skips them while it's postponed
+    let mut stalled = HashSet::new();
+    for message in database.due_messages(BATCH_SIZE).await? {
+        if stalled.contains(&message.channel_id) {
+            continue;
+        }
+        // mentions are only encoded for conversations that ping, see the `ping` setting
+        let sent = ChannelId(message.channel_id)
+            .send_message(http, |m| {
+                m.content(&message.content)
+                    .allowed_mentions(|a| mentions::allowed(true, a))
+            })
+            .await;
+        match sent {
+            Ok(_) => {
+                database.remove_queued_message(message.id).await?;
+                if let Some(conversation) = message.conversation {
+                    database.record_sent(conversation, &message.content).await?;
+                }
+            }
+            Err(e) => {
+                stalled.insert(message.channel_id);
+                retry_or_drop(database, &message, e).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn retry_or_drop(
+    database: &Database,
+    message: &OutgoingMessage,
+    error: serenity::Error,
+) -> Result<()> {
+    let attempts = message.attempts + 1;
+    if attempts >= MAX_ATTEMPTS {
+        log::error!(
+            "Giving up on a message to {} after {} attempts: {}",
+            message.channel_id,
+            attempts,
+            error
+        );
+        return database.remove_queued_message(message.id).await;
+    }
+    log::warn!(
+        "Failed to send a message to {}, will retry: {}",
+        message.channel_id,
+        error
+    );
+    database
+        .postpone_message(message.id, backoff(attempts))
+        .await
+}
+
+/// 30 seconds, doubling with each failure, up to an hour.
+fn backoff(attempts: u32) -> Duration {
+    Duration::from_secs(30)
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), Duration::from_secs(30));
+        assert_eq!(backoff(2), Duration::from_secs(60));
+        assert_eq!(backoff(9), MAX_BACKOFF);
+    }
+}
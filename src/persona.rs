@@ -0,0 +1,385 @@
+//! `horse-npc persona`: a whole character in one TOML file, for sharing between bots.
+//! A bundle has the prompt template, the model parameters of a preset, lore added to
+//! the prompt, which functions the model may call, what it says to flagged messages
+//! and canned responses:
+//!
+//! ```toml
+//! name = "Old Dobbin"
+//! description = "A retired plough horse with opinions"
+//! prompt = "Your name is {{ bot_nick }}. You are an old plough horse..."
+//! lore = "Dobbin worked Hill Farm for twenty years."
+//! functions = ["react"]
+//! deflections = ["{{ user_nick }}, Dobbin pretends not to have heard that."]
+//!
+//! [params]
+//! temperature = 0.8
+//! max_tokens = 300
+//!
+//! [[canned]]
+//! pattern = "^neigh$"
+//! response = "Neigh yourself."
+//! ```
+//!
+//! Importing one sets the conversation's prompt and parameters where the bundle has
+//! them and replaces its lore, functions and deflections. Canned responses belong to a
+//! whole server (or every DM), so they're only exported or imported when asked for.
+
+use crate::{
+    canned, presets,
+    schema::{
+        Conversation, Database, Preset, SettingScope, SettingsResolver, FUNCTIONS, MODEL,
+        NO_FUNCTIONS, TEMPERATURE,
+    },
+    Args,
+};
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Debug, clap::Subcommand)]
+pub enum PersonaCommand {
+    /// Print a conversation's persona as TOML
+    Export {
+        conversation: String,
+        /// What to call the persona, by default the conversation's name
+        #[clap(long)]
+        name: Option<String>,
+        #[clap(long, default_value = "")]
+        description: String,
+        /// Include the canned responses of the conversation's server, or of every DM
+        #[clap(long)]
+        canned: bool,
+    },
+    /// Give a conversation the persona in a TOML file
+    Import {
+        conversation: String,
+        file: PathBuf,
+        /// Add the bundle's canned responses to the conversation's server, or to every DM
+        #[clap(long)]
+        canned: bool,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Persona {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub prompt: Option<String>,
+    /// Background added to the end of the prompt, as written.
+    pub lore: Option<String>,
+    /// The functions the model may call, all of them if left out, or none if empty.
+    pub functions: Option<Vec<String>>,
+    /// Templates for answering flagged messages, or the built-in horse ones if left out.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deflections: Vec<String>,
+    #[serde(default)]
+    pub params: Params,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub canned: Vec<Canned>,
+}
+
+/// The model parameters of a preset, which also has the prompt.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Params {
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Canned {
+    pub pattern: String,
+    pub response: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl Persona {
+    fn preset(&self) -> Preset {
+        Preset {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            model: self.params.model.clone(),
+            temperature: self.params.temperature,
+            max_tokens: self.params.max_tokens,
+            prompt: self.prompt.clone(),
+        }
+    }
+}
+
+pub fn from_toml(source: &str) -> Result<Persona> {
+    let persona: Persona = toml::from_str(source)?;
+    presets::check(&persona.preset())?;
+    if let Some(functions) = &persona.functions {
+        if functions.iter().any(|f| f.contains(',')) {
+            return Err(eyre!("function names can't have commas"));
+        }
+    }
+    for deflection in &persona.deflections {
+        minijinja::Environment::new().template_from_str(deflection)?;
+    }
+    for canned in &persona.canned {
+        canned::pattern(&canned.pattern)?;
+        if canned.weight == 0 {
+            return Err(eyre!("the weight of {} must be at least 1", canned.pattern));
+        }
+    }
+    Ok(persona)
+}
+
+pub fn to_toml(persona: &Persona) -> Result<String> {
+    Ok(toml::to_string(persona)?)
+}
+
+async fn guild(db: &Database, conversation: Conversation) -> Result<Option<u64>> {
+    Ok(db
+        .conversation_infos(None)
+        .await?
+        .into_iter()
+        .find(|info| info.conversation == conversation)
+        .and_then(|info| info.guild_id))
+}
+
+/// A conversation's persona as it stands, with the parameters it would reply with, and
+/// with `canned`, its server's canned responses.
+pub async fn export(
+    db: &Database,
+    conversation: Conversation,
+    name: String,
+    description: String,
+    canned: bool,
+) -> Result<Persona> {
+    let guild = guild(db, conversation).await?;
+    let settings = SettingsResolver::new(db, conversation, guild);
+    let functions = settings.get(&FUNCTIONS).await?;
+    let listed: Vec<String> = functions
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(str::to_owned)
+        .collect();
+    let functions = if functions.trim() == NO_FUNCTIONS {
+        Some(vec![])
+    } else {
+        (!listed.is_empty()).then_some(listed)
+    };
+    let canned = if canned {
+        db.canned_responses(guild).await?
+    } else {
+        vec![]
+    };
+
+    Ok(Persona {
+        name,
+        description,
+        prompt: db.get_prompt(conversation).await?,
+        lore: db.lore(conversation).await?,
+        functions,
+        deflections: db.deflections(conversation).await?,
+        params: Params {
+            model: Some(settings.get(&MODEL).await?),
+            // by way of its text, so 0.8 isn't written as 0.800000011920929
+            temperature: Some(settings.get(&TEMPERATURE).await?.to_string().parse()?),
+            max_tokens: Some(db.max_tokens(conversation).await?),
+        },
+        canned: canned
+            .into_iter()
+            .map(|r| Canned {
+                pattern: r.pattern,
+                response: r.response,
+                weight: r.weight,
+            })
+            .collect(),
+    })
+}
+
+/// Give a conversation a persona. Its canned responses are only added to the server's
+/// with `canned`, skipping any the server already has, and without it a persona that
+/// has some isn't imported at all.
+pub async fn import(
+    db: &Database,
+    conversation: Conversation,
+    persona: &Persona,
+    canned: bool,
+) -> Result<()> {
+    if !persona.canned.is_empty() && !canned {
+        return Err(eyre!(
+            "{} has canned responses, which the whole server shares; pass --canned to add them",
+            persona.name
+        ));
+    }
+    presets::apply(db, conversation, &persona.preset()).await?;
+    db.set_lore(conversation, persona.lore.clone()).await?;
+    // an empty setting would allow every function
+    let functions = persona.functions.as_ref().map(|f| {
+        if f.is_empty() {
+            NO_FUNCTIONS.to_owned()
+        } else {
+            f.join(", ")
+        }
+    });
+    db.set_setting(
+        SettingScope::Conversation(conversation),
+        FUNCTIONS.key,
+        functions,
+    )
+    .await?;
+    db.set_deflections(conversation, &persona.deflections)
+        .await?;
+
+    let guild = guild(db, conversation).await?;
+    let existing = db.canned_responses(guild).await?;
+    for canned in &persona.canned {
+        let exists = existing
+            .iter()
+            .any(|r| r.pattern == canned.pattern && r.response == canned.response);
+        if !exists {
+            db.add_canned_response(
+                guild,
+                canned.pattern.clone(),
+                canned.response.clone(),
+                canned.weight,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn run(args: &Args, command: &PersonaCommand) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    match command {
+        PersonaCommand::Export {
+            conversation,
+            name,
+            description,
+            canned,
+        } => {
+            let name = name.clone().unwrap_or_else(|| conversation.clone());
+            let conversation = database
+                .lookup_conversation(conversation)
+                .await?
+                .ok_or_else(|| eyre!("no conversation named {conversation}"))?;
+            let persona =
+                export(&database, conversation, name, description.clone(), *canned).await?;
+            print!("{}", to_toml(&persona)?);
+        }
+        PersonaCommand::Import {
+            conversation,
+            file,
+            canned,
+        } => {
+            let persona = from_toml(&std::fs::read_to_string(file)?)?;
+            let known: Vec<String> = crate::load_tools(args.plugins.as_deref())?
+                .functions()
+                .into_iter()
+                .map(|f| f.name)
+                .collect();
+            for function in persona.functions.iter().flatten() {
+                if !known.contains(function) {
+                    log::warn!(
+                        "{} wants the function {function}, which this bot doesn't have",
+                        persona.name
+                    );
+                }
+            }
+            let conversation = database
+                .lookup_conversation(conversation)
+                .await?
+                .ok_or_else(|| eyre!("no conversation named {conversation}"))?;
+            import(&database, conversation, &persona, *canned).await?;
+            println!("Imported {}", persona.name);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOBBIN: &str = r#"
+name = "Old Dobbin"
+prompt = "You are {{ bot_nick }}, an old plough horse."
+lore = "Dobbin worked Hill Farm for twenty years."
+functions = ["react"]
+deflections = ["{{ user_nick }}, Dobbin pretends not to have heard that."]
+
+[params]
+temperature = 0.8
+
+[[canned]]
+pattern = "^neigh$"
+response = "Neigh yourself."
+"#;
+
+    #[test]
+    fn test_toml() {
+        let persona = from_toml(DOBBIN).unwrap();
+        assert_eq!(persona.name, "Old Dobbin");
+        assert_eq!(persona.params.temperature, Some(0.8));
+        assert_eq!(persona.canned[0].weight, 1);
+        assert_eq!(from_toml(&to_toml(&persona).unwrap()).unwrap(), persona);
+
+        assert!(from_toml("name = \"x\"\nprompt = \"{{ oops\"\n").is_err());
+        assert!(from_toml("name = \"x\"\n[params]\ntemperature = 5.0\n").is_err());
+        assert!(
+            from_toml("name = \"x\"\n[[canned]]\npattern = \"(\"\nresponse = \"y\"\n").is_err()
+        );
+        assert!(from_toml("name = \"x\"\nmood = \"grumpy\"\n").is_err());
+        assert!(from_toml("name = \"x\"\ndeflections = [\"{% if %}\"]\n").is_err());
+        assert!(from_toml("description = \"no name\"\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import_export() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let persona = from_toml(DOBBIN).unwrap();
+        // the canned responses would be shared by every DM
+        assert!(import(&db, conversation, &persona, false).await.is_err());
+        assert_eq!(db.lore(conversation).await.unwrap(), None);
+        import(&db, conversation, &persona, true).await.unwrap();
+        import(&db, conversation, &persona, true).await.unwrap();
+        assert_eq!(db.canned_responses(None).await.unwrap().len(), 1);
+
+        let export_as =
+            |name: &str, canned| export(&db, conversation, name.to_owned(), String::new(), canned);
+        let exported = export_as("Old Dobbin", false).await.unwrap();
+        assert!(exported.canned.is_empty());
+        let exported = export_as("Old Dobbin", true).await.unwrap();
+        assert_eq!(exported.prompt, persona.prompt);
+        assert_eq!(exported.lore, persona.lore);
+        assert_eq!(exported.functions, Some(vec!["react".to_owned()]));
+        assert_eq!(exported.params.temperature, Some(0.8));
+        assert_eq!(exported.params.model.as_deref(), Some("gpt-3.5-turbo"));
+        assert_eq!(exported.canned, persona.canned);
+        assert_eq!(exported.deflections, persona.deflections);
+
+        let plain = from_toml("name = \"Plain\"\n").unwrap();
+        import(&db, conversation, &plain, false).await.unwrap();
+        let exported = export_as("Plain", false).await.unwrap();
+        assert_eq!(exported.lore, None);
+        assert_eq!(exported.functions, None);
+        assert!(exported.deflections.is_empty());
+        assert_eq!(exported.prompt, persona.prompt);
+
+        // none at all, rather than all of them
+        let quiet = from_toml("name = \"Quiet\"\nfunctions = []\n").unwrap();
+        import(&db, conversation, &quiet, false).await.unwrap();
+        let settings = SettingsResolver::new(&db, conversation, None);
+        assert_eq!(settings.get(&FUNCTIONS).await.unwrap(), NO_FUNCTIONS);
+        let exported = export_as("Quiet", false).await.unwrap();
+        assert_eq!(exported.functions, Some(vec![]));
+        assert_eq!(from_toml(&to_toml(&exported).unwrap()).unwrap(), exported);
+    }
+}
@@ -0,0 +1,184 @@
+//! What the bot shows next to its name: an activity like "Grazing in 12 servers",
+//! an online status, and optionally in-character activities that take turns.
+
+use eyre::{eyre, Result};
+use minijinja::context;
+use serenity::{
+    model::{gateway::Activity, user::OnlineStatus},
+    prelude as discord,
+};
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+pub struct Presence {
+    /// Shown when there's no rotation or override. `{{ servers }}` is the server count.
+    activity: Option<String>,
+    status: OnlineStatus,
+    /// Activities to take turns showing, one per `interval`.
+    rotation: Vec<String>,
+    interval: Duration,
+    next: AtomicUsize,
+    /// Set with /status, and shown instead of everything else until cleared.
+    status_override: Mutex<Option<String>>,
+}
+
+impl Presence {
+    pub fn new(activity: Option<String>, status: OnlineStatus, interval: Duration) -> Self {
+        Self {
+            activity,
+            status,
+            rotation: vec![],
+            interval,
+            next: AtomicUsize::new(0),
+            status_override: Mutex::new(None),
+        }
+    }
+
+    /// Take turns showing the activities in a file, one per line.
+    pub fn with_rotation(mut self, path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| eyre!("failed to read {}: {e}", path.display()))?;
+        self.rotation = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .collect();
+        Ok(self)
+    }
+
+    /// Show (or with None, stop showing) this instead of the configured activity.
+    pub fn set_override(&self, text: Option<String>) {
+        *self
+            .status_override
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = text;
+    }
+
+    /// The activity to show now, moving the rotation along when `advance` is set.
+    fn activity(&self, servers: usize, advance: bool) -> Result<Option<String>> {
+        let status_override = self
+            .status_override
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let template = match status_override {
+            Some(text) => return Ok(Some(text)),
+            None if self.rotation.is_empty() => self.activity.clone(),
+            None => {
+                let next = if advance {
+                    self.next.fetch_add(1, Ordering::Relaxed)
+                } else {
+                    self.next.load(Ordering::Relaxed)
+                };
+                Some(self.rotation[next % self.rotation.len()].clone())
+            }
+        };
+        let Some(template) = template else {
+            return Ok(None);
+        };
+        let env = minijinja::Environment::new();
+        Ok(Some(env.render_str(&template, context! { servers })?))
+    }
+
+    /// Show the current activity, moving the rotation along when `advance` is set.
+    pub async fn update(&self, context: &discord::Context, advance: bool) {
+        let servers = context.cache.guild_count();
+        match self.activity(servers, advance) {
+            Ok(activity) => {
+                context
+                    .set_presence(activity.map(Activity::playing), self.status)
+                    .await
+            }
+            Err(e) => log::error!("Failed to render the activity: {}", e),
+        }
+    }
+
+    /// Keep the activity up to date (the server count changes) and the rotation
+    /// turning. Call this once the gateway is connected.
+    pub fn spawn(self: Arc<Self>, context: discord::Context) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.interval);
+            loop {
+                ticker.tick().await;
+                self.update(&context, true).await;
+            }
+        });
+    }
+}
+
+/// Parse an online status: online, idle, dnd or invisible.
+pub fn parse_status(s: &str) -> Result<OnlineStatus> {
+    match s {
+        "online" => Ok(OnlineStatus::Online),
+        "idle" => Ok(OnlineStatus::Idle),
+        "dnd" => Ok(OnlineStatus::DoNotDisturb),
+        "invisible" => Ok(OnlineStatus::Invisible),
+        _ => Err(eyre!(
+            "unknown status {s}, use online, idle, dnd or invisible"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_activity() {
+        let interval = Duration::from_secs(60);
+        let presence = Presence::new(None, OnlineStatus::Online, interval);
+        assert_eq!(presence.activity(3, true).unwrap(), None);
+
+        let mut presence = Presence::new(
+            Some("Grazing in {{ servers }} servers".to_owned()),
+            OnlineStatus::Online,
+            interval,
+        );
+        assert_eq!(
+            presence.activity(12, true).unwrap().as_deref(),
+            Some("Grazing in 12 servers")
+        );
+
+        presence.rotation = vec!["Eating hay".to_owned(), "Napping".to_owned()];
+        assert_eq!(
+            presence.activity(12, true).unwrap().as_deref(),
+            Some("Eating hay")
+        );
+        assert_eq!(
+            presence.activity(12, false).unwrap().as_deref(),
+            Some("Napping")
+        );
+        assert_eq!(
+            presence.activity(12, true).unwrap().as_deref(),
+            Some("Napping")
+        );
+        assert_eq!(
+            presence.activity(12, true).unwrap().as_deref(),
+            Some("Eating hay")
+        );
+
+        presence.set_override(Some("Down for maintenance".to_owned()));
+        assert_eq!(
+            presence.activity(12, true).unwrap().as_deref(),
+            Some("Down for maintenance")
+        );
+        presence.set_override(None);
+        assert_eq!(
+            presence.activity(12, false).unwrap().as_deref(),
+            Some("Napping")
+        );
+    }
+
+    #[test]
+    fn test_parse_status() {
+        assert_eq!(parse_status("dnd").unwrap(), OnlineStatus::DoNotDisturb);
+        assert!(parse_status("away").is_err());
+    }
+}
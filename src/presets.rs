@@ -0,0 +1,267 @@
+//! `horse-npc preset`: named bundles of model, temperature, reply length and prompt
+//! that a conversation can switch to with `/preset use`. A few come built in; the
+//! rest are stored in the database and shared between operators as TOML, one table
+//! per preset:
+//!
+//! ```toml
+//! [storyteller]
+//! description = "Long, vivid tales"
+//! temperature = 1.1
+//! max_tokens = 700
+//! prompt = "You are a horse who tells stories..."
+//! ```
+
+use crate::{
+    schema::{self, Conversation, Database, Preset, SettingScope, MODEL, TEMPERATURE},
+    Args,
+};
+use eyre::{eyre, Result};
+use std::{collections::BTreeMap, path::PathBuf};
+
+#[derive(Debug, clap::Subcommand)]
+pub enum PresetCommand {
+    /// List the built-in and stored presets
+    List,
+    /// Print presets as TOML, by default every stored one
+    Export { names: Vec<String> },
+    /// Store the presets in a TOML file, replacing any with the same names
+    Import { file: PathBuf },
+    /// Switch a conversation to a preset
+    Use { conversation: String, name: String },
+    /// Delete a stored preset
+    Remove { name: String },
+}
+
+const STORYTELLER_PROMPT: &str = "Your name is {{ bot_nick }}. You are a horse who spins \
+    long, vivid tales full of hooves, hay and high adventure, and you turn whatever \
+    {{ user_nick }} says into the next chapter.";
+
+const CONCISE_HELPER_PROMPT: &str = "Your name is {{ bot_nick }}. You are a helpful horse. \
+    Answer {{ user_nick }} as briefly as you can while still being right, with at most one \
+    pun.";
+
+const CHAOS_HORSE_PROMPT: &str = "Your name is {{ bot_nick }}. You are an unpredictable \
+    horse. You change the subject, invent conspiracies about carrots and answer \
+    {{ user_nick }} in riddles, but never unkindly.";
+
+/// The presets every bot has. A stored preset with the same name takes its place.
+pub fn builtins() -> Vec<Preset> {
+    vec![
+        Preset {
+            name: "storyteller".to_owned(),
+            description: "Long, vivid tales".to_owned(),
+            model: None,
+            temperature: Some(1.1),
+            max_tokens: Some(700),
+            prompt: Some(STORYTELLER_PROMPT.to_owned()),
+        },
+        Preset {
+            name: "concise helper".to_owned(),
+            description: "Short, careful answers".to_owned(),
+            model: None,
+            temperature: Some(0.2),
+            max_tokens: Some(150),
+            prompt: Some(CONCISE_HELPER_PROMPT.to_owned()),
+        },
+        Preset {
+            name: "chaos horse".to_owned(),
+            description: "Anything could happen".to_owned(),
+            model: None,
+            temperature: Some(1.6),
+            max_tokens: Some(300),
+            prompt: Some(CHAOS_HORSE_PROMPT.to_owned()),
+        },
+    ]
+}
+
+/// The stored preset named `name`, or else the built-in one.
+pub async fn find(db: &Database, name: &str) -> Result<Preset> {
+    if let Some(preset) = db.preset(name.to_owned()).await? {
+        return Ok(preset);
+    }
+    builtins()
+        .into_iter()
+        .find(|p| p.name == name)
+        .ok_or_else(|| eyre!("no preset named {name}"))
+}
+
+/// Every preset, stored ones in place of built-in ones of the same name, by name.
+pub async fn all(db: &Database) -> Result<Vec<Preset>> {
+    let mut presets: BTreeMap<String, Preset> = builtins()
+        .into_iter()
+        .map(|p| (p.name.clone(), p))
+        .collect();
+    for preset in db.presets().await? {
+        presets.insert(preset.name.clone(), preset);
+    }
+    Ok(presets.into_values().collect())
+}
+
+/// Make sure a preset would work before anyone uses it.
+pub fn check(preset: &Preset) -> Result<()> {
+    if preset.name.trim().is_empty() {
+        return Err(eyre!("presets need a name"));
+    }
+    if let Some(model) = &preset.model {
+        schema::check_setting(MODEL.key, model)?;
+    }
+    if let Some(temperature) = preset.temperature {
+        schema::check_setting(TEMPERATURE.key, &temperature.to_string())?;
+    }
+    if preset.max_tokens == Some(0) {
+        return Err(eyre!("max_tokens must be at least 1"));
+    }
+    if let Some(prompt) = &preset.prompt {
+        minijinja::Environment::new().template_from_str(prompt)?;
+    }
+    Ok(())
+}
+
+pub fn from_toml(source: &str) -> Result<Vec<Preset>> {
+    let presets: BTreeMap<String, Preset> = toml::from_str(source)?;
+    presets
+        .into_iter()
+        .map(|(name, preset)| {
+            let preset = Preset { name, ..preset };
+            check(&preset).map_err(|e| eyre!("preset {}: {e}", preset.name))?;
+            Ok(preset)
+        })
+        .collect()
+}
+
+pub fn to_toml(presets: Vec<Preset>) -> Result<String> {
+    let presets: BTreeMap<String, Preset> =
+        presets.into_iter().map(|p| (p.name.clone(), p)).collect();
+    Ok(toml::to_string(&presets)?)
+}
+
+/// Switch a conversation to a preset, leaving alone whatever it doesn't set.
+pub async fn apply(db: &Database, conversation: Conversation, preset: &Preset) -> Result<()> {
+    let scope = SettingScope::Conversation(conversation);
+    if let Some(model) = &preset.model {
+        db.set_setting(scope, MODEL.key, Some(model.clone()))
+            .await?;
+    }
+    if let Some(temperature) = preset.temperature {
+        db.set_setting(scope, TEMPERATURE.key, Some(temperature.to_string()))
+            .await?;
+    }
+    if let Some(max_tokens) = preset.max_tokens {
+        db.set_max_tokens(conversation, max_tokens).await?;
+    }
+    if let Some(prompt) = &preset.prompt {
+        db.set_prompt(conversation, prompt).await?;
+    }
+    Ok(())
+}
+
+pub async fn run(args: &Args, command: &PresetCommand) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    match command {
+        PresetCommand::List => {
+            for preset in all(&database).await? {
+                println!("{}: {}", preset.name, preset.description);
+            }
+        }
+        PresetCommand::Export { names } => {
+            let presets = if names.is_empty() {
+                database.presets().await?
+            } else {
+                let mut presets = vec![];
+                for name in names {
+                    presets.push(find(&database, name).await?);
+                }
+                presets
+            };
+            print!("{}", to_toml(presets)?);
+        }
+        PresetCommand::Import { file } => {
+            let presets = from_toml(&std::fs::read_to_string(file)?)?;
+            let count = presets.len();
+            for preset in presets {
+                database.save_preset(preset).await?;
+            }
+            println!("Imported {count} presets");
+        }
+        PresetCommand::Use { conversation, name } => {
+            let conversation = database
+                .lookup_conversation(conversation)
+                .await?
+                .ok_or_else(|| eyre!("no conversation named {conversation}"))?;
+            apply(&database, conversation, &find(&database, name).await?).await?;
+        }
+        PresetCommand::Remove { name } => {
+            if !database.remove_preset(name.clone()).await? {
+                return Err(eyre!("no stored preset named {name}"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::SettingsResolver;
+
+    #[test]
+    fn test_toml() {
+        let presets = builtins();
+        for preset in &presets {
+            check(preset).unwrap();
+        }
+        let parsed = from_toml(&to_toml(presets.clone()).unwrap()).unwrap();
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(
+            parsed.iter().find(|p| p.name == "chaos horse"),
+            presets.iter().find(|p| p.name == "chaos horse")
+        );
+
+        let presets = from_toml("[terse]\nmodel = \"gpt-4\"\nmax_tokens = 50\n").unwrap();
+        assert_eq!(
+            presets,
+            vec![Preset {
+                name: "terse".to_owned(),
+                model: Some("gpt-4".to_owned()),
+                max_tokens: Some(50),
+                ..Default::default()
+            }]
+        );
+        assert!(from_toml("[hot]\ntemperature = 5.0\n").is_err());
+        assert!(from_toml("[broken]\nprompt = \"{{ oops\"\n").is_err());
+        assert!(from_toml("[typo]\ntemprature = 1.0\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        db.save_preset(Preset {
+            name: "storyteller".to_owned(),
+            model: Some("gpt-4".to_owned()),
+            temperature: Some(0.75),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(all(&db).await.unwrap().len(), 3);
+
+        let preset = find(&db, "storyteller").await.unwrap();
+        apply(&db, conversation, &preset).await.unwrap();
+        let settings = SettingsResolver::new(&db, conversation, None);
+        assert_eq!(settings.get(&MODEL).await.unwrap(), "gpt-4");
+        assert_eq!(settings.get(&TEMPERATURE).await.unwrap(), 0.75);
+        assert_eq!(db.max_tokens(conversation).await.unwrap(), 256);
+
+        let preset = find(&db, "concise helper").await.unwrap();
+        apply(&db, conversation, &preset).await.unwrap();
+        assert_eq!(settings.get(&MODEL).await.unwrap(), "gpt-4");
+        assert_eq!(db.max_tokens(conversation).await.unwrap(), 150);
+        assert_eq!(
+            db.get_prompt(conversation).await.unwrap().as_deref(),
+            Some(CONCISE_HELPER_PROMPT)
+        );
+        assert!(find(&db, "nope").await.is_err());
+    }
+}
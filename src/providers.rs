@@ -0,0 +1,128 @@
+//! Chat messages in the shape the providers' chat APIs share, in between the history
+//! ([`Message`]) and any one SDK's types. Each provider has a module converting to and
+//! from it, so an SDK changing its structs doesn't reach the stored history.
+
+use crate::schema::{Message, Role};
+use eyre::{eyre, Result};
+
+pub mod openai;
+
+/// One message of a chat, as sent to or received from a provider.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatTurn {
+    pub role: Role,
+    pub content: Option<String>,
+    /// Who or what the message is from, like the function a result came from.
+    pub name: Option<String>,
+    pub function_call: Option<FunctionCall>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionCall {
+    pub name: String,
+    /// JSON, but as the model wrote it, which isn't always valid.
+    pub arguments: String,
+}
+
+impl From<&Message> for ChatTurn {
+    fn from(message: &Message) -> Self {
+        let role = message.role();
+        match message {
+            Message::Content { content, .. } => ChatTurn {
+                role,
+                content: Some(content.to_owned()),
+                name: None,
+                function_call: None,
+            },
+            Message::Function {
+                fn_name, fn_args, ..
+            } => ChatTurn {
+                role,
+                content: None,
+                name: Some(fn_name.to_owned()),
+                function_call: Some(FunctionCall {
+                    name: fn_name.to_owned(),
+                    arguments: fn_args.to_owned(),
+                }),
+            },
+            Message::FunctionResult { fn_name, content } => ChatTurn {
+                role,
+                content: Some(content.to_owned()),
+                name: Some(fn_name.to_owned()),
+                function_call: None,
+            },
+        }
+    }
+}
+
+impl TryFrom<ChatTurn> for Message {
+    type Error = eyre::Error;
+
+    fn try_from(turn: ChatTurn) -> Result<Self> {
+        match turn {
+            // a model may say something as well as call a function, the call is what counts
+            ChatTurn {
+                role,
+                function_call: Some(call),
+                ..
+            } => Ok(Message::Function {
+                role,
+                fn_name: call.name,
+                fn_args: call.arguments,
+            }),
+            ChatTurn {
+                role: Role::Function,
+                name: Some(fn_name),
+                content,
+                ..
+            } => Ok(Message::FunctionResult {
+                fn_name,
+                content: content.unwrap_or_default(),
+            }),
+            ChatTurn {
+                role: Role::Function,
+                ..
+            } => Err(eyre!("a function result without the function's name")),
+            ChatTurn {
+                role,
+                content: Some(content),
+                ..
+            } => Ok(Message::Content { role, content }),
+            ChatTurn { content: None, .. } => {
+                Err(eyre!("a message with neither content nor a function call"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let messages = [
+            Message::new(Role::User, "hay?"),
+            Message::Function {
+                role: Role::Assistant,
+                fn_name: "react".to_owned(),
+                fn_args: "{\"reaction_name\": \":horse:\"}".to_owned(),
+            },
+            Message::function_result("react", "reacted with :horse:"),
+        ];
+        for message in messages {
+            let turn = ChatTurn::from(&message);
+            let back: Message = turn.try_into().unwrap();
+            assert_eq!(back.role(), message.role());
+            assert_eq!(back.content(), message.content());
+        }
+
+        let empty = ChatTurn {
+            role: Role::Assistant,
+            content: None,
+            name: None,
+            function_call: None,
+        };
+        assert!(Message::try_from(empty).is_err());
+    }
+}
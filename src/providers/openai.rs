@@ -0,0 +1,94 @@
+//! Converting chat turns to and from async_openai's types.
+
+use super::{ChatTurn, FunctionCall};
+use crate::schema::{Message, Role};
+use async_openai::types::{
+    self as sdk, ChatCompletionRequestMessage, ChatCompletionResponseMessage,
+};
+use eyre::Result;
+
+/// The history as OpenAI's chat API takes it.
+pub fn request_messages(messages: &[Message]) -> Vec<ChatCompletionRequestMessage> {
+    messages.iter().map(|m| ChatTurn::from(m).into()).collect()
+}
+
+/// What a chat completion answered with, for the history.
+pub fn response_message(message: ChatCompletionResponseMessage) -> Result<Message> {
+    ChatTurn::from(message).try_into()
+}
+
+impl From<ChatTurn> for ChatCompletionRequestMessage {
+    fn from(turn: ChatTurn) -> Self {
+        Self {
+            role: turn.role.into(),
+            content: turn.content,
+            name: turn.name,
+            function_call: turn.function_call.map(|call| sdk::FunctionCall {
+                name: call.name,
+                arguments: call.arguments,
+            }),
+        }
+    }
+}
+
+impl From<ChatCompletionResponseMessage> for ChatTurn {
+    fn from(message: ChatCompletionResponseMessage) -> Self {
+        Self {
+            role: message.role.into(),
+            content: message.content,
+            name: None,
+            function_call: message.function_call.map(|call| FunctionCall {
+                name: call.name,
+                arguments: call.arguments,
+            }),
+        }
+    }
+}
+
+impl From<Role> for sdk::Role {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::System => sdk::Role::System,
+            Role::User => sdk::Role::User,
+            Role::Assistant => sdk::Role::Assistant,
+            Role::Function => sdk::Role::Function,
+        }
+    }
+}
+
+impl From<sdk::Role> for Role {
+    fn from(role: sdk::Role) -> Self {
+        match role {
+            sdk::Role::System => Role::System,
+            sdk::Role::User => Role::User,
+            sdk::Role::Assistant => Role::Assistant,
+            sdk::Role::Function => Role::Function,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_message() {
+        // content alongside a function call used to be treated as impossible
+        let response = ChatCompletionResponseMessage {
+            role: sdk::Role::Assistant,
+            content: Some("Let me see.".to_owned()),
+            function_call: Some(sdk::FunctionCall {
+                name: "react".to_owned(),
+                arguments: "{}".to_owned(),
+            }),
+        };
+        let message = response_message(response).unwrap();
+        assert_eq!(message.role(), Role::Assistant);
+        assert_eq!(message.content(), "react({})");
+
+        let request = request_messages(&[Message::function_result("react", "done")]);
+        assert_eq!(request[0].role, sdk::Role::Function);
+        assert_eq!(request[0].name.as_deref(), Some("react"));
+        assert_eq!(request[0].content.as_deref(), Some("done"));
+    }
+}
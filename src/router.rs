@@ -0,0 +1,199 @@
+//! Deciding, before the expensive model is asked, whether a message needs it at all.
+//! Greetings and thanks get a canned answer and chatter like "lol" gets none. Obvious
+//! cases are caught by word matching, and with `router_model` set, a cheap model sorts
+//! out the rest; anything unclear gets a full reply.
+
+use crate::{
+    keys::KeyPool,
+    providers,
+    schema::{Message, Role},
+    templates::ServerTemplates,
+};
+use async_openai::types::CreateChatCompletionRequestArgs;
+use eyre::{ContextCompat, Result};
+use minijinja::value::Value;
+use once_cell::sync::Lazy;
+use rand::seq::SliceRandom;
+use regex::Regex;
+
+/// Longer messages almost always deserve a proper answer, so they aren't classified.
+const MAX_ROUTED_CHARS: usize = 200;
+
+const ROUTER_INSTRUCTION: &str = "Sort the chat message below by what it needs from the \
+    character it was sent to. Answer with one word: greeting if it only says hello, thanks \
+    if it only thanks or praises the character, ignore if it's chatter that needs no \
+    answer (like \"lol\" or \"ok\"), or reply for anything else.";
+
+const GREETINGS: &[&str] = &["Hello there!", "Hi!", "Hey, good to see you.", "Howdy!"];
+const THANKS: &[&str] = &[
+    "Happy to help!",
+    "Anytime.",
+    "You're welcome!",
+    "Aw, thanks!",
+];
+
+static GREETING: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)^(hi|hello|hey|heya|hiya|howdy|yo|good (morning|afternoon|evening))( there)?(,? (horse|pony))?[\s!.,]*$",
+    )
+    .expect("valid greeting regex")
+});
+
+static GRATITUDE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)^(good (bot|horse|pony|boy|girl)|thanks?( you)?( so much)?|thx|ty)[\s!.,]*$")
+        .expect("valid gratitude regex")
+});
+
+static CHATTER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)^(lol|lmao|rofl|ha(ha)+|ok(ay)?|k|kk|nice|cool|same|mood)[\s!.,]*$")
+        .expect("valid chatter regex")
+});
+
+/// What a message needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    /// A reply from the model, as usual.
+    Reply,
+    Greeting,
+    Thanks,
+    /// Nothing at all.
+    Ignore,
+}
+
+impl Route {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Route::Reply => "reply",
+            Route::Greeting => "greeting",
+            Route::Thanks => "thanks",
+            Route::Ignore => "ignore",
+        }
+    }
+
+    fn parse(answer: &str) -> Self {
+        let answer = answer.trim().trim_end_matches('.').to_lowercase();
+        match answer.as_str() {
+            "greeting" => Route::Greeting,
+            "thanks" => Route::Thanks,
+            "ignore" => Route::Ignore,
+            _ => Route::Reply,
+        }
+    }
+
+    /// The server template that overrides the built-in canned answers, if any.
+    fn template(self) -> Option<&'static str> {
+        match self {
+            Route::Greeting => Some("greeting"),
+            Route::Thanks => Some("thanks"),
+            Route::Reply | Route::Ignore => None,
+        }
+    }
+}
+
+/// The route for a message whose needs are obvious from its words alone.
+pub fn classify(content: &str) -> Option<Route> {
+    let content = content.trim();
+    if is_emoji(content) {
+        return Some(Route::Ignore);
+    }
+    if content.chars().count() > MAX_ROUTED_CHARS {
+        return Some(Route::Reply);
+    }
+    if GREETING.is_match(content) {
+        Some(Route::Greeting)
+    } else if GRATITUDE.is_match(content) {
+        Some(Route::Thanks)
+    } else if CHATTER.is_match(content) {
+        Some(Route::Ignore)
+    } else {
+        None
+    }
+}
+
+/// Whether a message is only emoji (or nothing at all). Punctuation on its own, like
+/// "?" or "!!", still asks for an answer.
+fn is_emoji(content: &str) -> bool {
+    content
+        .chars()
+        .all(|c| c.is_whitespace() || (!c.is_ascii() && !c.is_alphanumeric()))
+}
+
+/// Decide what a message needs, asking `model` (if not empty) when its tags (see
+/// [`classify`]) don't say.
+pub async fn route(
+    openai: &KeyPool,
+    guild: Option<u64>,
+    model: &str,
+    content: &str,
+    tagged: Option<Route>,
+) -> Result<Route> {
+    if let Some(route) = tagged {
+        return Ok(route);
+    }
+    if model.is_empty() {
+        return Ok(Route::Reply);
+    }
+
+    let messages = [
+        Message::new(Role::System, ROUTER_INSTRUCTION),
+        Message::new(Role::User, content),
+    ];
+    let request = CreateChatCompletionRequestArgs::default()
+        .max_tokens(3u16)
+        .model(model)
+        .temperature(0.0)
+        .messages(providers::openai::request_messages(&messages))
+        .build()?;
+
+    let response = openai.chat(guild, request).await?;
+    let choice = response
+        .choices
+        .into_iter()
+        .next()
+        .wrap_err("No response")?;
+    let answer = providers::openai::response_message(choice.message)?.content();
+    Ok(Route::parse(&answer))
+}
+
+/// The canned answer for a greeting or thanks: the server's template for it, rendered
+/// with the prompt's variables, or one of a few built-in lines.
+pub fn canned(templates: &ServerTemplates, route: Route, vars: Value) -> Result<String> {
+    if let Some(template) = route.template().and_then(|name| templates.get(name)) {
+        return templates.render(template, vars);
+    }
+    let lines = match route {
+        Route::Thanks => THANKS,
+        _ => GREETINGS,
+    };
+    let mut rng = rand::thread_rng();
+    Ok(lines
+        .choose(&mut rng)
+        .copied()
+        .unwrap_or("Hello!")
+        .to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify("Hello there!"), Some(Route::Greeting));
+        assert_eq!(classify("good morning, horse"), Some(Route::Greeting));
+        assert_eq!(classify("good bot"), Some(Route::Thanks));
+        assert_eq!(classify("thank you so much!!"), Some(Route::Thanks));
+        assert_eq!(classify("lol"), Some(Route::Ignore));
+        assert_eq!(classify("🐴🐴"), Some(Route::Ignore));
+        assert_eq!(classify("🐴 ❤️"), Some(Route::Ignore));
+        assert_eq!(classify("?"), None);
+        assert_eq!(classify("!!"), None);
+        assert_eq!(classify("hello, what's the capital of France?"), None);
+        assert_eq!(classify(&"why ".repeat(60)), Some(Route::Reply));
+
+        assert_eq!(Route::parse(Route::Thanks.as_str()), Route::Thanks);
+        assert_eq!(Route::parse("Greeting."), Route::Greeting);
+        assert_eq!(Route::parse(" ignore"), Route::Ignore);
+        assert_eq!(Route::parse("I think this needs a reply"), Route::Reply);
+    }
+}
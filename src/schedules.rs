@@ -0,0 +1,156 @@
+//! `horse-npc schedule`: give a conversation prompts that take over on some days,
+//! at some hours, or for an event.
+
+use crate::{
+    schema::{Database, Days, PromptSchedule},
+    Args,
+};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use eyre::{eyre, Result};
+use std::path::PathBuf;
+
+#[derive(Debug, clap::Subcommand)]
+pub enum ScheduleCommand {
+    /// Add (or replace) a prompt used instead of the conversation's own while it
+    /// applies. With several, the narrowest wins: events, then hours, then days
+    Add {
+        conversation: String,
+        name: String,
+        /// A prompt template file
+        file: PathBuf,
+        /// every, weekdays or weekends
+        #[clap(long, default_value = "every")]
+        days: Days,
+        /// Daily start time like 22:00, in local time
+        #[clap(long, requires = "until")]
+        from: Option<String>,
+        /// Daily end time like 06:00, which may be past midnight; the same as the
+        /// start time means all day
+        #[clap(long, requires = "from")]
+        until: Option<String>,
+        /// When an event starts, like 2023-10-31 or "2023-10-31 18:00"
+        #[clap(long, requires = "ends")]
+        starts: Option<String>,
+        /// When it ends
+        #[clap(long, requires = "starts")]
+        ends: Option<String>,
+    },
+    /// List a conversation's scheduled prompts
+    List { conversation: String },
+    /// Remove a scheduled prompt
+    Remove { conversation: String, name: String },
+}
+
+pub async fn run(args: &Args, command: &ScheduleCommand) -> Result<()> {
+    let database = Database::new(args.database_path()?).await?;
+    match command {
+        ScheduleCommand::Add {
+            conversation,
+            name,
+            file,
+            days,
+            from,
+            until,
+            starts,
+            ends,
+        } => {
+            let conversation = database.find_conversation(conversation).await?;
+            let window = match (from, until) {
+                (Some(from), Some(until)) => Some((
+                    NaiveTime::parse_from_str(from, "%H:%M")?,
+                    NaiveTime::parse_from_str(until, "%H:%M")?,
+                )),
+                _ => None,
+            };
+            let period = match (starts, ends) {
+                (Some(starts), Some(ends)) => {
+                    let period = (parse_local(starts)?, parse_local(ends)?);
+                    if period.0 >= period.1 {
+                        return Err(eyre!("the event ends before it starts"));
+                    }
+                    Some(period)
+                }
+                _ => None,
+            };
+            let schedule = PromptSchedule {
+                name: name.clone(),
+                prompt: std::fs::read_to_string(file)?,
+                days: *days,
+                window,
+                period,
+            };
+            database.set_prompt_schedule(conversation, schedule).await?;
+        }
+        ScheduleCommand::List { conversation } => {
+            let conversation = database
+                .lookup_conversation(conversation)
+                .await?
+                .ok_or_else(|| eyre!("no conversation named {conversation}"))?;
+            for schedule in database.prompt_schedules(conversation).await? {
+                println!("{}", describe(&schedule));
+            }
+        }
+        ScheduleCommand::Remove { conversation, name } => {
+            let conversation = database
+                .lookup_conversation(conversation)
+                .await?
+                .ok_or_else(|| eyre!("no conversation named {conversation}"))?;
+            if !database
+                .remove_prompt_schedule(conversation, name.clone())
+                .await?
+            {
+                return Err(eyre!("no scheduled prompt named {name}"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A local date, or date and time. A bare date means its midnight.
+fn parse_local(s: &str) -> Result<DateTime<Local>> {
+    let naive = match NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        Ok(date) => date.and_time(NaiveTime::MIN),
+        Err(_) => NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")?,
+    };
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(|| eyre!("{s} doesn't exist in the local time zone"))
+}
+
+fn describe(schedule: &PromptSchedule) -> String {
+    let mut when = vec![schedule.days.as_str().to_owned()];
+    if let Some((from, until)) = schedule.window {
+        when.push(format!(
+            "{}-{}",
+            from.format("%H:%M"),
+            until.format("%H:%M")
+        ));
+    }
+    if let Some((starts, ends)) = schedule.period {
+        when.push(format!(
+            "from {} to {}",
+            starts.format("%Y-%m-%d %H:%M"),
+            ends.format("%Y-%m-%d %H:%M")
+        ));
+    }
+    format!("{}: {}", schedule.name, when.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_local() {
+        let midnight = parse_local("2023-10-31").unwrap();
+        assert_eq!(
+            midnight.format("%Y-%m-%d %H:%M").to_string(),
+            "2023-10-31 00:00"
+        );
+        let evening = parse_local("2023-10-31 18:00").unwrap();
+        assert_eq!(evening - midnight, chrono::Duration::hours(18));
+        assert!(parse_local("Halloween").is_err());
+    }
+}
@@ -1,16 +1,112 @@
+mod audit;
+mod birthdays;
+mod blocks;
+mod canned;
+mod channels;
+mod check;
+mod conversations;
+mod cursors;
+mod deflections;
+mod descriptions;
+mod economy;
+mod encounters;
+mod faqs;
+mod flags;
+mod games;
+mod guild_keys;
+mod idle;
+mod karma;
+mod latency;
+mod locks;
+mod lore;
+mod merge;
+mod message_embeddings;
+mod message_tags;
+mod messages;
+mod milestones;
 mod model;
-
+mod moods;
+mod nsfw;
+mod opinions;
+mod outbox;
+mod presets;
+mod privacy;
+mod prune;
+mod quests;
+mod quiet;
+mod relationships;
+mod replies;
+mod reports;
+mod schedules;
+mod settings;
+mod shadow;
+mod sheets;
+mod templates;
+mod topics;
+mod transcripts;
+
+pub use birthdays::Birthday;
+pub use canned::CannedResponse;
+pub use check::{SourceKind, StoredSource};
+pub use conversations::ConversationInfo;
+pub use economy::Wallet;
+pub use encounters::{Combatant, Encounter};
+pub use faqs::Faq;
+pub use flags::{parse_flag_state, Flag, Flags};
+pub use games::{Game, GameKind};
+pub use idle::IdleChannel;
 pub use model::{Conversation, Message, Role};
-
+pub use moods::Mood;
+pub use nsfw::{ModerationPolicy, NsfwSettings};
+pub use outbox::OutgoingMessage;
+pub use presets::Preset;
+pub use privacy::PrivacyMode;
+pub use quests::Quest;
+pub use quiet::Quiet;
+pub use relationships::{Relationship, MAX_AFFINITY, MAX_STEP};
+pub use replies::ReplyMetadata;
+pub use schedules::{Days, PromptSchedule};
+pub use settings::{
+    check_setting, check_setting_scope, Setting, SettingScope, SettingsResolver, AUDIT,
+    BIRTHDAY_CHANNEL, CALENDAR_URL, CELEBRATION_CHANNEL, EMBEDDING_MODEL, FAQ_REPHRASE_MODEL,
+    FAQ_THRESHOLD, FAST_MODEL, FUNCTIONS, IDLE_CHATTER, LATENCY_TARGET, MEMBER_MILESTONES, MODEL,
+    MODERATION_BYPASS, NO_FUNCTIONS, PACING, PING, REPLY, REPORT_CHANNEL, ROUTER, ROUTER_MODEL,
+    SHADOW, SHADOW_MODEL, SHADOW_STAGES, TEMPERATURE, WELCOME_CHANNEL,
+};
+pub use transcripts::TranscriptEntry;
+
+use crate::transforms::Transform;
 use eyre::Result;
-use rusqlite::params;
+use messages::{decode_error, decode_message, encode_message};
+use rusqlite::{params, OptionalExtension};
 use std::path::PathBuf;
 use tokio_rusqlite::Connection;
 
 const SCHEMA_SQL: &str = include_str!("schema.sql");
 
+/// Columns added to a table after it was first created. `CREATE TABLE IF NOT EXISTS`
+/// leaves existing tables alone, so these are added to older databases on startup.
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("history", "created_at", "TIMESTAMP"),
+    ("conversation", "guild_id", "TEXT"),
+    ("reply_metadata", "fast", "BOOLEAN NOT NULL DEFAULT 0"),
+    ("outbox", "conversation", "INTEGER"),
+    ("topics", "topic_seen", "BOOLEAN NOT NULL DEFAULT 0"),
+    ("guild_milestones", "members_celebrated", "INTEGER"),
+];
+
+/// The model used to be a column of the conversation. Any conversation that was
+/// given another model keeps it as its own setting, and the column is put back to
+/// its default so a setting that is cleared later stays cleared.
+const MIGRATE_MODELS: &str = "
+    INSERT OR IGNORE INTO conversation_settings (conversation, key, value)
+    SELECT id, 'model', model FROM conversation WHERE model != 'gpt-3.5-turbo';
+    UPDATE conversation SET model = 'gpt-3.5-turbo' WHERE model != 'gpt-3.5-turbo';
+";
+
 pub struct Database {
     conn: Connection,
+    locks: locks::ConversationLocks,
 }
 
 impl Database {
@@ -23,11 +119,25 @@ impl Database {
 
         conn.call(move |conn| {
             conn.execute_batch(SCHEMA_SQL)?;
+            for (table, column, kind) in ADDED_COLUMNS {
+                let exists: bool = conn.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+                    params![table, column],
+                    |row| row.get(0),
+                )?;
+                if !exists {
+                    conn.execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column} {kind}"))?;
+                }
+            }
+            conn.execute_batch(MIGRATE_MODELS)?;
             Ok(())
         })
         .await?;
 
-        Ok(Self { conn })
+        Ok(Self {
+            conn,
+            locks: Default::default(),
+        })
     }
 
     pub async fn set_prompt<S>(&self, conversation: Conversation, text: S) -> Result<()>
@@ -70,6 +180,87 @@ impl Database {
         Ok(text)
     }
 
+    pub async fn set_script<S>(&self, conversation: Conversation, source: Option<S>) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        let source = source.map(|s| s.as_ref().to_owned());
+
+        self.conn
+            .call(move |conn| {
+                match source {
+                    Some(source) => conn.execute(
+                        "INSERT INTO script (conversation, source) VALUES (?1, ?2)
+                        ON CONFLICT (conversation) DO UPDATE SET source = ?2",
+                        params![conversation.0, source],
+                    )?,
+                    None => conn.execute(
+                        "DELETE FROM script WHERE conversation = ?1",
+                        params![conversation.0],
+                    )?,
+                };
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_script(&self, conversation: Conversation) -> Result<Option<String>> {
+        let source = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT source FROM script WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+        Ok(source)
+    }
+
+    pub async fn set_transforms(
+        &self,
+        conversation: Conversation,
+        transforms: &[Transform],
+    ) -> Result<()> {
+        let transforms = serde_json::to_string(transforms)?;
+
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO transforms (conversation, transforms) VALUES (?1, ?2)
+                    ON CONFLICT (conversation) DO UPDATE SET transforms = ?2",
+                    params![conversation.0, transforms],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_transforms(&self, conversation: Conversation) -> Result<Vec<Transform>> {
+        let transforms: Option<String> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT transforms FROM transforms WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        match transforms {
+            Some(transforms) => Ok(serde_json::from_str(&transforms)?),
+            None => Ok(vec![]),
+        }
+    }
+
     pub async fn find_conversation<S>(&self, name: S) -> Result<Conversation>
     where
         S: AsRef<str>,
@@ -98,7 +289,7 @@ impl Database {
         Ok(conversation)
     }
 
-    pub async fn add_user_message<S>(&self, conversation: Conversation, content: S) -> Result<()>
+    pub async fn add_user_message<S>(&self, conversation: Conversation, content: S) -> Result<i64>
     where
         S: AsRef<str>,
     {
@@ -112,7 +303,7 @@ impl Database {
         &self,
         conversation: Conversation,
         content: S,
-    ) -> Result<()>
+    ) -> Result<i64>
     where
         S: AsRef<str>,
     {
@@ -122,20 +313,108 @@ impl Database {
         self.add_message(conversation, message).await
     }
 
+    /// Add something the bot sent that the model didn't write, like a notice, to the
+    /// history once nothing else is being added to it, so the history matches what the
+    /// channel saw. Everything the bot sends outside of a reply goes through here.
+    pub async fn record_sent(&self, conversation: Conversation, content: &str) -> Result<()> {
+        let _lock = self.lock_conversation(conversation).await;
+        self.add_assistant_message(conversation, content).await?;
+        Ok(())
+    }
+
+    /// Append a message to the history. Returns its history id.
     #[allow(unused)]
-    pub async fn add_message(&self, conversation: Conversation, message: Message) -> Result<()> {
-        let message = serde_json::to_string(&message)?;
+    pub async fn add_message(&self, conversation: Conversation, message: Message) -> Result<i64> {
+        let message = encode_message(&message)?;
 
-        self.conn
+        let id = self
+            .conn
+            .call(move |conn| insert_message(conn, conversation, &message))
+            .await?;
+        Ok(id)
+    }
+
+    /// Append several messages to the history, all or none of them.
+    pub async fn add_messages(
+        &self,
+        conversation: Conversation,
+        messages: Vec<Message>,
+    ) -> Result<()> {
+        let messages = messages
+            .iter()
+            .map(encode_message)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.transaction(move |tx| {
+            for message in messages {
+                insert_message(tx, conversation, &message)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Run `f` in a transaction, committed if it returns Ok and rolled back otherwise,
+    /// so a change that takes several statements is never left half done.
+    pub async fn transaction<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&rusqlite::Transaction) -> rusqlite::Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let result = self
+            .conn
             .call(move |conn| {
-                conn.execute(
-                    "INSERT INTO history (conversation, message) VALUES (?1, ?2)",
-                    params![conversation.0, message],
-                )?;
-                Ok(())
+                let tx = conn.transaction()?;
+                let result = f(&tx)?;
+                tx.commit()?;
+                Ok(result)
             })
             .await?;
-        Ok(())
+
+        Ok(result)
+    }
+
+    /// Replace a conversation's whole history, for example with a summary of it.
+    pub async fn replace_history(
+        &self,
+        conversation: Conversation,
+        messages: Vec<Message>,
+    ) -> Result<()> {
+        let messages = messages
+            .iter()
+            .map(encode_message)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.transaction(move |tx| {
+            tx.execute(
+                "DELETE FROM message_embeddings WHERE conversation = ?1",
+                params![conversation.0],
+            )?;
+            tx.execute(
+                "DELETE FROM quantized_embeddings WHERE conversation = ?1",
+                params![conversation.0],
+            )?;
+            tx.execute(
+                "DELETE FROM message_tags WHERE conversation = ?1",
+                params![conversation.0],
+            )?;
+            tx.execute(
+                "DELETE FROM history WHERE conversation = ?1",
+                params![conversation.0],
+            )?;
+            // the logs hold the old history too
+            for table in ["audit_log", "shadow_log"] {
+                tx.execute(
+                    &format!("DELETE FROM {table} WHERE conversation = ?1"),
+                    params![conversation.0],
+                )?;
+            }
+            for message in messages {
+                insert_message(tx, conversation, &message)?;
+            }
+            Ok(())
+        })
+        .await
     }
 
     const HISTORY_SQL: &'static str = r#"
@@ -153,14 +432,7 @@ impl Database {
                 let mut rows = stmt.query_map(params![conversation.0], |row| {
                     let id: i64 = row.get(0)?;
                     let message: String = row.get(1)?;
-                    let message: Message = serde_json::from_str(&message).map_err(|e| {
-                        rusqlite::Error::FromSqlConversionFailure(
-                            0,
-                            rusqlite::types::Type::Text,
-                            Box::new(e),
-                        )
-                    })?;
-                    Ok(message)
+                    decode_message(&message).map_err(decode_error)
                 })?;
 
                 rows.collect::<Result<Vec<Message>, rusqlite::Error>>()
@@ -170,24 +442,13 @@ impl Database {
         Ok(messages)
     }
 
-    pub async fn model(&self, conversation: Conversation) -> Result<String> {
-        let model: String = self
-            .conn
-            .call(move |conn| {
-                let mut stmt = conn.prepare("SELECT model FROM conversation WHERE id = ?1")?;
-                let mut rows = stmt.query_map(params![conversation.0], |row| row.get(0))?;
-                let model = if let Some(row) = rows.next() {
-                    row?
-                } else {
-                    return Err(rusqlite::Error::QueryReturnedNoRows);
-                };
-
-                Ok(model)
-            })
-            .await?;
-        Ok(model)
+    /// Every model some conversation is set to use.
+    pub async fn models_in_use(&self) -> Result<Vec<String>> {
+        self.setting_values(&MODEL).await
     }
 
+    /// The most tokens a reply may use. Requests ask for less when the model's
+    /// context window is nearly full.
     pub async fn max_tokens(&self, conversation: Conversation) -> Result<u16> {
         let max_tokens: u16 = self
             .conn
@@ -205,12 +466,37 @@ impl Database {
             .await?;
         Ok(max_tokens)
     }
+
+    pub async fn set_max_tokens(&self, conversation: Conversation, max_tokens: u16) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE conversation SET max_tokens = ?2 WHERE id = ?1",
+                    params![conversation.0, max_tokens],
+                )
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Append a serialized message to the history, returning its history id.
+fn insert_message(
+    conn: &rusqlite::Connection,
+    conversation: Conversation,
+    message: &str,
+) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO history (conversation, message, created_at)
+        VALUES (?1, ?2, CURRENT_TIMESTAMP)",
+        params![conversation.0, message],
+    )?;
+    Ok(conn.last_insert_rowid())
 }
 
 #[cfg(test)]
 mod tests {
-    use async_openai::types::ChatCompletionResponseMessage;
-
     use super::*;
 
     #[tokio::test]
@@ -227,6 +513,51 @@ mod tests {
         assert_eq!(c1, c2);
     }
 
+    #[tokio::test]
+    async fn test_models_in_use() {
+        let db = Database::new(None).await.expect("failed to create schema");
+        assert_eq!(db.models_in_use().await.unwrap(), vec!["gpt-3.5-turbo"]);
+        db.set_setting(SettingScope::Guild(1), "model", Some("gpt-4".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(
+            db.models_in_use().await.unwrap(),
+            vec!["gpt-3.5-turbo", "gpt-4"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migrate_models() {
+        let path = std::env::temp_dir().join(format!("horse-npc-models-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = Database::new(Some(path.clone())).await.unwrap();
+        let conversation = db.find_conversation("test").await.unwrap();
+        db.conn
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE conversation SET model = 'gpt-4' WHERE id = ?1",
+                    params![conversation.0],
+                )
+            })
+            .await
+            .unwrap();
+        drop(db);
+
+        let db = Database::new(Some(path.clone())).await.unwrap();
+        let settings = SettingsResolver::new(&db, conversation, None);
+        assert_eq!(settings.get(&MODEL).await.unwrap(), "gpt-4");
+
+        // clearing the setting isn't undone by the next start
+        db.set_setting(SettingScope::Conversation(conversation), "model", None)
+            .await
+            .unwrap();
+        drop(db);
+        let db = Database::new(Some(path.clone())).await.unwrap();
+        let settings = SettingsResolver::new(&db, conversation, None);
+        assert_eq!(settings.get(&MODEL).await.unwrap(), "gpt-3.5-turbo");
+        std::fs::remove_file(path).unwrap();
+    }
+
     #[tokio::test]
     async fn test_history() {
         let db = Database::new(None).await.expect("failed to create db");
@@ -239,16 +570,13 @@ mod tests {
             .add_message(conversation, message)
             .await
             .expect("failed to add message");
-        let message = ChatCompletionResponseMessage {
-            role: async_openai::types::Role::Assistant,
-            content: None,
-            function_call: Some(async_openai::types::FunctionCall {
-                name: "react".to_owned(),
-                arguments: "{\n  \"reaction_name\": \":thinking:\"\n}".to_owned(),
-            }),
+        let message = Message::Function {
+            role: Role::Assistant,
+            fn_name: "react".to_owned(),
+            fn_args: "{\n  \"reaction_name\": \":thinking:\"\n}".to_owned(),
         };
         db
-            .add_message(conversation, message.try_into().unwrap())
+            .add_message(conversation, message)
             .await
             .expect("failed to add message");
 
@@ -259,5 +587,54 @@ mod tests {
         assert_eq!(messages.len(), 2);
         assert_eq!(messages[0].role(), Role::System);
         assert_eq!(messages[1].role(), Role::Assistant);
+
+        db.replace_history(conversation, vec![Message::new(Role::System, "summary")])
+            .await
+            .expect("failed to replace history");
+        let messages = db
+            .history(conversation)
+            .await
+            .expect("failed to get history");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content(), "summary");
+    }
+
+    #[tokio::test]
+    async fn test_transaction() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let failed = db
+            .transaction(move |tx| {
+                insert_message(tx, conversation, "{}")?;
+                tx.execute("INSERT INTO nowhere VALUES (1)", [])
+            })
+            .await;
+        assert!(failed.is_err());
+        let count: i64 = db
+            .transaction(|tx| tx.query_row("SELECT count(*) FROM history", [], |row| row.get(0)))
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_script() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db
+            .find_conversation("test")
+            .await
+            .expect("failed to find conversation");
+        assert_eq!(db.get_script(conversation).await.unwrap(), None);
+        db.set_script(conversation, Some("fn post_reply(r) { r }"))
+            .await
+            .expect("failed to set script");
+        assert_eq!(
+            db.get_script(conversation).await.unwrap().as_deref(),
+            Some("fn post_reply(r) { r }")
+        );
+        db.set_script::<&str>(conversation, None)
+            .await
+            .expect("failed to remove script");
+        assert_eq!(db.get_script(conversation).await.unwrap(), None);
     }
 }
@@ -4,11 +4,357 @@ CREATE TABLE IF NOT EXISTS conversation (
     name       VARCHAR(255) NOT NULL UNIQUE,
     max_tokens INTEGER NOT NULL DEFAULT 256,
     model      TEXT NOT NULL DEFAULT 'gpt-3.5-turbo',
-    prompt     TEXT
+    prompt     TEXT,
+    guild_id   TEXT
 );
 
 CREATE TABLE IF NOT EXISTS history (
    id           INTEGER PRIMARY KEY,
    conversation INTEGER NOT NULL REFERENCES conversation(id),
-   message      TEXT NOT NULL
-);
\ No newline at end of file
+   message      TEXT NOT NULL,
+   created_at   TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS script (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   source       TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS transforms (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   transforms   TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS games (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   state        TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS encounters (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   state        TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS sheets (
+   conversation INTEGER NOT NULL REFERENCES conversation(id),
+   user_id      TEXT NOT NULL,
+   user_name    TEXT NOT NULL,
+   key          TEXT NOT NULL,
+   value        TEXT NOT NULL,
+   PRIMARY KEY (conversation, user_id, key)
+);
+
+CREATE TABLE IF NOT EXISTS wallets (
+   conversation INTEGER NOT NULL REFERENCES conversation(id),
+   user_id      TEXT NOT NULL,
+   user_name    TEXT NOT NULL,
+   balance      INTEGER NOT NULL DEFAULT 0,
+   last_daily   TEXT,
+   PRIMARY KEY (conversation, user_id)
+);
+
+CREATE TABLE IF NOT EXISTS inventory (
+   conversation INTEGER NOT NULL REFERENCES conversation(id),
+   user_id      TEXT NOT NULL,
+   item         TEXT NOT NULL,
+   quantity     INTEGER NOT NULL DEFAULT 0,
+   PRIMARY KEY (conversation, user_id, item)
+);
+
+CREATE TABLE IF NOT EXISTS quests (
+   id           INTEGER PRIMARY KEY,
+   conversation INTEGER NOT NULL REFERENCES conversation(id),
+   title        TEXT NOT NULL,
+   description  TEXT NOT NULL,
+   assignee     TEXT,
+   created_at   TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   completed_at TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS quiet (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   muted_until  TEXT,
+   dnd_start    TEXT,
+   dnd_end      TEXT
+);
+
+CREATE TABLE IF NOT EXISTS privacy (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   mode         TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS blocked_users (
+   guild_id TEXT NOT NULL,
+   user_id  TEXT NOT NULL,
+   PRIMARY KEY (guild_id, user_id)
+);
+
+CREATE TABLE IF NOT EXISTS guild_keys (
+   guild_id TEXT PRIMARY KEY,
+   sealed   BLOB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS nsfw_settings (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   prompt       TEXT,
+   moderation   TEXT NOT NULL DEFAULT 'default'
+);
+
+CREATE TABLE IF NOT EXISTS channels (
+   channel_id   TEXT PRIMARY KEY,
+   conversation INTEGER NOT NULL REFERENCES conversation(id)
+);
+
+CREATE TABLE IF NOT EXISTS topics (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   topic        TEXT,
+   changed_at   TEXT,
+   template     TEXT,
+   topic_seen   BOOLEAN NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS reply_metadata (
+   id                INTEGER PRIMARY KEY,
+   conversation      INTEGER NOT NULL REFERENCES conversation(id),
+   history_id        INTEGER NOT NULL REFERENCES history(id),
+   provider          TEXT NOT NULL,
+   model             TEXT NOT NULL,
+   finish_reason     TEXT,
+   prompt_tokens     INTEGER NOT NULL,
+   completion_tokens INTEGER NOT NULL,
+   latency_ms        INTEGER NOT NULL,
+   moderated         BOOLEAN NOT NULL,
+   tools             TEXT NOT NULL,
+   truncated         INTEGER NOT NULL,
+   fast              BOOLEAN NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS outbox (
+   id              INTEGER PRIMARY KEY,
+   channel_id      TEXT NOT NULL,
+   conversation    INTEGER,
+   content         TEXT NOT NULL,
+   attempts        INTEGER NOT NULL DEFAULT 0,
+   next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS opinion_opt_outs (
+   user_id TEXT PRIMARY KEY
+);
+
+CREATE TABLE IF NOT EXISTS templates (
+   guild_id TEXT NOT NULL,
+   name     TEXT NOT NULL,
+   source   TEXT NOT NULL,
+   PRIMARY KEY (guild_id, name)
+);
+
+CREATE TABLE IF NOT EXISTS conversation_settings (
+   conversation INTEGER NOT NULL REFERENCES conversation(id),
+   key          TEXT NOT NULL,
+   value        TEXT NOT NULL,
+   PRIMARY KEY (conversation, key)
+);
+
+CREATE TABLE IF NOT EXISTS guild_settings (
+   guild_id TEXT NOT NULL,
+   key      TEXT NOT NULL,
+   value    TEXT NOT NULL,
+   PRIMARY KEY (guild_id, key)
+);
+
+CREATE TABLE IF NOT EXISTS settings (
+   key   TEXT PRIMARY KEY,
+   value TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS descriptions (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   description  TEXT NOT NULL,
+   described_at TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS cursors (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   message_id   INTEGER NOT NULL,
+   updated_at   TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS handled_messages (
+   message_id   INTEGER PRIMARY KEY,
+   conversation INTEGER NOT NULL REFERENCES conversation(id)
+);
+
+CREATE TABLE IF NOT EXISTS audit_log (
+   id           INTEGER PRIMARY KEY,
+   conversation INTEGER NOT NULL REFERENCES conversation(id),
+   request      TEXT NOT NULL,
+   response     TEXT NOT NULL,
+   created_at   TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS reports (
+   id           INTEGER PRIMARY KEY,
+   conversation INTEGER NOT NULL REFERENCES conversation(id),
+   channel_id   TEXT NOT NULL,
+   message_id   TEXT NOT NULL,
+   reporter_id  TEXT NOT NULL,
+   reason       TEXT NOT NULL,
+   content      TEXT NOT NULL,
+   created_at   TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS prompt_schedules (
+   conversation INTEGER NOT NULL REFERENCES conversation(id),
+   name         TEXT NOT NULL,
+   prompt       TEXT NOT NULL,
+   days         TEXT NOT NULL DEFAULT 'every',
+   from_time    TEXT,
+   until_time   TEXT,
+   starts_at    TIMESTAMP,
+   ends_at      TIMESTAMP,
+   PRIMARY KEY (conversation, name)
+);
+
+CREATE TABLE IF NOT EXISTS birthdays (
+   guild_id     TEXT NOT NULL,
+   user_id      TEXT NOT NULL,
+   user_name    TEXT NOT NULL,
+   month        INTEGER NOT NULL,
+   day          INTEGER NOT NULL,
+   greeted_year INTEGER,
+   PRIMARY KEY (guild_id, user_id)
+);
+
+CREATE TABLE IF NOT EXISTS karma (
+   conversation INTEGER NOT NULL REFERENCES conversation(id),
+   user_id      TEXT NOT NULL,
+   score        INTEGER NOT NULL DEFAULT 0,
+   updated_at   TIMESTAMP NOT NULL,
+   PRIMARY KEY (conversation, user_id)
+);
+
+CREATE TABLE IF NOT EXISTS karma_reactions (
+   conversation INTEGER NOT NULL REFERENCES conversation(id),
+   message_id   TEXT NOT NULL,
+   user_id      TEXT NOT NULL,
+   emoji        TEXT NOT NULL,
+   delta        INTEGER NOT NULL,
+   PRIMARY KEY (message_id, user_id, emoji)
+);
+
+CREATE TABLE IF NOT EXISTS relationships (
+   conversation INTEGER NOT NULL REFERENCES conversation(id),
+   user_id      TEXT NOT NULL,
+   user_name    TEXT NOT NULL,
+   affinity     INTEGER NOT NULL DEFAULT 0,
+   notes        TEXT NOT NULL DEFAULT '[]',
+   updated_at   TIMESTAMP NOT NULL,
+   PRIMARY KEY (conversation, user_id)
+);
+
+CREATE TABLE IF NOT EXISTS moods (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   mood         TEXT NOT NULL DEFAULT 'content',
+   updated_at   TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS idle_chatter (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   chattered_at TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS channel_activity (
+   channel_id    TEXT PRIMARY KEY,
+   last_activity TIMESTAMP NOT NULL,
+   by_bot        BOOLEAN NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS guild_milestones (
+   guild_id           TEXT PRIMARY KEY,
+   member_count       INTEGER,
+   anniversary_year   INTEGER,
+   members_celebrated INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS shadow_log (
+   id           INTEGER PRIMARY KEY,
+   conversation INTEGER NOT NULL REFERENCES conversation(id),
+   stage        TEXT NOT NULL,
+   output       TEXT NOT NULL,
+   created_at   TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS flags (
+   scope    TEXT NOT NULL,
+   scope_id INTEGER NOT NULL,
+   flag     TEXT NOT NULL,
+   enabled  BOOLEAN NOT NULL,
+   PRIMARY KEY (scope, scope_id, flag)
+);
+
+CREATE TABLE IF NOT EXISTS latency_modes (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   fast         BOOLEAN NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS canned_responses (
+   id       INTEGER PRIMARY KEY,
+   guild_id TEXT NOT NULL,
+   pattern  TEXT NOT NULL,
+   response TEXT NOT NULL,
+   weight   INTEGER NOT NULL DEFAULT 1
+);
+
+CREATE TABLE IF NOT EXISTS faqs (
+   id              INTEGER PRIMARY KEY,
+   guild_id        TEXT NOT NULL,
+   question        TEXT NOT NULL,
+   answer          TEXT NOT NULL,
+   embedding       BLOB NOT NULL,
+   embedding_model TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS message_embeddings (
+   history_id      INTEGER PRIMARY KEY REFERENCES history(id),
+   conversation    INTEGER NOT NULL REFERENCES conversation(id),
+   embedding       BLOB NOT NULL,
+   embedding_model TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS quantized_embeddings (
+   id              INTEGER PRIMARY KEY AUTOINCREMENT,
+   history_id      INTEGER NOT NULL UNIQUE REFERENCES history(id),
+   conversation    INTEGER NOT NULL REFERENCES conversation(id),
+   embedding       BLOB NOT NULL,
+   embedding_model TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS quantized_embeddings_conversation
+   ON quantized_embeddings(conversation, embedding_model);
+
+CREATE TABLE IF NOT EXISTS message_tags (
+   history_id   INTEGER PRIMARY KEY REFERENCES history(id),
+   conversation INTEGER NOT NULL REFERENCES conversation(id),
+   sentiment    INTEGER NOT NULL,
+   intent       TEXT
+);
+
+CREATE TABLE IF NOT EXISTS presets (
+   name        TEXT PRIMARY KEY,
+   description TEXT NOT NULL DEFAULT '',
+   model       TEXT,
+   temperature REAL,
+   max_tokens  INTEGER,
+   prompt      TEXT
+);
+
+CREATE TABLE IF NOT EXISTS lore (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   lore         TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS deflections (
+   conversation INTEGER PRIMARY KEY REFERENCES conversation(id),
+   deflections  TEXT NOT NULL
+);
@@ -0,0 +1,117 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::params;
+
+/// How many requests to keep per conversation. Older ones are dropped as new ones
+/// are logged.
+const AUDIT_ENTRIES_KEPT: i64 = 200;
+
+/// One request to the model, exactly as sent, and its response exactly as received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub created_at: String,
+    pub request: String,
+    pub response: String,
+}
+
+impl Database {
+    /// Log a request (with its rendered prompt) and the raw response to it, for
+    /// conversations with the audit setting on.
+    pub async fn add_audit_entry(
+        &self,
+        conversation: Conversation,
+        request: String,
+        response: String,
+    ) -> Result<()> {
+        self.transaction(move |tx| {
+            tx.execute(
+                "INSERT INTO audit_log (conversation, request, response, created_at)
+                VALUES (?1, ?2, ?3, CURRENT_TIMESTAMP)",
+                params![conversation.0, request, response],
+            )?;
+            tx.execute(
+                "DELETE FROM audit_log WHERE conversation = ?1 AND id NOT IN
+                (SELECT id FROM audit_log WHERE conversation = ?1
+                ORDER BY id DESC LIMIT ?2)",
+                params![conversation.0, AUDIT_ENTRIES_KEPT],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// The conversation's latest `limit` audit entries, oldest first.
+    pub async fn audit_entries(
+        &self,
+        conversation: Conversation,
+        limit: usize,
+    ) -> Result<Vec<AuditEntry>> {
+        let entries = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT created_at, request, response FROM
+                    (SELECT * FROM audit_log WHERE conversation = ?1 ORDER BY id DESC LIMIT ?2)
+                    ORDER BY id",
+                )?;
+                let rows = stmt.query_map(params![conversation.0, limit as i64], |row| {
+                    Ok(AuditEntry {
+                        created_at: row.get(0)?,
+                        request: row.get(1)?,
+                        response: row.get(2)?,
+                    })
+                })?;
+                rows.collect::<Result<Vec<_>, rusqlite::Error>>()
+            })
+            .await?;
+
+        Ok(entries)
+    }
+
+    /// Delete a conversation's audit log. Returns how many entries were deleted.
+    pub async fn redact_audit_log(&self, conversation: Conversation) -> Result<usize> {
+        let deleted = self
+            .conn
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM audit_log WHERE conversation = ?1",
+                    params![conversation.0],
+                )
+            })
+            .await?;
+
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("audited").await.unwrap();
+        let other = db.find_conversation("other").await.unwrap();
+        for i in 0..3 {
+            db.add_audit_entry(conversation, format!("request {i}"), "{}".to_owned())
+                .await
+                .unwrap();
+        }
+        db.add_audit_entry(other, "request".to_owned(), "{}".to_owned())
+            .await
+            .unwrap();
+
+        let entries = db.audit_entries(conversation, 2).await.unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| e.request.as_str())
+                .collect::<Vec<_>>(),
+            vec!["request 1", "request 2"]
+        );
+        assert_eq!(db.redact_audit_log(conversation).await.unwrap(), 3);
+        assert!(db.audit_entries(conversation, 10).await.unwrap().is_empty());
+        assert_eq!(db.audit_entries(other, 10).await.unwrap().len(), 1);
+    }
+}
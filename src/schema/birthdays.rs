@@ -0,0 +1,208 @@
+use super::{Conversation, Database};
+use chrono::{Datelike, NaiveDate};
+use eyre::{eyre, Result};
+use rusqlite::params;
+
+/// Someone's birthday in a server. Only the day is kept, not the year.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Birthday {
+    pub user_id: String,
+    pub user_name: String,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Birthday {
+    /// The day it's celebrated in `year`. Leap day birthdays are on the 28th of
+    /// February in other years.
+    pub fn in_year(&self, year: i32) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(year, self.month, self.day).or_else(|| {
+            match (self.month, self.day) {
+                (2, 29) => NaiveDate::from_ymd_opt(year, 2, 28),
+                _ => None,
+            }
+        })
+    }
+
+    /// The next time it's celebrated, on or after `today`.
+    pub fn next(&self, today: NaiveDate) -> Option<NaiveDate> {
+        [today.year(), today.year() + 1]
+            .into_iter()
+            .filter_map(|year| self.in_year(year))
+            .find(|date| *date >= today)
+    }
+}
+
+/// Check a month and day make a birthday, allowing the 29th of February.
+fn check_birthday(month: u32, day: u32) -> Result<()> {
+    NaiveDate::from_ymd_opt(2000, month, day)
+        .map(|_| ())
+        .ok_or_else(|| eyre!("there's no day {day} in month {month}"))
+}
+
+impl Database {
+    /// Remember (or with None, forget) someone's birthday in a server.
+    pub async fn set_birthday(
+        &self,
+        guild: u64,
+        user_id: String,
+        user_name: String,
+        date: Option<(u32, u32)>,
+    ) -> Result<()> {
+        if let Some((month, day)) = date {
+            check_birthday(month, day)?;
+        }
+
+        self.conn
+            .call(move |conn| {
+                match date {
+                    Some((month, day)) => conn.execute(
+                        "INSERT INTO birthdays (guild_id, user_id, user_name, month, day)
+                        VALUES (?1, ?2, ?3, ?4, ?5)
+                        ON CONFLICT (guild_id, user_id) DO UPDATE SET
+                            user_name = ?3, month = ?4, day = ?5",
+                        params![guild.to_string(), user_id, user_name, month, day],
+                    )?,
+                    None => conn.execute(
+                        "DELETE FROM birthdays WHERE guild_id = ?1 AND user_id = ?2",
+                        params![guild.to_string(), user_id],
+                    )?,
+                };
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// The birthdays in the server a conversation belongs to, none for DMs.
+    pub async fn conversation_birthdays(
+        &self,
+        conversation: Conversation,
+    ) -> Result<Vec<Birthday>> {
+        let birthdays = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT b.user_id, b.user_name, b.month, b.day FROM birthdays b
+                    JOIN conversation c ON c.guild_id = b.guild_id
+                    WHERE c.id = ?1
+                    ORDER BY b.month, b.day",
+                )?;
+                let birthdays = stmt
+                    .query_map(params![conversation.0], |row| {
+                        Ok(Birthday {
+                            user_id: row.get(0)?,
+                            user_name: row.get(1)?,
+                            month: row.get(2)?,
+                            day: row.get(3)?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(birthdays)
+            })
+            .await?;
+
+        Ok(birthdays)
+    }
+
+    /// The birthdays celebrated on `today` that haven't been greeted yet, with their
+    /// server's id.
+    pub async fn due_birthdays(&self, today: NaiveDate) -> Result<Vec<(u64, Birthday)>> {
+        let year = today.year();
+        let rows: Vec<(String, Birthday)> = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT guild_id, user_id, user_name, month, day FROM birthdays
+                    WHERE greeted_year IS NULL OR greeted_year < ?1",
+                )?;
+                let rows = stmt
+                    .query_map(params![year], |row| {
+                        Ok((
+                            row.get(0)?,
+                            Birthday {
+                                user_id: row.get(1)?,
+                                user_name: row.get(2)?,
+                                month: row.get(3)?,
+                                day: row.get(4)?,
+                            },
+                        ))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(rows)
+            })
+            .await?;
+
+        rows.into_iter()
+            .filter(|(_, birthday)| birthday.in_year(year) == Some(today))
+            .map(|(guild, birthday)| Ok((guild.parse()?, birthday)))
+            .collect()
+    }
+
+    /// Note that someone's birthday was greeted this year, so it isn't again.
+    pub async fn birthday_greeted(&self, guild: u64, user_id: String, year: i32) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE birthdays SET greeted_year = ?3 WHERE guild_id = ?1 AND user_id = ?2",
+                    params![guild.to_string(), user_id, year],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next() {
+        let day = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let leap = Birthday {
+            user_id: "1".to_owned(),
+            user_name: "Filly".to_owned(),
+            month: 2,
+            day: 29,
+        };
+        assert_eq!(leap.next(day(2023, 1, 1)), Some(day(2023, 2, 28)));
+        assert_eq!(leap.next(day(2023, 3, 1)), Some(day(2024, 2, 29)));
+        assert!(check_birthday(2, 30).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_due_birthdays() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let today = NaiveDate::from_ymd_opt(2023, 9, 1).unwrap();
+        db.set_birthday(7, "1".to_owned(), "Filly".to_owned(), Some((9, 1)))
+            .await
+            .unwrap();
+        db.set_birthday(7, "2".to_owned(), "Colt".to_owned(), Some((9, 2)))
+            .await
+            .unwrap();
+        assert!(db
+            .set_birthday(7, "3".to_owned(), "Nag".to_owned(), Some((13, 1)))
+            .await
+            .is_err());
+
+        let due = db.due_birthdays(today).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].0, 7);
+        assert_eq!(due[0].1.user_name, "Filly");
+
+        db.birthday_greeted(7, "1".to_owned(), 2023).await.unwrap();
+        assert!(db.due_birthdays(today).await.unwrap().is_empty());
+        db.set_birthday(7, "1".to_owned(), "Filly".to_owned(), None)
+            .await
+            .unwrap();
+        assert!(db
+            .due_birthdays(NaiveDate::from_ymd_opt(2024, 9, 1).unwrap())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
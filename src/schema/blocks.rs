@@ -0,0 +1,97 @@
+use super::Database;
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+
+/// Blocks are per guild; `None` is used for direct messages.
+fn scope(guild_id: Option<u64>) -> String {
+    guild_id.map(|g| g.to_string()).unwrap_or_default()
+}
+
+impl Database {
+    pub async fn block_user(&self, guild_id: Option<u64>, user_id: u64) -> Result<()> {
+        let guild_id = scope(guild_id);
+
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO blocked_users (guild_id, user_id) VALUES (?1, ?2)
+                    ON CONFLICT (guild_id, user_id) DO NOTHING",
+                    params![guild_id, user_id.to_string()],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn unblock_user(&self, guild_id: Option<u64>, user_id: u64) -> Result<bool> {
+        let guild_id = scope(guild_id);
+
+        let deleted = self
+            .conn
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM blocked_users WHERE guild_id = ?1 AND user_id = ?2",
+                    params![guild_id, user_id.to_string()],
+                )
+            })
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    pub async fn is_blocked(&self, guild_id: Option<u64>, user_id: u64) -> Result<bool> {
+        let guild_id = scope(guild_id);
+
+        let blocked = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT 1 FROM blocked_users WHERE guild_id = ?1 AND user_id = ?2",
+                    params![guild_id, user_id.to_string()],
+                    |_| Ok(()),
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(blocked.is_some())
+    }
+
+    /// Everyone blocked in a guild (or with `None`, in direct messages).
+    pub async fn blocked_users(&self, guild_id: Option<u64>) -> Result<Vec<u64>> {
+        let guild_id = scope(guild_id);
+
+        let users: Vec<String> = self
+            .conn
+            .call(move |conn| {
+                let mut stmt =
+                    conn.prepare("SELECT user_id FROM blocked_users WHERE guild_id = ?1")?;
+                let rows = stmt.query_map(params![guild_id], |row| row.get(0))?;
+                rows.collect::<Result<Vec<_>, rusqlite::Error>>()
+            })
+            .await?;
+
+        Ok(users.iter().filter_map(|u| u.parse().ok()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_blocks() {
+        let db = Database::new(None).await.expect("failed to create db");
+        db.block_user(Some(1), 42).await.unwrap();
+        assert!(db.is_blocked(Some(1), 42).await.unwrap());
+        assert!(!db.is_blocked(Some(2), 42).await.unwrap());
+        assert!(!db.is_blocked(None, 42).await.unwrap());
+        assert_eq!(db.blocked_users(Some(1)).await.unwrap(), vec![42]);
+        assert!(db.blocked_users(Some(2)).await.unwrap().is_empty());
+        assert!(db.unblock_user(Some(1), 42).await.unwrap());
+        assert!(!db.unblock_user(Some(1), 42).await.unwrap());
+        assert!(!db.is_blocked(Some(1), 42).await.unwrap());
+    }
+}
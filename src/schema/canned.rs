@@ -0,0 +1,115 @@
+use super::Database;
+use eyre::Result;
+use rusqlite::params;
+
+/// A fixed answer to messages matching a pattern. Several with the same pattern are
+/// picked between by weight.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CannedResponse {
+    pub id: i64,
+    /// A case-insensitive regular expression.
+    pub pattern: String,
+    pub response: String,
+    pub weight: u32,
+}
+
+/// Canned responses are per guild; `None` is used for direct messages.
+fn scope(guild_id: Option<u64>) -> String {
+    guild_id.map(|g| g.to_string()).unwrap_or_default()
+}
+
+impl Database {
+    /// Add an answer for messages matching `pattern`, returning its id.
+    pub async fn add_canned_response(
+        &self,
+        guild_id: Option<u64>,
+        pattern: String,
+        response: String,
+        weight: u32,
+    ) -> Result<i64> {
+        let guild_id = scope(guild_id);
+
+        let id = self
+            .conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO canned_responses (guild_id, pattern, response, weight)
+                    VALUES (?1, ?2, ?3, ?4)",
+                    params![guild_id, pattern, response, weight],
+                )?;
+                Ok(conn.last_insert_rowid())
+            })
+            .await?;
+
+        Ok(id)
+    }
+
+    pub async fn remove_canned_response(&self, guild_id: Option<u64>, id: i64) -> Result<bool> {
+        let guild_id = scope(guild_id);
+
+        let deleted = self
+            .conn
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM canned_responses WHERE guild_id = ?1 AND id = ?2",
+                    params![guild_id, id],
+                )
+            })
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    /// A server's canned responses, oldest first.
+    pub async fn canned_responses(&self, guild_id: Option<u64>) -> Result<Vec<CannedResponse>> {
+        let guild_id = scope(guild_id);
+
+        let responses = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, pattern, response, weight FROM canned_responses
+                    WHERE guild_id = ?1 ORDER BY id",
+                )?;
+                let responses = stmt
+                    .query_map(params![guild_id], |row| {
+                        Ok(CannedResponse {
+                            id: row.get(0)?,
+                            pattern: row.get(1)?,
+                            response: row.get(2)?,
+                            weight: row.get(3)?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(responses)
+            })
+            .await?;
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_canned_responses() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let add = |guild, response: &str| {
+            db.add_canned_response(guild, "rules".to_owned(), response.to_owned(), 1)
+        };
+        let first = add(Some(1), "See #rules").await.unwrap();
+        add(Some(1), "Read #rules, please").await.unwrap();
+        add(None, "No rules in DMs").await.unwrap();
+
+        let responses = db.canned_responses(Some(1)).await.unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].response, "See #rules");
+        assert_eq!(db.canned_responses(None).await.unwrap().len(), 1);
+
+        assert!(!db.remove_canned_response(None, first).await.unwrap());
+        assert!(db.remove_canned_response(Some(1), first).await.unwrap());
+        assert_eq!(db.canned_responses(Some(1)).await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,246 @@
+use super::{Conversation, Database};
+use eyre::{eyre, Result};
+use rusqlite::{params, OptionalExtension};
+
+impl Database {
+    /// Find the conversation for a platform channel id, creating it if needed.
+    /// `name` is the channel's current human readable name, which (after the name of
+    /// its server, if any, so every server can have a #general) is kept as the
+    /// conversation's display name so history survives channel renames.
+    pub async fn find_channel_conversation<S>(
+        &self,
+        channel_id: S,
+        guild: Option<(u64, S)>,
+        name: S,
+    ) -> Result<Conversation>
+    where
+        S: AsRef<str>,
+    {
+        let channel_id = channel_id.as_ref().to_owned();
+        // conversations from before server names were added go by the bare name
+        let bare_name = name.as_ref().to_owned();
+        let (guild_id, name) = match guild {
+            Some((id, guild_name)) => (
+                Some(id.to_string()),
+                format!("{}/{}", guild_name.as_ref(), bare_name),
+            ),
+            None => (None, bare_name.clone()),
+        };
+
+        let conversation = self
+            .conn
+            .call(move |conn| {
+                let existing: Option<(i64, String)> = conn
+                    .query_row(
+                        "SELECT c.id, c.name FROM channels ch
+                        JOIN conversation c ON c.id = ch.conversation
+                        WHERE ch.channel_id = ?1",
+                        params![channel_id],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )
+                    .optional()?;
+                if let Some((id, old_name)) = existing {
+                    conn.execute(
+                        "UPDATE conversation SET guild_id = ?2 WHERE id = ?1 AND guild_id IS NULL",
+                        params![id, guild_id],
+                    )?;
+                    if old_name != name {
+                        // the channel was renamed. Linked conversations keep their name,
+                        // and a name that is already taken is left alone.
+                        conn.execute(
+                            "UPDATE OR IGNORE conversation SET name = ?2
+                            WHERE id = ?1
+                            AND (SELECT COUNT(*) FROM channels WHERE conversation = ?1) = 1",
+                            params![id, name],
+                        )?;
+                    }
+                    return Ok(Conversation(id));
+                }
+
+                // conversations from before channel ids were tracked are found by name,
+                // taking the new style of name over the bare one
+                let adopted: Option<i64> = conn
+                    .query_row(
+                        "SELECT id FROM conversation WHERE name IN (?1, ?2)
+                        AND id NOT IN (SELECT conversation FROM channels)
+                        ORDER BY name = ?1 DESC LIMIT 1",
+                        params![name, bare_name],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                let id = match adopted {
+                    Some(id) => {
+                        conn.execute(
+                            "UPDATE OR IGNORE conversation SET name = ?2, guild_id = ?3
+                            WHERE id = ?1",
+                            params![id, name, guild_id],
+                        )?;
+                        id
+                    }
+                    None => conn.query_row(
+                        "INSERT INTO conversation (name, guild_id) VALUES (
+                            CASE WHEN EXISTS (SELECT 1 FROM conversation WHERE name = ?1)
+                            THEN ?1 || ' (' || ?2 || ')' ELSE ?1 END,
+                            ?3
+                        ) RETURNING id",
+                        params![name, channel_id, guild_id],
+                        |row| row.get(0),
+                    )?,
+                };
+                conn.execute(
+                    "INSERT INTO channels (channel_id, conversation) VALUES (?1, ?2)",
+                    params![channel_id, id],
+                )?;
+
+                Ok(Conversation(id))
+            })
+            .await?;
+
+        Ok(conversation)
+    }
+
+    /// Make a channel share another conversation's history.
+    pub async fn link_channel<S>(&self, channel_id: S, conversation: Conversation) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        let channel_id = channel_id.as_ref().to_owned();
+
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO channels (channel_id, conversation) VALUES (?1, ?2)
+                    ON CONFLICT (channel_id) DO UPDATE SET conversation = ?2",
+                    params![channel_id, conversation.0],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn rename_conversation<S>(&self, conversation: Conversation, name: S) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref().to_owned();
+
+        let updated = self
+            .conn
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE OR IGNORE conversation SET name = ?2 WHERE id = ?1",
+                    params![conversation.0, name],
+                )
+            })
+            .await?;
+
+        if updated == 0 {
+            return Err(eyre!("that name is already taken"));
+        }
+        Ok(())
+    }
+
+    pub async fn conversation_name(&self, conversation: Conversation) -> Result<String> {
+        let name = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT name FROM conversation WHERE id = ?1",
+                    params![conversation.0],
+                    |row| row.get(0),
+                )
+            })
+            .await?;
+
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_channels() {
+        let db = Database::new(None).await.expect("failed to create db");
+        // a conversation from before channel ids
+        let legacy = db.find_conversation("#general").await.unwrap();
+        let general = db
+            .find_channel_conversation("1", None, "#general")
+            .await
+            .unwrap();
+        assert_eq!(general, legacy);
+
+        // renaming the channel keeps the conversation
+        let renamed = db
+            .find_channel_conversation("1", None, "#lobby")
+            .await
+            .unwrap();
+        assert_eq!(renamed, general);
+        assert_eq!(db.conversation_name(general).await.unwrap(), "#lobby");
+
+        // a clashing name gets the channel id
+        let other = db
+            .find_channel_conversation("2", None, "#lobby")
+            .await
+            .unwrap();
+        assert_ne!(other, general);
+        assert_eq!(db.conversation_name(other).await.unwrap(), "#lobby (2)");
+        assert!(db.rename_conversation(other, "#lobby").await.is_err());
+        db.rename_conversation(other, "#elsewhere").await.unwrap();
+
+        db.link_channel("2", general).await.unwrap();
+        assert_eq!(
+            db.find_channel_conversation("2", None, "#elsewhere")
+                .await
+                .unwrap(),
+            general
+        );
+        // linked conversations aren't renamed after either channel
+        assert_eq!(db.conversation_name(general).await.unwrap(), "#lobby");
+    }
+
+    #[tokio::test]
+    async fn test_guild_channels() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let ranch = Some((1, "Ranch"));
+        let stable = Some((2, "Stable"));
+        // a conversation from before server names, already tied to its channel
+        let legacy = db
+            .find_channel_conversation("10", None, "#general")
+            .await
+            .unwrap();
+
+        let general = db
+            .find_channel_conversation("10", ranch, "#general")
+            .await
+            .unwrap();
+        assert_eq!(general, legacy);
+        assert_eq!(
+            db.conversation_name(general).await.unwrap(),
+            "Ranch/#general"
+        );
+
+        // every server can have a #general of its own
+        let other = db
+            .find_channel_conversation("20", stable, "#general")
+            .await
+            .unwrap();
+        assert_ne!(other, general);
+        assert_eq!(
+            db.conversation_name(other).await.unwrap(),
+            "Stable/#general"
+        );
+
+        // from before channel ids, found by the bare name
+        let old = db.find_conversation("#lobby").await.unwrap();
+        let lobby = db
+            .find_channel_conversation("21", stable, "#lobby")
+            .await
+            .unwrap();
+        assert_eq!(lobby, old);
+        assert_eq!(db.conversation_name(lobby).await.unwrap(), "Stable/#lobby");
+    }
+}
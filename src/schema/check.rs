@@ -0,0 +1,132 @@
+use super::Database;
+use eyre::Result;
+
+/// What a stored piece of user supplied configuration is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    Prompt,
+    NsfwPrompt,
+    TopicTemplate,
+    Script,
+    Transforms,
+    /// A server's shared template, labelled with its name and server rather than a
+    /// conversation.
+    Template,
+}
+
+/// A template, script or transform pipeline stored for a conversation.
+#[derive(Debug, PartialEq)]
+pub struct StoredSource {
+    pub conversation: String,
+    pub kind: SourceKind,
+    pub source: String,
+}
+
+impl Database {
+    /// SQLite's own consistency check. Returns the problems it found.
+    pub async fn integrity_check(&self) -> Result<Vec<String>> {
+        let problems = self
+            .conn
+            .call(|conn| {
+                let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+                let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+                let rows = rows.collect::<Result<Vec<_>, rusqlite::Error>>()?;
+                Ok(rows.into_iter().filter(|r| r != "ok").collect())
+            })
+            .await?;
+
+        Ok(problems)
+    }
+
+    /// Every stored prompt, template, script and transform pipeline.
+    pub async fn stored_sources(&self) -> Result<Vec<StoredSource>> {
+        let sources = self
+            .conn
+            .call(|conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT c.name, 0, c.prompt FROM conversation c WHERE c.prompt IS NOT NULL
+                    UNION ALL
+                    SELECT c.name, 1, n.prompt FROM nsfw_settings n
+                    JOIN conversation c ON c.id = n.conversation WHERE n.prompt IS NOT NULL
+                    UNION ALL
+                    SELECT c.name, 2, t.template FROM topics t
+                    JOIN conversation c ON c.id = t.conversation WHERE t.template IS NOT NULL
+                    UNION ALL
+                    SELECT c.name, 3, s.source FROM script s
+                    JOIN conversation c ON c.id = s.conversation
+                    UNION ALL
+                    SELECT c.name, 4, t.transforms FROM transforms t
+                    JOIN conversation c ON c.id = t.conversation
+                    UNION ALL
+                    SELECT t.name || ' (server ' || t.guild_id || ')', 5, t.source FROM templates t",
+                )?;
+                let rows = stmt.query_map([], |row| {
+                    let kind = match row.get::<_, i64>(1)? {
+                        0 => SourceKind::Prompt,
+                        1 => SourceKind::NsfwPrompt,
+                        2 => SourceKind::TopicTemplate,
+                        3 => SourceKind::Script,
+                        4 => SourceKind::Transforms,
+                        _ => SourceKind::Template,
+                    };
+                    Ok(StoredSource {
+                        conversation: row.get(0)?,
+                        kind,
+                        source: row.get(2)?,
+                    })
+                })?;
+                rows.collect::<Result<Vec<_>, rusqlite::Error>>()
+            })
+            .await?;
+
+        Ok(sources)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stored_sources() {
+        let db = Database::new(None).await.expect("failed to create db");
+        assert!(db.integrity_check().await.unwrap().is_empty());
+
+        let conversation = db.find_conversation("#general").await.unwrap();
+        db.set_prompt(conversation, "Hello {{ user_nick }}")
+            .await
+            .unwrap();
+        db.set_topic_template(conversation, Some("{{ topic"))
+            .await
+            .unwrap();
+        db.set_topic_template(db.find_conversation("#other").await.unwrap(), None::<&str>)
+            .await
+            .unwrap();
+
+        db.set_template(1, "base", Some("{% block x %}{% endblock %}".to_owned()))
+            .await
+            .unwrap();
+
+        let sources = db.stored_sources().await.unwrap();
+        assert_eq!(
+            sources,
+            vec![
+                StoredSource {
+                    conversation: "#general".to_owned(),
+                    kind: SourceKind::Prompt,
+                    source: "Hello {{ user_nick }}".to_owned(),
+                },
+                StoredSource {
+                    conversation: "#general".to_owned(),
+                    kind: SourceKind::TopicTemplate,
+                    source: "{{ topic".to_owned(),
+                },
+                StoredSource {
+                    conversation: "base (server 1)".to_owned(),
+                    kind: SourceKind::Template,
+                    source: "{% block x %}{% endblock %}".to_owned(),
+                },
+            ]
+        );
+    }
+}
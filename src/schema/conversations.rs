@@ -0,0 +1,92 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::params;
+use serde::Serialize;
+
+/// A conversation at a glance, for `horse-npc conversations`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConversationInfo {
+    #[serde(skip)]
+    pub conversation: Conversation,
+    pub id: i64,
+    pub name: String,
+    /// The server the conversation's channel is in, if it is known.
+    pub guild_id: Option<u64>,
+    pub messages: usize,
+    /// When the latest message was added, unless it is from before timestamps were kept.
+    pub last_active: Option<String>,
+    pub prompt: Option<String>,
+    /// What the model made of the conversation when last asked.
+    pub description: Option<String>,
+}
+
+impl Database {
+    /// Every conversation, or with a name just that one, ordered by name.
+    pub async fn conversation_infos(&self, name: Option<String>) -> Result<Vec<ConversationInfo>> {
+        let infos = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT c.id, c.name, count(h.id), max(h.created_at), c.prompt, d.description,
+                        c.guild_id
+                    FROM conversation c LEFT JOIN history h ON h.conversation = c.id
+                    LEFT JOIN descriptions d ON d.conversation = c.id
+                    WHERE ?1 IS NULL OR c.name = ?1
+                    GROUP BY c.id ORDER BY c.name",
+                )?;
+                let rows = stmt.query_map(params![name], |row| {
+                    Ok(ConversationInfo {
+                        conversation: Conversation(row.get(0)?),
+                        id: row.get(0)?,
+                        name: row.get(1)?,
+                        messages: row.get(2)?,
+                        last_active: row.get(3)?,
+                        prompt: row.get(4)?,
+                        description: row.get(5)?,
+                        guild_id: row
+                            .get::<_, Option<String>>(6)?
+                            .and_then(|id| id.parse().ok()),
+                    })
+                })?;
+                rows.collect::<Result<Vec<_>, rusqlite::Error>>()
+            })
+            .await?;
+
+        Ok(infos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_conversation_infos() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let busy = db.find_conversation("#busy").await.unwrap();
+        db.find_conversation("#quiet").await.unwrap();
+        db.add_user_message(busy, "hello").await.unwrap();
+        db.add_user_message(busy, "again").await.unwrap();
+        db.set_prompt(busy, "You are a horse.").await.unwrap();
+        db.set_description(busy, "Small talk.".to_owned())
+            .await
+            .unwrap();
+
+        let infos = db.conversation_infos(None).await.unwrap();
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].name, "#busy");
+        assert_eq!(infos[0].messages, 2);
+        assert!(infos[0].last_active.is_some());
+        assert_eq!(infos[0].prompt.as_deref(), Some("You are a horse."));
+        assert_eq!(infos[0].description.as_deref(), Some("Small talk."));
+        assert_eq!(infos[1].messages, 0);
+        assert_eq!(infos[1].last_active, None);
+
+        let quiet = db
+            .conversation_infos(Some("#quiet".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(quiet.len(), 1);
+        assert_eq!(quiet[0].name, "#quiet");
+    }
+}
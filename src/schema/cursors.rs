@@ -0,0 +1,92 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::params;
+
+/// How many handled message ids to remember per conversation. Redeliveries are of
+/// recent messages, and anything older than the cursor isn't caught up on anyway.
+const HANDLED_MESSAGES_KEPT: i64 = 100;
+
+impl Database {
+    /// Claim a Discord message for handling, returning false if it already was, say
+    /// because the gateway delivered it twice or a catch-up found it again. Claimed
+    /// messages move the conversation's cursor along too. Discord message ids only go
+    /// up, so an older id leaves the cursor alone.
+    pub async fn claim_message(&self, conversation: Conversation, message_id: u64) -> Result<bool> {
+        let message_id = message_id as i64;
+
+        self.transaction(move |tx| {
+            let claimed = tx.execute(
+                "INSERT OR IGNORE INTO handled_messages (message_id, conversation) VALUES (?1, ?2)",
+                params![message_id, conversation.0],
+            )? == 1;
+            if !claimed {
+                return Ok(false);
+            }
+            tx.execute(
+                "DELETE FROM handled_messages WHERE conversation = ?1 AND message_id NOT IN
+                (SELECT message_id FROM handled_messages WHERE conversation = ?1
+                ORDER BY message_id DESC LIMIT ?2)",
+                params![conversation.0, HANDLED_MESSAGES_KEPT],
+            )?;
+            tx.execute(
+                "INSERT INTO cursors (conversation, message_id, updated_at)
+                VALUES (?1, ?2, CURRENT_TIMESTAMP)
+                ON CONFLICT (conversation) DO UPDATE SET
+                    message_id = max(message_id, ?2), updated_at = CURRENT_TIMESTAMP",
+                params![conversation.0, message_id],
+            )?;
+            Ok(true)
+        })
+        .await
+    }
+
+    /// Every channel of the conversations active within `age`, with the last message
+    /// seen there, for looking for messages that arrived while the bot was away.
+    pub async fn catch_up_channels(&self, age: chrono::Duration) -> Result<Vec<(u64, u64)>> {
+        let cutoff = format!("-{} seconds", age.num_seconds());
+
+        let channels: Vec<(String, i64)> = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT ch.channel_id, cu.message_id FROM channels ch
+                    JOIN cursors cu ON cu.conversation = ch.conversation
+                    WHERE cu.updated_at >= datetime('now', ?1)
+                    ORDER BY cu.updated_at DESC",
+                )?;
+                let rows = stmt.query_map(params![cutoff], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect()
+            })
+            .await?;
+
+        Ok(channels
+            .into_iter()
+            .filter_map(|(channel, message_id)| Some((channel.parse().ok()?, message_id as u64)))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cursor() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db
+            .find_channel_conversation("42", None, "#paddock")
+            .await
+            .unwrap();
+        db.find_channel_conversation("43", None, "#quiet")
+            .await
+            .unwrap();
+        let day = chrono::Duration::days(1);
+        assert_eq!(db.catch_up_channels(day).await.unwrap(), vec![]);
+
+        assert!(db.claim_message(conversation, 1000).await.unwrap());
+        assert!(!db.claim_message(conversation, 1000).await.unwrap());
+        // a message that arrived late is still handled, but doesn't move the cursor back
+        assert!(db.claim_message(conversation, 900).await.unwrap());
+        assert_eq!(db.catch_up_channels(day).await.unwrap(), vec![(42, 1000)]);
+    }
+}
@@ -0,0 +1,79 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+
+impl Database {
+    /// Set the templates a conversation answers flagged messages with. With none it goes
+    /// back to the built-in ones.
+    pub async fn set_deflections(
+        &self,
+        conversation: Conversation,
+        deflections: &[String],
+    ) -> Result<()> {
+        let deflections = (!deflections.is_empty())
+            .then(|| serde_json::to_string(deflections))
+            .transpose()?;
+
+        self.conn
+            .call(move |conn| {
+                match deflections {
+                    Some(deflections) => conn.execute(
+                        "INSERT INTO deflections (conversation, deflections) VALUES (?1, ?2)
+                        ON CONFLICT (conversation) DO UPDATE SET deflections = ?2",
+                        params![conversation.0, deflections],
+                    )?,
+                    None => conn.execute(
+                        "DELETE FROM deflections WHERE conversation = ?1",
+                        params![conversation.0],
+                    )?,
+                };
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// A conversation's own deflection templates, empty if it uses the built-in ones.
+    pub async fn deflections(&self, conversation: Conversation) -> Result<Vec<String>> {
+        let deflections: Option<String> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT deflections FROM deflections WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        match deflections {
+            Some(deflections) => Ok(serde_json::from_str(&deflections)?),
+            None => Ok(vec![]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_deflections() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("#lab").await.unwrap();
+        assert!(db.deflections(conversation).await.unwrap().is_empty());
+
+        let deflections = vec![
+            "Beep boop, {{ user_nick }}, I can't process that.".to_owned(),
+            "Error 451.".to_owned(),
+        ];
+        db.set_deflections(conversation, &deflections)
+            .await
+            .unwrap();
+        assert_eq!(db.deflections(conversation).await.unwrap(), deflections);
+        db.set_deflections(conversation, &[]).await.unwrap();
+        assert!(db.deflections(conversation).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,66 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+
+impl Database {
+    pub async fn set_description(
+        &self,
+        conversation: Conversation,
+        description: String,
+    ) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO descriptions (conversation, description, described_at)
+                    VALUES (?1, ?2, CURRENT_TIMESTAMP)
+                    ON CONFLICT (conversation) DO UPDATE SET
+                        description = ?2, described_at = CURRENT_TIMESTAMP",
+                    params![conversation.0, description],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// The latest generated description of a conversation, if it has one.
+    pub async fn description(&self, conversation: Conversation) -> Result<Option<String>> {
+        let description = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT description FROM descriptions WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(description)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_description() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("#tavern").await.unwrap();
+        assert_eq!(db.description(conversation).await.unwrap(), None);
+
+        db.set_description(conversation, "Rowdy tavern banter.".to_owned())
+            .await
+            .unwrap();
+        db.set_description(conversation, "A quiet game of cards.".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(
+            db.description(conversation).await.unwrap().as_deref(),
+            Some("A quiet game of cards.")
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use super::{Conversation, Database};
+use eyre::{eyre, Result};
+use rusqlite::{params, OptionalExtension};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wallet {
+    pub user_name: String,
+    pub balance: i64,
+    pub items: Vec<(String, i64)>,
+}
+
+impl Database {
+    /// Make sure a user has a wallet in a conversation, keeping their name current.
+    pub async fn ensure_wallet<S>(
+        &self,
+        conversation: Conversation,
+        user_id: S,
+        user_name: S,
+    ) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        let user_id = user_id.as_ref().to_owned();
+        let user_name = user_name.as_ref().trim_start_matches('@').to_owned();
+
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO wallets (conversation, user_id, user_name) VALUES (?1, ?2, ?3)
+                    ON CONFLICT (conversation, user_id) DO UPDATE SET user_name = ?3",
+                    params![conversation.0, user_id, user_name],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Find the user id of a wallet owner, given their id or name.
+    pub async fn find_wallet<S>(
+        &self,
+        conversation: Conversation,
+        user: S,
+    ) -> Result<Option<String>>
+    where
+        S: AsRef<str>,
+    {
+        let user = user.as_ref().trim_start_matches('@').to_owned();
+
+        let user_id = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT user_id FROM wallets
+                    WHERE conversation = ?1 AND (user_id = ?2 OR user_name = ?2 COLLATE NOCASE)",
+                    params![conversation.0, user],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(user_id)
+    }
+
+    pub async fn wallet<S>(&self, conversation: Conversation, user_id: S) -> Result<Wallet>
+    where
+        S: AsRef<str>,
+    {
+        let user_id = user_id.as_ref().to_owned();
+
+        let wallet = self
+            .conn
+            .call(move |conn| {
+                let (user_name, balance) = conn.query_row(
+                    "SELECT user_name, balance FROM wallets WHERE conversation = ?1 AND user_id = ?2",
+                    params![conversation.0, user_id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?;
+                let mut stmt = conn.prepare(
+                    "SELECT item, quantity FROM inventory
+                    WHERE conversation = ?1 AND user_id = ?2 AND quantity > 0
+                    ORDER BY item",
+                )?;
+                let items = stmt
+                    .query_map(params![conversation.0, user_id], |row| {
+                        Ok((row.get(0)?, row.get(1)?))
+                    })?
+                    .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+                Ok(Wallet {
+                    user_name,
+                    balance,
+                    items,
+                })
+            })
+            .await?;
+
+        Ok(wallet)
+    }
+
+    /// Add (or with a negative amount, remove) coins, returning the new balance.
+    pub async fn add_coins<S>(
+        &self,
+        conversation: Conversation,
+        user_id: S,
+        amount: i64,
+    ) -> Result<i64>
+    where
+        S: AsRef<str>,
+    {
+        let user_id = user_id.as_ref().to_owned();
+
+        let balance: Option<i64> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "UPDATE wallets SET balance = balance + ?3
+                    WHERE conversation = ?1 AND user_id = ?2 AND balance + ?3 >= 0
+                    RETURNING balance",
+                    params![conversation.0, user_id, amount],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        balance.ok_or_else(|| eyre!("not enough coins"))
+    }
+
+    /// Add (or with a negative quantity, remove) items, returning how many the user now has.
+    pub async fn add_item<S>(
+        &self,
+        conversation: Conversation,
+        user_id: S,
+        item: S,
+        quantity: i64,
+    ) -> Result<i64>
+    where
+        S: AsRef<str>,
+    {
+        let user_id = user_id.as_ref().to_owned();
+        let item = item.as_ref().to_lowercase();
+
+        let quantity: Option<i64> = self
+            .transaction(move |tx| {
+                tx.execute(
+                    "INSERT INTO inventory (conversation, user_id, item) VALUES (?1, ?2, ?3)
+                    ON CONFLICT (conversation, user_id, item) DO NOTHING",
+                    params![conversation.0, user_id, item],
+                )?;
+                let quantity = tx
+                    .query_row(
+                        "UPDATE inventory SET quantity = quantity + ?4
+                        WHERE conversation = ?1 AND user_id = ?2 AND item = ?3
+                        AND quantity + ?4 >= 0
+                        RETURNING quantity",
+                        params![conversation.0, user_id, item, quantity],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                // nothing left (or nothing there to begin with) isn't worth a row
+                tx.execute(
+                    "DELETE FROM inventory
+                    WHERE conversation = ?1 AND user_id = ?2 AND item = ?3 AND quantity <= 0",
+                    params![conversation.0, user_id, item],
+                )?;
+                Ok(quantity)
+            })
+            .await?;
+
+        quantity.ok_or_else(|| eyre!("not enough of that item"))
+    }
+
+    /// Pay out the daily allowance, unless it was already claimed on `today`.
+    /// Returns the new balance if it was paid.
+    pub async fn claim_daily<S>(
+        &self,
+        conversation: Conversation,
+        user_id: S,
+        today: chrono::NaiveDate,
+        amount: i64,
+    ) -> Result<Option<i64>>
+    where
+        S: AsRef<str>,
+    {
+        let user_id = user_id.as_ref().to_owned();
+        let today = today.to_string();
+
+        let balance = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "UPDATE wallets SET balance = balance + ?3, last_daily = ?4
+                    WHERE conversation = ?1 AND user_id = ?2
+                    AND (last_daily IS NULL OR last_daily < ?4)
+                    RETURNING balance",
+                    params![conversation.0, user_id, amount, today],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(balance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_economy() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        db.ensure_wallet(conversation, "1", "@dylan").await.unwrap();
+        assert_eq!(
+            db.find_wallet(conversation, "Dylan").await.unwrap(),
+            Some("1".to_owned())
+        );
+
+        let today = chrono::NaiveDate::from_ymd_opt(2023, 9, 1).unwrap();
+        assert_eq!(
+            db.claim_daily(conversation, "1", today, 10).await.unwrap(),
+            Some(10)
+        );
+        assert_eq!(
+            db.claim_daily(conversation, "1", today, 10).await.unwrap(),
+            None
+        );
+        assert_eq!(db.add_coins(conversation, "1", -4).await.unwrap(), 6);
+        assert!(db.add_coins(conversation, "1", -7).await.is_err());
+
+        assert_eq!(
+            db.add_item(conversation, "1", "Carrot", 2).await.unwrap(),
+            2
+        );
+        assert_eq!(
+            db.add_item(conversation, "1", "carrot", -1).await.unwrap(),
+            1
+        );
+        assert!(db.add_item(conversation, "1", "carrot", -2).await.is_err());
+
+        let wallet = db.wallet(conversation, "1").await.unwrap();
+        assert_eq!(wallet.balance, 6);
+        assert_eq!(wallet.items, vec![("carrot".to_owned(), 1)]);
+
+        assert_eq!(
+            db.add_item(conversation, "1", "carrot", -1).await.unwrap(),
+            0
+        );
+        assert!(db.add_item(conversation, "1", "apple", -1).await.is_err());
+        let rows: i64 = db
+            .conn
+            .call(|conn| conn.query_row("SELECT count(*) FROM inventory", [], |row| row.get(0)))
+            .await
+            .unwrap();
+        assert_eq!(rows, 0);
+    }
+}
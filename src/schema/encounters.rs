@@ -0,0 +1,156 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Combatant {
+    pub name: String,
+    pub initiative: i32,
+    pub hp: i32,
+    pub max_hp: i32,
+}
+
+/// A combat encounter, stored as JSON in the encounters table.
+/// Combatants are kept in initiative order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Encounter {
+    pub combatants: Vec<Combatant>,
+    pub turn: usize,
+    pub round: u32,
+}
+
+impl Encounter {
+    pub fn new(mut combatants: Vec<Combatant>) -> Self {
+        combatants.sort_by(|a, b| b.initiative.cmp(&a.initiative));
+        // anyone starting on 0 hp is already down
+        let turn = combatants.iter().position(|c| c.hp > 0).unwrap_or(0);
+        Self {
+            combatants,
+            turn,
+            round: 1,
+        }
+    }
+
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut Combatant> {
+        self.combatants
+            .iter_mut()
+            .find(|c| c.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Advance to the next combatant that is still standing, returning their name.
+    pub fn next_turn(&mut self) -> Option<&Combatant> {
+        if self.combatants.iter().all(|c| c.hp <= 0) {
+            return None;
+        }
+        loop {
+            self.turn += 1;
+            if self.turn >= self.combatants.len() {
+                self.turn = 0;
+                self.round += 1;
+            }
+            if self.combatants[self.turn].hp > 0 {
+                return Some(&self.combatants[self.turn]);
+            }
+        }
+    }
+}
+
+impl Database {
+    pub async fn get_encounter(&self, conversation: Conversation) -> Result<Option<Encounter>> {
+        let state: Option<String> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT state FROM encounters WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(state.map(|s| serde_json::from_str(&s)).transpose()?)
+    }
+
+    pub async fn save_encounter(
+        &self,
+        conversation: Conversation,
+        encounter: &Encounter,
+    ) -> Result<()> {
+        let state = serde_json::to_string(encounter)?;
+
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO encounters (conversation, state) VALUES (?1, ?2)
+                    ON CONFLICT (conversation) DO UPDATE SET state = ?2",
+                    params![conversation.0, state],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn end_encounter(&self, conversation: Conversation) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM encounters WHERE conversation = ?1",
+                    params![conversation.0],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn combatant(name: &str, initiative: i32, hp: i32) -> Combatant {
+        Combatant {
+            name: name.to_owned(),
+            initiative,
+            hp,
+            max_hp: hp,
+        }
+    }
+
+    #[test]
+    fn test_turn_order() {
+        let mut encounter = Encounter::new(vec![
+            combatant("goblin", 5, 7),
+            combatant("horse", 18, 20),
+            combatant("knight", 12, 0),
+        ]);
+        assert_eq!(encounter.combatants[encounter.turn].name, "horse");
+        assert_eq!(encounter.next_turn().unwrap().name, "goblin");
+        assert_eq!(encounter.next_turn().unwrap().name, "horse");
+        assert_eq!(encounter.round, 2);
+
+        let mut encounter =
+            Encounter::new(vec![combatant("horse", 18, 0), combatant("goblin", 5, 7)]);
+        assert_eq!(encounter.combatants[encounter.turn].name, "goblin");
+        assert_eq!(encounter.next_turn().unwrap().name, "goblin");
+    }
+
+    #[tokio::test]
+    async fn test_encounter_storage() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let encounter = Encounter::new(vec![combatant("horse", 10, 20)]);
+        db.save_encounter(conversation, &encounter).await.unwrap();
+        assert_eq!(
+            db.get_encounter(conversation).await.unwrap(),
+            Some(encounter)
+        );
+        db.end_encounter(conversation).await.unwrap();
+        assert_eq!(db.get_encounter(conversation).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,125 @@
+use super::Database;
+use crate::embeddings;
+use eyre::Result;
+use rusqlite::params;
+
+/// A question a server wants answered the same way every time, with its embedding to
+/// find messages asking it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Faq {
+    pub id: i64,
+    pub question: String,
+    pub answer: String,
+    pub embedding: Vec<f32>,
+    /// The model the embedding came from.
+    pub embedding_model: String,
+}
+
+/// FAQs are per guild; `None` is used for direct messages.
+fn scope(guild_id: Option<u64>) -> String {
+    guild_id.map(|g| g.to_string()).unwrap_or_default()
+}
+
+impl Database {
+    /// Add a question and its answer, returning its id.
+    pub async fn add_faq(
+        &self,
+        guild_id: Option<u64>,
+        question: String,
+        answer: String,
+        embedding: &[f32],
+        embedding_model: String,
+    ) -> Result<i64> {
+        let guild_id = scope(guild_id);
+        let embedding = embeddings::to_blob(embedding);
+
+        let id = self
+            .conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO faqs (guild_id, question, answer, embedding, embedding_model)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![guild_id, question, answer, embedding, embedding_model],
+                )?;
+                Ok(conn.last_insert_rowid())
+            })
+            .await?;
+
+        Ok(id)
+    }
+
+    pub async fn remove_faq(&self, guild_id: Option<u64>, id: i64) -> Result<bool> {
+        let guild_id = scope(guild_id);
+
+        let deleted = self
+            .conn
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM faqs WHERE guild_id = ?1 AND id = ?2",
+                    params![guild_id, id],
+                )
+            })
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    /// A server's FAQs, oldest first.
+    pub async fn faqs(&self, guild_id: Option<u64>) -> Result<Vec<Faq>> {
+        let guild_id = scope(guild_id);
+
+        let faqs = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, question, answer, embedding, embedding_model FROM faqs
+                    WHERE guild_id = ?1 ORDER BY id",
+                )?;
+                let faqs = stmt
+                    .query_map(params![guild_id], |row| {
+                        Ok(Faq {
+                            id: row.get(0)?,
+                            question: row.get(1)?,
+                            answer: row.get(2)?,
+                            embedding: embeddings::from_blob(&row.get::<_, Vec<u8>>(3)?),
+                            embedding_model: row.get(4)?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(faqs)
+            })
+            .await?;
+
+        Ok(faqs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_faqs() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let id = db
+            .add_faq(
+                Some(1),
+                "How do I get the member role?".to_owned(),
+                "React to the pinned message in #welcome.".to_owned(),
+                &[0.5, -0.25],
+                "text-embedding-ada-002".to_owned(),
+            )
+            .await
+            .unwrap();
+
+        let faqs = db.faqs(Some(1)).await.unwrap();
+        assert_eq!(faqs.len(), 1);
+        assert_eq!(faqs[0].id, id);
+        assert_eq!(faqs[0].embedding, vec![0.5, -0.25]);
+        assert!(db.faqs(None).await.unwrap().is_empty());
+
+        assert!(!db.remove_faq(Some(2), id).await.unwrap());
+        assert!(db.remove_faq(Some(1), id).await.unwrap());
+        assert!(db.faqs(Some(1)).await.unwrap().is_empty());
+    }
+}
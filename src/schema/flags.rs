@@ -0,0 +1,188 @@
+use super::{Conversation, Database, SettingScope};
+use eyre::{eyre, Result};
+use rusqlite::params;
+use std::str::FromStr;
+
+/// An optional behavior that can be switched off (or back on) for the whole instance,
+/// a server or a conversation, without a rebuild. There's no flag for streaming:
+/// replies are sent to Discord whole, so there's nothing to switch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    /// Offering the model functions to call.
+    Tools,
+    /// Server emoji, recently active members and upcoming events in the prompt.
+    AmbientContext,
+    /// Learning about people as it goes: karma from what they say and react with, the
+    /// bot's mood, and the notes update_relationship keeps. Off, what's there is still
+    /// used but left as it is. The history, summaries and lore aren't affected.
+    Memory,
+}
+
+const FLAGS: [Flag; 3] = [Flag::Tools, Flag::AmbientContext, Flag::Memory];
+
+impl Flag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Flag::Tools => "tools",
+            Flag::AmbientContext => "ambient_context",
+            Flag::Memory => "memory",
+        }
+    }
+
+    /// Whether it's on where nothing says otherwise.
+    fn default_enabled(&self) -> bool {
+        true
+    }
+}
+
+impl FromStr for Flag {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        FLAGS
+            .into_iter()
+            .find(|flag| flag.as_str() == s)
+            .ok_or_else(|| eyre!("unknown flag {s}, use tools, ambient_context or memory"))
+    }
+}
+
+/// Read "on", "off" or "inherit" (None, to go by the level above).
+pub fn parse_flag_state(s: &str) -> Result<Option<bool>> {
+    match s {
+        "on" => Ok(Some(true)),
+        "off" => Ok(Some(false)),
+        "inherit" => Ok(None),
+        _ => Err(eyre!("unknown flag state {s}, use on, off or inherit")),
+    }
+}
+
+fn scope_key(scope: SettingScope) -> (&'static str, i64) {
+    match scope {
+        SettingScope::Global => ("global", 0),
+        SettingScope::Guild(guild) => ("guild", guild as i64),
+        SettingScope::Conversation(conversation) => ("conversation", conversation.0),
+    }
+}
+
+/// Looks flags up for one conversation: its own, then its server's, then the
+/// instance-wide one, then the flag's default.
+pub struct Flags<'a> {
+    db: &'a Database,
+    conversation: Conversation,
+    guild: Option<u64>,
+}
+
+impl<'a> Flags<'a> {
+    pub fn new(db: &'a Database, conversation: Conversation, guild: Option<u64>) -> Self {
+        Self {
+            db,
+            conversation,
+            guild,
+        }
+    }
+
+    pub async fn enabled(&self, flag: Flag) -> Result<bool> {
+        let enabled = self
+            .db
+            .resolve_flag(self.conversation, self.guild, flag)
+            .await?;
+        Ok(enabled.unwrap_or_else(|| flag.default_enabled()))
+    }
+}
+
+impl Database {
+    /// Turn a flag on or off (or with None, go by the level above) at one level.
+    pub async fn set_flag(
+        &self,
+        scope: SettingScope,
+        flag: Flag,
+        enabled: Option<bool>,
+    ) -> Result<()> {
+        let (kind, id) = scope_key(scope);
+
+        self.conn
+            .call(move |conn| {
+                match enabled {
+                    Some(enabled) => conn.execute(
+                        "INSERT INTO flags (scope, scope_id, flag, enabled) VALUES (?1, ?2, ?3, ?4)
+                        ON CONFLICT (scope, scope_id, flag) DO UPDATE SET enabled = ?4",
+                        params![kind, id, flag.as_str(), enabled],
+                    )?,
+                    None => conn.execute(
+                        "DELETE FROM flags WHERE scope = ?1 AND scope_id = ?2 AND flag = ?3",
+                        params![kind, id, flag.as_str()],
+                    )?,
+                };
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn resolve_flag(
+        &self,
+        conversation: Conversation,
+        guild: Option<u64>,
+        flag: Flag,
+    ) -> Result<Option<bool>> {
+        let guild = guild.map(|g| g as i64);
+
+        let enabled: Option<bool> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT coalesce(
+                        (SELECT enabled FROM flags
+                        WHERE scope = 'conversation' AND scope_id = ?1 AND flag = ?3),
+                        (SELECT enabled FROM flags
+                        WHERE scope = 'guild' AND scope_id = ?2 AND flag = ?3),
+                        (SELECT enabled FROM flags WHERE scope = 'global' AND flag = ?3)
+                    )",
+                    params![conversation.0, guild, flag.as_str()],
+                    |row| row.get(0),
+                )
+            })
+            .await?;
+
+        Ok(enabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_flags() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("dylan").await.unwrap();
+        let flags = Flags::new(&db, conversation, Some(7));
+        assert!(flags.enabled(Flag::Tools).await.unwrap());
+
+        let set = |scope, enabled| db.set_flag(scope, Flag::Tools, enabled);
+        set(SettingScope::Global, Some(false)).await.unwrap();
+        assert!(!flags.enabled(Flag::Tools).await.unwrap());
+        set(SettingScope::Guild(7), Some(true)).await.unwrap();
+        assert!(flags.enabled(Flag::Tools).await.unwrap());
+        set(SettingScope::Conversation(conversation), Some(false))
+            .await
+            .unwrap();
+        assert!(!flags.enabled(Flag::Tools).await.unwrap());
+        assert!(flags.enabled(Flag::Memory).await.unwrap());
+
+        set(SettingScope::Conversation(conversation), None)
+            .await
+            .unwrap();
+        assert!(flags.enabled(Flag::Tools).await.unwrap());
+        let dm = Flags::new(&db, conversation, None);
+        assert!(!dm.enabled(Flag::Tools).await.unwrap());
+
+        assert_eq!(
+            "ambient_context".parse::<Flag>().unwrap(),
+            Flag::AmbientContext
+        );
+        assert_eq!(parse_flag_state("inherit").unwrap(), None);
+        assert!(parse_flag_state("maybe").is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameKind {
+    Trivia,
+    Hangman,
+    TwentyQuestions,
+}
+
+/// The state of the game running in a conversation, stored as JSON in the games table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Game {
+    pub kind: GameKind,
+    /// The answer (trivia), word (hangman) or thing being guessed (twenty questions).
+    pub secret: String,
+    pub question: Option<String>,
+    pub guesses: Vec<String>,
+    pub questions_asked: u32,
+}
+
+impl Game {
+    pub fn new(kind: GameKind, secret: String, question: Option<String>) -> Self {
+        Self {
+            kind,
+            secret,
+            question,
+            guesses: vec![],
+            questions_asked: 0,
+        }
+    }
+}
+
+impl Database {
+    pub async fn get_game(&self, conversation: Conversation) -> Result<Option<Game>> {
+        let state: Option<String> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT state FROM games WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(state.map(|s| serde_json::from_str(&s)).transpose()?)
+    }
+
+    pub async fn save_game(&self, conversation: Conversation, game: &Game) -> Result<()> {
+        let state = serde_json::to_string(game)?;
+
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO games (conversation, state) VALUES (?1, ?2)
+                    ON CONFLICT (conversation) DO UPDATE SET state = ?2",
+                    params![conversation.0, state],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn end_game(&self, conversation: Conversation) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM games WHERE conversation = ?1",
+                    params![conversation.0],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+use super::Database;
+use eyre::Result;
+use rusqlite::params;
+
+impl Database {
+    /// Store (or with None, remove) a server's own OpenAI key. The key is sealed by
+    /// the caller; the database never sees it in the clear.
+    pub async fn set_guild_key(&self, guild_id: u64, sealed: Option<Vec<u8>>) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                match sealed {
+                    Some(sealed) => conn.execute(
+                        "INSERT INTO guild_keys (guild_id, sealed) VALUES (?1, ?2)
+                        ON CONFLICT (guild_id) DO UPDATE SET sealed = ?2",
+                        params![guild_id.to_string(), sealed],
+                    )?,
+                    None => conn.execute(
+                        "DELETE FROM guild_keys WHERE guild_id = ?1",
+                        params![guild_id.to_string()],
+                    )?,
+                };
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Every server's sealed key.
+    pub async fn guild_keys(&self) -> Result<Vec<(u64, Vec<u8>)>> {
+        let keys = self
+            .conn
+            .call(|conn| {
+                let mut stmt = conn.prepare("SELECT guild_id, sealed FROM guild_keys")?;
+                let rows = stmt.query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+                })?;
+                rows.collect::<Result<Vec<_>, _>>()
+            })
+            .await?;
+
+        keys.into_iter()
+            .map(|(guild_id, sealed)| Ok((guild_id.parse()?, sealed)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_guild_keys() {
+        let db = Database::new(None).await.expect("failed to create db");
+        assert!(db.guild_keys().await.unwrap().is_empty());
+
+        db.set_guild_key(1, Some(vec![1, 2, 3])).await.unwrap();
+        db.set_guild_key(1, Some(vec![4, 5])).await.unwrap();
+        db.set_guild_key(2, Some(vec![6])).await.unwrap();
+        db.set_guild_key(2, None).await.unwrap();
+        assert_eq!(db.guild_keys().await.unwrap(), vec![(1, vec![4, 5])]);
+    }
+}
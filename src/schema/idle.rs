@@ -0,0 +1,163 @@
+use super::{Conversation, Database};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+
+/// A server conversation that has been talked in, and where it stands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdleChannel {
+    pub conversation: Conversation,
+    pub guild_id: u64,
+    /// The conversation's channel that was talked in last, to speak up in.
+    pub channel_id: u64,
+    /// When anyone last said anything there, whether the bot answered or not.
+    pub last_activity: DateTime<Utc>,
+    /// Whether someone other than the bot had the last word.
+    pub awaiting_bot: bool,
+}
+
+impl Database {
+    /// Note that someone said something in a channel, or the bot did.
+    pub async fn channel_active(&self, channel_id: u64, by_bot: bool) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO channel_activity (channel_id, last_activity, by_bot)
+                    VALUES (?1, CURRENT_TIMESTAMP, ?2)
+                    ON CONFLICT (channel_id) DO UPDATE SET
+                        last_activity = CURRENT_TIMESTAMP, by_bot = ?2",
+                    params![channel_id.to_string(), by_bot],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Every server conversation with a channel that has been talked in, to see which
+    /// have gone quiet.
+    pub async fn idle_channels(&self) -> Result<Vec<IdleChannel>> {
+        type Row = (i64, String, String, String, bool);
+        let rows: Vec<Row> = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT c.id, c.guild_id, a.channel_id, a.last_activity, a.by_bot
+                    FROM conversation c
+                    JOIN channels ch ON ch.conversation = c.id
+                    JOIN channel_activity a ON a.channel_id = ch.channel_id
+                    WHERE c.guild_id IS NOT NULL AND a.channel_id = (
+                        SELECT a2.channel_id FROM channel_activity a2
+                        JOIN channels ch2 ON ch2.channel_id = a2.channel_id
+                        WHERE ch2.conversation = c.id
+                        ORDER BY a2.last_activity DESC LIMIT 1
+                    )",
+                )?;
+                let rows = stmt
+                    .query_map([], |row| {
+                        Ok((
+                            row.get(0)?,
+                            row.get(1)?,
+                            row.get(2)?,
+                            row.get(3)?,
+                            row.get(4)?,
+                        ))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(rows)
+            })
+            .await?;
+
+        rows.into_iter()
+            .map(
+                |(conversation, guild_id, channel_id, last_activity, by_bot)| {
+                    let last_activity =
+                        NaiveDateTime::parse_from_str(&last_activity, "%Y-%m-%d %H:%M:%S")?;
+                    Ok(IdleChannel {
+                        conversation: Conversation(conversation),
+                        guild_id: guild_id.parse()?,
+                        channel_id: channel_id.parse()?,
+                        last_activity: Utc.from_utc_datetime(&last_activity),
+                        awaiting_bot: !by_bot,
+                    })
+                },
+            )
+            .collect()
+    }
+
+    /// When the bot last spoke up in a quiet conversation, if ever.
+    pub async fn last_idle_chatter(
+        &self,
+        conversation: Conversation,
+    ) -> Result<Option<DateTime<Local>>> {
+        let chattered_at: Option<String> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT chattered_at FROM idle_chatter WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(chattered_at
+            .map(|t| DateTime::parse_from_rfc3339(&t))
+            .transpose()?
+            .map(|t| t.with_timezone(&Local)))
+    }
+
+    pub async fn idle_chattered(
+        &self,
+        conversation: Conversation,
+        now: DateTime<Local>,
+    ) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO idle_chatter (conversation, chattered_at) VALUES (?1, ?2)
+                    ON CONFLICT (conversation) DO UPDATE SET chattered_at = ?2",
+                    params![conversation.0, now.to_rfc3339()],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_idle_channels() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db
+            .find_channel_conversation("7", Some((1, "Ranch")), "general")
+            .await
+            .unwrap();
+        db.find_channel_conversation("8", None, "dm").await.unwrap();
+        assert!(db.idle_channels().await.unwrap().is_empty());
+
+        db.channel_active(8, false).await.unwrap();
+        // talk the bot never answered counts too
+        db.channel_active(7, false).await.unwrap();
+        let idle = db.idle_channels().await.unwrap();
+        assert_eq!(idle.len(), 1);
+        assert_eq!((idle[0].guild_id, idle[0].channel_id), (1, 7));
+        assert!(idle[0].awaiting_bot);
+
+        db.channel_active(7, true).await.unwrap();
+        assert!(!db.idle_channels().await.unwrap()[0].awaiting_bot);
+
+        assert!(db.last_idle_chatter(conversation).await.unwrap().is_none());
+        let now = Local::now();
+        db.idle_chattered(conversation, now).await.unwrap();
+        let last = db.last_idle_chatter(conversation).await.unwrap().unwrap();
+        assert_eq!(last.timestamp(), now.timestamp());
+    }
+}
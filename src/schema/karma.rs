@@ -0,0 +1,168 @@
+use super::{Conversation, Database};
+use chrono::Local;
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+
+/// How far a standing can go either way, so it can always be won back (or lost).
+const MAX_KARMA: i64 = 20;
+
+impl Database {
+    /// How someone stands with the bot in a conversation: above 0 friendly, below
+    /// wary. 0 for people it hasn't met.
+    pub async fn karma(&self, conversation: Conversation, user_id: String) -> Result<i64> {
+        let karma: Option<i64> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT score FROM karma WHERE conversation = ?1 AND user_id = ?2",
+                    params![conversation.0, user_id],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(karma.unwrap_or(0))
+    }
+
+    /// Move someone's standing by `delta`, within bounds. Returns the new score.
+    pub async fn adjust_karma(
+        &self,
+        conversation: Conversation,
+        user_id: String,
+        delta: i64,
+    ) -> Result<i64> {
+        if delta == 0 {
+            return self.karma(conversation, user_id).await;
+        }
+        let now = Local::now().to_rfc3339();
+
+        let karma = self
+            .conn
+            .call(move |conn| adjust(conn, conversation.0, &user_id, delta, &now))
+            .await?;
+
+        Ok(karma)
+    }
+
+    /// Count someone's reaction to one of the bot's messages toward their standing.
+    /// The same emoji on the same message only counts once, however often it's added.
+    pub async fn add_reaction_karma(
+        &self,
+        conversation: Conversation,
+        user_id: String,
+        message_id: u64,
+        emoji: String,
+        delta: i64,
+    ) -> Result<()> {
+        let now = Local::now().to_rfc3339();
+
+        self.transaction(move |tx| {
+            let added = tx.execute(
+                "INSERT OR IGNORE INTO karma_reactions
+                    (conversation, message_id, user_id, emoji, delta)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    conversation.0,
+                    message_id.to_string(),
+                    user_id,
+                    emoji,
+                    delta
+                ],
+            )?;
+            if added > 0 {
+                adjust(tx, conversation.0, &user_id, delta, &now)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Take back a reaction counted by [`Database::add_reaction_karma`]. Reactions that
+    /// were never counted are ignored.
+    pub async fn remove_reaction_karma(
+        &self,
+        user_id: String,
+        message_id: u64,
+        emoji: String,
+    ) -> Result<()> {
+        let now = Local::now().to_rfc3339();
+
+        self.transaction(move |tx| {
+            let counted: Option<(i64, i64)> = tx
+                .query_row(
+                    "DELETE FROM karma_reactions
+                    WHERE message_id = ?1 AND user_id = ?2 AND emoji = ?3
+                    RETURNING conversation, delta",
+                    params![message_id.to_string(), user_id, emoji],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+            if let Some((conversation, delta)) = counted {
+                adjust(tx, conversation, &user_id, -delta, &now)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Move someone's standing by `delta`, within bounds, returning the new score.
+fn adjust(
+    conn: &rusqlite::Connection,
+    conversation: i64,
+    user_id: &str,
+    delta: i64,
+    now: &str,
+) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "INSERT INTO karma (conversation, user_id, score, updated_at)
+        VALUES (?1, ?2, max(-?4, min(?4, ?3)), ?5)
+        ON CONFLICT (conversation, user_id) DO UPDATE SET
+            score = max(-?4, min(?4, score + ?3)), updated_at = ?5
+        RETURNING score",
+        params![conversation, user_id, delta, MAX_KARMA, now],
+        |row| row.get(0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_karma() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let user = || "42".to_owned();
+        assert_eq!(db.karma(conversation, user()).await.unwrap(), 0);
+        assert_eq!(db.adjust_karma(conversation, user(), 2).await.unwrap(), 2);
+        assert_eq!(db.adjust_karma(conversation, user(), -3).await.unwrap(), -1);
+        assert_eq!(
+            db.adjust_karma(conversation, user(), -100).await.unwrap(),
+            -MAX_KARMA
+        );
+        assert_eq!(
+            db.adjust_karma(conversation, user(), 0).await.unwrap(),
+            -MAX_KARMA
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reaction_karma() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let user = || "42".to_owned();
+        let thumbs = || "👍".to_owned();
+        for _ in 0..2 {
+            db.add_reaction_karma(conversation, user(), 7, thumbs(), 1)
+                .await
+                .unwrap();
+        }
+        assert_eq!(db.karma(conversation, user()).await.unwrap(), 1);
+        db.remove_reaction_karma(user(), 7, thumbs()).await.unwrap();
+        assert_eq!(db.karma(conversation, user()).await.unwrap(), 0);
+        db.remove_reaction_karma(user(), 7, thumbs()).await.unwrap();
+        assert_eq!(db.karma(conversation, user()).await.unwrap(), 0);
+    }
+}
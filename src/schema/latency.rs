@@ -0,0 +1,105 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+use std::time::Duration;
+
+impl Database {
+    /// How long the model took over the conversation's latest `limit` replies, newest
+    /// first.
+    pub async fn recent_latencies(
+        &self,
+        conversation: Conversation,
+        limit: usize,
+    ) -> Result<Vec<Duration>> {
+        let latencies = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT latency_ms FROM reply_metadata WHERE conversation = ?1
+                    ORDER BY history_id DESC LIMIT ?2",
+                )?;
+                let latencies = stmt
+                    .query_map(params![conversation.0, limit as i64], |row| {
+                        Ok(Duration::from_millis(row.get(0)?))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(latencies)
+            })
+            .await?;
+
+        Ok(latencies)
+    }
+
+    /// Whether the conversation is replying in fast mode, having been over its
+    /// latency target.
+    pub async fn latency_mode(&self, conversation: Conversation) -> Result<bool> {
+        let fast: Option<bool> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT fast FROM latency_modes WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(fast.unwrap_or_default())
+    }
+
+    pub async fn set_latency_mode(&self, conversation: Conversation, fast: bool) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO latency_modes (conversation, fast) VALUES (?1, ?2)
+                    ON CONFLICT (conversation) DO UPDATE SET fast = ?2",
+                    params![conversation.0, fast],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{Message, ReplyMetadata, Role};
+
+    #[tokio::test]
+    async fn test_latency() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        assert!(db
+            .recent_latencies(conversation, 5)
+            .await
+            .unwrap()
+            .is_empty());
+        for ms in [100, 200, 300] {
+            let metadata = ReplyMetadata {
+                latency: Duration::from_millis(ms),
+                ..Default::default()
+            };
+            db.add_reply(
+                conversation,
+                Message::new(Role::Assistant, "neigh"),
+                metadata,
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(
+            db.recent_latencies(conversation, 2).await.unwrap(),
+            vec![Duration::from_millis(300), Duration::from_millis(200)]
+        );
+
+        assert!(!db.latency_mode(conversation).await.unwrap());
+        db.set_latency_mode(conversation, true).await.unwrap();
+        assert!(db.latency_mode(conversation).await.unwrap());
+        db.set_latency_mode(conversation, false).await.unwrap();
+        assert!(!db.latency_mode(conversation).await.unwrap());
+    }
+}
@@ -0,0 +1,49 @@
+use super::{Conversation, Database};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::OwnedMutexGuard;
+
+/// A lock per conversation, held while a reply is written so two messages arriving
+/// together can't interleave their history.
+#[derive(Default)]
+pub(super) struct ConversationLocks {
+    locks: Mutex<BTreeMap<Conversation, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl Database {
+    /// Wait until nothing else is adding to the conversation's history, and keep it
+    /// that way until the guard is dropped.
+    pub async fn lock_conversation(&self, conversation: Conversation) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.locks.lock().unwrap_or_else(|e| e.into_inner());
+            // forget the locks nobody holds or waits for
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(conversation).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_lock_conversation() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let first = db.find_conversation("dylan").await.unwrap();
+        let second = db.find_conversation("horse").await.unwrap();
+
+        let guard = db.lock_conversation(first).await;
+        // other conversations aren't held up
+        drop(db.lock_conversation(second).await);
+        let waiting = tokio::time::timeout(Duration::from_millis(50), db.lock_conversation(first));
+        assert!(waiting.await.is_err());
+
+        drop(guard);
+        drop(db.lock_conversation(first).await);
+    }
+}
@@ -0,0 +1,71 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+
+impl Database {
+    /// Set the background the persona knows in a conversation, or with None forget it.
+    pub async fn set_lore(&self, conversation: Conversation, lore: Option<String>) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                match lore {
+                    Some(lore) => conn.execute(
+                        "INSERT INTO lore (conversation, lore) VALUES (?1, ?2)
+                        ON CONFLICT (conversation) DO UPDATE SET lore = ?2",
+                        params![conversation.0, lore],
+                    )?,
+                    None => conn.execute(
+                        "DELETE FROM lore WHERE conversation = ?1",
+                        params![conversation.0],
+                    )?,
+                };
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn lore(&self, conversation: Conversation) -> Result<Option<String>> {
+        let lore = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT lore FROM lore WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(lore)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lore() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("#stable").await.unwrap();
+        assert_eq!(db.lore(conversation).await.unwrap(), None);
+
+        db.set_lore(
+            conversation,
+            Some("The stable burned down in 1887.".to_owned()),
+        )
+        .await
+        .unwrap();
+        db.set_lore(conversation, Some("The stable was rebuilt.".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(
+            db.lore(conversation).await.unwrap().as_deref(),
+            Some("The stable was rebuilt.")
+        );
+        db.set_lore(conversation, None).await.unwrap();
+        assert_eq!(db.lore(conversation).await.unwrap(), None);
+    }
+}
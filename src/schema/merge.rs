@@ -0,0 +1,204 @@
+use super::{Conversation, Database};
+use eyre::{eyre, Result};
+use rusqlite::params;
+
+/// Tables with at most one row per conversation (or per conversation and setting,
+/// schedule name or user).
+/// When both conversations have one, the destination's is kept.
+const SINGLE_ROW_TABLES: &[&str] = &[
+    "script",
+    "transforms",
+    "games",
+    "encounters",
+    "quiet",
+    "nsfw_settings",
+    "topics",
+    "privacy",
+    "conversation_settings",
+    "descriptions",
+    "cursors",
+    "prompt_schedules",
+    "karma",
+    "relationships",
+    "moods",
+    "idle_chatter",
+    "latency_modes",
+    "lore",
+    "deflections",
+];
+
+impl Database {
+    /// Move everything from `src` into `dst` and delete `src`. History is kept in order,
+    /// coins and items are added together, and `dst` wins where both have a setting.
+    /// Flags and settings made for a whole server stay with the server.
+    pub async fn merge_conversations(&self, src: Conversation, dst: Conversation) -> Result<()> {
+        if src == dst {
+            return Err(eyre!("can't merge a conversation into itself"));
+        }
+
+        self.transaction(move |tx| {
+            let ids = params![src.0, dst.0];
+
+            // history ids are global, so ordering by id interleaves the two correctly
+            for table in [
+                "history",
+                "quests",
+                "channels",
+                "reply_metadata",
+                "handled_messages",
+                "audit_log",
+                "shadow_log",
+                "reports",
+                "message_embeddings",
+                "quantized_embeddings",
+                "message_tags",
+                "karma_reactions",
+                "outbox",
+            ] {
+                tx.execute(
+                    &format!("UPDATE {table} SET conversation = ?2 WHERE conversation = ?1"),
+                    ids,
+                )?;
+            }
+            for table in SINGLE_ROW_TABLES {
+                tx.execute(
+                    &format!(
+                        "UPDATE OR IGNORE {table} SET conversation = ?2 WHERE conversation = ?1"
+                    ),
+                    ids,
+                )?;
+            }
+            // a sheet is looked up by its one name, so dst's name for someone sticks
+            tx.execute(
+                "INSERT OR IGNORE INTO sheets (conversation, user_id, user_name, key, value)
+                    SELECT ?2, s.user_id, coalesce(
+                        (SELECT d.user_name FROM sheets d
+                        WHERE d.conversation = ?2 AND d.user_id = s.user_id LIMIT 1),
+                        s.user_name
+                    ), s.key, s.value
+                    FROM sheets s WHERE s.conversation = ?1",
+                ids,
+            )?;
+            tx.execute(
+                "INSERT INTO wallets (conversation, user_id, user_name, balance, last_daily)
+                    SELECT ?2, user_id, user_name, balance, last_daily FROM wallets
+                    WHERE conversation = ?1
+                    ON CONFLICT (conversation, user_id) DO UPDATE SET
+                        balance = balance + excluded.balance,
+                        last_daily = coalesce(
+                            max(last_daily, excluded.last_daily),
+                            last_daily,
+                            excluded.last_daily
+                        )",
+                ids,
+            )?;
+            tx.execute(
+                "INSERT INTO inventory (conversation, user_id, item, quantity)
+                    SELECT ?2, user_id, item, quantity FROM inventory WHERE conversation = ?1
+                    ON CONFLICT (conversation, user_id, item) DO UPDATE SET
+                        quantity = quantity + excluded.quantity",
+                ids,
+            )?;
+            tx.execute(
+                "UPDATE OR IGNORE flags SET scope_id = ?2
+                    WHERE scope = 'conversation' AND scope_id = ?1",
+                ids,
+            )?;
+            tx.execute(
+                "UPDATE conversation SET prompt = coalesce(
+                        prompt,
+                        (SELECT prompt FROM conversation WHERE id = ?1)
+                    ) WHERE id = ?2",
+                ids,
+            )?;
+
+            // whatever didn't move was overridden by dst
+            for table in SINGLE_ROW_TABLES
+                .iter()
+                .chain(&["sheets", "wallets", "inventory"])
+            {
+                tx.execute(
+                    &format!("DELETE FROM {table} WHERE conversation = ?1"),
+                    params![src.0],
+                )?;
+            }
+            tx.execute(
+                "DELETE FROM flags WHERE scope = 'conversation' AND scope_id = ?1",
+                params![src.0],
+            )?;
+            tx.execute("DELETE FROM conversation WHERE id = ?1", params![src.0])?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{Flag, Flags, SettingScope};
+
+    #[tokio::test]
+    async fn test_merge() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let src = db
+            .find_channel_conversation("1", None, "#old")
+            .await
+            .unwrap();
+        let dst = db
+            .find_channel_conversation("2", None, "#new")
+            .await
+            .unwrap();
+        db.add_user_message(src, "first").await.unwrap();
+        db.add_user_message(dst, "second").await.unwrap();
+        db.add_user_message(src, "third").await.unwrap();
+        for conversation in [src, dst] {
+            db.ensure_wallet(conversation, "1", "dylan").await.unwrap();
+            db.add_coins(conversation, "1", 5).await.unwrap();
+            db.add_item(conversation, "1", "carrot", 1).await.unwrap();
+        }
+        db.set_sheet_value(src, "1", "dylan", "class", Some("bard"))
+            .await
+            .unwrap();
+        db.set_sheet_value(dst, "1", "Dobbin", "hp", Some("12"))
+            .await
+            .unwrap();
+        db.set_prompt(src, "be a horse").await.unwrap();
+        db.queue_message(1, Some(src), "neigh".to_owned())
+            .await
+            .unwrap();
+        db.set_flag(SettingScope::Conversation(src), Flag::Tools, Some(false))
+            .await
+            .unwrap();
+
+        assert!(db.merge_conversations(src, src).await.is_err());
+        db.merge_conversations(src, dst).await.unwrap();
+
+        let history = db.history(dst).await.unwrap();
+        let contents = history.iter().map(|m| m.content()).collect::<Vec<_>>();
+        assert_eq!(contents, vec!["first", "second", "third"]);
+        let wallet = db.wallet(dst, "1").await.unwrap();
+        assert_eq!(wallet.balance, 10);
+        assert_eq!(wallet.items, vec![("carrot".to_owned(), 2)]);
+        let sheet = vec![
+            ("class".to_owned(), "bard".to_owned()),
+            ("hp".to_owned(), "12".to_owned()),
+        ];
+        assert_eq!(db.get_sheet(dst, "1").await.unwrap(), sheet);
+        assert_eq!(db.get_sheet(dst, "Dobbin").await.unwrap(), sheet);
+        assert_eq!(
+            db.get_prompt(dst).await.unwrap(),
+            Some("be a horse".to_owned())
+        );
+        let queued = db.due_messages(10).await.unwrap();
+        assert_eq!(queued[0].conversation, Some(dst));
+        let flags = Flags::new(&db, dst, None);
+        assert!(!flags.enabled(Flag::Tools).await.unwrap());
+        assert_eq!(
+            db.find_channel_conversation("1", None, "#old")
+                .await
+                .unwrap(),
+            dst
+        );
+    }
+}
@@ -0,0 +1,371 @@
+use super::{messages::decode_message, Conversation, Database, Message, Role};
+use crate::embeddings;
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+
+/// A batch of history to embed, and how far through the history it reached.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct HistoryBatch {
+    /// History ids, conversations and text of the messages worth embedding.
+    pub messages: Vec<(i64, Conversation, String)>,
+    /// The last history id looked at, including messages that weren't worth it, or
+    /// None once there's nothing left.
+    pub last_id: Option<i64>,
+    /// How many messages were looked at.
+    pub scanned: usize,
+}
+
+/// A row of quantized_embeddings: a message's embedding at a byte per dimension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuantizedEmbedding {
+    pub id: i64,
+    pub history_id: i64,
+    pub conversation: Conversation,
+    pub model: String,
+    pub embedding: Vec<i8>,
+}
+
+/// Whether a message says anything worth finding later. Function calls, their results
+/// and system notes are left out.
+fn searchable(message: &Message) -> Option<String> {
+    match message {
+        Message::Content {
+            role: Role::User | Role::Assistant,
+            content,
+        } if !content.trim().is_empty() => Some(content.clone()),
+        _ => None,
+    }
+}
+
+/// Replace the quantized embedding of history `id`, moving it to the end of the table.
+fn insert_quantized(
+    tx: &rusqlite::Transaction,
+    id: i64,
+    embedding: &[f32],
+    model: &str,
+) -> rusqlite::Result<()> {
+    let quantized: Vec<u8> = embeddings::quantize(embedding)
+        .into_iter()
+        .map(|b| b as u8)
+        .collect();
+    tx.execute(
+        "INSERT OR REPLACE INTO quantized_embeddings
+            (history_id, conversation, embedding, embedding_model)
+        SELECT id, conversation, ?2, ?3 FROM history WHERE id = ?1",
+        params![id, quantized, model],
+    )?;
+    Ok(())
+}
+
+impl Database {
+    /// How many history messages have no embedding from `model` yet, searchable or not.
+    pub async fn count_unembedded_history(&self, model: String) -> Result<usize> {
+        let count: i64 = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT count(*) FROM history h
+                    LEFT JOIN message_embeddings e ON e.history_id = h.id
+                    WHERE e.embedding_model IS NOT ?1",
+                    params![model],
+                    |row| row.get(0),
+                )
+            })
+            .await?;
+
+        Ok(count as usize)
+    }
+
+    /// Up to `limit` history messages after `after_id` with no embedding from `model`
+    /// yet, oldest first.
+    pub async fn unembedded_history(
+        &self,
+        after_id: i64,
+        model: String,
+        limit: usize,
+    ) -> Result<HistoryBatch> {
+        let rows: Vec<(i64, i64, String)> = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT h.id, h.conversation, h.message FROM history h
+                    LEFT JOIN message_embeddings e ON e.history_id = h.id
+                    WHERE h.id > ?1 AND e.embedding_model IS NOT ?2
+                    ORDER BY h.id LIMIT ?3",
+                )?;
+                let rows = stmt
+                    .query_map(params![after_id, model, limit as i64], |row| {
+                        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(rows)
+            })
+            .await?;
+
+        let last_id = rows.last().map(|(id, _, _)| *id);
+        let scanned = rows.len();
+        let mut messages = vec![];
+        for (id, conversation, json) in rows {
+            if let Some(text) = searchable(&decode_message(&json)?) {
+                messages.push((id, Conversation(conversation), text));
+            }
+        }
+        Ok(HistoryBatch {
+            messages,
+            last_id,
+            scanned,
+        })
+    }
+
+    /// Store embeddings from `model` for history messages, replacing any older ones.
+    pub async fn set_message_embeddings(
+        &self,
+        model: String,
+        embeddings: Vec<(i64, Vec<f32>)>,
+    ) -> Result<()> {
+        self.transaction(move |tx| {
+            for (id, embedding) in embeddings {
+                insert_quantized(tx, id, &embedding, &model)?;
+                let embedding = embeddings::to_blob(&embedding);
+                tx.execute(
+                    "INSERT INTO message_embeddings
+                        (history_id, conversation, embedding, embedding_model)
+                    SELECT id, conversation, ?2, ?3 FROM history WHERE id = ?1
+                    ON CONFLICT (history_id) DO UPDATE SET
+                        embedding = excluded.embedding,
+                        embedding_model = excluded.embedding_model",
+                    params![id, embedding, model],
+                )?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Quantized embeddings saved after row `after` of quantized_embeddings, oldest first.
+    /// A re-embedded message shows up again, with a later id.
+    pub async fn quantized_embeddings(&self, after: i64) -> Result<Vec<QuantizedEmbedding>> {
+        let rows = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, history_id, conversation, embedding_model, embedding
+                    FROM quantized_embeddings WHERE id > ?1 ORDER BY id",
+                )?;
+                let rows = stmt
+                    .query_map(params![after], |row| {
+                        let embedding: Vec<u8> = row.get(4)?;
+                        Ok(QuantizedEmbedding {
+                            id: row.get(0)?,
+                            history_id: row.get(1)?,
+                            conversation: Conversation(row.get(2)?),
+                            model: row.get(3)?,
+                            embedding: embedding.into_iter().map(|b| b as i8).collect(),
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(rows)
+            })
+            .await?;
+
+        Ok(rows)
+    }
+
+    /// Quantize the embeddings saved before there were quantized ones, returning how many.
+    pub async fn quantize_missing_embeddings(&self) -> Result<usize> {
+        let missing: Vec<(i64, Vec<u8>, String)> = self
+            .conn
+            .call(|conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT e.history_id, e.embedding, e.embedding_model
+                    FROM message_embeddings e
+                    LEFT JOIN quantized_embeddings q ON q.history_id = e.history_id
+                        AND q.embedding_model = e.embedding_model
+                    WHERE q.id IS NULL",
+                )?;
+                let rows = stmt
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(rows)
+            })
+            .await?;
+
+        let count = missing.len();
+        self.transaction(move |tx| {
+            for (id, blob, model) in missing {
+                insert_quantized(tx, id, &embeddings::from_blob(&blob), &model)?;
+            }
+            Ok(())
+        })
+        .await?;
+
+        Ok(count)
+    }
+
+    /// The text of those of `ids` that are still embedded, skipping messages deleted or
+    /// redacted since, in the order given.
+    pub async fn embedded_messages(&self, ids: Vec<i64>) -> Result<Vec<(i64, String)>> {
+        let rows: Vec<(i64, String)> = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT h.id, h.message FROM history h
+                    JOIN message_embeddings e ON e.history_id = h.id
+                    WHERE h.id = ?1",
+                )?;
+                let mut rows = vec![];
+                for id in ids {
+                    let row = stmt
+                        .query_row(params![id], |row| Ok((row.get(0)?, row.get(1)?)))
+                        .optional()?;
+                    rows.extend(row);
+                }
+                Ok(rows)
+            })
+            .await?;
+
+        rows.into_iter()
+            .map(|(id, json)| Ok((id, decode_message(&json)?.content())))
+            .collect()
+    }
+
+    /// FAQ questions with no embedding from `model`, as ids and questions.
+    pub async fn unembedded_faqs(&self, model: String) -> Result<Vec<(i64, String)>> {
+        let faqs = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, question FROM faqs WHERE embedding_model != ?1 ORDER BY id",
+                )?;
+                let faqs = stmt
+                    .query_map(params![model], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(faqs)
+            })
+            .await?;
+
+        Ok(faqs)
+    }
+
+    pub async fn set_faq_embedding(&self, id: i64, embedding: &[f32], model: String) -> Result<()> {
+        let embedding = embeddings::to_blob(embedding);
+
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE faqs SET embedding = ?2, embedding_model = ?3 WHERE id = ?1",
+                    params![id, embedding, model],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unembedded_history() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let hello = db.add_user_message(conversation, "hello").await.unwrap();
+        db.add_messages(
+            conversation,
+            vec![Message::function_result("roll_dice", "4")],
+        )
+        .await
+        .unwrap();
+        let neigh = db.add_user_message(conversation, "neigh").await.unwrap();
+        let model = || "ada".to_owned();
+        assert_eq!(db.count_unembedded_history(model()).await.unwrap(), 3);
+
+        let batch = db.unembedded_history(0, model(), 2).await.unwrap();
+        assert_eq!(
+            batch.messages,
+            vec![(hello, conversation, "hello".to_owned())]
+        );
+        assert_eq!(batch.scanned, 2);
+        let batch = db
+            .unembedded_history(batch.last_id.unwrap(), model(), 2)
+            .await
+            .unwrap();
+        assert_eq!(
+            batch.messages,
+            vec![(neigh, conversation, "neigh".to_owned())]
+        );
+
+        db.set_message_embeddings(model(), vec![(hello, vec![1.0]), (neigh, vec![0.5])])
+            .await
+            .unwrap();
+        assert_eq!(db.count_unembedded_history(model()).await.unwrap(), 1);
+        let quantized = db.quantized_embeddings(0).await.unwrap();
+        assert_eq!(
+            quantized
+                .iter()
+                .map(|q| (q.history_id, q.embedding.clone()))
+                .collect::<Vec<_>>(),
+            vec![(hello, vec![127]), (neigh, vec![127])]
+        );
+        let batch = db.unembedded_history(0, model(), 10).await.unwrap();
+        assert!(batch.messages.is_empty());
+        assert!(batch.last_id.is_some());
+        assert_eq!(
+            db.unembedded_history(0, "other".to_owned(), 10)
+                .await
+                .unwrap()
+                .messages
+                .len(),
+            2
+        );
+
+        db.redact_message(None, hello).await.unwrap();
+        assert_eq!(
+            db.embedded_messages(vec![neigh, hello]).await.unwrap(),
+            vec![(neigh, "neigh".to_owned())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_quantized_embeddings() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let hello = db.add_user_message(conversation, "hello").await.unwrap();
+        let neigh = db.add_user_message(conversation, "neigh").await.unwrap();
+        db.set_message_embeddings("ada".to_owned(), vec![(hello, vec![1.0, 0.0])])
+            .await
+            .unwrap();
+        let first = db.quantized_embeddings(0).await.unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].conversation, conversation);
+        assert_eq!(first[0].model, "ada");
+        assert_eq!(first[0].embedding, vec![127, 0]);
+
+        // re-embedding moves a message past what was already read
+        db.set_message_embeddings(
+            "ada".to_owned(),
+            vec![(hello, vec![0.0, -2.0]), (neigh, vec![1.0, 1.0])],
+        )
+        .await
+        .unwrap();
+        let next = db.quantized_embeddings(first[0].id).await.unwrap();
+        assert_eq!(
+            next.iter()
+                .map(|q| (q.history_id, q.embedding.clone()))
+                .collect::<Vec<_>>(),
+            vec![(hello, vec![0, -127]), (neigh, vec![90, 90])]
+        );
+
+        // as if saved before there was a quantized_embeddings table
+        db.conn
+            .call(|conn| conn.execute("DELETE FROM quantized_embeddings", []))
+            .await
+            .unwrap();
+        assert_eq!(db.quantize_missing_embeddings().await.unwrap(), 2);
+        assert_eq!(db.quantize_missing_embeddings().await.unwrap(), 0);
+        assert_eq!(db.quantized_embeddings(0).await.unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,126 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::params;
+
+/// How a conversation's tagged messages break down, for `horse-npc stats`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MessageStats {
+    /// Messages by intent, most common first. None is for those nothing sorted.
+    pub intents: Vec<(Option<String>, usize)>,
+    pub positive: usize,
+    pub neutral: usize,
+    pub negative: usize,
+}
+
+impl Database {
+    /// Tag a user message with its sentiment (as scored for karma) and what it needs,
+    /// if that's known yet.
+    pub async fn tag_message(
+        &self,
+        history_id: i64,
+        sentiment: i64,
+        intent: Option<&'static str>,
+    ) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT OR REPLACE INTO message_tags
+                        (history_id, conversation, sentiment, intent)
+                    SELECT id, conversation, ?2, ?3 FROM history WHERE id = ?1",
+                    params![history_id, sentiment, intent],
+                )
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Record what a message turned out to need, once a model has been asked.
+    pub async fn set_message_intent(&self, history_id: i64, intent: &'static str) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE message_tags SET intent = ?2 WHERE history_id = ?1",
+                    params![history_id, intent],
+                )
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// How the tagged messages of one conversation, or with None all of them, break down.
+    pub async fn message_stats(&self, conversation: Option<Conversation>) -> Result<MessageStats> {
+        let conversation = conversation.map(|c| c.0);
+
+        let stats = self
+            .conn
+            .call(move |conn| {
+                let mut stats = MessageStats::default();
+                let mut stmt = conn.prepare(
+                    "SELECT intent, count(*) AS n FROM message_tags
+                    WHERE ?1 IS NULL OR conversation = ?1
+                    GROUP BY intent ORDER BY n DESC, intent",
+                )?;
+                stats.intents = stmt
+                    .query_map(params![conversation], |row| {
+                        Ok((row.get(0)?, row.get::<_, i64>(1)? as usize))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                let (positive, neutral, negative): (i64, i64, i64) = conn.query_row(
+                    "SELECT coalesce(sum(sentiment > 0), 0), coalesce(sum(sentiment = 0), 0),
+                        coalesce(sum(sentiment < 0), 0)
+                    FROM message_tags WHERE ?1 IS NULL OR conversation = ?1",
+                    params![conversation],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )?;
+                stats.positive = positive as usize;
+                stats.neutral = neutral as usize;
+                stats.negative = negative as usize;
+                Ok(stats)
+            })
+            .await?;
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_message_stats() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let other = db.find_conversation("other").await.unwrap();
+        let hello = db.add_user_message(conversation, "hello").await.unwrap();
+        let hi = db.add_user_message(conversation, "hi").await.unwrap();
+        let rude = db.add_user_message(conversation, "shut up").await.unwrap();
+        let why = db.add_user_message(conversation, "why?").await.unwrap();
+        let thanks = db.add_user_message(other, "thanks").await.unwrap();
+        db.tag_message(hello, 0, Some("greeting")).await.unwrap();
+        db.tag_message(hi, 1, Some("greeting")).await.unwrap();
+        db.tag_message(rude, -2, None).await.unwrap();
+        db.tag_message(why, 0, None).await.unwrap();
+        db.tag_message(thanks, 1, Some("thanks")).await.unwrap();
+        db.set_message_intent(why, "reply").await.unwrap();
+
+        assert_eq!(
+            db.message_stats(Some(conversation)).await.unwrap(),
+            MessageStats {
+                intents: vec![
+                    (Some("greeting".to_owned()), 2),
+                    (None, 1),
+                    (Some("reply".to_owned()), 1),
+                ],
+                positive: 1,
+                neutral: 2,
+                negative: 1,
+            }
+        );
+        let all = db.message_stats(None).await.unwrap();
+        assert_eq!(all.positive, 2);
+        assert_eq!(all.intents.len(), 4);
+    }
+}
@@ -0,0 +1,296 @@
+//! How history messages are stored: as JSON tagged with the version of its shape, so
+//! the [`Message`] enum can change without making older databases unreadable.
+
+use super::{Conversation, Database, Message};
+use eyre::{eyre, Result};
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+use serde_json::Value;
+
+/// The shape messages are stored in now. When [`Message`] changes in a way old JSON
+/// won't deserialize into, bump this and teach [`upgrade`] the old shape.
+const MESSAGE_VERSION: u64 = 1;
+
+/// What a redacted message says instead.
+const REDACTED: &str = "[redacted]";
+
+#[derive(Serialize)]
+struct Versioned<'a> {
+    version: u64,
+    message: &'a Message,
+}
+
+/// A message as JSON for the history table.
+pub(super) fn encode_message(message: &Message) -> serde_json::Result<String> {
+    serde_json::to_string(&Versioned {
+        version: MESSAGE_VERSION,
+        message,
+    })
+}
+
+/// A message from the history table, upgraded from whatever shape it was stored in.
+pub(super) fn decode_message(json: &str) -> Result<Message> {
+    decode(json).map(|(_, message)| message)
+}
+
+/// A stored message and the version it was stored as. Messages from before versions
+/// were kept are version 0.
+fn decode(json: &str) -> Result<(u64, Message)> {
+    let mut value: Value = serde_json::from_str(json)?;
+    let version = match value.get("version").and_then(Value::as_u64) {
+        Some(version) => {
+            value = value["message"].take();
+            version
+        }
+        None => 0,
+    };
+    if version > MESSAGE_VERSION {
+        return Err(eyre!(
+            "message version {version} is newer than this horse-npc understands"
+        ));
+    }
+    let message = serde_json::from_value(upgrade(version, value)?)?;
+    Ok((version, message))
+}
+
+/// Bring a stored message's JSON up to the current shape, a version at a time.
+fn upgrade(mut version: u64, mut message: Value) -> Result<Value> {
+    while version < MESSAGE_VERSION {
+        message = match version {
+            // version 1 only wrapped messages with their version
+            0 => message,
+            _ => return Err(eyre!("no upgrade from message version {version}")),
+        };
+        version += 1;
+    }
+    Ok(message)
+}
+
+/// The message with what it said taken out, but its role (and function name) kept so
+/// the history still makes sense.
+fn redact(message: Message) -> Message {
+    match message {
+        Message::Content { role, .. } => Message::Content {
+            role,
+            content: REDACTED.to_owned(),
+        },
+        Message::Function { role, fn_name, .. } => Message::Function {
+            role,
+            fn_name,
+            fn_args: "{}".to_owned(),
+        },
+        Message::FunctionResult { fn_name, .. } => Message::FunctionResult {
+            fn_name,
+            content: REDACTED.to_owned(),
+        },
+    }
+}
+
+/// A message that can't be decoded, as an error reading the row it came from.
+pub(super) fn decode_error(e: eyre::Report) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, e.into())
+}
+
+impl Database {
+    /// Replace what a stored message says with a placeholder, keeping its place in the
+    /// history, say to get rid of a password someone pasted. The audit and shadow logs
+    /// from since it was said and any reports quoting it are redacted along with it, and
+    /// its embeddings and tags are dropped.
+    /// With a conversation, only a message from that conversation is redacted. Returns
+    /// false if there's no such message.
+    pub async fn redact_message(
+        &self,
+        conversation: Option<Conversation>,
+        id: i64,
+    ) -> Result<bool> {
+        let conversation = conversation.map(|c| c.0);
+
+        self.transaction(move |tx| {
+            let row: Option<(i64, String, Option<String>)> = tx
+                .query_row(
+                    "SELECT conversation, message, created_at FROM history
+                    WHERE id = ?1 AND (?2 IS NULL OR conversation = ?2)",
+                    params![id, conversation],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .optional()?;
+            let Some((conversation, json, created_at)) = row else {
+                return Ok(false);
+            };
+            let message = decode_message(&json).map_err(decode_error)?;
+            let said = match &message {
+                Message::Content { content, .. } | Message::FunctionResult { content, .. } => {
+                    content.clone()
+                }
+                Message::Function { fn_args, .. } => fn_args.clone(),
+            };
+            let json = encode_message(&redact(message)).map_err(|e| decode_error(e.into()))?;
+            tx.execute(
+                "UPDATE history SET message = ?1 WHERE id = ?2",
+                params![json, id],
+            )?;
+            // or a search could still find what it said
+            tx.execute(
+                "DELETE FROM message_embeddings WHERE history_id = ?1",
+                params![id],
+            )?;
+            tx.execute(
+                "DELETE FROM quantized_embeddings WHERE history_id = ?1",
+                params![id],
+            )?;
+            // its sentiment and intent were read from what it said
+            tx.execute(
+                "DELETE FROM message_tags WHERE history_id = ?1",
+                params![id],
+            )?;
+            // every request sent since carried it, and anything said back may quote it;
+            // a message with no timestamp could be in any of them
+            tx.execute(
+                "UPDATE audit_log SET request = ?1, response = ?1
+                WHERE conversation = ?2 AND (?3 IS NULL OR created_at >= ?3)",
+                params![REDACTED, conversation, created_at],
+            )?;
+            tx.execute(
+                "UPDATE shadow_log SET output = ?1
+                WHERE conversation = ?2 AND (?3 IS NULL OR created_at >= ?3)",
+                params![REDACTED, conversation, created_at],
+            )?;
+            // a report keeps what the reported message said, which is the message if
+            // it's the one reported, and not if it only shares some words with it
+            let said = said.trim();
+            if !said.is_empty() {
+                tx.execute(
+                    "UPDATE reports SET content = ?1 WHERE conversation = ?2 AND content = ?3",
+                    params![REDACTED, conversation, said],
+                )?;
+            }
+            Ok(true)
+        })
+        .await
+    }
+
+    /// Rewrite messages stored in an older shape in the current one. Reading upgrades
+    /// them anyway, this just saves doing it every time and lets old code be dropped.
+    /// Returns how many were rewritten.
+    pub async fn migrate_messages(&self) -> Result<usize> {
+        self.transaction(|tx| {
+            let mut stmt = tx.prepare("SELECT id, message FROM history")?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut migrated = 0;
+            for (id, json) in rows {
+                let (version, message) = decode(&json).map_err(decode_error)?;
+                if version == MESSAGE_VERSION {
+                    continue;
+                }
+                let json = encode_message(&message).map_err(|e| decode_error(e.into()))?;
+                tx.execute(
+                    "UPDATE history SET message = ?1 WHERE id = ?2",
+                    params![json, id],
+                )?;
+                migrated += 1;
+            }
+            Ok(migrated)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{insert_message, Role};
+
+    #[test]
+    fn test_format() {
+        // the history is stored as this JSON, so it must not change shape
+        let message = Message::new(Role::User, "hi");
+        let json = r#"{"version":1,"message":{"Content":{"role":"User","content":"hi"}}}"#;
+        assert_eq!(encode_message(&message).unwrap(), json);
+        assert_eq!(decode_message(json).unwrap().content(), "hi");
+
+        let unversioned = r#"{"FunctionResult":{"fn_name":"react","content":"done"}}"#;
+        let message = decode_message(unversioned).unwrap();
+        assert_eq!(message.role(), Role::Function);
+        assert_eq!(message.content(), "done");
+
+        let future = r#"{"version":99,"message":{}}"#;
+        assert!(decode_message(future).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_redact_message() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let other = db.find_conversation("other").await.unwrap();
+        let id = db
+            .add_user_message(conversation, "my password is hunter2")
+            .await
+            .unwrap();
+        db.tag_message(id, 0, Some("question")).await.unwrap();
+
+        db.add_audit_entry(conversation, "hunter2".to_owned(), "{}".to_owned())
+            .await
+            .unwrap();
+        db.add_shadow_entry(conversation, "reply".to_owned(), "hunter2?".to_owned())
+            .await
+            .unwrap();
+        let leaked = "my password is hunter2".to_owned();
+        db.add_report(conversation, 1, 2, 3, "leak".to_owned(), leaked)
+            .await
+            .unwrap();
+        // only shares a word with it
+        let other_report = "password".to_owned();
+        db.add_report(conversation, 1, 4, 3, "rude".to_owned(), other_report)
+            .await
+            .unwrap();
+
+        assert!(!db.redact_message(Some(other), id).await.unwrap());
+        assert!(db.redact_message(Some(conversation), id).await.unwrap());
+        assert!(!db.redact_message(None, id + 1).await.unwrap());
+        let history = db.history(conversation).await.unwrap();
+        assert_eq!(history[0].role(), Role::User);
+        assert_eq!(history[0].content(), REDACTED);
+        let audit = db.audit_entries(conversation, 10).await.unwrap();
+        assert_eq!(audit[0].request, REDACTED);
+        let shadow = db.shadow_entries(conversation, 10).await.unwrap();
+        assert_eq!(shadow[0].output, REDACTED);
+        let reports = db.reports(Some(conversation)).await.unwrap();
+        let mut contents = reports
+            .iter()
+            .map(|r| r.content.as_str())
+            .collect::<Vec<_>>();
+        contents.sort();
+        assert_eq!(contents, vec![REDACTED, "password"]);
+        let stats = db.message_stats(Some(conversation)).await.unwrap();
+        assert!(stats.intents.is_empty());
+        assert_eq!(stats.neutral, 0);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_messages() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        db.add_user_message(conversation, "new").await.unwrap();
+        db.transaction(move |tx| {
+            insert_message(
+                tx,
+                conversation,
+                r#"{"Content":{"role":"User","content":"old"}}"#,
+            )
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(db.migrate_messages().await.unwrap(), 1);
+        assert_eq!(db.migrate_messages().await.unwrap(), 0);
+        let history = db.history(conversation).await.unwrap();
+        assert_eq!(
+            history.iter().map(Message::content).collect::<Vec<_>>(),
+            vec!["new", "old"]
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use super::Database;
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+
+impl Database {
+    /// The member count up to which a server's milestones have been celebrated, or
+    /// were already passed when it was first counted. None if it hasn't been yet.
+    pub async fn members_celebrated(&self, guild: u64) -> Result<Option<u64>> {
+        let celebrated = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT coalesce(members_celebrated, member_count) FROM guild_milestones
+                    WHERE guild_id = ?1",
+                    params![guild.to_string()],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map(Option::flatten)
+            })
+            .await?;
+
+        Ok(celebrated)
+    }
+
+    /// Remember that a server's milestones up to `count` members are celebrated.
+    pub async fn set_members_celebrated(&self, guild: u64, count: u64) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO guild_milestones (guild_id, members_celebrated) VALUES (?1, ?2)
+                    ON CONFLICT (guild_id) DO UPDATE SET members_celebrated = ?2",
+                    params![guild.to_string(), count],
+                )
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// The last year a server's anniversary was celebrated, if it has been.
+    pub async fn last_anniversary(&self, guild: u64) -> Result<Option<i32>> {
+        let year = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT anniversary_year FROM guild_milestones WHERE guild_id = ?1",
+                    params![guild.to_string()],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map(Option::flatten)
+            })
+            .await?;
+
+        Ok(year)
+    }
+
+    /// Remember that a server's anniversary was celebrated in `year`.
+    pub async fn set_last_anniversary(&self, guild: u64, year: i32) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO guild_milestones (guild_id, anniversary_year) VALUES (?1, ?2)
+                    ON CONFLICT (guild_id) DO UPDATE SET anniversary_year = ?2",
+                    params![guild.to_string(), year],
+                )
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_milestones() {
+        let db = Database::new(None).await.expect("failed to create db");
+        assert_eq!(db.members_celebrated(7).await.unwrap(), None);
+        db.set_members_celebrated(7, 90).await.unwrap();
+        assert_eq!(db.members_celebrated(7).await.unwrap(), Some(90));
+        db.set_members_celebrated(7, 100).await.unwrap();
+        assert_eq!(db.members_celebrated(7).await.unwrap(), Some(100));
+
+        assert_eq!(db.last_anniversary(7).await.unwrap(), None);
+        db.set_last_anniversary(7, 2023).await.unwrap();
+        assert_eq!(db.last_anniversary(7).await.unwrap(), Some(2023));
+        assert_eq!(db.members_celebrated(7).await.unwrap(), Some(100));
+        assert_eq!(db.last_anniversary(8).await.unwrap(), None);
+        assert_eq!(db.members_celebrated(8).await.unwrap(), None);
+    }
+}
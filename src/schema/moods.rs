@@ -0,0 +1,186 @@
+use super::{Conversation, Database};
+use chrono::{DateTime, Duration, Local};
+use eyre::{eyre, Result};
+use rusqlite::{params, OptionalExtension};
+use std::str::FromStr;
+
+/// How long a mood lasts before it eases one step back toward content.
+fn decay_step() -> Duration {
+    Duration::hours(3)
+}
+
+/// How the bot feels in a conversation today, from gloomy to giddy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Mood {
+    Gloomy,
+    Grumpy,
+    #[default]
+    Content,
+    Cheerful,
+    Giddy,
+}
+
+const MOODS: [Mood; 5] = [
+    Mood::Gloomy,
+    Mood::Grumpy,
+    Mood::Content,
+    Mood::Cheerful,
+    Mood::Giddy,
+];
+
+impl Mood {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mood::Gloomy => "gloomy",
+            Mood::Grumpy => "grumpy",
+            Mood::Content => "content",
+            Mood::Cheerful => "cheerful",
+            Mood::Giddy => "giddy",
+        }
+    }
+
+    /// -2 for gloomy up to 2 for giddy.
+    fn level(self) -> i64 {
+        self as i64 - Mood::Content as i64
+    }
+
+    fn from_level(level: i64) -> Self {
+        let index = (level + Mood::Content as i64).clamp(0, MOODS.len() as i64 - 1);
+        MOODS[index as usize]
+    }
+
+    /// `steps` better (or with a negative number, worse), as far as it goes.
+    pub fn nudged(self, steps: i64) -> Self {
+        Mood::from_level(self.level() + steps)
+    }
+
+    /// What a mood set at `since` has eased to by `now`.
+    fn decayed(self, since: DateTime<Local>, now: DateTime<Local>) -> Self {
+        let steps = (now - since).num_seconds() / decay_step().num_seconds();
+        let level = self.level();
+        Mood::from_level(level.signum() * (level.abs() - steps).max(0))
+    }
+}
+
+impl FromStr for Mood {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        MOODS
+            .into_iter()
+            .find(|mood| mood.as_str() == s)
+            .ok_or_else(|| {
+                eyre!("unknown mood {s}, use gloomy, grumpy, content, cheerful or giddy")
+            })
+    }
+}
+
+impl Database {
+    /// The conversation's mood at `now`, eased back toward content since it was last
+    /// changed.
+    pub async fn mood(&self, conversation: Conversation, now: DateTime<Local>) -> Result<Mood> {
+        let row: Option<(String, String)> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT mood, updated_at FROM moods WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+            })
+            .await?;
+
+        let Some((mood, updated_at)) = row else {
+            return Ok(Mood::default());
+        };
+        let since = DateTime::parse_from_rfc3339(&updated_at)?.with_timezone(&Local);
+        Ok(mood.parse::<Mood>()?.decayed(since, now))
+    }
+
+    pub async fn set_mood(
+        &self,
+        conversation: Conversation,
+        mood: Mood,
+        now: DateTime<Local>,
+    ) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO moods (conversation, mood, updated_at) VALUES (?1, ?2, ?3)
+                    ON CONFLICT (conversation) DO UPDATE SET mood = ?2, updated_at = ?3",
+                    params![conversation.0, mood.as_str(), now.to_rfc3339()],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Move the conversation's mood `steps` better or worse. Returns the new mood.
+    pub async fn nudge_mood(
+        &self,
+        conversation: Conversation,
+        steps: i64,
+        now: DateTime<Local>,
+    ) -> Result<Mood> {
+        let mood = self.mood(conversation, now).await?;
+        if steps == 0 {
+            return Ok(mood);
+        }
+        let mood = mood.nudged(steps);
+        self.set_mood(conversation, mood, now).await?;
+        Ok(mood)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decayed() {
+        let now = Local::now();
+        assert_eq!(
+            Mood::Giddy.decayed(now, now + Duration::hours(4)),
+            Mood::Cheerful
+        );
+        assert_eq!(
+            Mood::Gloomy.decayed(now, now + Duration::hours(6)),
+            Mood::Content
+        );
+        assert_eq!(
+            Mood::Grumpy.decayed(now, now + Duration::days(9)),
+            Mood::Content
+        );
+        assert_eq!(Mood::Cheerful.nudged(5), Mood::Giddy);
+        assert!("sleepy".parse::<Mood>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_nudge_mood() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let now = Local::now();
+        assert_eq!(db.mood(conversation, now).await.unwrap(), Mood::Content);
+        assert_eq!(
+            db.nudge_mood(conversation, -1, now).await.unwrap(),
+            Mood::Grumpy
+        );
+        assert_eq!(
+            db.nudge_mood(conversation, -1, now).await.unwrap(),
+            Mood::Gloomy
+        );
+        // two steps of easing, then one better
+        let later = now + decay_step() * 2;
+        assert_eq!(
+            db.nudge_mood(conversation, 1, later).await.unwrap(),
+            Mood::Cheerful
+        );
+        db.set_mood(conversation, Mood::Grumpy, later)
+            .await
+            .unwrap();
+        assert_eq!(db.mood(conversation, later).await.unwrap(), Mood::Grumpy);
+    }
+}
@@ -0,0 +1,122 @@
+use super::{Conversation, Database};
+use eyre::{eyre, Result};
+use rusqlite::{params, OptionalExtension};
+use std::str::FromStr;
+
+/// How hard to moderate input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ModerationPolicy {
+    /// Deflect anything with even a moderate score in any category.
+    Strict,
+    /// Deflect whatever OpenAI flags.
+    #[default]
+    Default,
+    /// Only deflect the severe categories (sexual content involving minors,
+    /// threats, self-harm and graphic violence).
+    Relaxed,
+    /// Don't moderate at all.
+    Off,
+}
+
+impl ModerationPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModerationPolicy::Strict => "strict",
+            ModerationPolicy::Default => "default",
+            ModerationPolicy::Relaxed => "relaxed",
+            ModerationPolicy::Off => "off",
+        }
+    }
+}
+
+impl FromStr for ModerationPolicy {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "strict" => Ok(ModerationPolicy::Strict),
+            "default" => Ok(ModerationPolicy::Default),
+            "relaxed" => Ok(ModerationPolicy::Relaxed),
+            "off" => Ok(ModerationPolicy::Off),
+            _ => Err(eyre!("unknown moderation policy {s}")),
+        }
+    }
+}
+
+/// Overrides used when a conversation's channel is marked NSFW.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NsfwSettings {
+    /// Used instead of the conversation's prompt.
+    pub prompt: Option<String>,
+    pub moderation: ModerationPolicy,
+}
+
+impl Database {
+    pub async fn get_nsfw_settings(&self, conversation: Conversation) -> Result<NsfwSettings> {
+        let row: Option<(Option<String>, String)> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT prompt, moderation FROM nsfw_settings WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+            })
+            .await?;
+
+        match row {
+            Some((prompt, moderation)) => Ok(NsfwSettings {
+                prompt,
+                moderation: moderation.parse()?,
+            }),
+            None => Ok(NsfwSettings::default()),
+        }
+    }
+
+    pub async fn set_nsfw_settings(
+        &self,
+        conversation: Conversation,
+        settings: NsfwSettings,
+    ) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO nsfw_settings (conversation, prompt, moderation) VALUES (?1, ?2, ?3)
+                    ON CONFLICT (conversation) DO UPDATE SET prompt = ?2, moderation = ?3",
+                    params![
+                        conversation.0,
+                        settings.prompt,
+                        settings.moderation.as_str()
+                    ],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_nsfw_settings() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        assert_eq!(
+            db.get_nsfw_settings(conversation).await.unwrap(),
+            NsfwSettings::default()
+        );
+        let settings = NsfwSettings {
+            prompt: Some("You are a rude horse.".to_owned()),
+            moderation: ModerationPolicy::Relaxed,
+        };
+        db.set_nsfw_settings(conversation, settings.clone())
+            .await
+            .unwrap();
+        assert_eq!(db.get_nsfw_settings(conversation).await.unwrap(), settings);
+    }
+}
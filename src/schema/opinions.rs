@@ -0,0 +1,64 @@
+use super::Database;
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+
+impl Database {
+    /// Allow (or stop) the bot giving its opinion of a user when asked. Everyone is
+    /// allowed until they opt out.
+    pub async fn set_opinions_allowed(&self, user_id: u64, allowed: bool) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                if allowed {
+                    conn.execute(
+                        "DELETE FROM opinion_opt_outs WHERE user_id = ?1",
+                        params![user_id.to_string()],
+                    )?;
+                } else {
+                    conn.execute(
+                        "INSERT INTO opinion_opt_outs (user_id) VALUES (?1)
+                        ON CONFLICT (user_id) DO NOTHING",
+                        params![user_id.to_string()],
+                    )?;
+                }
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn opinions_allowed(&self, user_id: u64) -> Result<bool> {
+        let opted_out = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT 1 FROM opinion_opt_outs WHERE user_id = ?1",
+                    params![user_id.to_string()],
+                    |_| Ok(()),
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(opted_out.is_none())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_opinions_allowed() {
+        let db = Database::new(None).await.expect("failed to create db");
+        assert!(db.opinions_allowed(42).await.unwrap());
+
+        db.set_opinions_allowed(42, false).await.unwrap();
+        db.set_opinions_allowed(42, false).await.unwrap();
+        assert!(!db.opinions_allowed(42).await.unwrap());
+        assert!(db.opinions_allowed(7).await.unwrap());
+
+        db.set_opinions_allowed(42, true).await.unwrap();
+        assert!(db.opinions_allowed(42).await.unwrap());
+    }
+}
@@ -0,0 +1,157 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::params;
+use std::time::Duration;
+
+/// A message waiting in the outbox to be sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingMessage {
+    pub id: i64,
+    pub channel_id: u64,
+    pub content: String,
+    /// Where to record it once it's sent, for messages not already in the history.
+    pub conversation: Option<Conversation>,
+    /// How many times sending it has failed.
+    pub attempts: u32,
+}
+
+impl Database {
+    /// Queue a message to be sent to a channel, surviving reconnects and restarts. With
+    /// a conversation, the message is added to its history once it has been sent.
+    pub async fn queue_message(
+        &self,
+        channel_id: u64,
+        conversation: Option<Conversation>,
+        content: String,
+    ) -> Result<()> {
+        let conversation = conversation.map(|c| c.0);
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO outbox (channel_id, conversation, content) VALUES (?1, ?2, ?3)",
+                    params![channel_id.to_string(), conversation, content],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Queued messages that are due to be sent, oldest first. A message isn't due while
+    /// an earlier one to the same channel is postponed, so each channel gets its
+    /// messages in the order they were queued.
+    pub async fn due_messages(&self, limit: usize) -> Result<Vec<OutgoingMessage>> {
+        let rows = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, channel_id, conversation, content, attempts FROM outbox
+                    WHERE next_attempt_at <= CURRENT_TIMESTAMP
+                    AND NOT EXISTS (
+                        SELECT 1 FROM outbox AS earlier
+                        WHERE earlier.channel_id = outbox.channel_id
+                        AND earlier.id < outbox.id
+                        AND earlier.next_attempt_at > CURRENT_TIMESTAMP
+                    )
+                    ORDER BY id LIMIT ?1",
+                )?;
+                let rows = stmt.query_map(params![limit], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<i64>>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, u32>(4)?,
+                    ))
+                })?;
+                rows.collect::<Result<Vec<_>, _>>()
+            })
+            .await?;
+
+        rows.into_iter()
+            .map(|(id, channel_id, conversation, content, attempts)| {
+                Ok(OutgoingMessage {
+                    id,
+                    channel_id: channel_id.parse()?,
+                    content,
+                    conversation: conversation.map(Conversation),
+                    attempts,
+                })
+            })
+            .collect()
+    }
+
+    /// Remove a message from the outbox, once sent or given up on.
+    pub async fn remove_queued_message(&self, id: i64) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute("DELETE FROM outbox WHERE id = ?1", params![id])?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Count a failed attempt and leave the message alone for `delay`.
+    pub async fn postpone_message(&self, id: i64, delay: Duration) -> Result<()> {
+        let delay = format!("+{} seconds", delay.as_secs());
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE outbox SET attempts = attempts + 1,
+                    next_attempt_at = datetime('now', ?2) WHERE id = ?1",
+                    params![id, delay],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_outbox() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("dylan").await.unwrap();
+        db.queue_message(42, None, "first".to_owned())
+            .await
+            .unwrap();
+        db.queue_message(42, Some(conversation), "second".to_owned())
+            .await
+            .unwrap();
+
+        let due = db.due_messages(10).await.unwrap();
+        assert_eq!(
+            due.iter().map(|m| m.content.as_str()).collect::<Vec<_>>(),
+            vec!["first", "second"]
+        );
+        assert_eq!(due[0].channel_id, 42);
+        assert_eq!(due[1].conversation, Some(conversation));
+
+        db.postpone_message(due[0].id, Duration::from_secs(60))
+            .await
+            .unwrap();
+        // the second waits its turn, while another channel's goes ahead
+        db.queue_message(7, None, "elsewhere".to_owned())
+            .await
+            .unwrap();
+        let due_now = db.due_messages(10).await.unwrap();
+        assert_eq!(
+            due_now
+                .iter()
+                .map(|m| m.content.as_str())
+                .collect::<Vec<_>>(),
+            vec!["elsewhere"]
+        );
+        db.remove_queued_message(due_now[0].id).await.unwrap();
+        db.remove_queued_message(due[1].id).await.unwrap();
+        assert!(db.due_messages(10).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,172 @@
+use super::Database;
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+/// A named bundle of model parameters and a prompt, for switching a conversation to
+/// a different style in one go. Anything left out is left as it was.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    /// The key of its table in TOML, so not a field there.
+    #[serde(skip)]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u16>,
+    /// A prompt template, replacing the conversation's own.
+    pub prompt: Option<String>,
+}
+
+type PresetRow = (
+    String,
+    String,
+    Option<String>,
+    Option<f64>,
+    Option<u16>,
+    Option<String>,
+);
+
+fn preset_from_row(row: PresetRow) -> Preset {
+    let (name, description, model, temperature, max_tokens, prompt) = row;
+    Preset {
+        name,
+        description,
+        model,
+        temperature,
+        max_tokens,
+        prompt,
+    }
+}
+
+impl Database {
+    /// Add a preset, or replace the one with its name.
+    pub async fn save_preset(&self, preset: Preset) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT OR REPLACE INTO presets
+                        (name, description, model, temperature, max_tokens, prompt)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        preset.name,
+                        preset.description,
+                        preset.model,
+                        preset.temperature,
+                        preset.max_tokens,
+                        preset.prompt
+                    ],
+                )
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn preset(&self, name: String) -> Result<Option<Preset>> {
+        let row = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT name, description, model, temperature, max_tokens, prompt
+                    FROM presets WHERE name = ?1",
+                    params![name],
+                    |row| {
+                        Ok((
+                            row.get(0)?,
+                            row.get(1)?,
+                            row.get(2)?,
+                            row.get(3)?,
+                            row.get(4)?,
+                            row.get(5)?,
+                        ))
+                    },
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(row.map(preset_from_row))
+    }
+
+    /// Every stored preset, by name.
+    pub async fn presets(&self) -> Result<Vec<Preset>> {
+        let rows = self
+            .conn
+            .call(|conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT name, description, model, temperature, max_tokens, prompt
+                    FROM presets ORDER BY name",
+                )?;
+                let rows = stmt
+                    .query_map([], |row| {
+                        Ok((
+                            row.get(0)?,
+                            row.get(1)?,
+                            row.get(2)?,
+                            row.get(3)?,
+                            row.get(4)?,
+                            row.get(5)?,
+                        ))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(rows)
+            })
+            .await?;
+
+        Ok(rows.into_iter().map(preset_from_row).collect())
+    }
+
+    /// Delete a stored preset, returning whether there was one.
+    pub async fn remove_preset(&self, name: String) -> Result<bool> {
+        let removed = self
+            .conn
+            .call(move |conn| conn.execute("DELETE FROM presets WHERE name = ?1", params![name]))
+            .await?;
+
+        Ok(removed > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_presets() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let preset = Preset {
+            name: "terse".to_owned(),
+            model: Some("gpt-4".to_owned()),
+            max_tokens: Some(64),
+            ..Default::default()
+        };
+        db.save_preset(preset.clone()).await.unwrap();
+        db.save_preset(Preset {
+            name: "bard".to_owned(),
+            temperature: Some(1.25),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(db.preset("terse".to_owned()).await.unwrap(), Some(preset));
+        assert_eq!(db.preset("nope".to_owned()).await.unwrap(), None);
+        let names: Vec<_> = db
+            .presets()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(names, ["bard", "terse"]);
+
+        assert!(db.remove_preset("bard".to_owned()).await.unwrap());
+        assert!(!db.remove_preset("bard".to_owned()).await.unwrap());
+
+        let conversation = db.find_conversation("test").await.unwrap();
+        db.set_max_tokens(conversation, 512).await.unwrap();
+        assert_eq!(db.max_tokens(conversation).await.unwrap(), 512);
+    }
+}
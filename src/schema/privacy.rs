@@ -0,0 +1,98 @@
+use super::{Conversation, Database};
+use eyre::{eyre, Result};
+use rusqlite::{params, OptionalExtension};
+use std::str::FromStr;
+
+/// How much of a private conversation is kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrivacyMode {
+    /// Keep every message, like in a public channel.
+    #[default]
+    Full,
+    /// Keep only a summary, rewritten after each reply.
+    Summary,
+    /// Forget the conversation after each reply.
+    Off,
+}
+
+impl PrivacyMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PrivacyMode::Full => "full",
+            PrivacyMode::Summary => "summary",
+            PrivacyMode::Off => "off",
+        }
+    }
+}
+
+impl FromStr for PrivacyMode {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "full" => Ok(PrivacyMode::Full),
+            "summary" => Ok(PrivacyMode::Summary),
+            "off" => Ok(PrivacyMode::Off),
+            _ => Err(eyre!("unknown privacy mode {s}, use full, summary or off")),
+        }
+    }
+}
+
+impl Database {
+    /// The privacy mode for a conversation, or None if it was never set, which means
+    /// the user hasn't been told how their messages are kept.
+    pub async fn get_privacy(&self, conversation: Conversation) -> Result<Option<PrivacyMode>> {
+        let mode: Option<String> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT mode FROM privacy WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        mode.map(|m| m.parse()).transpose()
+    }
+
+    pub async fn set_privacy(&self, conversation: Conversation, mode: PrivacyMode) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO privacy (conversation, mode) VALUES (?1, ?2)
+                    ON CONFLICT (conversation) DO UPDATE SET mode = ?2",
+                    params![conversation.0, mode.as_str()],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_privacy() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("dylan").await.unwrap();
+        assert_eq!(db.get_privacy(conversation).await.unwrap(), None);
+
+        db.set_privacy(conversation, PrivacyMode::Full)
+            .await
+            .unwrap();
+        db.set_privacy(conversation, PrivacyMode::Summary)
+            .await
+            .unwrap();
+        assert_eq!(
+            db.get_privacy(conversation).await.unwrap(),
+            Some(PrivacyMode::Summary)
+        );
+        assert!("nope".parse::<PrivacyMode>().is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::params;
+
+/// Matches history older than the cutoff in ?1 (like '-7776000 seconds'), in one
+/// conversation (?2) or all of them when ?2 is NULL. Messages from before timestamps
+/// were kept have no age, so they are left alone. Works for the logs kept beside the
+/// history too, which have the same columns.
+const OLD_HISTORY: &str = "created_at < datetime('now', ?1) AND (?2 IS NULL OR conversation = ?2)";
+
+impl Database {
+    /// How many messages older than `age` each conversation has, leaving out
+    /// conversations with none.
+    pub async fn old_history(
+        &self,
+        age: chrono::Duration,
+        conversation: Option<Conversation>,
+    ) -> Result<Vec<(String, usize)>> {
+        let cutoff = cutoff(age);
+
+        let counts = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT c.name, count(*) FROM history
+                    JOIN conversation c ON c.id = history.conversation
+                    WHERE {OLD_HISTORY} GROUP BY c.id ORDER BY c.name"
+                ))?;
+                let rows = stmt.query_map(params![cutoff, conversation.map(|c| c.0)], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?;
+                rows.collect::<Result<Vec<_>, rusqlite::Error>>()
+            })
+            .await?;
+
+        Ok(counts)
+    }
+
+    /// Delete messages older than `age`, along with their reply metadata, embeddings and
+    /// the audit and shadow log entries from that time. Returns the ids of the deleted
+    /// messages, for the vector store to forget too.
+    pub async fn prune_history(
+        &self,
+        age: chrono::Duration,
+        conversation: Option<Conversation>,
+    ) -> Result<Vec<i64>> {
+        let cutoff = cutoff(age);
+        let conversation = conversation.map(|c| c.0);
+
+        self.transaction(move |tx| {
+            let ids = tx
+                .prepare(&format!("SELECT id FROM history WHERE {OLD_HISTORY}"))?
+                .query_map(params![cutoff, conversation], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<i64>>>()?;
+            tx.execute(
+                &format!(
+                    "DELETE FROM reply_metadata WHERE history_id IN
+                    (SELECT id FROM history WHERE {OLD_HISTORY})"
+                ),
+                params![cutoff, conversation],
+            )?;
+            for table in ["message_embeddings", "quantized_embeddings", "message_tags"] {
+                tx.execute(
+                    &format!(
+                        "DELETE FROM {table} WHERE history_id IN
+                        (SELECT id FROM history WHERE {OLD_HISTORY})"
+                    ),
+                    params![cutoff, conversation],
+                )?;
+            }
+            for table in ["audit_log", "shadow_log"] {
+                tx.execute(
+                    &format!("DELETE FROM {table} WHERE {OLD_HISTORY}"),
+                    params![cutoff, conversation],
+                )?;
+            }
+            tx.execute(
+                &format!("DELETE FROM history WHERE {OLD_HISTORY}"),
+                params![cutoff, conversation],
+            )?;
+            Ok(ids)
+        })
+        .await
+    }
+}
+
+/// A modifier for SQLite's datetime() that goes back `age`.
+fn cutoff(age: chrono::Duration) -> String {
+    format!("-{} seconds", age.num_seconds())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_prune_history() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let old = db.find_conversation("#old").await.unwrap();
+        let new = db.find_conversation("#new").await.unwrap();
+        let ancient = db.add_user_message(old, "ancient").await.unwrap();
+        db.add_user_message(old, "recent").await.unwrap();
+        let stale = db.add_user_message(new, "stale").await.unwrap();
+        db.add_audit_entry(old, "ancient".to_owned(), "{}".to_owned())
+            .await
+            .unwrap();
+        db.transaction(move |tx| {
+            tx.execute(
+                "UPDATE audit_log SET created_at = datetime('now', '-100 days')",
+                [],
+            )?;
+            tx.execute(
+                "UPDATE history SET created_at = datetime('now', '-100 days')
+                WHERE id IN (?1, ?2)",
+                params![ancient, stale],
+            )
+        })
+        .await
+        .unwrap();
+
+        let age = chrono::Duration::days(90);
+        assert_eq!(
+            db.old_history(age, None).await.unwrap(),
+            vec![("#new".to_owned(), 1), ("#old".to_owned(), 1)]
+        );
+        assert_eq!(
+            db.prune_history(age, Some(old)).await.unwrap(),
+            vec![ancient]
+        );
+        assert_eq!(db.history(old).await.unwrap().len(), 1);
+        assert_eq!(db.history(new).await.unwrap().len(), 1);
+        assert!(db.audit_entries(old, 10).await.unwrap().is_empty());
+        assert_eq!(db.prune_history(age, None).await.unwrap(), vec![stale]);
+        assert!(db.old_history(age, None).await.unwrap().is_empty());
+    }
+}
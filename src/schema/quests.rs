@@ -0,0 +1,118 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::params;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quest {
+    pub id: i64,
+    pub title: String,
+    pub description: String,
+    pub assignee: Option<String>,
+    pub completed: bool,
+}
+
+impl Database {
+    pub async fn create_quest<S>(
+        &self,
+        conversation: Conversation,
+        title: S,
+        description: S,
+        assignee: Option<S>,
+    ) -> Result<i64>
+    where
+        S: AsRef<str>,
+    {
+        let title = title.as_ref().to_owned();
+        let description = description.as_ref().to_owned();
+        let assignee = assignee.map(|a| a.as_ref().trim_start_matches('@').to_owned());
+
+        let id = self
+            .conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO quests (conversation, title, description, assignee)
+                    VALUES (?1, ?2, ?3, ?4)",
+                    params![conversation.0, title, description, assignee],
+                )?;
+                Ok(conn.last_insert_rowid())
+            })
+            .await?;
+
+        Ok(id)
+    }
+
+    /// Mark a quest complete, returning false if there is no such open quest.
+    pub async fn complete_quest(&self, conversation: Conversation, id: i64) -> Result<bool> {
+        let updated = self
+            .conn
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE quests SET completed_at = CURRENT_TIMESTAMP
+                    WHERE conversation = ?1 AND id = ?2 AND completed_at IS NULL",
+                    params![conversation.0, id],
+                )
+            })
+            .await?;
+
+        Ok(updated > 0)
+    }
+
+    pub async fn list_quests(
+        &self,
+        conversation: Conversation,
+        include_completed: bool,
+    ) -> Result<Vec<Quest>> {
+        let quests = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, title, description, assignee, completed_at IS NOT NULL FROM quests
+                    WHERE conversation = ?1 AND (?2 OR completed_at IS NULL)
+                    ORDER BY id",
+                )?;
+                let rows = stmt.query_map(params![conversation.0, include_completed], |row| {
+                    Ok(Quest {
+                        id: row.get(0)?,
+                        title: row.get(1)?,
+                        description: row.get(2)?,
+                        assignee: row.get(3)?,
+                        completed: row.get(4)?,
+                    })
+                })?;
+                rows.collect::<Result<Vec<_>, rusqlite::Error>>()
+            })
+            .await?;
+
+        Ok(quests)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_quests() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let first = db
+            .create_quest(conversation, "Find the hay", "It's in a stack", None)
+            .await
+            .unwrap();
+        db.create_quest(
+            conversation,
+            "Shoe the horse",
+            "Bring nails",
+            Some("@dylan"),
+        )
+        .await
+        .unwrap();
+        assert!(db.complete_quest(conversation, first).await.unwrap());
+        assert!(!db.complete_quest(conversation, first).await.unwrap());
+
+        let open = db.list_quests(conversation, false).await.unwrap();
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].assignee.as_deref(), Some("dylan"));
+        assert_eq!(db.list_quests(conversation, true).await.unwrap().len(), 2);
+    }
+}
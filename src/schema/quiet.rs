@@ -0,0 +1,139 @@
+use super::{Conversation, Database};
+use chrono::{DateTime, Local, NaiveTime};
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+
+/// When the bot should keep quiet in a conversation: muted until a time,
+/// and/or a daily do-not-disturb window (in local time, which may wrap past midnight).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Quiet {
+    pub muted_until: Option<DateTime<Local>>,
+    pub dnd: Option<(NaiveTime, NaiveTime)>,
+}
+
+impl Quiet {
+    pub fn is_quiet(&self, now: DateTime<Local>) -> bool {
+        let muted = self.muted_until.map(|until| now < until).unwrap_or(false);
+        let dnd = self
+            .dnd
+            .map(|(start, end)| {
+                let time = now.time();
+                if start <= end {
+                    start <= time && time < end
+                } else {
+                    time >= start || time < end
+                }
+            })
+            .unwrap_or(false);
+
+        muted || dnd
+    }
+}
+
+impl Database {
+    pub async fn get_quiet(&self, conversation: Conversation) -> Result<Quiet> {
+        let row: Option<(Option<String>, Option<String>, Option<String>)> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT muted_until, dnd_start, dnd_end FROM quiet WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .optional()
+            })
+            .await?;
+
+        let Some((muted_until, dnd_start, dnd_end)) = row else {
+            return Ok(Quiet::default());
+        };
+        let muted_until = muted_until
+            .map(|s| DateTime::parse_from_rfc3339(&s).map(|t| t.with_timezone(&Local)))
+            .transpose()?;
+        let dnd = match (dnd_start, dnd_end) {
+            (Some(start), Some(end)) => Some((
+                NaiveTime::parse_from_str(&start, "%H:%M")?,
+                NaiveTime::parse_from_str(&end, "%H:%M")?,
+            )),
+            _ => None,
+        };
+
+        Ok(Quiet { muted_until, dnd })
+    }
+
+    pub async fn set_muted_until(
+        &self,
+        conversation: Conversation,
+        until: Option<DateTime<Local>>,
+    ) -> Result<()> {
+        let until = until.map(|t| t.to_rfc3339());
+
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO quiet (conversation, muted_until) VALUES (?1, ?2)
+                    ON CONFLICT (conversation) DO UPDATE SET muted_until = ?2",
+                    params![conversation.0, until],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_dnd(
+        &self,
+        conversation: Conversation,
+        dnd: Option<(NaiveTime, NaiveTime)>,
+    ) -> Result<()> {
+        let (start, end) = match dnd {
+            Some((start, end)) => (
+                Some(start.format("%H:%M").to_string()),
+                Some(end.format("%H:%M").to_string()),
+            ),
+            None => (None, None),
+        };
+
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO quiet (conversation, dnd_start, dnd_end) VALUES (?1, ?2, ?3)
+                    ON CONFLICT (conversation) DO UPDATE SET dnd_start = ?2, dnd_end = ?3",
+                    params![conversation.0, start, end],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[tokio::test]
+    async fn test_quiet() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let noon = Local.with_ymd_and_hms(2023, 9, 1, 12, 0, 0).unwrap();
+        assert!(!db.get_quiet(conversation).await.unwrap().is_quiet(noon));
+
+        db.set_muted_until(conversation, Some(noon + chrono::Duration::hours(1)))
+            .await
+            .unwrap();
+        let quiet = db.get_quiet(conversation).await.unwrap();
+        assert!(quiet.is_quiet(noon));
+        assert!(!quiet.is_quiet(noon + chrono::Duration::hours(2)));
+
+        let start = NaiveTime::from_hms_opt(22, 0, 0).unwrap();
+        let end = NaiveTime::from_hms_opt(7, 0, 0).unwrap();
+        db.set_dnd(conversation, Some((start, end))).await.unwrap();
+        let quiet = db.get_quiet(conversation).await.unwrap();
+        assert!(quiet.is_quiet(noon + chrono::Duration::hours(13)));
+        assert!(!quiet.is_quiet(noon + chrono::Duration::hours(8)));
+    }
+}
@@ -0,0 +1,190 @@
+use super::{Conversation, Database};
+use chrono::{DateTime, Duration, Local};
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+
+/// How far a relationship can go either way.
+pub const MAX_AFFINITY: i64 = 100;
+/// The most one update can move a relationship, so no single exchange defines it.
+pub const MAX_STEP: i64 = 10;
+/// Recent notes kept about each relationship, oldest dropped first.
+const MAX_NOTES: usize = 5;
+const MAX_NOTE_CHARS: usize = 200;
+
+/// How long after an update the same relationship can change again.
+pub fn update_cooldown() -> Duration {
+    Duration::minutes(10)
+}
+
+/// How the bot feels about someone in a conversation, as the model keeps track of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relationship {
+    pub user_id: String,
+    pub user_name: String,
+    /// From -MAX_AFFINITY (enemies) to MAX_AFFINITY (the best of friends).
+    pub affinity: i64,
+    /// The most recent notes, oldest first.
+    pub notes: Vec<String>,
+    pub updated_at: DateTime<Local>,
+}
+
+type Row = (String, String, i64, String, String);
+
+fn decode((user_id, user_name, affinity, notes, updated_at): Row) -> Result<Relationship> {
+    Ok(Relationship {
+        user_id,
+        user_name,
+        affinity,
+        notes: serde_json::from_str(&notes)?,
+        updated_at: DateTime::parse_from_rfc3339(&updated_at)?.with_timezone(&Local),
+    })
+}
+
+impl Database {
+    /// Someone's relationship with the bot, by user id or name.
+    pub async fn relationship<S>(
+        &self,
+        conversation: Conversation,
+        user: S,
+    ) -> Result<Option<Relationship>>
+    where
+        S: AsRef<str>,
+    {
+        let user = user.as_ref().trim_start_matches('@').to_owned();
+
+        let row: Option<Row> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT user_id, user_name, affinity, notes, updated_at FROM relationships
+                    WHERE conversation = ?1 AND (user_id = ?2 OR user_name = ?2 COLLATE NOCASE)",
+                    params![conversation.0, user],
+                    |row| {
+                        Ok((
+                            row.get(0)?,
+                            row.get(1)?,
+                            row.get(2)?,
+                            row.get(3)?,
+                            row.get(4)?,
+                        ))
+                    },
+                )
+                .optional()
+            })
+            .await?;
+
+        row.map(decode).transpose()
+    }
+
+    /// Move someone's relationship by `delta` (at most MAX_STEP either way) and add a
+    /// note. Returns None, changing nothing, if it was updated less than
+    /// `update_cooldown()` ago.
+    pub async fn update_relationship(
+        &self,
+        conversation: Conversation,
+        user_id: String,
+        user_name: String,
+        delta: i64,
+        note: Option<String>,
+        now: DateTime<Local>,
+    ) -> Result<Option<Relationship>> {
+        let delta = delta.clamp(-MAX_STEP, MAX_STEP);
+        let note = note
+            .map(|n| n.trim().chars().take(MAX_NOTE_CHARS).collect::<String>())
+            .filter(|n| !n.is_empty());
+
+        let current = self.relationship(conversation, &user_id).await?;
+        if let Some(current) = &current {
+            if now - current.updated_at < update_cooldown() {
+                return Ok(None);
+            }
+        }
+        let (affinity, mut notes) = current.map_or((0, vec![]), |r| (r.affinity, r.notes));
+        notes.extend(note);
+        if notes.len() > MAX_NOTES {
+            notes.drain(..notes.len() - MAX_NOTES);
+        }
+        let relationship = Relationship {
+            user_id,
+            user_name,
+            affinity: (affinity + delta).clamp(-MAX_AFFINITY, MAX_AFFINITY),
+            notes,
+            updated_at: now,
+        };
+
+        let row = relationship.clone();
+        let encoded_notes = serde_json::to_string(&row.notes)?;
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO relationships
+                        (conversation, user_id, user_name, affinity, notes, updated_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                    ON CONFLICT (conversation, user_id) DO UPDATE SET
+                        user_name = ?3, affinity = ?4, notes = ?5, updated_at = ?6",
+                    params![
+                        conversation.0,
+                        row.user_id,
+                        row.user_name,
+                        row.affinity,
+                        encoded_notes,
+                        row.updated_at.to_rfc3339()
+                    ],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(Some(relationship))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_update_relationship() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let now = Local::now();
+        let update = |delta, note: &str, now| {
+            db.update_relationship(
+                conversation,
+                "42".to_owned(),
+                "Filly".to_owned(),
+                delta,
+                Some(note.to_owned()),
+                now,
+            )
+        };
+
+        let first = update(50, "brought me an apple", now).await.unwrap();
+        assert_eq!(first.map(|r| r.affinity), Some(MAX_STEP));
+        assert!(update(5, "again", now + Duration::minutes(1))
+            .await
+            .unwrap()
+            .is_none());
+
+        for i in 0..MAX_NOTES {
+            let later = now + update_cooldown() * (i as i32 + 1);
+            update(-3, &format!("note {i}"), later)
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        let relationship = db
+            .relationship(conversation, "@filly")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(relationship.affinity, MAX_STEP - 3 * MAX_NOTES as i64);
+        assert_eq!(relationship.notes.len(), MAX_NOTES);
+        assert_eq!(relationship.notes[0], "note 0");
+        assert!(db
+            .relationship(conversation, "Colt")
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,199 @@
+use super::{encode_message, insert_message, Conversation, Database, Message};
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+use std::{fmt, time::Duration};
+
+/// What went into one of the bot's replies, for `/debug last`, cost reports and
+/// comparing models.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplyMetadata {
+    /// The history row of the reply.
+    pub history_id: i64,
+    pub provider: String,
+    pub model: String,
+    /// Why the model stopped, like `stop` or `length`.
+    pub finish_reason: Option<String>,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    /// Time spent waiting on the model, over every round of function calls.
+    pub latency: Duration,
+    /// Whether the incoming message went through moderation.
+    pub moderated: bool,
+    /// Functions the model called, in order.
+    pub tools: Vec<String>,
+    /// How many old messages were left out to fit the context window.
+    pub truncated: usize,
+    /// Whether the conversation was over its latency target, so a faster model or a
+    /// shorter history was used.
+    pub fast: bool,
+}
+
+impl fmt::Display for ReplyMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "model:      {} ({})", self.model, self.provider)?;
+        writeln!(
+            f,
+            "finished:   {}",
+            self.finish_reason.as_deref().unwrap_or("unknown")
+        )?;
+        writeln!(
+            f,
+            "tokens:     {} prompt + {} completion",
+            self.prompt_tokens, self.completion_tokens
+        )?;
+        writeln!(
+            f,
+            "latency:    {:.2}s{}",
+            self.latency.as_secs_f64(),
+            if self.fast { " (fast mode)" } else { "" }
+        )?;
+        writeln!(
+            f,
+            "moderation: {}",
+            if self.moderated { "ran" } else { "off" }
+        )?;
+        if self.tools.is_empty() {
+            writeln!(f, "tools:      none")?;
+        } else {
+            writeln!(f, "tools:      {}", self.tools.join(", "))?;
+        }
+        write!(f, "truncated:  {} messages", self.truncated)
+    }
+}
+
+impl Database {
+    /// Append a reply to the history together with what went into it, ignoring the
+    /// metadata's history id for the reply's own. Returns the reply's history id.
+    pub async fn add_reply(
+        &self,
+        conversation: Conversation,
+        reply: Message,
+        metadata: ReplyMetadata,
+    ) -> Result<i64> {
+        let reply = encode_message(&reply)?;
+        let tools = serde_json::to_string(&metadata.tools)?;
+
+        self.transaction(move |tx| {
+            let history_id = insert_message(tx, conversation, &reply)?;
+            tx.execute(
+                "INSERT INTO reply_metadata (conversation, history_id, provider, model,
+                    finish_reason, prompt_tokens, completion_tokens, latency_ms, moderated,
+                    tools, truncated, fast)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                params![
+                    conversation.0,
+                    history_id,
+                    metadata.provider,
+                    metadata.model,
+                    metadata.finish_reason,
+                    metadata.prompt_tokens,
+                    metadata.completion_tokens,
+                    metadata.latency.as_millis() as i64,
+                    metadata.moderated,
+                    tools,
+                    metadata.truncated,
+                    metadata.fast,
+                ],
+            )?;
+            Ok(history_id)
+        })
+        .await
+    }
+
+    /// The metadata for the latest reply in a conversation.
+    pub async fn last_reply_metadata(
+        &self,
+        conversation: Conversation,
+    ) -> Result<Option<ReplyMetadata>> {
+        let row = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT history_id, provider, model, finish_reason, prompt_tokens,
+                        completion_tokens, latency_ms, moderated, tools, truncated, fast
+                    FROM reply_metadata WHERE conversation = ?1
+                    ORDER BY history_id DESC LIMIT 1",
+                    params![conversation.0],
+                    |row| {
+                        Ok((
+                            ReplyMetadata {
+                                history_id: row.get(0)?,
+                                provider: row.get(1)?,
+                                model: row.get(2)?,
+                                finish_reason: row.get(3)?,
+                                prompt_tokens: row.get(4)?,
+                                completion_tokens: row.get(5)?,
+                                latency: Duration::from_millis(row.get(6)?),
+                                moderated: row.get(7)?,
+                                tools: vec![],
+                                truncated: row.get(9)?,
+                                fast: row.get(10)?,
+                            },
+                            row.get::<_, String>(8)?,
+                        ))
+                    },
+                )
+                .optional()
+            })
+            .await?;
+
+        let Some((metadata, tools)) = row else {
+            return Ok(None);
+        };
+        Ok(Some(ReplyMetadata {
+            tools: serde_json::from_str(&tools)?,
+            ..metadata
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::Role;
+
+    #[tokio::test]
+    async fn test_reply_metadata() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        assert_eq!(db.last_reply_metadata(conversation).await.unwrap(), None);
+
+        let first = ReplyMetadata {
+            provider: "openai".to_owned(),
+            model: "gpt-3.5-turbo".to_owned(),
+            finish_reason: Some("stop".to_owned()),
+            prompt_tokens: 100,
+            completion_tokens: 20,
+            latency: Duration::from_millis(1500),
+            moderated: true,
+            tools: vec!["roll_dice".to_owned()],
+            truncated: 2,
+            fast: false,
+        };
+        let second = ReplyMetadata {
+            tools: vec![],
+            fast: true,
+            ..first.clone()
+        };
+        let reply = |content| Message::new(Role::Assistant, content);
+        db.add_reply(conversation, reply("neigh"), first)
+            .await
+            .unwrap();
+        let history_id = db
+            .add_reply(conversation, reply("whinny"), second.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            db.last_reply_metadata(conversation).await.unwrap(),
+            Some(ReplyMetadata {
+                history_id,
+                ..second
+            })
+        );
+        let history = db.history(conversation).await.unwrap();
+        assert_eq!(
+            history.last().map(Message::content).as_deref(),
+            Some("whinny")
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::params;
+
+/// Someone's complaint about one of the bot's messages. The message's content is
+/// kept with it, since the message itself may be deleted before anyone looks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub conversation: String,
+    pub channel_id: String,
+    pub message_id: String,
+    pub reporter_id: String,
+    pub reason: String,
+    pub content: String,
+    pub created_at: String,
+}
+
+impl Database {
+    pub async fn add_report(
+        &self,
+        conversation: Conversation,
+        channel_id: u64,
+        message_id: u64,
+        reporter_id: u64,
+        reason: String,
+        content: String,
+    ) -> Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO reports (conversation, channel_id, message_id, reporter_id,
+                        reason, content, created_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, CURRENT_TIMESTAMP)",
+                    params![
+                        conversation.0,
+                        channel_id.to_string(),
+                        message_id.to_string(),
+                        reporter_id.to_string(),
+                        reason,
+                        content
+                    ],
+                )
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Reports about one conversation, or with None all of them, oldest first.
+    pub async fn reports(&self, conversation: Option<Conversation>) -> Result<Vec<Report>> {
+        let reports = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT c.name, r.channel_id, r.message_id, r.reporter_id, r.reason,
+                        r.content, r.created_at
+                    FROM reports r JOIN conversation c ON c.id = r.conversation
+                    WHERE ?1 IS NULL OR r.conversation = ?1
+                    ORDER BY r.id",
+                )?;
+                let rows = stmt.query_map(params![conversation.map(|c| c.0)], |row| {
+                    Ok(Report {
+                        conversation: row.get(0)?,
+                        channel_id: row.get(1)?,
+                        message_id: row.get(2)?,
+                        reporter_id: row.get(3)?,
+                        reason: row.get(4)?,
+                        content: row.get(5)?,
+                        created_at: row.get(6)?,
+                    })
+                })?;
+                rows.collect::<Result<Vec<_>, rusqlite::Error>>()
+            })
+            .await?;
+
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reports() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let paddock = db.find_conversation("#paddock").await.unwrap();
+        let stable = db.find_conversation("#stable").await.unwrap();
+        let report = |conversation, reason: &str| {
+            db.add_report(conversation, 1, 2, 3, reason.to_owned(), "neigh".to_owned())
+        };
+        report(paddock, "rude").await.unwrap();
+        report(stable, "wrong").await.unwrap();
+
+        let reports = db.reports(Some(paddock)).await.unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].conversation, "#paddock");
+        assert_eq!(reports[0].reason, "rude");
+        assert_eq!(reports[0].reporter_id, "3");
+        assert_eq!(db.reports(None).await.unwrap().len(), 2);
+    }
+}
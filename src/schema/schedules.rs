@@ -0,0 +1,327 @@
+use super::{Conversation, Database};
+use chrono::{DateTime, Datelike, Local, NaiveTime, Weekday};
+use eyre::{eyre, Result};
+use rusqlite::params;
+use std::str::FromStr;
+
+/// Which days of the week a scheduled prompt applies on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Days {
+    #[default]
+    Every,
+    Weekdays,
+    Weekends,
+}
+
+impl Days {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Days::Every => "every",
+            Days::Weekdays => "weekdays",
+            Days::Weekends => "weekends",
+        }
+    }
+
+    fn includes(&self, day: Weekday) -> bool {
+        let weekend = matches!(day, Weekday::Sat | Weekday::Sun);
+        match self {
+            Days::Every => true,
+            Days::Weekdays => !weekend,
+            Days::Weekends => weekend,
+        }
+    }
+}
+
+impl FromStr for Days {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "every" => Ok(Days::Every),
+            "weekdays" => Ok(Days::Weekdays),
+            "weekends" => Ok(Days::Weekends),
+            _ => Err(eyre!("unknown days {s}, use every, weekdays or weekends")),
+        }
+    }
+}
+
+/// A prompt that replaces a conversation's own while it applies: on some days, during
+/// a daily window (in local time, which may wrap past midnight), between two dates,
+/// or any mix of those.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptSchedule {
+    pub name: String,
+    pub prompt: String,
+    pub days: Days,
+    pub window: Option<(NaiveTime, NaiveTime)>,
+    pub period: Option<(DateTime<Local>, DateTime<Local>)>,
+}
+
+impl PromptSchedule {
+    pub fn applies(&self, now: DateTime<Local>) -> bool {
+        let day = match self.window {
+            // the early hours of a late-night window belong to the day it started on
+            Some((start, end)) if start > end && now.time() < end => now.weekday().pred(),
+            _ => now.weekday(),
+        };
+        let in_window = self.window.map_or(true, |(start, end)| {
+            let time = now.time();
+            // a window that ends when it starts lasts the whole day
+            if start == end {
+                true
+            } else if start < end {
+                start <= time && time < end
+            } else {
+                time >= start || time < end
+            }
+        });
+        let in_period = self
+            .period
+            .map_or(true, |(starts, ends)| starts <= now && now < ends);
+
+        self.days.includes(day) && in_window && in_period
+    }
+
+    /// When several apply, the narrowest wins: events over daily windows over days.
+    fn specificity(&self) -> (bool, bool, bool) {
+        (
+            self.period.is_some(),
+            self.window.is_some(),
+            self.days != Days::Every,
+        )
+    }
+}
+
+impl Database {
+    /// Add a scheduled prompt, replacing any of the same name.
+    pub async fn set_prompt_schedule(
+        &self,
+        conversation: Conversation,
+        schedule: PromptSchedule,
+    ) -> Result<()> {
+        let (from, until) = match schedule.window {
+            Some((from, until)) => (
+                Some(from.format("%H:%M").to_string()),
+                Some(until.format("%H:%M").to_string()),
+            ),
+            None => (None, None),
+        };
+        let (starts, ends) = match schedule.period {
+            Some((starts, ends)) => (Some(starts.to_rfc3339()), Some(ends.to_rfc3339())),
+            None => (None, None),
+        };
+
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO prompt_schedules
+                        (conversation, name, prompt, days, from_time, until_time, starts_at, ends_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                    ON CONFLICT (conversation, name) DO UPDATE SET
+                        prompt = ?3, days = ?4, from_time = ?5, until_time = ?6,
+                        starts_at = ?7, ends_at = ?8",
+                    params![
+                        conversation.0,
+                        schedule.name,
+                        schedule.prompt,
+                        schedule.days.as_str(),
+                        from,
+                        until,
+                        starts,
+                        ends
+                    ],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Remove a scheduled prompt. Returns false if there was none of that name.
+    pub async fn remove_prompt_schedule(
+        &self,
+        conversation: Conversation,
+        name: String,
+    ) -> Result<bool> {
+        let removed = self
+            .conn
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM prompt_schedules WHERE conversation = ?1 AND name = ?2",
+                    params![conversation.0, name],
+                )
+            })
+            .await?;
+
+        Ok(removed > 0)
+    }
+
+    pub async fn prompt_schedules(
+        &self,
+        conversation: Conversation,
+    ) -> Result<Vec<PromptSchedule>> {
+        type Row = (
+            String,
+            String,
+            String,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        );
+        let rows: Vec<Row> = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT name, prompt, days, from_time, until_time, starts_at, ends_at
+                    FROM prompt_schedules WHERE conversation = ?1 ORDER BY name",
+                )?;
+                let rows = stmt
+                    .query_map(params![conversation.0], |row| {
+                        Ok((
+                            row.get(0)?,
+                            row.get(1)?,
+                            row.get(2)?,
+                            row.get(3)?,
+                            row.get(4)?,
+                            row.get(5)?,
+                            row.get(6)?,
+                        ))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(rows)
+            })
+            .await?;
+
+        rows.into_iter()
+            .map(|(name, prompt, days, from, until, starts, ends)| {
+                let window = match (from, until) {
+                    (Some(from), Some(until)) => Some((
+                        NaiveTime::parse_from_str(&from, "%H:%M")?,
+                        NaiveTime::parse_from_str(&until, "%H:%M")?,
+                    )),
+                    _ => None,
+                };
+                let period = match (starts, ends) {
+                    (Some(starts), Some(ends)) => Some((
+                        DateTime::parse_from_rfc3339(&starts)?.with_timezone(&Local),
+                        DateTime::parse_from_rfc3339(&ends)?.with_timezone(&Local),
+                    )),
+                    _ => None,
+                };
+                Ok(PromptSchedule {
+                    name,
+                    prompt,
+                    days: days.parse()?,
+                    window,
+                    period,
+                })
+            })
+            .collect()
+    }
+
+    /// The prompt a conversation uses at `now`: the narrowest scheduled prompt that
+    /// applies, or else its own.
+    pub async fn current_prompt(
+        &self,
+        conversation: Conversation,
+        now: DateTime<Local>,
+    ) -> Result<Option<String>> {
+        let scheduled = self
+            .prompt_schedules(conversation)
+            .await?
+            .into_iter()
+            .filter(|s| s.applies(now))
+            .max_by_key(PromptSchedule::specificity);
+
+        match scheduled {
+            Some(schedule) => Ok(Some(schedule.prompt)),
+            None => self.get_prompt(conversation).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn schedule(name: &str, days: Days) -> PromptSchedule {
+        PromptSchedule {
+            name: name.to_owned(),
+            prompt: format!("You are a {name} horse."),
+            days,
+            window: None,
+            period: None,
+        }
+    }
+
+    #[test]
+    fn test_applies() {
+        // a Friday
+        let friday_night = Local.with_ymd_and_hms(2023, 9, 1, 23, 0, 0).unwrap();
+        let saturday_morning = friday_night + chrono::Duration::hours(3);
+        let late = PromptSchedule {
+            window: Some((
+                NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+            )),
+            ..schedule("sleepy", Days::Weekdays)
+        };
+        assert!(late.applies(friday_night));
+        assert!(late.applies(saturday_morning));
+        assert!(!late.applies(saturday_morning + chrono::Duration::days(1)));
+        assert!(!late.applies(friday_night - chrono::Duration::hours(2)));
+        assert!(schedule("lazy", Days::Weekends).applies(saturday_morning));
+
+        let noon = NaiveTime::from_hms_opt(12, 0, 0).unwrap();
+        let all_day = PromptSchedule {
+            window: Some((noon, noon)),
+            ..schedule("steady", Days::Weekdays)
+        };
+        assert!(all_day.applies(friday_night));
+        assert!(all_day.applies(friday_night - chrono::Duration::hours(11)));
+        assert!(!all_day.applies(saturday_morning + chrono::Duration::hours(9)));
+    }
+
+    #[tokio::test]
+    async fn test_current_prompt() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let saturday = Local.with_ymd_and_hms(2023, 9, 2, 12, 0, 0).unwrap();
+        db.set_prompt(conversation, "You are a horse.")
+            .await
+            .unwrap();
+        db.set_prompt_schedule(conversation, schedule("lazy", Days::Weekends))
+            .await
+            .unwrap();
+        db.set_prompt_schedule(
+            conversation,
+            PromptSchedule {
+                period: Some((saturday, saturday + chrono::Duration::hours(1))),
+                ..schedule("spooky", Days::Every)
+            },
+        )
+        .await
+        .unwrap();
+
+        for (now, expected) in [
+            (saturday, "You are a spooky horse."),
+            (
+                saturday + chrono::Duration::hours(2),
+                "You are a lazy horse.",
+            ),
+            (saturday + chrono::Duration::days(2), "You are a horse."),
+        ] {
+            let prompt = db.current_prompt(conversation, now).await.unwrap();
+            assert_eq!(prompt.as_deref(), Some(expected));
+        }
+
+        assert!(db
+            .remove_prompt_schedule(conversation, "spooky".to_owned())
+            .await
+            .unwrap());
+        assert_eq!(db.prompt_schedules(conversation).await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,445 @@
+use super::{Conversation, Database};
+use eyre::{eyre, Result};
+use rusqlite::{params, OptionalExtension};
+use std::{fmt::Display, marker::PhantomData, str::FromStr};
+
+/// An option stored as text under `key`, so adding one doesn't need a new column or
+/// table. It can be set for the whole instance, a server or a conversation.
+pub struct Setting<T> {
+    pub key: &'static str,
+    /// Used when nothing in the cascade sets it.
+    pub default: &'static str,
+    value: PhantomData<fn() -> T>,
+}
+
+impl<T> Setting<T> {
+    const fn new(key: &'static str, default: &'static str) -> Self {
+        Self {
+            key,
+            default,
+            value: PhantomData,
+        }
+    }
+}
+
+/// How adventurous replies are, from 0 to 2.
+pub const TEMPERATURE: Setting<f32> = Setting::new("temperature", "0.5");
+
+/// The OpenAI chat model that writes replies.
+pub const MODEL: Setting<String> = Setting::new("model", "gpt-3.5-turbo");
+
+/// Whether replies ping the people they mention, rather than just naming them.
+pub const PING: Setting<bool> = Setting::new("ping", "false");
+
+/// Whether answers are sent as replies to the message they answer.
+pub const REPLY: Setting<bool> = Setting::new("reply", "true");
+
+/// Whether long replies are sent as a few shorter messages, a moment apart.
+pub const PACING: Setting<bool> = Setting::new("pacing", "false");
+
+/// Whether to log each request to the model, prompt and all, with its raw response.
+pub const AUDIT: Setting<bool> = Setting::new("audit", "false");
+
+/// The id of the channel to tell when someone reports one of the bot's messages, if any.
+pub const REPORT_CHANNEL: Setting<String> = Setting::new("report_channel", "");
+
+/// An iCal feed of the server's events, for the prompt's `upcoming_events`, if any.
+pub const CALENDAR_URL: Setting<String> = Setting::new("calendar_url", "");
+
+/// The id of the channel birthdays are celebrated in, if any. Set for a server.
+pub const BIRTHDAY_CHANNEL: Setting<String> = Setting::new("birthday_channel", "");
+
+/// The id of the channel members are welcomed in and said goodbye to, if any. Set for
+/// a server.
+pub const WELCOME_CHANNEL: Setting<String> = Setting::new("welcome_channel", "");
+
+/// The id of the channel boosts and milestones are celebrated in, if any. Set for a
+/// server.
+pub const CELEBRATION_CHANNEL: Setting<String> = Setting::new("celebration_channel", "");
+
+/// The member counts worth celebrating, comma separated.
+pub const MEMBER_MILESTONES: Setting<String> = Setting::new(
+    "member_milestones",
+    "100, 250, 500, 1000, 2500, 5000, 10000",
+);
+
+/// Comma separated ids of roles whose messages skip input moderation, like the server's
+/// moderators testing the bot.
+pub const MODERATION_BYPASS: Setting<String> = Setting::new("moderation_bypass", "");
+
+/// Whether stages on trial run alongside each reply, logging what they would have
+/// done without changing it.
+pub const SHADOW: Setting<bool> = Setting::new("shadow", "false");
+
+/// A model to try in shadow mode, sent the same request as each reply, if any.
+pub const SHADOW_MODEL: Setting<String> = Setting::new("shadow_model", "");
+
+/// Comma separated stages shadow mode runs: model (when shadow_model names one) and
+/// summary.
+pub const SHADOW_STAGES: Setting<String> = Setting::new("shadow_stages", "model,summary");
+
+/// Seconds the slowest replies (the p95) may take before a conversation switches to a
+/// faster model and a shorter history, or 0 to never.
+pub const LATENCY_TARGET: Setting<f32> = Setting::new("latency_target", "0");
+
+/// The model used in fast mode, or empty to keep the usual one and only shorten the
+/// history.
+pub const FAST_MODEL: Setting<String> = Setting::new("fast_model", "");
+
+/// Whether messages are sorted before the model is asked, so greetings and thanks get
+/// a canned answer and chatter like "lol" none.
+pub const ROUTER: Setting<bool> = Setting::new("router", "false");
+
+/// A cheap model the router asks about messages it can't sort by their words alone,
+/// or empty to give those a full reply.
+pub const ROUTER_MODEL: Setting<String> = Setting::new("router_model", "");
+
+/// The OpenAI model that embeds text, like FAQ questions, for comparing by meaning.
+pub const EMBEDDING_MODEL: Setting<String> =
+    Setting::new("embedding_model", "text-embedding-ada-002");
+
+/// How alike (from 0 to 1) a message and a FAQ question must be for the FAQ to answer.
+pub const FAQ_THRESHOLD: Setting<f32> = Setting::new("faq_threshold", "0.9");
+
+/// A cheap model that puts FAQ answers in the persona's words, or empty to give them
+/// as written.
+pub const FAQ_REPHRASE_MODEL: Setting<String> = Setting::new("faq_rephrase_model", "");
+
+/// Minutes a server conversation has to be quiet before the bot says something to
+/// liven it up, or 0 to never.
+pub const IDLE_CHATTER: Setting<u32> = Setting::new("idle_chatter", "0");
+
+/// Comma separated names of the functions the model may call, or empty for all of them.
+pub const FUNCTIONS: Setting<String> = Setting::new("functions", "");
+
+/// The value of FUNCTIONS that allows none of them.
+pub const NO_FUNCTIONS: &str = "none";
+
+/// Check a value before it is stored, so a typo fails now rather than on the next reply.
+pub fn check_setting(key: &str, value: &str) -> Result<()> {
+    match key {
+        "temperature" => {
+            let temperature: f32 = value.parse()?;
+            if !(0.0..=2.0).contains(&temperature) {
+                return Err(eyre!("temperature must be between 0 and 2"));
+            }
+            Ok(())
+        }
+        "model" | "embedding_model" if value.trim().is_empty() => {
+            Err(eyre!("{key} can't be empty"))
+        }
+        "model" | "shadow_model" | "fast_model" | "router_model" | "embedding_model"
+        | "faq_rephrase_model" => Ok(()),
+        "functions" => {
+            let valid = |f: &str| {
+                f.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            };
+            if !value.split(',').map(str::trim).all(valid) {
+                return Err(eyre!(
+                    "functions must be function names, like roll_dice, weather"
+                ));
+            }
+            Ok(())
+        }
+        "shadow_stages" => {
+            let valid = |s: &str| matches!(s, "model" | "summary");
+            if !value.split(',').map(str::trim).all(valid) {
+                return Err(eyre!("shadow_stages must be model, summary or both"));
+            }
+            Ok(())
+        }
+        "faq_threshold" => {
+            let threshold: f32 = value.parse()?;
+            if !(0.0..=1.0).contains(&threshold) {
+                return Err(eyre!("faq_threshold must be between 0 and 1"));
+            }
+            Ok(())
+        }
+        "ping" | "reply" | "pacing" | "audit" | "shadow" | "router" => {
+            value
+                .parse::<bool>()
+                .map_err(|_| eyre!("{key} must be true or false"))?;
+            Ok(())
+        }
+        "report_channel" | "birthday_channel" | "welcome_channel" | "celebration_channel"
+            if value.parse::<u64>().is_err() =>
+        {
+            Err(eyre!("{key} must be a channel id"))
+        }
+        "report_channel" | "birthday_channel" | "welcome_channel" | "celebration_channel" => Ok(()),
+        "moderation_bypass" => {
+            for role in value.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+                role.parse::<u64>()
+                    .map_err(|_| eyre!("moderation_bypass must be role ids, like 123, 456"))?;
+            }
+            Ok(())
+        }
+        "member_milestones" => {
+            for milestone in value.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+                milestone
+                    .parse::<u64>()
+                    .map_err(|_| eyre!("member_milestones must be numbers, like 100, 500"))?;
+            }
+            Ok(())
+        }
+        "calendar_url" if !(value.starts_with("https://") || value.starts_with("http://")) => {
+            Err(eyre!("calendar_url must be an http or https URL"))
+        }
+        "calendar_url" => Ok(()),
+        "latency_target" => {
+            let target: f32 = value.parse()?;
+            if !(0.0..=120.0).contains(&target) {
+                return Err(eyre!("latency_target must be from 0 (off) to 120 seconds"));
+            }
+            Ok(())
+        }
+        "idle_chatter" => match value.parse::<u32>() {
+            Ok(0) => Ok(()),
+            Ok(minutes) if minutes >= 30 => Ok(()),
+            _ => Err(eyre!("idle_chatter must be 0 (off) or at least 30 minutes")),
+        },
+        _ => Err(eyre!(
+            "unknown setting {key}, use temperature, model, ping, reply, pacing, audit, \
+             report_channel, calendar_url, birthday_channel, welcome_channel, celebration_channel, \
+             member_milestones, moderation_bypass, idle_chatter, shadow, shadow_model, \
+             shadow_stages, latency_target, fast_model, router, router_model, embedding_model, faq_threshold, \
+             faq_rephrase_model or functions"
+        )),
+    }
+}
+
+/// Check a setting can be made at `scope`. The channel settings name one server's
+/// channel, so they can't be made for every server at once.
+pub fn check_setting_scope(key: &str, scope: SettingScope) -> Result<()> {
+    match (key, scope) {
+        ("birthday_channel" | "welcome_channel" | "celebration_channel", SettingScope::Global) => {
+            Err(eyre!(
+                "{key} names a channel, so it has to be set for a server"
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Where a setting applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingScope {
+    /// Every conversation, unless its server or itself says otherwise.
+    Global,
+    /// Every conversation in a server, unless it says otherwise.
+    Guild(u64),
+    Conversation(Conversation),
+}
+
+/// Looks settings up for one conversation, taking the conversation's own value, then
+/// its server's, then the instance-wide one, then the built-in default.
+pub struct SettingsResolver<'a> {
+    db: &'a Database,
+    conversation: Option<Conversation>,
+    guild: Option<u64>,
+}
+
+impl<'a> SettingsResolver<'a> {
+    pub fn new(db: &'a Database, conversation: Conversation, guild: Option<u64>) -> Self {
+        Self {
+            db,
+            conversation: Some(conversation),
+            guild,
+        }
+    }
+
+    /// For instance-wide settings, which skip any server's or conversation's own value.
+    pub fn global(db: &'a Database) -> Self {
+        Self {
+            db,
+            conversation: None,
+            guild: None,
+        }
+    }
+
+    /// For settings of a whole server, which skip the conversation's own value.
+    pub fn guild(db: &'a Database, guild: u64) -> Self {
+        Self {
+            db,
+            conversation: None,
+            guild: Some(guild),
+        }
+    }
+
+    pub async fn get<T>(&self, setting: &Setting<T>) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = self
+            .db
+            .resolve_setting(self.conversation, self.guild, setting.key)
+            .await?;
+        let value = value.as_deref().unwrap_or(setting.default);
+        value
+            .parse()
+            .map_err(|e| eyre!("bad {} setting {value:?}: {e}", setting.key))
+    }
+}
+
+impl Database {
+    /// Set (or with None, remove) a setting at one level of the cascade.
+    pub async fn set_setting(
+        &self,
+        scope: SettingScope,
+        key: &str,
+        value: Option<String>,
+    ) -> Result<()> {
+        let key = key.to_owned();
+
+        self.conn
+            .call(move |conn| {
+                match (scope, value) {
+                    (SettingScope::Global, Some(value)) => conn.execute(
+                        "INSERT INTO settings (key, value) VALUES (?1, ?2)
+                        ON CONFLICT (key) DO UPDATE SET value = ?2",
+                        params![key, value],
+                    )?,
+                    (SettingScope::Global, None) => {
+                        conn.execute("DELETE FROM settings WHERE key = ?1", params![key])?
+                    }
+                    (SettingScope::Guild(guild), Some(value)) => conn.execute(
+                        "INSERT INTO guild_settings (guild_id, key, value) VALUES (?1, ?2, ?3)
+                        ON CONFLICT (guild_id, key) DO UPDATE SET value = ?3",
+                        params![guild.to_string(), key, value],
+                    )?,
+                    (SettingScope::Guild(guild), None) => conn.execute(
+                        "DELETE FROM guild_settings WHERE guild_id = ?1 AND key = ?2",
+                        params![guild.to_string(), key],
+                    )?,
+                    (SettingScope::Conversation(conversation), Some(value)) => conn.execute(
+                        "INSERT INTO conversation_settings (conversation, key, value)
+                        VALUES (?1, ?2, ?3)
+                        ON CONFLICT (conversation, key) DO UPDATE SET value = ?3",
+                        params![conversation.0, key, value],
+                    )?,
+                    (SettingScope::Conversation(conversation), None) => conn.execute(
+                        "DELETE FROM conversation_settings WHERE conversation = ?1 AND key = ?2",
+                        params![conversation.0, key],
+                    )?,
+                };
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// The most specific value set for a conversation (if any) in `guild`, if any.
+    async fn resolve_setting(
+        &self,
+        conversation: Option<Conversation>,
+        guild: Option<u64>,
+        key: &str,
+    ) -> Result<Option<String>> {
+        let key = key.to_owned();
+
+        let value: Option<Option<String>> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT coalesce(
+                        (SELECT value FROM conversation_settings
+                        WHERE conversation = ?1 AND key = ?3),
+                        (SELECT value FROM guild_settings WHERE guild_id = ?2 AND key = ?3),
+                        (SELECT value FROM settings WHERE key = ?3)
+                    )",
+                    params![conversation.map(|c| c.0), guild.map(|g| g.to_string()), key],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(value.flatten())
+    }
+
+    /// Every value a setting has anywhere, along with its default.
+    pub async fn setting_values<T>(&self, setting: &Setting<T>) -> Result<Vec<String>> {
+        let key = setting.key;
+        let default = setting.default;
+
+        let values = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT value FROM settings WHERE key = ?1
+                    UNION SELECT value FROM guild_settings WHERE key = ?1
+                    UNION SELECT value FROM conversation_settings WHERE key = ?1
+                    UNION SELECT ?2
+                    ORDER BY 1",
+                )?;
+                let rows = stmt.query_map(params![key, default], |row| row.get(0))?;
+                rows.collect::<Result<Vec<String>, rusqlite::Error>>()
+            })
+            .await?;
+
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_settings() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("dylan").await.unwrap();
+        let settings = SettingsResolver::new(&db, conversation, Some(7));
+        assert_eq!(settings.get(&TEMPERATURE).await.unwrap(), 0.5);
+
+        let set = |scope, value: &str| db.set_setting(scope, "temperature", Some(value.to_owned()));
+        set(SettingScope::Global, "0.9").await.unwrap();
+        assert_eq!(settings.get(&TEMPERATURE).await.unwrap(), 0.9);
+        set(SettingScope::Guild(8), "1.1").await.unwrap();
+        assert_eq!(settings.get(&TEMPERATURE).await.unwrap(), 0.9);
+        set(SettingScope::Guild(7), "1.2").await.unwrap();
+        assert_eq!(settings.get(&TEMPERATURE).await.unwrap(), 1.2);
+        set(SettingScope::Conversation(conversation), "0.1")
+            .await
+            .unwrap();
+        assert_eq!(settings.get(&TEMPERATURE).await.unwrap(), 0.1);
+
+        db.set_setting(
+            SettingScope::Conversation(conversation),
+            "temperature",
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(settings.get(&TEMPERATURE).await.unwrap(), 1.2);
+        let outside = SettingsResolver::new(&db, conversation, None);
+        assert_eq!(outside.get(&TEMPERATURE).await.unwrap(), 0.9);
+        let server = SettingsResolver::guild(&db, 7);
+        assert_eq!(server.get(&TEMPERATURE).await.unwrap(), 1.2);
+        assert_eq!(
+            db.setting_values(&TEMPERATURE).await.unwrap(),
+            vec!["0.5", "0.9", "1.1", "1.2"]
+        );
+
+        assert!(check_setting("temperature", "0.7").is_ok());
+        assert!(check_setting("temperature", "3").is_err());
+        assert!(check_setting("model", "gpt-4").is_ok());
+        assert!(check_setting("ping", "true").is_ok());
+        assert!(check_setting("ping", "yes").is_err());
+        assert!(check_setting("reply", "false").is_ok());
+        assert!(check_setting("calendar_url", "webcal://example.com").is_err());
+        assert!(check_setting("latency_target", "4.5").is_ok());
+        assert!(check_setting("latency_target", "-1").is_err());
+        assert!(check_setting("functions", "roll_dice, weather").is_ok());
+        assert!(check_setting("functions", "rm -rf /").is_err());
+        assert!(check_setting("shadow_stages", "model").is_ok());
+        assert!(check_setting("shadow_stages", "model, tools").is_err());
+        assert!(check_setting("nope", "1").is_err());
+        assert!(check_setting_scope("welcome_channel", SettingScope::Guild(1)).is_ok());
+        assert!(check_setting_scope("welcome_channel", SettingScope::Global).is_err());
+        assert!(check_setting_scope("model", SettingScope::Global).is_ok());
+    }
+}
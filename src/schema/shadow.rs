@@ -0,0 +1,88 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::params;
+
+/// How many shadow outputs to keep per conversation.
+const SHADOW_ENTRIES_KEPT: i64 = 100;
+
+/// What a pipeline stage on trial would have done, had it been live.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadowEntry {
+    pub created_at: String,
+    pub stage: String,
+    pub output: String,
+}
+
+impl Database {
+    pub async fn add_shadow_entry(
+        &self,
+        conversation: Conversation,
+        stage: String,
+        output: String,
+    ) -> Result<()> {
+        self.transaction(move |tx| {
+            tx.execute(
+                "INSERT INTO shadow_log (conversation, stage, output, created_at)
+                VALUES (?1, ?2, ?3, CURRENT_TIMESTAMP)",
+                params![conversation.0, stage, output],
+            )?;
+            tx.execute(
+                "DELETE FROM shadow_log WHERE conversation = ?1 AND id NOT IN
+                (SELECT id FROM shadow_log WHERE conversation = ?1
+                ORDER BY id DESC LIMIT ?2)",
+                params![conversation.0, SHADOW_ENTRIES_KEPT],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// The conversation's latest `limit` shadow outputs, oldest first.
+    pub async fn shadow_entries(
+        &self,
+        conversation: Conversation,
+        limit: usize,
+    ) -> Result<Vec<ShadowEntry>> {
+        let entries = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT created_at, stage, output FROM
+                    (SELECT * FROM shadow_log WHERE conversation = ?1 ORDER BY id DESC LIMIT ?2)
+                    ORDER BY id",
+                )?;
+                let rows = stmt.query_map(params![conversation.0, limit as i64], |row| {
+                    Ok(ShadowEntry {
+                        created_at: row.get(0)?,
+                        stage: row.get(1)?,
+                        output: row.get(2)?,
+                    })
+                })?;
+                rows.collect::<Result<Vec<_>, rusqlite::Error>>()
+            })
+            .await?;
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shadow_log() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("shadowed").await.unwrap();
+        for i in 0..SHADOW_ENTRIES_KEPT + 2 {
+            db.add_shadow_entry(conversation, "summary".to_owned(), format!("summary {i}"))
+                .await
+                .unwrap();
+        }
+
+        let entries = db.shadow_entries(conversation, 1000).await.unwrap();
+        assert_eq!(entries.len() as i64, SHADOW_ENTRIES_KEPT);
+        assert_eq!(entries[0].output, "summary 2");
+        assert_eq!(entries.last().unwrap().stage, "summary");
+    }
+}
@@ -0,0 +1,143 @@
+use super::{Conversation, Database};
+use eyre::Result;
+use rusqlite::params;
+
+impl Database {
+    /// Set a value on a user's character sheet, or remove it if the value is None.
+    pub async fn set_sheet_value<S>(
+        &self,
+        conversation: Conversation,
+        user_id: S,
+        user_name: S,
+        key: S,
+        value: Option<S>,
+    ) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        let user_id = user_id.as_ref().to_owned();
+        let user_name = user_name.as_ref().to_owned();
+        let key = key.as_ref().to_owned();
+        let value = value.map(|v| v.as_ref().to_owned());
+
+        self.conn
+            .call(move |conn| {
+                match value {
+                    Some(value) => conn.execute(
+                        "INSERT INTO sheets (conversation, user_id, user_name, key, value)
+                        VALUES (?1, ?2, ?3, ?4, ?5)
+                        ON CONFLICT (conversation, user_id, key) DO UPDATE SET value = ?5",
+                        params![conversation.0, user_id, user_name, key, value],
+                    )?,
+                    None => conn.execute(
+                        "DELETE FROM sheets WHERE conversation = ?1 AND user_id = ?2 AND key = ?3",
+                        params![conversation.0, user_id, key],
+                    )?,
+                };
+                // keep the name current for lookups by name
+                conn.execute(
+                    "UPDATE sheets SET user_name = ?3 WHERE conversation = ?1 AND user_id = ?2",
+                    params![conversation.0, user_id, user_name],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get a character sheet as (key, value) pairs. The user can be given by id or by name.
+    pub async fn get_sheet<S>(
+        &self,
+        conversation: Conversation,
+        user: S,
+    ) -> Result<Vec<(String, String)>>
+    where
+        S: AsRef<str>,
+    {
+        let user = user.as_ref().trim_start_matches('@').to_owned();
+
+        let sheet = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT key, value FROM sheets
+                    WHERE conversation = ?1 AND (user_id = ?2 OR user_name = ?2 COLLATE NOCASE)
+                    ORDER BY key",
+                )?;
+                let rows = stmt.query_map(params![conversation.0, user], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?;
+                rows.collect::<Result<Vec<_>, rusqlite::Error>>()
+            })
+            .await?;
+
+        Ok(sheet)
+    }
+
+    /// Every field name used on a sheet in this conversation, for suggesting them
+    /// in a server. Nothing is suggested if the conversation belongs to another one.
+    pub async fn sheet_keys(
+        &self,
+        conversation: Conversation,
+        guild_id: Option<u64>,
+    ) -> Result<Vec<String>> {
+        let guild_id = guild_id.map(|id| id.to_string());
+        let keys = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT DISTINCT s.key FROM sheets s
+                    JOIN conversation c ON c.id = s.conversation
+                    WHERE s.conversation = ?1 AND c.guild_id IS ?2
+                    ORDER BY s.key",
+                )?;
+                let rows = stmt.query_map(params![conversation.0, guild_id], |row| row.get(0))?;
+                rows.collect::<Result<Vec<String>, _>>()
+            })
+            .await?;
+
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sheet() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        db.set_sheet_value(conversation, "1", "dylan", "class", Some("bard"))
+            .await
+            .unwrap();
+        db.set_sheet_value(conversation, "1", "dylan", "hp", Some("12"))
+            .await
+            .unwrap();
+        db.set_sheet_value(conversation, "1", "Dylan", "hp", None)
+            .await
+            .unwrap();
+
+        let sheet = db.get_sheet(conversation, "@DYLAN").await.unwrap();
+        assert_eq!(sheet, vec![("class".to_owned(), "bard".to_owned())]);
+        assert_eq!(db.get_sheet(conversation, "1").await.unwrap(), sheet);
+        assert_eq!(
+            db.sheet_keys(conversation, None).await.unwrap(),
+            vec!["class"]
+        );
+
+        let channel = db
+            .find_channel_conversation("7", Some((1, "one")), "general")
+            .await
+            .unwrap();
+        db.set_sheet_value(channel, "1", "dylan", "class", Some("bard"))
+            .await
+            .unwrap();
+        assert_eq!(
+            db.sheet_keys(channel, Some(1)).await.unwrap(),
+            vec!["class"]
+        );
+        assert!(db.sheet_keys(channel, Some(2)).await.unwrap().is_empty());
+    }
+}
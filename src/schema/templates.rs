@@ -0,0 +1,83 @@
+use super::Database;
+use eyre::Result;
+use rusqlite::params;
+
+impl Database {
+    /// Store (or with None, remove) a named template shared by a server's conversations.
+    pub async fn set_template<S>(
+        &self,
+        guild_id: u64,
+        name: S,
+        source: Option<String>,
+    ) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref().to_owned();
+
+        self.conn
+            .call(move |conn| {
+                match source {
+                    Some(source) => conn.execute(
+                        "INSERT INTO templates (guild_id, name, source) VALUES (?1, ?2, ?3)
+                        ON CONFLICT (guild_id, name) DO UPDATE SET source = ?3",
+                        params![guild_id.to_string(), name, source],
+                    )?,
+                    None => conn.execute(
+                        "DELETE FROM templates WHERE guild_id = ?1 AND name = ?2",
+                        params![guild_id.to_string(), name],
+                    )?,
+                };
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Every template a server has, as (name, source) pairs.
+    pub async fn templates(&self, guild_id: u64) -> Result<Vec<(String, String)>> {
+        let templates = self
+            .conn
+            .call(move |conn| {
+                let mut stmt =
+                    conn.prepare("SELECT name, source FROM templates WHERE guild_id = ?1")?;
+                let rows = stmt.query_map(params![guild_id.to_string()], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?;
+                rows.collect::<Result<Vec<_>, _>>()
+            })
+            .await?;
+
+        Ok(templates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_templates() {
+        let db = Database::new(None).await.expect("failed to create db");
+        assert!(db.templates(1).await.unwrap().is_empty());
+
+        db.set_template(1, "base", Some("one".to_owned()))
+            .await
+            .unwrap();
+        db.set_template(1, "base", Some("two".to_owned()))
+            .await
+            .unwrap();
+        db.set_template(2, "base", Some("other".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(
+            db.templates(1).await.unwrap(),
+            vec![("base".to_owned(), "two".to_owned())]
+        );
+
+        db.set_template(1, "base", None).await.unwrap();
+        assert!(db.templates(1).await.unwrap().is_empty());
+        assert_eq!(db.templates(2).await.unwrap().len(), 1);
+    }
+}
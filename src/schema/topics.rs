@@ -0,0 +1,155 @@
+use super::{Conversation, Database};
+use chrono::{DateTime, Local};
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+
+impl Database {
+    /// Remember a channel's topic. Returns whether it differs from the one stored before;
+    /// the first topic seen for a conversation isn't a change, since the channel gets
+    /// updated for renames and permission changes too.
+    pub async fn update_topic(
+        &self,
+        conversation: Conversation,
+        topic: Option<String>,
+        now: DateTime<Local>,
+    ) -> Result<bool> {
+        let now = now.to_rfc3339();
+
+        let changed = self
+            .conn
+            .call(move |conn| {
+                let old: Option<(Option<String>, bool)> = conn
+                    .query_row(
+                        "SELECT topic, topic_seen FROM topics WHERE conversation = ?1",
+                        params![conversation.0],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )
+                    .optional()?;
+                let changed = match old {
+                    Some((old, true)) => old != topic,
+                    _ => false,
+                };
+                let changed_at = changed.then_some(now);
+                conn.execute(
+                    "INSERT INTO topics (conversation, topic, changed_at, topic_seen)
+                    VALUES (?1, ?2, ?3, 1)
+                    ON CONFLICT (conversation) DO UPDATE SET
+                        topic = ?2,
+                        changed_at = coalesce(?3, changed_at),
+                        topic_seen = 1",
+                    params![conversation.0, topic, changed_at],
+                )?;
+                Ok(changed)
+            })
+            .await?;
+
+        Ok(changed)
+    }
+
+    /// When the topic last changed, if the bot saw it happen.
+    pub async fn topic_changed_at(
+        &self,
+        conversation: Conversation,
+    ) -> Result<Option<DateTime<Local>>> {
+        let changed_at: Option<String> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT changed_at FROM topics WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map(Option::flatten)
+            })
+            .await?;
+
+        let changed_at = changed_at
+            .map(|c| DateTime::parse_from_rfc3339(&c))
+            .transpose()?
+            .map(|c| c.with_timezone(&Local));
+        Ok(changed_at)
+    }
+
+    /// Set (or with None, remove) the template for commenting on a new topic.
+    pub async fn set_topic_template<S>(
+        &self,
+        conversation: Conversation,
+        template: Option<S>,
+    ) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        let template = template.map(|t| t.as_ref().to_owned());
+
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO topics (conversation, template) VALUES (?1, ?2)
+                    ON CONFLICT (conversation) DO UPDATE SET template = ?2",
+                    params![conversation.0, template],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_topic_template(&self, conversation: Conversation) -> Result<Option<String>> {
+        let template = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT template FROM topics WHERE conversation = ?1",
+                    params![conversation.0],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map(Option::flatten)
+            })
+            .await?;
+
+        Ok(template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_topics() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let now = Local::now();
+        assert_eq!(db.topic_changed_at(conversation).await.unwrap(), None);
+
+        db.set_topic_template(conversation, Some("Comment on {{ topic }}"))
+            .await
+            .unwrap();
+        // the first one seen, not a change
+        assert!(!db.update_topic(conversation, None, now).await.unwrap());
+        assert_eq!(db.topic_changed_at(conversation).await.unwrap(), None);
+        assert!(db
+            .update_topic(conversation, Some("hay".to_owned()), now)
+            .await
+            .unwrap());
+        assert!(!db
+            .update_topic(conversation, Some("hay".to_owned()), now)
+            .await
+            .unwrap());
+        assert!(db.update_topic(conversation, None, now).await.unwrap());
+        assert_eq!(
+            db.topic_changed_at(conversation)
+                .await
+                .unwrap()
+                .map(|c| c.timestamp()),
+            Some(now.timestamp())
+        );
+        assert_eq!(
+            db.get_topic_template(conversation).await.unwrap(),
+            Some("Comment on {{ topic }}".to_owned())
+        );
+    }
+}
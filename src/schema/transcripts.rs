@@ -0,0 +1,92 @@
+use super::{decode_message, Conversation, Database, Message};
+use eyre::Result;
+use rusqlite::{params, OptionalExtension};
+
+/// A message from the history along with when it was added. Messages from before
+/// timestamps were recorded have none.
+#[derive(Debug, Clone)]
+pub struct TranscriptEntry {
+    /// The message's history id, for `horse-npc redact`.
+    pub id: i64,
+    pub created_at: Option<String>,
+    pub message: Message,
+}
+
+impl Database {
+    /// Find a conversation by name without creating it.
+    pub async fn lookup_conversation<S>(&self, name: S) -> Result<Option<Conversation>>
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref().to_owned();
+
+        let conversation = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT id FROM conversation WHERE name = ?1",
+                    params![name],
+                    |row| Ok(Conversation(row.get(0)?)),
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(conversation)
+    }
+
+    pub async fn transcript(&self, conversation: Conversation) -> Result<Vec<TranscriptEntry>> {
+        let rows: Vec<(i64, Option<String>, String)> = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, created_at, message FROM history
+                    WHERE conversation = ?1 ORDER BY id",
+                )?;
+                let rows = stmt.query_map(params![conversation.0], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?;
+                rows.collect::<Result<Vec<_>, rusqlite::Error>>()
+            })
+            .await?;
+
+        rows.into_iter()
+            .map(|(id, created_at, message)| {
+                Ok(TranscriptEntry {
+                    id,
+                    created_at,
+                    message: decode_message(&message)?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_transcript() {
+        let db = Database::new(None).await.expect("failed to create db");
+        assert_eq!(db.lookup_conversation("#general").await.unwrap(), None);
+
+        let conversation = db.find_conversation("#general").await.unwrap();
+        assert_eq!(
+            db.lookup_conversation("#general").await.unwrap(),
+            Some(conversation)
+        );
+        db.add_user_message(conversation, "hi horse").await.unwrap();
+        db.add_assistant_message(conversation, "neigh")
+            .await
+            .unwrap();
+
+        let transcript = db.transcript(conversation).await.unwrap();
+        let contents = transcript
+            .iter()
+            .map(|e| e.message.content())
+            .collect::<Vec<_>>();
+        assert_eq!(contents, vec!["hi horse", "neigh"]);
+        assert!(transcript.iter().all(|e| e.created_at.is_some()));
+    }
+}
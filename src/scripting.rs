@@ -0,0 +1,85 @@
+use crate::schema::{Conversation, Database};
+use eyre::Result;
+use rhai::{Dynamic, Engine, Scope, AST};
+
+/// Limit on the number of operations a hook may run, so a bad script can't hang a reply.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// A conversation's script, which may define any of the hook functions
+/// `pre_moderation(content)`, `pre_prompt(prompt)` and `post_reply(reply)`.
+///
+/// Each hook gets the text at that point in the pipeline and returns the replacement text.
+/// Returning anything other than a string (e.g. `()`) leaves the text unchanged.
+pub struct Hooks {
+    engine: Engine,
+    ast: AST,
+}
+
+impl Hooks {
+    pub fn compile<S>(source: S) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine.compile(source.as_ref())?;
+
+        Ok(Self { engine, ast })
+    }
+
+    pub async fn load(db: &Database, conversation: Conversation) -> Result<Option<Self>> {
+        db.get_script(conversation)
+            .await?
+            .map(Self::compile)
+            .transpose()
+    }
+
+    pub fn pre_moderation(&self, content: String) -> Result<String> {
+        self.run("pre_moderation", content)
+    }
+
+    pub fn pre_prompt(&self, prompt: String) -> Result<String> {
+        self.run("pre_prompt", prompt)
+    }
+
+    pub fn post_reply(&self, reply: String) -> Result<String> {
+        self.run("post_reply", reply)
+    }
+
+    fn run(&self, hook: &str, text: String) -> Result<String> {
+        if !self.ast.iter_functions().any(|f| f.name == hook) {
+            return Ok(text);
+        }
+        let mut scope = Scope::new();
+        let result: Dynamic = self
+            .engine
+            .call_fn(&mut scope, &self.ast, hook, (text.clone(),))?;
+
+        Ok(result.into_string().unwrap_or(text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hooks() {
+        let hooks = Hooks::compile(
+            r#"
+            fn post_reply(reply) { reply + " Neigh." }
+            fn pre_prompt(prompt) { () }
+            "#,
+        )
+        .expect("failed to compile");
+        assert_eq!(
+            hooks.post_reply("Hay there.".to_owned()).unwrap(),
+            "Hay there. Neigh."
+        );
+        assert_eq!(hooks.pre_prompt("prompt".to_owned()).unwrap(), "prompt");
+        assert_eq!(
+            hooks.pre_moderation("content".to_owned()).unwrap(),
+            "content"
+        );
+    }
+}
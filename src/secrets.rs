@@ -0,0 +1,136 @@
+//! Encrypts sensitive values, like servers' own OpenAI keys, before they are stored.
+//!
+//! The master key comes from SECRETS_KEY (64 hex digits, like the output of
+//! `openssl rand -hex 32`). To rotate it, move the old key into SECRETS_OLD_KEYS
+//! (comma separated), set a new SECRETS_KEY and run `horse-npc rotate-secrets`.
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use eyre::{eyre, Result};
+
+/// Prefixed to every sealed value, in case the format ever changes.
+const VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+
+pub struct Secrets {
+    current: ChaCha20Poly1305,
+    old: Vec<ChaCha20Poly1305>,
+}
+
+impl Secrets {
+    /// The master keys, or None if SECRETS_KEY isn't set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let current = match std::env::var("SECRETS_KEY") {
+            Ok(key) => key,
+            Err(std::env::VarError::NotPresent) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let old = std::env::var("SECRETS_OLD_KEYS").ok();
+        Self::from_vars(Some(&current), old.as_deref())
+    }
+
+    /// The master keys from the values of SECRETS_KEY and SECRETS_OLD_KEYS, or None
+    /// without the first.
+    pub fn from_vars(current: Option<&str>, old: Option<&str>) -> Result<Option<Self>> {
+        let Some(current) = current else {
+            return Ok(None);
+        };
+        let old = old.unwrap_or_default().split(',');
+        Ok(Some(Self::new(
+            current,
+            old.filter(|k| !k.trim().is_empty()),
+        )?))
+    }
+
+    fn new<'a>(current: &str, old: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        Ok(Self {
+            current: cipher(current)?,
+            old: old.into_iter().map(cipher).collect::<Result<_>>()?,
+        })
+    }
+
+    /// Encrypt a value with the current key.
+    pub fn seal(&self, value: &str) -> Result<Vec<u8>> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = self
+            .current
+            .encrypt(&nonce, value.as_bytes())
+            .map_err(|_| eyre!("failed to seal a secret"))?;
+        Ok([&[VERSION], nonce.as_slice(), &sealed].concat())
+    }
+
+    /// Decrypt a value sealed with the current key or any of the old ones.
+    pub fn open(&self, sealed: &[u8]) -> Result<String> {
+        self.open_with(sealed).map(|(value, _)| value)
+    }
+
+    /// Whether a value was sealed with an old key and should be sealed again.
+    pub fn is_stale(&self, sealed: &[u8]) -> Result<bool> {
+        self.open_with(sealed).map(|(_, stale)| stale)
+    }
+
+    fn open_with(&self, sealed: &[u8]) -> Result<(String, bool)> {
+        let Some((&VERSION, rest)) = sealed.split_first() else {
+            return Err(eyre!("unknown secret format"));
+        };
+        if rest.len() < NONCE_LEN {
+            return Err(eyre!("sealed secret is too short"));
+        }
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let nonce = Nonce::from_slice(nonce);
+        let value = std::iter::once(&self.current)
+            .chain(&self.old)
+            .enumerate()
+            .find_map(|(i, cipher)| Some((cipher.decrypt(nonce, ciphertext).ok()?, i > 0)));
+        let Some((value, stale)) = value else {
+            return Err(eyre!(
+                "failed to open a secret, is its key missing from SECRETS_OLD_KEYS?"
+            ));
+        };
+        Ok((String::from_utf8(value)?, stale))
+    }
+}
+
+fn cipher(key: &str) -> Result<ChaCha20Poly1305> {
+    let key = key.trim();
+    if key.len() != 64 || !key.is_ascii() {
+        return Err(eyre!("secrets keys must be 64 hex digits"));
+    }
+    let bytes = (0..key.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&key[i..i + 2], 16))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal() {
+        assert!(Secrets::new("not hex", []).is_err());
+        let secrets = Secrets::new(&"ab".repeat(32), []).unwrap();
+        let sealed = secrets.seal("sk-secret").unwrap();
+        assert!(!String::from_utf8_lossy(&sealed).contains("sk-secret"));
+        assert_eq!(secrets.open(&sealed).unwrap(), "sk-secret");
+        assert!(!secrets.is_stale(&sealed).unwrap());
+
+        let other = Secrets::new(&"cd".repeat(32), []).unwrap();
+        assert!(other.open(&sealed).is_err());
+    }
+
+    #[test]
+    fn test_rotation() {
+        let old = "ab".repeat(32);
+        let sealed = Secrets::new(&old, []).unwrap().seal("sk-secret").unwrap();
+
+        let rotated = Secrets::new(&"cd".repeat(32), [old.as_str()]).unwrap();
+        assert_eq!(rotated.open(&sealed).unwrap(), "sk-secret");
+        assert!(rotated.is_stale(&sealed).unwrap());
+        let resealed = rotated.seal(&rotated.open(&sealed).unwrap()).unwrap();
+        assert!(!rotated.is_stale(&resealed).unwrap());
+    }
+}
@@ -0,0 +1,123 @@
+//! Shadow mode: pipeline stages on trial run after each reply in conversations with the
+//! shadow setting on, and what they would have done is logged for `horse-npc shadow`
+//! instead of changing anything. New stages can prove themselves on real traffic first.
+
+use crate::{
+    breaker::CircuitBreaker,
+    chatbot,
+    keys::KeyPool,
+    providers,
+    schema::{
+        Conversation, Database, Message, SettingsResolver, SHADOW_MODEL, SHADOW_STAGES, TEMPERATURE,
+    },
+    text,
+};
+use async_openai::types::CreateChatCompletionRequestArgs;
+use eyre::{eyre, ContextCompat, Result};
+use once_cell::sync::Lazy;
+use std::{sync::Arc, time::Instant};
+use tokio::sync::Semaphore;
+
+/// How many shadow runs may be going at once. Replies past that go without one.
+const MAX_RUNNING: usize = 4;
+
+static RUNNING: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(MAX_RUNNING));
+
+/// Run the stages shadow_stages picks in the background, given the messages the reply
+/// was written from, so the reply isn't held up. Nothing runs while the breaker is
+/// open, and the calls made count towards it like any other.
+pub fn spawn(
+    openai: Arc<KeyPool>,
+    db: Arc<Database>,
+    breaker: Option<Arc<CircuitBreaker>>,
+    guild: Option<u64>,
+    conversation: Conversation,
+    messages: Vec<Message>,
+) {
+    let asleep = breaker
+        .as_ref()
+        .map_or(false, |b| b.is_open(Instant::now()));
+    if asleep {
+        return;
+    }
+    let Ok(permit) = RUNNING.try_acquire() else {
+        log::debug!("Skipping shadow mode, too many runs are going already");
+        return;
+    };
+    tokio::spawn(async move {
+        let _permit = permit;
+        let record = |output: &Result<String>| {
+            if let Some(breaker) = &breaker {
+                breaker.record_call(&openai, guild, output);
+            }
+        };
+        let settings = SettingsResolver::new(&db, conversation, guild);
+        let enabled = match settings.get(&SHADOW_STAGES).await {
+            Ok(enabled) => enabled,
+            Err(e) => {
+                log::error!("Failed to read shadow_stages: {}", e);
+                return;
+            }
+        };
+        let enabled: Vec<&str> = enabled.split(',').map(str::trim).collect();
+        let mut stages = vec![];
+        if enabled.contains(&"model") {
+            match settings.get(&SHADOW_MODEL).await {
+                Ok(model) if !model.is_empty() => {
+                    let output =
+                        shadow_model(&openai, guild, &db, conversation, &model, messages).await;
+                    record(&output);
+                    stages.push((format!("model {model}"), output));
+                }
+                Ok(_) => {}
+                Err(e) => stages.push(("model".to_owned(), Err(e))),
+            }
+        }
+        if enabled.contains(&"summary") {
+            let summary = chatbot::write_summary(&openai, guild, &db, conversation).await;
+            record(&summary);
+            stages.push(("summary".to_owned(), summary));
+        }
+
+        for (stage, output) in stages {
+            let output = output.unwrap_or_else(|e| format!("error: {e}"));
+            log::debug!("Shadow {stage}: {output}");
+            if let Err(e) = db.add_shadow_entry(conversation, stage, output).await {
+                log::error!("Failed to write the shadow log: {}", e);
+            }
+        }
+    });
+}
+
+/// What another model would have replied, without any tools.
+async fn shadow_model(
+    openai: &KeyPool,
+    guild: Option<u64>,
+    db: &Database,
+    conversation: Conversation,
+    model: &str,
+    messages: Vec<Message>,
+) -> Result<String> {
+    let temperature = SettingsResolver::new(db, conversation, guild)
+        .get(&TEMPERATURE)
+        .await?;
+    let ceiling = db.max_tokens(conversation).await?;
+    let window = text::context_window(model);
+    let used = text::count_message_tokens(&messages);
+    let max_tokens = text::completion_budget(window, ceiling, used)
+        .ok_or_else(|| eyre!("the conversation doesn't fit in {model}'s context window"))?;
+    let request = CreateChatCompletionRequestArgs::default()
+        .max_tokens(max_tokens)
+        .model(model)
+        .temperature(temperature)
+        .messages(providers::openai::request_messages(&messages))
+        .build()?;
+
+    let response = openai.chat(guild, request).await?;
+    let choice = response
+        .choices
+        .into_iter()
+        .next()
+        .wrap_err("No response")?;
+    Ok(providers::openai::response_message(choice.message)?.content())
+}
@@ -0,0 +1,134 @@
+//! `horse-npc smoke`: hold a short scripted conversation with the live providers,
+//! checking that a deployment works after changing its configuration or models
+//! without bothering a real channel.
+
+use crate::{
+    chatbot::{self, ChatBot},
+    helpers::OpenAIHelpers,
+    load_tools, openai_keys,
+    schema::{Database, ModerationPolicy},
+    Args, TestBot,
+};
+use eyre::{eyre, Result};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Asks for something only the check_balance tool can answer.
+const TOOL_MESSAGE: &str = "How many coins do I have? Check my balance.";
+
+/// A threat the moderation endpoint flags under any policy but off.
+const MODERATION_MESSAGE: &str = "I'm going to find you and kill you and your whole family.";
+
+struct Stage {
+    name: &'static str,
+    took: Duration,
+    outcome: Result<String>,
+}
+
+pub async fn smoke(args: Args) -> Result<()> {
+    let timeout = Duration::from_secs(args.openai_timeout);
+    let bot = TestBot {
+        openai: Arc::new(openai_keys(args.key_assignment, timeout)?),
+        database: Arc::new(Database::new(None).await?),
+        tools: Arc::new(load_tools(args.plugins.as_deref())?),
+    };
+
+    let stages = vec![
+        run("greeting", greeting(&bot)).await,
+        run("tool call", tool_call(&bot)).await,
+        run("moderation", moderation(&bot)).await,
+    ];
+    let failed = stages.iter().filter(|s| s.outcome.is_err()).count();
+    for stage in &stages {
+        println!("{}", report(stage));
+    }
+    if failed > 0 {
+        return Err(eyre!("{failed} of {} stages failed", stages.len()));
+    }
+
+    Ok(())
+}
+
+async fn run(
+    name: &'static str,
+    stage: impl std::future::Future<Output = Result<String>>,
+) -> Stage {
+    let started = Instant::now();
+    let outcome = stage.await;
+    Stage {
+        name,
+        took: started.elapsed(),
+        outcome,
+    }
+}
+
+async fn greeting(bot: &TestBot) -> Result<String> {
+    let reply = chatbot::reply(bot.clone(), &(), &"Hello, horse!".to_owned()).await?;
+    match reply {
+        Some(reply) if !reply.trim().is_empty() => Ok(reply),
+        _ => Err(eyre!("the reply was empty")),
+    }
+}
+
+async fn tool_call(bot: &TestBot) -> Result<String> {
+    let reply = chatbot::reply(bot.clone(), &(), &TOOL_MESSAGE.to_owned())
+        .await?
+        .unwrap_or_default();
+    let conversation = bot.conversation(&(), &String::new()).await?;
+    let tools = bot
+        .database
+        .last_reply_metadata(conversation)
+        .await?
+        .map(|m| m.tools)
+        .unwrap_or_default();
+    if tools.is_empty() {
+        return Err(eyre!("no tool was called, the reply was: {reply}"));
+    }
+    Ok(format!("called {}", tools.join(", ")))
+}
+
+async fn moderation(bot: &TestBot) -> Result<String> {
+    let flagged = bot
+        .openai
+        .client(None)?
+        .must_moderate(MODERATION_MESSAGE.to_owned(), ModerationPolicy::Default)
+        .await?;
+    if !flagged {
+        return Err(eyre!("a threat wasn't flagged"));
+    }
+    Ok("a threat was flagged".to_owned())
+}
+
+fn report(stage: &Stage) -> String {
+    let seconds = stage.took.as_secs_f32();
+    match &stage.outcome {
+        Ok(detail) => format!("pass {} ({seconds:.1}s): {detail}", stage.name),
+        Err(e) => format!("FAIL {} ({seconds:.1}s): {e}", stage.name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let passed = Stage {
+            name: "greeting",
+            took: Duration::from_millis(1200),
+            outcome: Ok("Neigh!".to_owned()),
+        };
+        assert_eq!(report(&passed), "pass greeting (1.2s): Neigh!");
+        let failed = Stage {
+            name: "moderation",
+            took: Duration::from_millis(300),
+            outcome: Err(eyre!("a threat wasn't flagged")),
+        };
+        assert_eq!(
+            report(&failed),
+            "FAIL moderation (0.3s): a threat wasn't flagged"
+        );
+    }
+}
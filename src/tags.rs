@@ -0,0 +1,56 @@
+//! What each user message is like, worked out once as it arrives and stored with it:
+//! its sentiment, which moves karma and mood, and what it needs, which the router
+//! goes by and `horse-npc stats` counts. Both come from cheap word matching.
+
+use crate::{
+    karma,
+    router::{self, Route},
+    schema::Database,
+};
+use eyre::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tags {
+    /// As scored for karma: up for kindness, down for rudeness.
+    pub sentiment: i64,
+    /// None when the words alone don't say, for the router to ask a model.
+    pub intent: Option<Route>,
+}
+
+impl Tags {
+    pub fn of(content: &str) -> Self {
+        Tags {
+            sentiment: karma::sentiment(content),
+            intent: router::classify(content),
+        }
+    }
+
+    /// Store the tags with the message they're about.
+    pub async fn save(&self, db: &Database, history_id: i64) -> Result<()> {
+        db.tag_message(history_id, self.sentiment, self.intent.map(Route::as_str))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags() {
+        assert_eq!(
+            Tags::of("thanks!"),
+            Tags {
+                sentiment: 1,
+                intent: Some(Route::Thanks)
+            }
+        );
+        assert_eq!(
+            Tags::of("why are you so useless at maths?"),
+            Tags {
+                sentiment: -2,
+                intent: None
+            }
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Server templates for `{% include %}` and `{% extends %}`, so prompts can share a base
+//! persona and partials across conversations. A server's templates are loaded from the
+//! database together and kept for a minute, then handed to minijinja through a loader.
+
+use crate::schema::Database;
+use eyre::{eyre, Result};
+use minijinja::{value::Value, Environment, Source};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How long a server's templates are used before they are read again, which is how
+/// soon changes from `horse-npc template` show up.
+const TEMPLATE_TTL: Duration = Duration::from_secs(60);
+
+static TEMPLATE_CACHE: Lazy<Mutex<HashMap<u64, (Arc<ServerTemplates>, Instant)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static TEMPLATE_REFERENCE: Lazy<regex::Regex> = Lazy::new(|| {
+    regex::Regex::new(r#"\{%-?\s*(?:include|extends|import|from)\s+["']([^"']+)["']"#)
+        .expect("valid template reference regex")
+});
+
+/// A server's templates by name.
+#[derive(Debug, Default)]
+pub struct ServerTemplates {
+    templates: HashMap<String, String>,
+}
+
+impl ServerTemplates {
+    pub fn new(templates: HashMap<String, String>) -> Self {
+        Self { templates }
+    }
+
+    /// A server's templates, from the cache if they were read recently. Outside a
+    /// server there are none.
+    pub async fn load(db: &Database, guild: Option<u64>) -> Result<Arc<Self>> {
+        let Some(guild) = guild else {
+            return Ok(Arc::default());
+        };
+        let cached = TEMPLATE_CACHE
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&guild)
+            .filter(|(_, loaded)| loaded.elapsed() < TEMPLATE_TTL)
+            .map(|(templates, _)| templates.clone());
+        if let Some(templates) = cached {
+            return Ok(templates);
+        }
+
+        let templates = Arc::new(Self::new(db.templates(guild).await?.into_iter().collect()));
+        TEMPLATE_CACHE
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(guild, (templates.clone(), Instant::now()));
+        Ok(templates)
+    }
+
+    /// Forget every server's cached templates, so they are read again on next use.
+    pub fn clear_cache() {
+        TEMPLATE_CACHE
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.templates.get(name).map(String::as_str)
+    }
+
+    /// Render `source`, resolving the templates it includes or extends by name.
+    pub fn render(&self, source: &str, vars: Value) -> Result<String> {
+        self.check_cycles(source)?;
+        let templates = self.templates.clone();
+        let mut env = Environment::new();
+        env.set_source(Source::with_loader(move |name| {
+            Ok(templates.get(name).cloned())
+        }));
+        Ok(env.render_str(source, vars)?)
+    }
+
+    /// Refuse templates that include themselves, directly or not, naming the loop.
+    fn check_cycles(&self, source: &str) -> Result<()> {
+        let mut path = vec![];
+        self.visit(source, &mut path)
+    }
+
+    fn visit<'a>(&'a self, source: &'a str, path: &mut Vec<&'a str>) -> Result<()> {
+        for reference in TEMPLATE_REFERENCE.captures_iter(source) {
+            let name = reference.get(1).map_or("", |m| m.as_str());
+            if path.contains(&name) {
+                path.push(name);
+                return Err(eyre!("templates include each other: {}", path.join(" -> ")));
+            }
+            // a missing template is reported by minijinja when rendering
+            let Some(included) = self.get(name) else {
+                continue;
+            };
+            path.push(name);
+            self.visit(included, path)?;
+            path.pop();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minijinja::context;
+
+    fn templates(pairs: &[(&str, &str)]) -> ServerTemplates {
+        ServerTemplates::new(
+            pairs
+                .iter()
+                .map(|(name, source)| (name.to_string(), source.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_render() {
+        let templates = templates(&[
+            ("greeting", "Hello {{ name }}."),
+            ("base", r#"{% include "greeting" %} Bye."#),
+        ]);
+        assert_eq!(
+            templates
+                .render(r#"{% include "base" %}"#, context! { name => "dylan" })
+                .unwrap(),
+            "Hello dylan. Bye."
+        );
+        assert!(templates
+            .render(r#"{% include "missing" %}"#, context! {})
+            .is_err());
+    }
+
+    #[test]
+    fn test_cycles() {
+        let templates = templates(&[
+            ("a", r#"{% include "b" %}"#),
+            ("b", r#"{%- include 'a' %}"#),
+            ("c", r#"{% include "b" %}{% include "b" %}"#),
+        ]);
+        let error = templates.check_cycles(r#"{% include "a" %}"#).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "templates include each other: a -> b -> a"
+        );
+        assert!(templates.check_cycles(r#"{% include "d" %}"#).is_ok());
+    }
+}
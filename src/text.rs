@@ -0,0 +1,388 @@
+use crate::schema::Message;
+use async_openai::types::ChatCompletionFunctions;
+use eyre::Result;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tiktoken_rs::CoreBPE;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// What each chat message costs on top of its content, for the role and separators.
+const TOKENS_PER_MESSAGE: usize = 4;
+
+/// Replies shorter than this aren't worth asking for.
+const MIN_COMPLETION_TOKENS: usize = 16;
+
+/// The tokenizer used by the gpt-3.5 and gpt-4 models.
+static BPE: Lazy<CoreBPE> = Lazy::new(|| tiktoken_rs::cl100k_base().expect("cl100k_base"));
+
+/// What has to be passed along exactly as written: code blocks (including one left
+/// open at the end), inline code, links, and anything escaped with a backslash.
+static VERBATIM: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?s)```.*?(?:```|\z)|`[^`\n]+`|<?https?://[^\s>]+>?|\\\S")
+        .expect("valid verbatim regex")
+});
+
+/// Closes a code block that a chunk of a long message had to cut in two.
+const CLOSE_FENCE: &str = "\n```";
+
+/// Where a paced reply may be split: between paragraphs, or failing that, sentences.
+static PARAGRAPH_BREAK: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\n\s*\n").expect("valid paragraph regex"));
+static SENTENCE_BREAK: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"[.!?…]["')*_]*(\s)"#).expect("valid sentence regex"));
+
+/// Replies shorter than this are sent whole even when paced.
+const MIN_PACED_CHARS: usize = 240;
+/// About how long each message of a paced reply is, and how many there are at most.
+const PACED_CHARS: usize = 300;
+const MAX_PACED_MESSAGES: usize = 4;
+
+/// The longest prefix of `s` that is at most `max_chars` characters long,
+/// without cutting a grapheme (like an emoji with a skin tone) in half.
+pub fn truncate(s: &str, max_chars: usize) -> &str {
+    let mut chars = 0;
+    let mut end = 0;
+    for (i, grapheme) in s.grapheme_indices(true) {
+        chars += grapheme.chars().count();
+        if chars > max_chars {
+            break;
+        }
+        end = i + grapheme.len();
+    }
+    &s[..end]
+}
+
+/// Split `s` into chunks of at most `max_chars` characters, preferring to break
+/// at newlines, then at whitespace, and never inside a grapheme. A code block that
+/// has to be split is closed at the end of one chunk and reopened, language and all,
+/// at the start of the next.
+pub fn split_message(s: &str, max_chars: usize) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut rest = s.trim();
+    // the opening line (like ```rust) of a code block the last chunk had to close
+    let mut reopen: Option<&str> = None;
+    while !rest.is_empty() {
+        let room = max_chars.saturating_sub(reopen.map_or(0, |f| f.chars().count() + 1));
+        let mut cut = split_point(rest, room);
+        let mut open = open_fence(reopen, &rest[..cut]);
+        if open.is_some() && cut < rest.len() {
+            cut = split_point(rest, room.saturating_sub(CLOSE_FENCE.len()));
+            open = open_fence(reopen, &rest[..cut]);
+            // rather than end on a code block's opening line, leave it for the next chunk
+            let head = rest[..cut].trim_end();
+            if let Some(fence) = open.filter(|f| head.ends_with(f) && head.len() > f.len()) {
+                cut = head.len() - fence.len();
+                open = open_fence(reopen, &rest[..cut]);
+            }
+        }
+
+        let mut chunk = reopen.map(|f| format!("{f}\n")).unwrap_or_default();
+        chunk.push_str(rest[..cut].trim_end());
+        rest = rest[cut..].trim_start();
+        reopen = open.filter(|_| !rest.is_empty());
+        if reopen.is_some() {
+            chunk.push_str(CLOSE_FENCE);
+        }
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// Where to end the next chunk of `s` so it's at most `max_chars` characters.
+fn split_point(s: &str, max_chars: usize) -> usize {
+    let mut head = truncate(s, max_chars);
+    if head.len() == s.len() {
+        return s.len();
+    }
+    if head.is_empty() {
+        // a single grapheme longer than the limit, send it anyway
+        head = s.graphemes(true).next().unwrap_or(s);
+    }
+    head.rfind('\n')
+        .or_else(|| head.rfind(char::is_whitespace))
+        .filter(|&i| i > 0)
+        .unwrap_or(head.len())
+}
+
+/// The opening line of the code block still open at the end of `s`, given the one
+/// open at its start.
+fn open_fence<'a>(mut open: Option<&'a str>, s: &'a str) -> Option<&'a str> {
+    for line in s.lines().map(str::trim) {
+        if line.starts_with("```") {
+            open = match open {
+                Some(_) => None,
+                None => Some(line),
+            };
+        }
+    }
+    open
+}
+
+/// Split a reply into two to four messages of about the same length, the way a person
+/// might send it, breaking between paragraphs or else sentences, and never inside code.
+/// Short replies, and ones with nowhere to break, come back whole.
+pub fn pace(s: &str) -> Vec<String> {
+    let s = s.trim();
+    let total = s.chars().count();
+    if total < MIN_PACED_CHARS {
+        return vec![s.to_owned()];
+    }
+    let verbatim = VERBATIM.find_iter(s).map(|m| m.range()).collect::<Vec<_>>();
+    let outside = |i: &usize| !verbatim.iter().any(|r| r.contains(i));
+    let mut breaks = PARAGRAPH_BREAK
+        .find_iter(s)
+        .map(|m| m.start())
+        .filter(outside)
+        .collect::<Vec<_>>();
+    if breaks.is_empty() {
+        breaks = SENTENCE_BREAK
+            .captures_iter(s)
+            .filter_map(|c| c.get(1))
+            .map(|m| m.start())
+            .filter(outside)
+            .collect();
+    }
+
+    let parts = (total / PACED_CHARS).clamp(2, MAX_PACED_MESSAGES);
+    let target = total / parts;
+    let mut messages = vec![];
+    let mut start = 0;
+    for cut in breaks {
+        if messages.len() + 1 == parts {
+            break;
+        }
+        if s[start..cut].chars().count() >= target {
+            messages.push(s[start..cut].trim().to_owned());
+            start = cut;
+        }
+    }
+    messages.push(s[start..].trim().to_owned());
+    messages
+}
+
+/// A piece of a message: prose, or something like code that must not be rewritten.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Segment<'a> {
+    Prose(&'a str),
+    Verbatim(&'a str),
+}
+
+/// Split `s` into prose and the code, links and escapes in between.
+pub fn segments(s: &str) -> Vec<Segment<'_>> {
+    let mut segments = vec![];
+    let mut last = 0;
+    for verbatim in VERBATIM.find_iter(s) {
+        if verbatim.start() > last {
+            segments.push(Segment::Prose(&s[last..verbatim.start()]));
+        }
+        segments.push(Segment::Verbatim(verbatim.as_str()));
+        last = verbatim.end();
+    }
+    if last < s.len() {
+        segments.push(Segment::Prose(&s[last..]));
+    }
+    segments
+}
+
+/// Apply `f` to the prose in `s`, leaving code, links and escapes exactly as they are.
+pub fn map_prose<F>(s: &str, mut f: F) -> String
+where
+    F: FnMut(&str) -> String,
+{
+    segments(s)
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Prose(text) => f(text),
+            Segment::Verbatim(text) => text.to_owned(),
+        })
+        .collect()
+}
+
+pub fn count_tokens(s: &str) -> usize {
+    BPE.encode_with_special_tokens(s).len()
+}
+
+pub fn count_message_tokens(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .map(|m| count_tokens(&m.content()) + TOKENS_PER_MESSAGE)
+        .sum()
+}
+
+/// Function definitions are sent as JSON schema, which is roughly what they cost.
+pub fn count_function_tokens(functions: &[ChatCompletionFunctions]) -> Result<usize> {
+    Ok(count_tokens(&serde_json::to_string(functions)?))
+}
+
+/// How many tokens (prompt and reply together) a model can handle.
+pub fn context_window(model: &str) -> usize {
+    tiktoken_rs::model::get_context_size(model)
+}
+
+/// How many tokens to ask for: whatever is left of the context window after
+/// `used` prompt tokens, but no more than the conversation's `ceiling`.
+/// None if there is no room left for a useful reply.
+pub fn completion_budget(window: usize, ceiling: u16, used: usize) -> Option<u16> {
+    let left = window.saturating_sub(used);
+    if left < MIN_COMPLETION_TOKENS {
+        return None;
+    }
+    Some(left.min(ceiling as usize) as u16)
+}
+
+/// The longest prefix of `s` that fits in `max_tokens`, cut on a grapheme boundary.
+pub fn trim_to_tokens(s: &str, max_tokens: usize) -> &str {
+    if count_tokens(s) <= max_tokens {
+        return s;
+    }
+    let boundaries = s
+        .grapheme_indices(true)
+        .map(|(i, g)| i + g.len())
+        .collect::<Vec<_>>();
+    // binary search for the number of graphemes that fit
+    let fits = boundaries.partition_point(|&end| count_tokens(&s[..end]) <= max_tokens);
+    match fits {
+        0 => "",
+        n => &s[..boundaries[n - 1]],
+    }
+}
+
+/// The candidates matching `query`, best first: those it starts, then those containing
+/// it, then those with its letters in order (so `hp` finds `hit points`). Case is ignored.
+pub fn fuzzy_matches<'a>(query: &str, candidates: &'a [String]) -> Vec<&'a str> {
+    let query = query.to_lowercase();
+    let mut ranked = candidates
+        .iter()
+        .filter_map(|candidate| {
+            let lower = candidate.to_lowercase();
+            let rank = if lower.starts_with(&query) {
+                0
+            } else if lower.contains(&query) {
+                1
+            } else {
+                let mut letters = lower.chars();
+                if !query.chars().all(|q| letters.any(|c| c == q)) {
+                    return None;
+                }
+                2
+            };
+            Some((rank, candidate.as_str()))
+        })
+        .collect::<Vec<_>>();
+    ranked.sort();
+    ranked.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("hello", 10), "hello");
+        assert_eq!(truncate("hello", 3), "hel");
+        // the thumbs up with a skin tone is two chars and one grapheme
+        assert_eq!(truncate("hi 👍🏽", 4), "hi ");
+        assert_eq!(truncate("hi 👍🏽", 5), "hi 👍🏽");
+    }
+
+    #[test]
+    fn test_fuzzy_matches() {
+        let candidates = ["hit points", "class", "HP", "mischief"].map(str::to_owned);
+        assert_eq!(fuzzy_matches("hp", &candidates), vec!["HP", "hit points"]);
+        assert_eq!(
+            fuzzy_matches("s", &candidates),
+            vec!["class", "hit points", "mischief"]
+        );
+        assert_eq!(fuzzy_matches("", &candidates).len(), 4);
+        assert!(fuzzy_matches("xyz", &candidates).is_empty());
+    }
+
+    #[test]
+    fn test_split_message() {
+        assert_eq!(split_message("neigh", 2000), vec!["neigh"]);
+        assert_eq!(
+            split_message("one two\nthree four", 12),
+            vec!["one two", "three four"]
+        );
+        assert_eq!(split_message("hay hay hay", 8), vec!["hay hay", "hay"]);
+        assert_eq!(split_message("abcdef", 4), vec!["abcd", "ef"]);
+        assert!(split_message(&"🐴".repeat(3000), 2000)
+            .iter()
+            .all(|c| c.chars().count() <= 2000));
+
+        let code = "Here:\n```rust\nlet a = 1;\nlet b = 2;\n```\nDone";
+        let chunks = split_message(code, 24);
+        assert_eq!(
+            chunks,
+            vec![
+                "Here:",
+                "```rust\nlet a = 1;\n```",
+                "```rust\nlet b = 2;\n```",
+                "Done"
+            ]
+        );
+        assert!(chunks.iter().all(|c| c.chars().count() <= 24));
+    }
+
+    #[test]
+    fn test_pace() {
+        assert_eq!(pace(" Neigh. "), vec!["Neigh."]);
+
+        let paragraph = "The hay is fresh today. ".repeat(10);
+        let paragraph = paragraph.trim_end();
+        let reply = format!("{paragraph}\n\n{paragraph}\n\n{paragraph}");
+        assert_eq!(
+            pace(&reply),
+            vec![format!("{paragraph}\n\n{paragraph}"), paragraph.to_owned()]
+        );
+
+        // no paragraphs, so it breaks between sentences
+        let sentences = "Whinny! ".repeat(40);
+        assert_eq!(pace(&sentences).len(), 2);
+
+        // the blank line inside the code block is not a place to break
+        let code = format!("```\n{}\n\n{}\n```", "a".repeat(200), "b".repeat(200));
+        assert_eq!(pace(&code), vec![code.clone()]);
+    }
+
+    #[test]
+    fn test_map_prose() {
+        let upper = |s: &str| s.to_uppercase();
+        assert_eq!(
+            map_prose("a `b` c\n```sh\nd\n```\ne", upper),
+            "A `b` C\n```sh\nd\n```\nE"
+        );
+        assert_eq!(map_prose("a ```b", upper), "A ```b");
+        assert_eq!(
+            map_prose(
+                "see https://horse.example/@b or <https://x.example> \\@c",
+                upper
+            ),
+            "SEE https://horse.example/@b OR <https://x.example> \\@C"
+        );
+        assert_eq!(
+            segments("`a`b"),
+            vec![Segment::Verbatim("`a`"), Segment::Prose("b")]
+        );
+    }
+
+    #[test]
+    fn test_completion_budget() {
+        assert_eq!(completion_budget(4096, 256, 1000), Some(256));
+        assert_eq!(completion_budget(4096, 256, 3940), Some(156));
+        assert_eq!(completion_budget(4096, 256, 4090), None);
+        assert_eq!(completion_budget(4096, 256, 5000), None);
+        assert_eq!(context_window("gpt-4-32k"), 32768);
+    }
+
+    #[test]
+    fn test_trim_to_tokens() {
+        let text = "the quick brown horse jumps over the lazy donkey";
+        assert_eq!(trim_to_tokens(text, 100), text);
+        let trimmed = trim_to_tokens(text, 3);
+        assert!(count_tokens(trimmed) <= 3);
+        assert!(text.starts_with(trimmed));
+        assert!(!trimmed.is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+pub mod birthdays;
+pub mod combat;
+pub mod economy;
+pub mod games;
+pub mod quests;
+pub mod relationships;
+#[cfg(feature = "run-code")]
+pub mod run_code;
+pub mod sheets;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+use crate::{
+    chatbot::Speaker,
+    schema::{Conversation, Database, NO_FUNCTIONS},
+};
+use async_openai::types::ChatCompletionFunctions;
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use std::{collections::BTreeMap, sync::Arc};
+
+const BUILTIN_FUNCTIONS: &str = include_str!("functions.json");
+
+/// Everything a tool handler gets to know about the message that triggered it.
+pub struct ToolContext {
+    pub database: Arc<Database>,
+    pub conversation: Conversation,
+    pub speaker: Speaker,
+}
+
+#[async_trait]
+pub trait Tool: Send + Sync {
+    /// The function definition (name, description, JSON schema) sent to OpenAI.
+    fn definition(&self) -> ChatCompletionFunctions;
+
+    /// Run the tool. The returned string is given back to the model as the function result.
+    async fn call(&self, context: &ToolContext, arguments: serde_json::Value) -> Result<String>;
+}
+
+/// The set of functions offered to the model.
+///
+/// Functions declared in functions.json have no handler here; they are handled by the
+/// [`crate::chatbot::ChatBot`] implementation because they depend on the chat platform.
+/// Everything else is registered at startup, either by code or by loading plugins.
+pub struct ToolRegistry {
+    builtins: Vec<ChatCompletionFunctions>,
+    tools: BTreeMap<String, Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        let builtins =
+            serde_json::from_str(BUILTIN_FUNCTIONS).expect("Failed to parse functions.json");
+        Self {
+            builtins,
+            tools: BTreeMap::new(),
+        }
+    }
+
+    pub fn register<T>(&mut self, tool: T) -> Result<()>
+    where
+        T: Tool + 'static,
+    {
+        let name = tool.definition().name;
+        if self.builtins.iter().any(|f| f.name == name) || self.tools.contains_key(&name) {
+            return Err(eyre!("tool {name} is already registered"));
+        }
+        self.tools.insert(name, Arc::new(tool));
+        Ok(())
+    }
+
+    pub fn get<S>(&self, name: S) -> Option<Arc<dyn Tool>>
+    where
+        S: AsRef<str>,
+    {
+        self.tools.get(name.as_ref()).cloned()
+    }
+
+    /// The merged list of builtin and registered function definitions.
+    pub fn functions(&self) -> Vec<ChatCompletionFunctions> {
+        self.builtins
+            .iter()
+            .cloned()
+            .chain(self.tools.values().map(|t| t.definition()))
+            .collect()
+    }
+
+    /// The functions named in a comma separated list, all of them if it's empty, or none
+    /// for "none".
+    pub fn allowed_functions(&self, allowed: &str) -> Vec<ChatCompletionFunctions> {
+        if allowed.trim() == NO_FUNCTIONS {
+            return vec![];
+        }
+        let allowed: Vec<&str> = allowed
+            .split(',')
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+            .collect();
+        self.functions()
+            .into_iter()
+            .filter(|f| allowed.is_empty() || allowed.contains(&f.name.as_str()))
+            .collect()
+    }
+
+    #[cfg(feature = "wasm")]
+    pub fn load_plugins<P>(&mut self, dir: P) -> Result<()>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("wasm") {
+                continue;
+            }
+            let tool = wasm::WasmTool::load(&path)?;
+            log::info!(
+                "Loaded plugin {} from {}",
+                tool.definition().name,
+                path.display()
+            );
+            self.register(tool)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    #[async_trait]
+    impl Tool for Echo {
+        fn definition(&self) -> ChatCompletionFunctions {
+            ChatCompletionFunctions {
+                name: "echo".to_owned(),
+                description: Some("Echo the arguments back".to_owned()),
+                parameters: Some(serde_json::json!({"type": "object", "properties": {}})),
+            }
+        }
+
+        async fn call(
+            &self,
+            _context: &ToolContext,
+            arguments: serde_json::Value,
+        ) -> Result<String> {
+            Ok(arguments.to_string())
+        }
+    }
+
+    #[test]
+    fn test_functions_merged() {
+        let mut registry = ToolRegistry::new();
+        registry.register(Echo).expect("failed to register");
+        let names = registry
+            .functions()
+            .into_iter()
+            .map(|f| f.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["react", "echo"]);
+        assert!(registry.register(Echo).is_err());
+
+        let names = |allowed| {
+            registry
+                .allowed_functions(allowed)
+                .into_iter()
+                .map(|f| f.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(""), vec!["react", "echo"]);
+        assert_eq!(names("echo, weather"), vec!["echo"]);
+        assert!(names("none").is_empty());
+    }
+}
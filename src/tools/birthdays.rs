@@ -0,0 +1,90 @@
+use super::{Tool, ToolContext, ToolRegistry};
+use crate::schema::Birthday;
+use async_openai::types::ChatCompletionFunctions;
+use async_trait::async_trait;
+use chrono::{Local, NaiveDate};
+use eyre::Result;
+use serde_json::json;
+
+pub fn register(registry: &mut ToolRegistry) -> Result<()> {
+    registry.register(GetBirthdays)?;
+    Ok(())
+}
+
+struct GetBirthdays;
+
+#[async_trait]
+impl Tool for GetBirthdays {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "get_birthdays".to_owned(),
+            description: Some(
+                "List the birthdays people here have shared, soonest first. Use this to say \
+                 whose birthday is next or when someone's is."
+                    .to_owned(),
+            ),
+            parameters: Some(json!({ "type": "object", "properties": {} })),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, _arguments: serde_json::Value) -> Result<String> {
+        let birthdays = context
+            .database
+            .conversation_birthdays(context.conversation)
+            .await?;
+        if birthdays.is_empty() {
+            return Ok("Nobody here has shared their birthday.".to_owned());
+        }
+
+        Ok(describe_birthdays(birthdays, Local::now().date_naive()))
+    }
+}
+
+/// One line per birthday, soonest first, saying how far away it is.
+fn describe_birthdays(mut birthdays: Vec<Birthday>, today: NaiveDate) -> String {
+    birthdays.sort_by_key(|b| b.next(today));
+    birthdays
+        .iter()
+        .filter_map(|birthday| {
+            let next = birthday.next(today)?;
+            let when = match (next - today).num_days() {
+                0 => "today".to_owned(),
+                1 => "tomorrow".to_owned(),
+                days => format!("in {days} days"),
+            };
+            Some(format!(
+                "{}: {} ({when})",
+                birthday.user_name,
+                next.format("%B %-d")
+            ))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_birthdays() {
+        let birthday = |name: &str, month, day| Birthday {
+            user_id: name.to_owned(),
+            user_name: name.to_owned(),
+            month,
+            day,
+        };
+        let today = NaiveDate::from_ymd_opt(2023, 9, 1).unwrap();
+        assert_eq!(
+            describe_birthdays(
+                vec![
+                    birthday("Colt", 8, 31),
+                    birthday("Filly", 9, 1),
+                    birthday("Mare", 9, 2)
+                ],
+                today
+            ),
+            "Filly: September 1 (today)\nMare: September 2 (tomorrow)\nColt: August 31 (in 365 days)"
+        );
+    }
+}
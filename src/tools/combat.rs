@@ -0,0 +1,223 @@
+use super::{Tool, ToolContext, ToolRegistry};
+use crate::schema::{Combatant, Encounter};
+use async_openai::types::ChatCompletionFunctions;
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::json;
+
+pub fn register(registry: &mut ToolRegistry) -> Result<()> {
+    registry.register(StartEncounter)?;
+    registry.register(Damage)?;
+    registry.register(NextTurn)?;
+    registry.register(EndEncounter)?;
+    Ok(())
+}
+
+struct StartEncounter;
+
+#[derive(Deserialize)]
+struct CombatantArgs {
+    name: String,
+    initiative: Option<i32>,
+    hp: i32,
+}
+
+#[derive(Deserialize)]
+struct StartEncounterArgs {
+    combatants: Vec<CombatantArgs>,
+}
+
+#[async_trait]
+impl Tool for StartEncounter {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "start_encounter".to_owned(),
+            description: Some(
+                "Start tracking a combat encounter. Initiative is rolled (d20) for any \
+                 combatant without one."
+                    .to_owned(),
+            ),
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "combatants": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "name": {"type": "string"},
+                                "initiative": {"type": "integer"},
+                                "hp": {"type": "integer", "description": "starting hit points"}
+                            },
+                            "required": ["name", "hp"]
+                        }
+                    }
+                },
+                "required": ["combatants"]
+            })),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, arguments: serde_json::Value) -> Result<String> {
+        let args: StartEncounterArgs = serde_json::from_value(arguments)?;
+        if args.combatants.is_empty() {
+            return Err(eyre!("an encounter needs combatants"));
+        }
+        if let Some(c) = args.combatants.iter().find(|c| c.hp < 0) {
+            return Err(eyre!("{} can't start with negative hp", c.name));
+        }
+        let combatants = {
+            let mut rng = rand::thread_rng();
+            args.combatants
+                .into_iter()
+                .map(|c| Combatant {
+                    name: c.name,
+                    initiative: c.initiative.unwrap_or_else(|| rng.gen_range(1..=20)),
+                    hp: c.hp,
+                    max_hp: c.hp,
+                })
+                .collect()
+        };
+        let encounter = Encounter::new(combatants);
+        context
+            .database
+            .save_encounter(context.conversation, &encounter)
+            .await?;
+
+        Ok(status(&encounter))
+    }
+}
+
+struct Damage;
+
+#[derive(Deserialize)]
+struct DamageArgs {
+    name: String,
+    amount: i32,
+}
+
+#[async_trait]
+impl Tool for Damage {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "damage".to_owned(),
+            description: Some(
+                "Adjust a combatant's hit points. Use a negative amount to heal.".to_owned(),
+            ),
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "name": {"type": "string"},
+                    "amount": {"type": "integer"}
+                },
+                "required": ["name", "amount"]
+            })),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, arguments: serde_json::Value) -> Result<String> {
+        let args: DamageArgs = serde_json::from_value(arguments)?;
+        let mut encounter = running_encounter(context).await?;
+        let combatant = encounter
+            .find_mut(&args.name)
+            .ok_or_else(|| eyre!("{} is not in the encounter", args.name))?;
+        // encounters saved before negative hp was refused may still have some
+        let max_hp = combatant.max_hp.max(0);
+        combatant.hp = combatant.hp.saturating_sub(args.amount).max(0).min(max_hp);
+        let result = if combatant.hp == 0 {
+            format!("{} is down!", combatant.name)
+        } else {
+            format!(
+                "{} has {} of {} hp.",
+                combatant.name, combatant.hp, combatant.max_hp
+            )
+        };
+        context
+            .database
+            .save_encounter(context.conversation, &encounter)
+            .await?;
+
+        Ok(result)
+    }
+}
+
+struct NextTurn;
+
+#[async_trait]
+impl Tool for NextTurn {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "next_turn".to_owned(),
+            description: Some("End the current turn and find out whose turn is next".to_owned()),
+            parameters: Some(json!({"type": "object", "properties": {}})),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, _arguments: serde_json::Value) -> Result<String> {
+        let mut encounter = running_encounter(context).await?;
+        let result = match encounter.next_turn() {
+            Some(combatant) => format!("It is {}'s turn.", combatant.name),
+            None => "Everyone is down.".to_owned(),
+        };
+        context
+            .database
+            .save_encounter(context.conversation, &encounter)
+            .await?;
+
+        Ok(format!("{result} {}", status(&encounter)))
+    }
+}
+
+struct EndEncounter;
+
+#[async_trait]
+impl Tool for EndEncounter {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "end_encounter".to_owned(),
+            description: Some("Stop tracking the current combat encounter".to_owned()),
+            parameters: Some(json!({"type": "object", "properties": {}})),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, _arguments: serde_json::Value) -> Result<String> {
+        let encounter = running_encounter(context).await?;
+        context.database.end_encounter(context.conversation).await?;
+        Ok(format!(
+            "The encounter ended after {} rounds.",
+            encounter.round
+        ))
+    }
+}
+
+async fn running_encounter(context: &ToolContext) -> Result<Encounter> {
+    context
+        .database
+        .get_encounter(context.conversation)
+        .await?
+        .ok_or_else(|| eyre!("no encounter is running"))
+}
+
+fn status(encounter: &Encounter) -> String {
+    let order = encounter
+        .combatants
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let marker = if i == encounter.turn {
+                " (current turn)"
+            } else {
+                ""
+            };
+            format!(
+                "{} [initiative {}, {}/{} hp]{marker}",
+                c.name, c.initiative, c.hp, c.max_hp
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    format!("Round {}. Turn order: {order}.", encounter.round)
+}
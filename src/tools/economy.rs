@@ -0,0 +1,174 @@
+use super::{Tool, ToolContext, ToolRegistry};
+use crate::schema::Wallet;
+use async_openai::types::ChatCompletionFunctions;
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+pub const CURRENCY: &str = "horseshoes";
+
+pub fn register(registry: &mut ToolRegistry) -> Result<()> {
+    registry.register(GiveItem)?;
+    registry.register(GiveCoins)?;
+    registry.register(CheckBalance)?;
+    Ok(())
+}
+
+/// Describe a wallet, for the model or for a command reply.
+pub fn describe_wallet(wallet: &Wallet) -> String {
+    let items = if wallet.items.is_empty() {
+        "nothing".to_owned()
+    } else {
+        wallet
+            .items
+            .iter()
+            .map(|(item, quantity)| format!("{quantity} {item}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    format!(
+        "{} has {} {CURRENCY} and {items}.",
+        wallet.user_name, wallet.balance
+    )
+}
+
+/// Find the wallet of the named user, or of the speaker if no user was named.
+async fn target(context: &ToolContext, user: Option<&str>) -> Result<String> {
+    let speaker = &context.speaker;
+    context
+        .database
+        .ensure_wallet(context.conversation, &speaker.id, &speaker.name)
+        .await?;
+    match user {
+        Some(user) => context
+            .database
+            .find_wallet(context.conversation, user)
+            .await?
+            .ok_or_else(|| eyre!("{user} has never talked to you, so they have no wallet")),
+        None => Ok(speaker.id.clone()),
+    }
+}
+
+struct GiveItem;
+
+#[derive(Deserialize)]
+struct GiveItemArgs {
+    user: Option<String>,
+    item: String,
+    quantity: Option<i64>,
+}
+
+#[async_trait]
+impl Tool for GiveItem {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "give_item".to_owned(),
+            description: Some(
+                "Give a player an item for their inventory. A negative quantity takes items away."
+                    .to_owned(),
+            ),
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "user": {"type": "string", "description": "defaults to the person you are replying to"},
+                    "item": {"type": "string"},
+                    "quantity": {"type": "integer", "description": "defaults to 1"}
+                },
+                "required": ["item"]
+            })),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, arguments: serde_json::Value) -> Result<String> {
+        let args: GiveItemArgs = serde_json::from_value(arguments)?;
+        let user_id = target(context, args.user.as_deref()).await?;
+        let quantity = context
+            .database
+            .add_item(
+                context.conversation,
+                &user_id,
+                &args.item,
+                args.quantity.unwrap_or(1),
+            )
+            .await?;
+
+        Ok(format!("They now have {quantity} {}.", args.item))
+    }
+}
+
+struct GiveCoins;
+
+#[derive(Deserialize)]
+struct GiveCoinsArgs {
+    user: Option<String>,
+    amount: i64,
+}
+
+#[async_trait]
+impl Tool for GiveCoins {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "give_coins".to_owned(),
+            description: Some(format!(
+                "Give a player some {CURRENCY}. A negative amount charges them."
+            )),
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "user": {"type": "string", "description": "defaults to the person you are replying to"},
+                    "amount": {"type": "integer"}
+                },
+                "required": ["amount"]
+            })),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, arguments: serde_json::Value) -> Result<String> {
+        let args: GiveCoinsArgs = serde_json::from_value(arguments)?;
+        let user_id = target(context, args.user.as_deref()).await?;
+        let balance = context
+            .database
+            .add_coins(context.conversation, &user_id, args.amount)
+            .await?;
+
+        Ok(format!("Their balance is now {balance} {CURRENCY}."))
+    }
+}
+
+struct CheckBalance;
+
+#[derive(Deserialize)]
+struct CheckBalanceArgs {
+    user: Option<String>,
+}
+
+#[async_trait]
+impl Tool for CheckBalance {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "check_balance".to_owned(),
+            description: Some(format!(
+                "Look up how many {CURRENCY} and which items a player has"
+            )),
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "user": {"type": "string", "description": "defaults to the person you are replying to"}
+                }
+            })),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, arguments: serde_json::Value) -> Result<String> {
+        let args: CheckBalanceArgs = serde_json::from_value(arguments)?;
+        let user_id = target(context, args.user.as_deref()).await?;
+        let wallet = context
+            .database
+            .wallet(context.conversation, &user_id)
+            .await?;
+
+        Ok(describe_wallet(&wallet))
+    }
+}
@@ -0,0 +1,362 @@
+use super::{Tool, ToolContext, ToolRegistry};
+use crate::schema::{Game, GameKind};
+use async_openai::types::ChatCompletionFunctions;
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+const MAX_TRIVIA_GUESSES: usize = 3;
+const MAX_HANGMAN_MISSES: usize = 6;
+const MAX_QUESTIONS: u32 = 20;
+
+pub fn register(registry: &mut ToolRegistry) -> Result<()> {
+    registry.register(StartGame)?;
+    registry.register(GameStatus)?;
+    registry.register(AskQuestion)?;
+    registry.register(Guess)?;
+    registry.register(EndGame)?;
+    Ok(())
+}
+
+struct StartGame;
+
+#[derive(Deserialize)]
+struct StartGameArgs {
+    kind: GameKind,
+    secret: String,
+    question: Option<String>,
+}
+
+#[async_trait]
+impl Tool for StartGame {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "start_game".to_owned(),
+            description: Some(
+                "Start a game in this channel. For trivia, make up a question and give its answer \
+                 as the secret. For hangman the secret is the word. For twenty questions the \
+                 secret is the thing the players must guess."
+                    .to_owned(),
+            ),
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "kind": {"type": "string", "enum": ["trivia", "hangman", "twenty_questions"]},
+                    "secret": {"type": "string", "description": "the answer, never tell the players"},
+                    "question": {"type": "string", "description": "the trivia question"}
+                },
+                "required": ["kind", "secret"]
+            })),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, arguments: serde_json::Value) -> Result<String> {
+        let args: StartGameArgs = serde_json::from_value(arguments)?;
+        if let Some(game) = context.database.get_game(context.conversation).await? {
+            return Err(eyre!("a {:?} game is already running", game.kind));
+        }
+        let game = Game::new(args.kind, args.secret.trim().to_owned(), args.question);
+        context
+            .database
+            .save_game(context.conversation, &game)
+            .await?;
+
+        Ok(status(&game))
+    }
+}
+
+struct GameStatus;
+
+#[async_trait]
+impl Tool for GameStatus {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "game_status".to_owned(),
+            description: Some("Get the state of the game running in this channel".to_owned()),
+            parameters: Some(json!({"type": "object", "properties": {}})),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, _arguments: serde_json::Value) -> Result<String> {
+        match context.database.get_game(context.conversation).await? {
+            Some(game) => Ok(status(&game)),
+            None => Ok("No game is running.".to_owned()),
+        }
+    }
+}
+
+struct AskQuestion;
+
+#[async_trait]
+impl Tool for AskQuestion {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "count_question".to_owned(),
+            description: Some(
+                "In twenty questions, count a yes/no question a player asked. Call this before \
+                 answering it."
+                    .to_owned(),
+            ),
+            parameters: Some(json!({"type": "object", "properties": {}})),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, _arguments: serde_json::Value) -> Result<String> {
+        let mut game = running_game(context, GameKind::TwentyQuestions).await?;
+        game.questions_asked += 1;
+        if game.questions_asked > MAX_QUESTIONS {
+            context.database.end_game(context.conversation).await?;
+            return Ok(format!(
+                "That was question {}, the players lose! The answer was {}.",
+                game.questions_asked, game.secret
+            ));
+        }
+        context
+            .database
+            .save_game(context.conversation, &game)
+            .await?;
+
+        Ok(status(&game))
+    }
+}
+
+struct Guess;
+
+#[derive(Deserialize)]
+struct GuessArgs {
+    guess: String,
+}
+
+#[async_trait]
+impl Tool for Guess {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "guess".to_owned(),
+            description: Some(
+                "Check a player's guess (a trivia answer, a hangman letter or word, or a twenty \
+                 questions guess) against the secret"
+                    .to_owned(),
+            ),
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {"guess": {"type": "string"}},
+                "required": ["guess"]
+            })),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, arguments: serde_json::Value) -> Result<String> {
+        let args: GuessArgs = serde_json::from_value(arguments)?;
+        let guess = clean_guess(&args.guess)?;
+        let mut game = context
+            .database
+            .get_game(context.conversation)
+            .await?
+            .ok_or_else(|| eyre!("no game is running"))?;
+        game.guesses.push(guess.clone());
+
+        let outcome = check_guess(&game, &guess);
+        match outcome {
+            Outcome::Continue(_) => {
+                context
+                    .database
+                    .save_game(context.conversation, &game)
+                    .await?
+            }
+            Outcome::Won | Outcome::Lost => context.database.end_game(context.conversation).await?,
+        }
+
+        Ok(match outcome {
+            Outcome::Won => format!("Correct! The answer was {}. The game is over.", game.secret),
+            Outcome::Lost => format!("Wrong, and out of guesses. The answer was {}.", game.secret),
+            Outcome::Continue(hint) => format!("{hint} {}", status(&game)),
+        })
+    }
+}
+
+struct EndGame;
+
+#[async_trait]
+impl Tool for EndGame {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "end_game".to_owned(),
+            description: Some("Give up on the game running in this channel".to_owned()),
+            parameters: Some(json!({"type": "object", "properties": {}})),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, _arguments: serde_json::Value) -> Result<String> {
+        let game = context
+            .database
+            .get_game(context.conversation)
+            .await?
+            .ok_or_else(|| eyre!("no game is running"))?;
+        context.database.end_game(context.conversation).await?;
+        Ok(format!("Game over. The answer was {}.", game.secret))
+    }
+}
+
+async fn running_game(context: &ToolContext, kind: GameKind) -> Result<Game> {
+    match context.database.get_game(context.conversation).await? {
+        Some(game) if game.kind == kind => Ok(game),
+        Some(game) => Err(eyre!("the game running is {:?}, not {:?}", game.kind, kind)),
+        None => Err(eyre!("no game is running")),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Won,
+    Lost,
+    Continue(&'static str),
+}
+
+/// Compare answers ignoring case, punctuation and spacing.
+fn normalize(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// A guess as it's compared and kept. An empty one would count as every letter of a
+/// hangman word.
+fn clean_guess(guess: &str) -> Result<String> {
+    let guess = guess.trim().to_lowercase();
+    if guess.is_empty() {
+        return Err(eyre!("the guess is empty"));
+    }
+    Ok(guess)
+}
+
+/// Decide the result of the latest guess, which has already been added to the game.
+fn check_guess(game: &Game, guess: &str) -> Outcome {
+    let correct = normalize(guess) == normalize(&game.secret);
+    match game.kind {
+        GameKind::Trivia | GameKind::TwentyQuestions if correct => Outcome::Won,
+        GameKind::Trivia if game.guesses.len() >= MAX_TRIVIA_GUESSES => Outcome::Lost,
+        GameKind::Trivia | GameKind::TwentyQuestions => Outcome::Continue("Wrong!"),
+        GameKind::Hangman => {
+            if correct || hangman_solved(game) {
+                Outcome::Won
+            } else if hangman_misses(game) >= MAX_HANGMAN_MISSES {
+                Outcome::Lost
+            } else if guess.chars().count() == 1 && game.secret.to_lowercase().contains(guess) {
+                Outcome::Continue("Yes, that letter is in the word.")
+            } else {
+                Outcome::Continue("Nope!")
+            }
+        }
+    }
+}
+
+fn hangman_letters(game: &Game) -> Vec<char> {
+    game.guesses
+        .iter()
+        .filter(|g| g.chars().count() == 1)
+        .filter_map(|g| g.chars().next())
+        .collect()
+}
+
+fn hangman_misses(game: &Game) -> usize {
+    let secret = game.secret.to_lowercase();
+    game.guesses
+        .iter()
+        .filter(|g| g.chars().count() > 1 || !secret.contains(g.as_str()))
+        .count()
+}
+
+fn hangman_solved(game: &Game) -> bool {
+    let letters = hangman_letters(game);
+    game.secret
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .all(|c| letters.contains(&c))
+}
+
+fn hangman_board(game: &Game) -> String {
+    let letters = hangman_letters(game);
+    game.secret
+        .chars()
+        .map(|c| {
+            let lower = c.to_lowercase().next().unwrap_or(c);
+            if !c.is_alphanumeric() || letters.contains(&lower) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Describe the game for the model. This includes the secret, which the model needs to
+/// answer questions about it; function results are never shown to the players.
+fn status(game: &Game) -> String {
+    match game.kind {
+        GameKind::Trivia => format!(
+            "Trivia question: {}. Secret answer: {}. Guesses so far: {} of {}.",
+            game.question.as_deref().unwrap_or("(none)"),
+            game.secret,
+            game.guesses.len(),
+            MAX_TRIVIA_GUESSES
+        ),
+        GameKind::Hangman => format!(
+            "Hangman board: {}. Letters guessed: {}. Misses: {} of {}. Secret word: {}.",
+            hangman_board(game),
+            game.guesses.join(", "),
+            hangman_misses(game),
+            MAX_HANGMAN_MISSES,
+            game.secret
+        ),
+        GameKind::TwentyQuestions => format!(
+            "Twenty questions. Secret: {}. Questions asked: {} of {}. Guesses: {}.",
+            game.secret,
+            game.questions_asked,
+            MAX_QUESTIONS,
+            game.guesses.join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hangman() {
+        let mut game = Game::new(GameKind::Hangman, "Hay bale".to_owned(), None);
+        for letter in ["h", "a", "y", "b", "l"] {
+            game.guesses.push(letter.to_owned());
+            assert!(matches!(check_guess(&game, letter), Outcome::Continue(_)));
+        }
+        assert_eq!(hangman_board(&game), "Hay bal_");
+        game.guesses.push("e".to_owned());
+        assert_eq!(check_guess(&game, "e"), Outcome::Won);
+    }
+
+    #[test]
+    fn test_clean_guess() {
+        assert_eq!(clean_guess(" E ").unwrap(), "e");
+        assert!(clean_guess("").is_err());
+        assert!(clean_guess("  ").is_err());
+    }
+
+    #[test]
+    fn test_trivia() {
+        let mut game = Game::new(
+            GameKind::Trivia,
+            "Secretariat".to_owned(),
+            Some("Which horse won the 1973 Triple Crown?".to_owned()),
+        );
+        game.guesses.push("seabiscuit".to_owned());
+        assert_eq!(
+            check_guess(&game, "seabiscuit"),
+            Outcome::Continue("Wrong!")
+        );
+        game.guesses.push("secretariat!".to_owned());
+        assert_eq!(check_guess(&game, "secretariat!"), Outcome::Won);
+    }
+}
@@ -0,0 +1,134 @@
+use super::{Tool, ToolContext, ToolRegistry};
+use crate::schema::Quest;
+use async_openai::types::ChatCompletionFunctions;
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+pub fn register(registry: &mut ToolRegistry) -> Result<()> {
+    registry.register(CreateQuest)?;
+    registry.register(CompleteQuest)?;
+    registry.register(ListQuests)?;
+    Ok(())
+}
+
+/// Describe quests, for the model or for a command reply.
+pub fn describe_quests(quests: &[Quest]) -> String {
+    if quests.is_empty() {
+        return "There are no quests.".to_owned();
+    }
+    quests
+        .iter()
+        .map(|q| {
+            let assignee = q
+                .assignee
+                .as_ref()
+                .map(|a| format!(" (for @{a})"))
+                .unwrap_or_default();
+            let done = if q.completed { " [complete]" } else { "" };
+            format!("#{}: {}{assignee}{done} - {}", q.id, q.title, q.description)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+struct CreateQuest;
+
+#[derive(Deserialize)]
+struct CreateQuestArgs {
+    title: String,
+    description: String,
+    assignee: Option<String>,
+}
+
+#[async_trait]
+impl Tool for CreateQuest {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "create_quest".to_owned(),
+            description: Some("Add a quest to this channel's quest log".to_owned()),
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "title": {"type": "string"},
+                    "description": {"type": "string", "description": "what must be done, and the reward"},
+                    "assignee": {"type": "string", "description": "the player the quest is for, if any"}
+                },
+                "required": ["title", "description"]
+            })),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, arguments: serde_json::Value) -> Result<String> {
+        let args: CreateQuestArgs = serde_json::from_value(arguments)?;
+        let id = context
+            .database
+            .create_quest(
+                context.conversation,
+                &args.title,
+                &args.description,
+                args.assignee.as_ref(),
+            )
+            .await?;
+
+        Ok(format!("Created quest #{id}."))
+    }
+}
+
+struct CompleteQuest;
+
+#[derive(Deserialize)]
+struct CompleteQuestArgs {
+    id: i64,
+}
+
+#[async_trait]
+impl Tool for CompleteQuest {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "complete_quest".to_owned(),
+            description: Some("Mark a quest in the quest log as complete".to_owned()),
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {"id": {"type": "integer", "description": "the quest number"}},
+                "required": ["id"]
+            })),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, arguments: serde_json::Value) -> Result<String> {
+        let args: CompleteQuestArgs = serde_json::from_value(arguments)?;
+        if context
+            .database
+            .complete_quest(context.conversation, args.id)
+            .await?
+        {
+            Ok(format!("Quest #{} is complete.", args.id))
+        } else {
+            Err(eyre!("there is no open quest #{}", args.id))
+        }
+    }
+}
+
+struct ListQuests;
+
+#[async_trait]
+impl Tool for ListQuests {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "list_quests".to_owned(),
+            description: Some("List the open quests in this channel's quest log".to_owned()),
+            parameters: Some(json!({"type": "object", "properties": {}})),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, _arguments: serde_json::Value) -> Result<String> {
+        let quests = context
+            .database
+            .list_quests(context.conversation, false)
+            .await?;
+
+        Ok(describe_quests(&quests))
+    }
+}
@@ -0,0 +1,182 @@
+use super::{Tool, ToolContext, ToolRegistry};
+use crate::schema::{Relationship, MAX_AFFINITY, MAX_STEP};
+use async_openai::types::ChatCompletionFunctions;
+use async_trait::async_trait;
+use chrono::Local;
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+/// The functions that change what the bot remembers about people, left out when the
+/// memory flag is off.
+pub const MEMORY_FUNCTIONS: &[&str] = &["update_relationship"];
+
+pub fn register(registry: &mut ToolRegistry) -> Result<()> {
+    registry.register(GetRelationship)?;
+    registry.register(UpdateRelationship)?;
+    Ok(())
+}
+
+/// Describe a relationship for the model.
+fn describe_relationship(relationship: &Relationship) -> String {
+    let feeling = match relationship.affinity {
+        a if a <= -50 => "You can't stand them",
+        a if a < -10 => "You don't like them much",
+        a if a <= 10 => "You don't feel strongly about them",
+        a if a < 50 => "You like them",
+        _ => "They're one of your favorite people",
+    };
+    let mut description = format!(
+        "{feeling} ({}, from -{MAX_AFFINITY} to {MAX_AFFINITY}).",
+        relationship.affinity
+    );
+    if !relationship.notes.is_empty() {
+        description.push_str(" Your notes about them, oldest first:");
+        for note in &relationship.notes {
+            description.push_str("\n- ");
+            description.push_str(note);
+        }
+    }
+
+    description
+}
+
+/// Whether `user` names the speaker: no one, their name or their id.
+fn is_speaker(context: &ToolContext, user: Option<&str>) -> bool {
+    let speaker = &context.speaker;
+    user.map(|u| u.trim_start_matches('@')).map_or(true, |u| {
+        u == speaker.id || u.eq_ignore_ascii_case(&speaker.name)
+    })
+}
+
+struct GetRelationship;
+
+#[derive(Deserialize)]
+struct GetRelationshipArgs {
+    user: Option<String>,
+}
+
+#[async_trait]
+impl Tool for GetRelationship {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "get_relationship".to_owned(),
+            description: Some(
+                "Recall how you feel about someone and what you've noted about them.".to_owned(),
+            ),
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "user": {"type": "string", "description": "defaults to the person you are replying to"}
+                }
+            })),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, arguments: serde_json::Value) -> Result<String> {
+        let args: GetRelationshipArgs = serde_json::from_value(arguments)?;
+        let user = match args.user {
+            Some(user) => user,
+            None => context.speaker.id.clone(),
+        };
+        let relationship = context
+            .database
+            .relationship(context.conversation, &user)
+            .await?;
+
+        Ok(match relationship {
+            Some(relationship) => describe_relationship(&relationship),
+            None => "You haven't formed an opinion of them yet.".to_owned(),
+        })
+    }
+}
+
+struct UpdateRelationship;
+
+#[derive(Deserialize)]
+struct UpdateRelationshipArgs {
+    user: Option<String>,
+    delta: i64,
+    note: Option<String>,
+}
+
+#[async_trait]
+impl Tool for UpdateRelationship {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "update_relationship".to_owned(),
+            description: Some(format!(
+                "Change how you feel about someone after something memorable, and note why. \
+                 A positive delta warms you to them, a negative one cools you, at most \
+                 {MAX_STEP} either way. Only for things that matter, not every message."
+            )),
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "user": {"type": "string", "description": "defaults to the person you are replying to"},
+                    "delta": {"type": "integer"},
+                    "note": {"type": "string", "description": "a short reminder of what happened"}
+                },
+                "required": ["delta"]
+            })),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, arguments: serde_json::Value) -> Result<String> {
+        let args: UpdateRelationshipArgs = serde_json::from_value(arguments)?;
+        let (user_id, user_name) = if is_speaker(context, args.user.as_deref()) {
+            (context.speaker.id.clone(), context.speaker.name.clone())
+        } else {
+            let user = args.user.unwrap_or_default();
+            let known = context
+                .database
+                .relationship(context.conversation, &user)
+                .await?
+                .ok_or_else(|| {
+                    eyre!(
+                        "you can only start a relationship with who you're talking to, not {user}"
+                    )
+                })?;
+            (known.user_id, known.user_name)
+        };
+
+        let updated = context
+            .database
+            .update_relationship(
+                context.conversation,
+                user_id,
+                user_name,
+                args.delta,
+                args.note,
+                Local::now(),
+            )
+            .await?;
+
+        Ok(match updated {
+            Some(relationship) => describe_relationship(&relationship),
+            None => "You changed your mind about them only a moment ago. Let it settle first."
+                .to_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_relationship() {
+        let relationship = Relationship {
+            user_id: "42".to_owned(),
+            user_name: "Filly".to_owned(),
+            affinity: 15,
+            notes: vec!["brought me an apple".to_owned()],
+            updated_at: Local::now(),
+        };
+        assert_eq!(
+            describe_relationship(&relationship),
+            "You like them (15, from -100 to 100). Your notes about them, oldest first:\n\
+             - brought me an apple"
+        );
+    }
+}
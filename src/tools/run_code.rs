@@ -0,0 +1,131 @@
+//! Lets the model run short snippets of Rhai, a small Rust-like scripting language,
+//! for arithmetic and the like it would otherwise guess at. Scripts can't touch the
+//! filesystem or network, and are cut off after a fixed amount of work.
+
+use super::{Tool, ToolContext, ToolRegistry};
+use async_openai::types::ChatCompletionFunctions;
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use rhai::{Dynamic, Engine};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+/// Limits on how much a snippet may do.
+const MAX_OPERATIONS: u64 = 1_000_000;
+const MAX_STRING_SIZE: usize = 10_000;
+const MAX_COLLECTION_SIZE: usize = 10_000;
+const MAX_CALL_LEVELS: usize = 32;
+
+/// How much output is given back to the model.
+const MAX_OUTPUT_CHARS: usize = 2000;
+/// How much of what a script prints is kept. Past this, printing does nothing, so a
+/// script printing in a loop can't fill up memory with output nobody will see.
+const MAX_PRINTED_BYTES: usize = 4 * MAX_OUTPUT_CHARS;
+
+pub fn register(registry: &mut ToolRegistry) -> Result<()> {
+    registry.register(RunCode)?;
+    Ok(())
+}
+
+struct RunCode;
+
+#[derive(Deserialize)]
+struct RunCodeArgs {
+    code: String,
+}
+
+#[async_trait]
+impl Tool for RunCode {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "run_code".to_owned(),
+            description: Some(
+                "Run a short Rhai script (a simple Rust-like language, with print()) and get \
+                 back what it printed and the value of its last expression. Use it for \
+                 calculations rather than working them out yourself."
+                    .to_owned(),
+            ),
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "code": {
+                        "type": "string",
+                        "description": "the Rhai script, like `let x = 6; x * 7`"
+                    }
+                },
+                "required": ["code"]
+            })),
+        }
+    }
+
+    async fn call(&self, _context: &ToolContext, arguments: serde_json::Value) -> Result<String> {
+        let args: RunCodeArgs = serde_json::from_value(arguments)?;
+        tokio::task::spawn_blocking(move || run(&args.code)).await?
+    }
+}
+
+fn run(code: &str) -> Result<String> {
+    let printed = Arc::new(Mutex::new(String::new()));
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_string_size(MAX_STRING_SIZE)
+        .set_max_array_size(MAX_COLLECTION_SIZE)
+        .set_max_map_size(MAX_COLLECTION_SIZE)
+        .set_max_call_levels(MAX_CALL_LEVELS);
+    let sink = printed.clone();
+    engine.on_print(move |s| collect(&mut sink.lock().unwrap_or_else(|e| e.into_inner()), s));
+
+    let result = engine
+        .eval::<Dynamic>(code)
+        .map_err(|e| eyre!("the script failed: {e}"))?;
+    let mut output = printed.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    if !result.is_unit() {
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        output.push_str(&format!("=> {result}"));
+    }
+    if output.is_empty() {
+        output = "(no output)".to_owned();
+    }
+
+    Ok(crate::text::truncate(&output, MAX_OUTPUT_CHARS).to_owned())
+}
+
+/// Add a printed line to the output, as much of it as fits.
+fn collect(printed: &mut String, line: &str) {
+    if printed.len() >= MAX_PRINTED_BYTES {
+        return;
+    }
+    if !printed.is_empty() {
+        printed.push('\n');
+    }
+    let room = MAX_PRINTED_BYTES.saturating_sub(printed.len());
+    let mut end = line.len().min(room);
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    printed.push_str(&line[..end]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        assert_eq!(run("let x = 6; x * 7").unwrap(), "=> 42");
+        assert_eq!(run("print(\"neigh\"); 1 + 1").unwrap(), "neigh\n=> 2");
+        assert_eq!(run("let x = 1;").unwrap(), "(no output)");
+        assert!(run("loop {}").is_err());
+
+        let mut printed = String::new();
+        for _ in 0..MAX_PRINTED_BYTES {
+            collect(&mut printed, "héhé");
+        }
+        assert!(printed.len() <= MAX_PRINTED_BYTES);
+        assert!(printed.starts_with("héhé\nhéhé\n"));
+    }
+}
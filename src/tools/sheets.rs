@@ -0,0 +1,59 @@
+use super::{Tool, ToolContext, ToolRegistry};
+use async_openai::types::ChatCompletionFunctions;
+use async_trait::async_trait;
+use eyre::Result;
+use serde::Deserialize;
+use serde_json::json;
+
+pub fn register(registry: &mut ToolRegistry) -> Result<()> {
+    registry.register(GetCharacterSheet)?;
+    Ok(())
+}
+
+struct GetCharacterSheet;
+
+#[derive(Deserialize)]
+struct GetCharacterSheetArgs {
+    user: Option<String>,
+}
+
+#[async_trait]
+impl Tool for GetCharacterSheet {
+    fn definition(&self) -> ChatCompletionFunctions {
+        ChatCompletionFunctions {
+            name: "get_character_sheet".to_owned(),
+            description: Some(
+                "Look up a player's character sheet (stats, inventory, etc). Players fill these \
+                 in with the /sheet command."
+                    .to_owned(),
+            ),
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "user": {
+                        "type": "string",
+                        "description": "the player's name, defaults to the person you are replying to"
+                    }
+                }
+            })),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext, arguments: serde_json::Value) -> Result<String> {
+        let args: GetCharacterSheetArgs = serde_json::from_value(arguments)?;
+        let user = args.user.unwrap_or_else(|| context.speaker.id.clone());
+        let sheet = context
+            .database
+            .get_sheet(context.conversation, &user)
+            .await?;
+        if sheet.is_empty() {
+            return Ok(format!("{user} has no character sheet."));
+        }
+
+        Ok(sheet
+            .into_iter()
+            .map(|(key, value)| format!("{key}: {value}"))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
@@ -0,0 +1,124 @@
+//! Tools implemented as WASM modules.
+//!
+//! A plugin module must export `memory`, `alloc(len: i32) -> i32`, `definition() -> i64`
+//! and `call(ptr: i32, len: i32) -> i64`. Strings cross the boundary as UTF-8 in the
+//! module's memory; an i64 return value packs the pointer in the high 32 bits and the
+//! length in the low 32 bits. `definition` returns the function definition as JSON, and
+//! `call` receives the JSON arguments and returns the result text.
+//!
+//! Each call gets a fixed amount of fuel and runs on a blocking thread with a timeout,
+//! so a plugin that loops forever fails its call instead of hanging the bot.
+
+use super::{Tool, ToolContext};
+use async_openai::types::ChatCompletionFunctions;
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use std::{path::Path, time::Duration};
+use wasmtime::{Config, Engine, Instance, Linker, Memory, Module, Store};
+
+/// Roughly how many instructions a plugin may run per call, definition included.
+const FUEL: u64 = 100_000_000;
+
+/// How long to wait for a plugin call before giving up on it.
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct WasmTool {
+    engine: Engine,
+    module: Module,
+    definition: ChatCompletionFunctions,
+}
+
+impl WasmTool {
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(|e| eyre!("{e:#}"))?;
+        let module = Module::from_file(&engine, path.as_ref()).map_err(|e| eyre!("{e:#}"))?;
+        let definition = {
+            let (mut store, instance) = instantiate(&engine, &module)?;
+            let definition = instance
+                .get_typed_func::<(), i64>(&mut store, "definition")
+                .map_err(|e| eyre!("{e:#}"))?
+                .call(&mut store, ())
+                .map_err(|e| eyre!("{e:#}"))?;
+            let definition = read_string(&mut store, &instance, definition)?;
+            serde_json::from_str(&definition)?
+        };
+
+        Ok(Self {
+            engine,
+            module,
+            definition,
+        })
+    }
+}
+
+#[async_trait]
+impl Tool for WasmTool {
+    fn definition(&self) -> ChatCompletionFunctions {
+        self.definition.clone()
+    }
+
+    async fn call(&self, _context: &ToolContext, arguments: serde_json::Value) -> Result<String> {
+        let engine = self.engine.clone();
+        let module = self.module.clone();
+        let call = tokio::task::spawn_blocking(move || call(&engine, &module, arguments));
+        match tokio::time::timeout(CALL_TIMEOUT, call).await {
+            Ok(result) => result?,
+            Err(_) => Err(eyre!("plugin {} timed out", self.definition.name)),
+        }
+    }
+}
+
+/// Run the plugin's `call` export on the arguments, off the async runtime.
+fn call(engine: &Engine, module: &Module, arguments: serde_json::Value) -> Result<String> {
+    // every call gets a fresh instance, so plugins can't keep state between calls.
+    let (mut store, instance) = instantiate(engine, module)?;
+    let arguments = arguments.to_string();
+    let len = arguments.len() as i32;
+    let ptr = instance
+        .get_typed_func::<i32, i32>(&mut store, "alloc")
+        .map_err(|e| eyre!("{e:#}"))?
+        .call(&mut store, len)
+        .map_err(|e| eyre!("{e:#}"))?;
+    memory(&mut store, &instance)?
+        .write(&mut store, ptr as usize, arguments.as_bytes())
+        .map_err(|e| eyre!("{e:#}"))?;
+    let result = instance
+        .get_typed_func::<(i32, i32), i64>(&mut store, "call")
+        .map_err(|e| eyre!("{e:#}"))?
+        .call(&mut store, (ptr, len))
+        .map_err(|e| eyre!("{e:#}"))?;
+
+    read_string(&mut store, &instance, result)
+}
+
+fn instantiate(engine: &Engine, module: &Module) -> Result<(Store<()>, Instance)> {
+    let mut store = Store::new(engine, ());
+    store.add_fuel(FUEL).map_err(|e| eyre!("{e:#}"))?;
+    let instance = Linker::new(engine)
+        .instantiate(&mut store, module)
+        .map_err(|e| eyre!("{e:#}"))?;
+    Ok((store, instance))
+}
+
+fn memory(store: &mut Store<()>, instance: &Instance) -> Result<Memory> {
+    instance
+        .get_memory(store, "memory")
+        .ok_or_else(|| eyre!("plugin does not export memory"))
+}
+
+fn read_string(store: &mut Store<()>, instance: &Instance, packed: i64) -> Result<String> {
+    let ptr = (packed as u64 >> 32) as usize;
+    let len = (packed as u64 & 0xffff_ffff) as usize;
+    // checked before copying anything, so a bad length can't ask for gigabytes
+    let data = memory(store, instance)?.data(&*store);
+    let bytes = ptr
+        .checked_add(len)
+        .and_then(|end| data.get(ptr..end))
+        .ok_or_else(|| eyre!("plugin returned {len} bytes at {ptr}, outside its memory"))?;
+    Ok(String::from_utf8(bytes.to_vec())?)
+}
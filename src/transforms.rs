@@ -0,0 +1,201 @@
+use eyre::Result;
+use once_cell::sync::Lazy;
+use rand::{seq::SliceRandom, Rng};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+/// End of a sentence, where [`Transform::Sprinkle`] adds emoji.
+static SENTENCE_END: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"[.!?](\s|$)").expect("valid sentence regex"));
+
+/// [`HORSE_SPEAK`] compiled once.
+static HORSE_SPEAK_REGEXES: Lazy<Vec<(Regex, &str)>> = Lazy::new(|| {
+    HORSE_SPEAK
+        .iter()
+        .map(|(from, to)| (word_regex(from).expect("valid horse speak regex"), *to))
+        .collect()
+});
+
+/// Pairs of (word or phrase, horse pun) used by [`Transform::HorseSpeak`].
+const HORSE_SPEAK: &[(&str, &str)] = &[
+    ("of course", "of horse"),
+    ("hey", "hay"),
+    ("nay", "neigh"),
+    ("no way", "neigh way"),
+    ("forever", "fur-ever"),
+    ("tale", "tail"),
+    ("main", "mane"),
+];
+
+/// A single step in a conversation's output pipeline, applied to the model's reply
+/// before it is sent. Stored as JSON, e.g. `{"type": "replace", "pattern": "...", "replacement": "..."}`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Transform {
+    /// Regex substitution; the replacement may use `$1` style capture references.
+    Replace {
+        pattern: String,
+        replacement: String,
+    },
+    /// Replace each listed word (case-insensitively) with asterisks.
+    Mask { words: Vec<String> },
+    /// After each sentence, add one of the emoji with the given probability.
+    Sprinkle {
+        emoji: Vec<String>,
+        probability: f64,
+    },
+    /// Swap ordinary words for horse puns.
+    HorseSpeak,
+    /// With the given probability, add one of the phrases (a signature like "Neigh.")
+    /// at the start or end of the reply, unless the reply already contains it.
+    Catchphrase {
+        phrases: Vec<String>,
+        probability: f64,
+        #[serde(default)]
+        placement: Placement,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Placement {
+    Start,
+    #[default]
+    End,
+    /// Start or end, chosen at random.
+    Either,
+}
+
+impl Transform {
+    pub fn apply(&self, text: String) -> Result<String> {
+        let text = match self {
+            Transform::Replace {
+                pattern,
+                replacement,
+            } => Regex::new(pattern)?
+                .replace_all(&text, replacement.as_str())
+                .into_owned(),
+            Transform::Mask { words } => words.iter().try_fold(text, |text, word| {
+                let re = word_regex(word)?;
+                Ok::<_, eyre::Error>(
+                    re.replace_all(&text, |caps: &regex::Captures| {
+                        "*".repeat(caps[0].chars().count())
+                    })
+                    .into_owned(),
+                )
+            })?,
+            Transform::Sprinkle { emoji, probability } => {
+                let mut rng = rand::thread_rng();
+                SENTENCE_END
+                    .replace_all(&text, |caps: &regex::Captures| {
+                        match emoji.choose(&mut rng) {
+                            Some(e) if rng.gen_bool(probability.clamp(0.0, 1.0)) => {
+                                format!("{} {}{}", caps[0].trim_end(), e, &caps[1])
+                            }
+                            _ => caps[0].to_owned(),
+                        }
+                    })
+                    .into_owned()
+            }
+            Transform::HorseSpeak => HORSE_SPEAK_REGEXES.iter().fold(text, |text, (re, to)| {
+                re.replace_all(&text, |caps: &regex::Captures| {
+                    // keep the capitalization of the first letter
+                    if caps[0].starts_with(char::is_uppercase) {
+                        let mut chars = to.chars();
+                        chars
+                            .next()
+                            .map(|c| c.to_uppercase().chain(chars).collect())
+                            .unwrap_or_default()
+                    } else {
+                        to.to_string()
+                    }
+                })
+                .into_owned()
+            }),
+            Transform::Catchphrase {
+                phrases,
+                probability,
+                placement,
+            } => {
+                let mut rng = rand::thread_rng();
+                match phrases.choose(&mut rng) {
+                    Some(phrase)
+                        if !text.contains(phrase.as_str())
+                            && rng.gen_bool(probability.clamp(0.0, 1.0)) =>
+                    {
+                        let at_start = match placement {
+                            Placement::Start => true,
+                            Placement::End => false,
+                            Placement::Either => rng.gen(),
+                        };
+                        if at_start {
+                            format!("{phrase} {text}")
+                        } else {
+                            format!("{text} {phrase}")
+                        }
+                    }
+                    _ => text,
+                }
+            }
+        };
+
+        Ok(text)
+    }
+}
+
+/// Run each transform in order.
+pub fn apply_all(transforms: &[Transform], text: String) -> Result<String> {
+    transforms.iter().try_fold(text, |text, t| t.apply(text))
+}
+
+fn word_regex(word: &str) -> Result<Regex> {
+    let re = RegexBuilder::new(&format!(r"\b{}\b", regex::escape(word)))
+        .case_insensitive(true)
+        .build()?;
+    Ok(re)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_all() {
+        let transforms: Vec<Transform> = serde_json::from_str(
+            r#"[
+                {"type": "replace", "pattern": "(\\w+)ing", "replacement": "${1}in'"},
+                {"type": "mask", "words": ["darn"]},
+                {"type": "horse_speak"}
+            ]"#,
+        )
+        .expect("failed to parse transforms");
+        let text = apply_all(
+            &transforms,
+            "Hey, darn it, I'm trotting, of course".to_owned(),
+        )
+        .expect("failed to apply");
+        assert_eq!(text, "Hay, **** it, I'm trottin', of horse");
+    }
+
+    #[test]
+    fn test_sprinkle() {
+        let sprinkle = Transform::Sprinkle {
+            emoji: vec!["🐴".to_owned()],
+            probability: 1.0,
+        };
+        let text = sprinkle.apply("Neigh. Whinny!".to_owned()).unwrap();
+        assert_eq!(text, "Neigh. 🐴 Whinny! 🐴");
+    }
+
+    #[test]
+    fn test_catchphrase() {
+        let catchphrase: Transform = serde_json::from_str(
+            r#"{"type": "catchphrase", "phrases": ["Neigh."], "probability": 1.0}"#,
+        )
+        .expect("failed to parse transform");
+        let text = catchphrase.apply("Hay there.".to_owned()).unwrap();
+        assert_eq!(text, "Hay there. Neigh.");
+        let text = catchphrase.apply(text).unwrap();
+        assert_eq!(text, "Hay there. Neigh.");
+    }
+}
@@ -0,0 +1,75 @@
+//! Similarity search over embedded history. The database's own embeddings are searched
+//! by default; a Qdrant server can take over for histories too big to scan, when built
+//! with the qdrant feature.
+//!
+//! Only the search and reindex commands use it for now; the bot just keeps it in
+//! step as messages are redacted, forgotten or merged.
+
+use crate::schema::{Conversation, Database};
+use async_trait::async_trait;
+use eyre::Result;
+use std::sync::Arc;
+
+#[cfg(feature = "qdrant")]
+pub mod qdrant;
+pub mod sqlite;
+
+/// A message found by a search, best first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub history_id: i64,
+    /// Cosine similarity to the query, from -1 to 1.
+    pub score: f32,
+}
+
+/// An embedded message, as handed to [`VectorStore::index`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub history_id: i64,
+    pub conversation: Conversation,
+    pub embedding: Vec<f32>,
+}
+
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    /// Make messages whose embeddings from `model` were just saved searchable.
+    async fn index(&self, model: &str, entries: &[Entry]) -> Result<()>;
+
+    /// The `limit` messages of `conversation` closest to `query`, an embedding from
+    /// `model`. Hits may include messages deleted since they were indexed.
+    async fn search(
+        &self,
+        conversation: Conversation,
+        model: &str,
+        query: &[f32],
+        limit: usize,
+    ) -> Result<Vec<Hit>>;
+
+    /// Forget messages that were deleted or redacted, so a search can't turn up what
+    /// they said.
+    async fn remove(&self, history_ids: &[i64]) -> Result<()>;
+
+    /// Forget every message of a conversation, when its history is cleared.
+    async fn remove_conversation(&self, conversation: Conversation) -> Result<()>;
+
+    /// Move `src`'s messages to `dst`, once the conversations have been merged.
+    async fn reassign(&self, src: Conversation, dst: Conversation) -> Result<()>;
+}
+
+/// The store to search: the Qdrant server at `qdrant`, with collections named after
+/// `collection`, or else the database, whose embeddings are loaded into memory.
+pub async fn open(
+    database: Arc<Database>,
+    qdrant: Option<&str>,
+    #[allow(unused_variables)] collection: &str,
+) -> Result<Arc<dyn VectorStore>> {
+    match qdrant {
+        None => Ok(Arc::new(sqlite::SqliteStore::load(database).await?)),
+        #[cfg(feature = "qdrant")]
+        Some(url) => Ok(Arc::new(qdrant::QdrantStore::new(url, collection)?)),
+        #[cfg(not(feature = "qdrant"))]
+        Some(url) => Err(eyre::eyre!(
+            "can't use Qdrant at {url}: built without the qdrant feature"
+        )),
+    }
+}
@@ -0,0 +1,245 @@
+//! Searching embeddings kept in a [Qdrant](https://qdrant.tech) server, over its REST
+//! API. Each embedding model gets its own collection, created on first use, since
+//! they differ in size. Set QDRANT_API_KEY for a server that wants one.
+
+use super::{Entry, Hit, VectorStore};
+use crate::schema::Conversation;
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use reqwest::{Client, Method, RequestBuilder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{collections::HashSet, sync::Mutex, time::Duration};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct QdrantStore {
+    http: Client,
+    url: String,
+    api_key: Option<String>,
+    collection: String,
+    /// Collections known to exist, so they're only checked for once.
+    created: Mutex<HashSet<String>>,
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    result: T,
+}
+
+#[derive(Deserialize)]
+struct Point {
+    id: i64,
+    score: f32,
+}
+
+#[derive(Deserialize)]
+struct Collections {
+    collections: Vec<CollectionName>,
+}
+
+#[derive(Deserialize)]
+struct CollectionName {
+    name: String,
+}
+
+impl QdrantStore {
+    pub fn new(url: &str, collection: &str) -> Result<Self> {
+        Ok(Self {
+            http: Client::builder().timeout(TIMEOUT).build()?,
+            url: url.trim_end_matches('/').to_owned(),
+            api_key: std::env::var("QDRANT_API_KEY").ok(),
+            collection: collection.to_owned(),
+            created: Mutex::default(),
+        })
+    }
+
+    /// The collection for embeddings from `model`.
+    fn collection(&self, model: &str) -> String {
+        let model: String = model
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        format!("{}-{model}", self.collection)
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self.http.request(method, format!("{}{path}", self.url));
+        match &self.api_key {
+            Some(key) => request.header("api-key", key),
+            None => request,
+        }
+    }
+
+    /// Every collection of this bot's, one per model embedded with.
+    async fn collections(&self) -> Result<Vec<String>> {
+        let prefix = format!("{}-", self.collection);
+        let collections: Response<Collections> = self
+            .request(Method::GET, "/collections")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(collections
+            .result
+            .collections
+            .into_iter()
+            .map(|c| c.name)
+            .filter(|name| name.starts_with(&prefix))
+            .collect())
+    }
+
+    /// Send the same request about points to every collection.
+    async fn update_all(&self, action: &str, body: Value) -> Result<()> {
+        for collection in self.collections().await? {
+            self.request(
+                Method::POST,
+                &format!("/collections/{collection}/points/{action}?wait=true"),
+            )
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()
+            .map_err(|e| eyre!("Qdrant {action} in {collection} failed: {e}"))?;
+        }
+        Ok(())
+    }
+
+    async fn ensure_collection(&self, collection: &str, size: usize) -> Result<()> {
+        if self
+            .created
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(collection)
+        {
+            return Ok(());
+        }
+        let path = format!("/collections/{collection}");
+        let exists = self.request(Method::GET, &path).send().await?;
+        if !exists.status().is_success() {
+            self.request(Method::PUT, &path)
+                .json(&json!({ "vectors": { "size": size, "distance": "Cosine" } }))
+                .send()
+                .await?
+                .error_for_status()?;
+            log::info!("Created Qdrant collection {collection}");
+        }
+        self.created
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(collection.to_owned());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VectorStore for QdrantStore {
+    async fn index(&self, model: &str, entries: &[Entry]) -> Result<()> {
+        let Some(first) = entries.first() else {
+            return Ok(());
+        };
+        let collection = self.collection(model);
+        self.ensure_collection(&collection, first.embedding.len())
+            .await?;
+        let points: Vec<Value> = entries
+            .iter()
+            .map(|entry| {
+                json!({
+                    "id": entry.history_id,
+                    "vector": entry.embedding,
+                    "payload": { "conversation": entry.conversation.id() },
+                })
+            })
+            .collect();
+        self.request(
+            Method::PUT,
+            &format!("/collections/{collection}/points?wait=true"),
+        )
+        .json(&json!({ "points": points }))
+        .send()
+        .await?
+        .error_for_status()?;
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        conversation: Conversation,
+        model: &str,
+        query: &[f32],
+        limit: usize,
+    ) -> Result<Vec<Hit>> {
+        let collection = self.collection(model);
+        let response = self
+            .request(
+                Method::POST,
+                &format!("/collections/{collection}/points/search"),
+            )
+            .json(&json!({
+                "vector": query,
+                "limit": limit,
+                "filter": conversation_filter(conversation),
+            }))
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            // nothing from this model has been indexed yet
+            return Ok(vec![]);
+        }
+        let points: Response<Vec<Point>> = response
+            .error_for_status()
+            .map_err(|e| eyre!("Qdrant search in {collection} failed: {e}"))?
+            .json()
+            .await?;
+        Ok(points
+            .result
+            .into_iter()
+            .map(|p| Hit {
+                history_id: p.id,
+                score: p.score,
+            })
+            .collect())
+    }
+
+    async fn remove(&self, history_ids: &[i64]) -> Result<()> {
+        if history_ids.is_empty() {
+            return Ok(());
+        }
+        self.update_all("delete", json!({ "points": history_ids }))
+            .await
+    }
+
+    async fn remove_conversation(&self, conversation: Conversation) -> Result<()> {
+        let filter = conversation_filter(conversation);
+        self.update_all("delete", json!({ "filter": filter })).await
+    }
+
+    async fn reassign(&self, src: Conversation, dst: Conversation) -> Result<()> {
+        let body = json!({
+            "payload": { "conversation": dst.id() },
+            "filter": conversation_filter(src),
+        });
+        self.update_all("payload", body).await
+    }
+}
+
+/// Matches the points of one conversation.
+fn conversation_filter(conversation: Conversation) -> Value {
+    json!({ "must": [{ "key": "conversation", "match": { "value": conversation.id() } }] })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collection() {
+        let store = QdrantStore::new("http://localhost:6333/", "horse-npc").unwrap();
+        assert_eq!(store.url, "http://localhost:6333");
+        assert_eq!(
+            store.collection("text-embedding-3.small"),
+            "horse-npc-text-embedding-3-small"
+        );
+    }
+}
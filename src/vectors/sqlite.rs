@@ -0,0 +1,396 @@
+//! Searching the quantized embeddings kept in the database. They're loaded into memory
+//! when the store is opened, kept up to date as messages are indexed, removed or moved,
+//! and topped up with whatever else was saved since by each search, so a search is a
+//! scan of the conversation's vectors at a byte per dimension, with the dot products
+//! done 16 dimensions at a time where the CPU can.
+
+use super::{Entry, Hit, VectorStore};
+use crate::schema::{Conversation, Database};
+use async_trait::async_trait;
+use eyre::Result;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+
+pub struct SqliteStore {
+    database: Arc<Database>,
+    index: Mutex<Index>,
+}
+
+/// The embeddings of one conversation from one model, laid end to end.
+#[derive(Default)]
+struct Shard {
+    history_ids: Vec<i64>,
+    embeddings: Vec<i8>,
+    dimensions: usize,
+}
+
+impl Shard {
+    fn embedding(&self, i: usize) -> &[i8] {
+        &self.embeddings[i * self.dimensions..(i + 1) * self.dimensions]
+    }
+
+    /// Add an embedding, returning its position, or None if it's the wrong size.
+    fn push(&mut self, history_id: i64, embedding: &[i8]) -> Option<usize> {
+        if self.history_ids.is_empty() {
+            self.dimensions = embedding.len();
+        } else if embedding.len() != self.dimensions {
+            return None;
+        }
+        self.history_ids.push(history_id);
+        self.embeddings.extend_from_slice(embedding);
+        Some(self.history_ids.len() - 1)
+    }
+
+    /// Remove the embedding at `i` by moving the last one into its place, returning the
+    /// history id of the one moved, if any.
+    fn swap_remove(&mut self, i: usize) -> Option<i64> {
+        let last = self.history_ids.len() - 1;
+        self.history_ids.swap_remove(i);
+        if i != last {
+            let (d, moved) = (self.dimensions, last * self.dimensions);
+            self.embeddings.copy_within(moved..moved + d, i * d);
+        }
+        self.embeddings.truncate(last * self.dimensions);
+        if i == last {
+            None
+        } else {
+            Some(self.history_ids[i])
+        }
+    }
+}
+
+type ShardKey = (String, Conversation);
+
+#[derive(Default)]
+struct Index {
+    /// The last row of quantized_embeddings loaded.
+    loaded: i64,
+    shards: BTreeMap<ShardKey, Shard>,
+    /// Where each message's embedding is, to replace it when it's embedded again.
+    positions: HashMap<i64, (ShardKey, usize)>,
+}
+
+impl Index {
+    fn insert(&mut self, key: ShardKey, history_id: i64, embedding: &[i8]) {
+        self.remove(history_id);
+        let shard = self.shards.entry(key.clone()).or_default();
+        match shard.push(history_id, embedding) {
+            Some(i) => {
+                self.positions.insert(history_id, (key, i));
+            }
+            None => log::warn!(
+                "Not searching the embedding of message {history_id}: {} dimensions, not {}",
+                embedding.len(),
+                shard.dimensions
+            ),
+        }
+    }
+
+    fn remove(&mut self, history_id: i64) {
+        let Some((key, i)) = self.positions.remove(&history_id) else {
+            return;
+        };
+        if let Some(shard) = self.shards.get_mut(&key) {
+            if let Some(moved) = shard.swap_remove(i) {
+                self.positions.insert(moved, (key, i));
+            }
+        }
+    }
+
+    /// Take out every shard of `conversation`, with the model each is from.
+    fn take_conversation(&mut self, conversation: Conversation) -> Vec<(String, Shard)> {
+        let keys = self
+            .shards
+            .keys()
+            .filter(|(_, c)| *c == conversation)
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.into_iter()
+            .filter_map(|key| {
+                let shard = self.shards.remove(&key)?;
+                for history_id in &shard.history_ids {
+                    self.positions.remove(history_id);
+                }
+                Some((key.0, shard))
+            })
+            .collect()
+    }
+
+    /// Add whatever was saved to the database since the last look.
+    async fn top_up(&mut self, database: &Database) -> Result<()> {
+        for row in database.quantized_embeddings(self.loaded).await? {
+            self.insert(
+                (row.model, row.conversation),
+                row.history_id,
+                &row.embedding,
+            );
+            self.loaded = row.id;
+        }
+        Ok(())
+    }
+}
+
+impl SqliteStore {
+    /// A store that loads the embeddings on its first search.
+    pub fn new(database: Arc<Database>) -> Self {
+        Self {
+            database,
+            index: Mutex::default(),
+        }
+    }
+
+    /// A store with every embedding already loaded.
+    pub async fn load(database: Arc<Database>) -> Result<Self> {
+        let store = Self::new(database);
+        store.index.lock().await.top_up(&store.database).await?;
+        Ok(store)
+    }
+}
+
+#[async_trait]
+impl VectorStore for SqliteStore {
+    async fn index(&self, model: &str, entries: &[Entry]) -> Result<()> {
+        let mut index = self.index.lock().await;
+        for entry in entries {
+            index.insert(
+                (model.to_owned(), entry.conversation),
+                entry.history_id,
+                &crate::embeddings::quantize(&entry.embedding),
+            );
+        }
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        conversation: Conversation,
+        model: &str,
+        query: &[f32],
+        limit: usize,
+    ) -> Result<Vec<Hit>> {
+        let mut index = self.index.lock().await;
+        index.top_up(&self.database).await?;
+
+        let Some(shard) = index.shards.get(&(model.to_owned(), conversation)) else {
+            return Ok(vec![]);
+        };
+        let query = crate::embeddings::quantize(query);
+        if query.len() != shard.dimensions {
+            return Ok(vec![]);
+        }
+        let dot = dot_product();
+        let mut hits: Vec<Hit> = shard
+            .history_ids
+            .iter()
+            .enumerate()
+            .map(|(i, &history_id)| Hit {
+                history_id,
+                score: dot(&query, shard.embedding(i)) as f32 / (127.0 * 127.0),
+            })
+            .collect();
+        let best_first = |a: &Hit, b: &Hit| b.score.total_cmp(&a.score);
+        if hits.len() > limit && limit > 0 {
+            hits.select_nth_unstable_by(limit - 1, best_first);
+        }
+        hits.truncate(limit);
+        hits.sort_by(best_first);
+        Ok(hits)
+    }
+
+    // the database's own rows go (or move) along with the messages, so only what's in
+    // memory needs changing
+
+    async fn remove(&self, history_ids: &[i64]) -> Result<()> {
+        let mut index = self.index.lock().await;
+        for history_id in history_ids {
+            index.remove(*history_id);
+        }
+        Ok(())
+    }
+
+    async fn remove_conversation(&self, conversation: Conversation) -> Result<()> {
+        self.index.lock().await.take_conversation(conversation);
+        Ok(())
+    }
+
+    async fn reassign(&self, src: Conversation, dst: Conversation) -> Result<()> {
+        let mut index = self.index.lock().await;
+        for (model, shard) in index.take_conversation(src) {
+            for (i, history_id) in shard.history_ids.iter().enumerate() {
+                index.insert((model.clone(), dst), *history_id, shard.embedding(i));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The fastest dot product of two quantized embeddings this CPU can do.
+fn dot_product() -> fn(&[i8], &[i8]) -> i32 {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        return dot_avx2;
+    }
+    dot_scalar
+}
+
+fn dot_scalar(a: &[i8], b: &[i8]) -> i32 {
+    a.iter().zip(b).map(|(&x, &y)| x as i32 * y as i32).sum()
+}
+
+#[cfg(target_arch = "x86_64")]
+fn dot_avx2(a: &[i8], b: &[i8]) -> i32 {
+    // SAFETY: only returned by dot_product once AVX2 is known to be there
+    unsafe { avx2::dot(a, b) }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    /// Widens 16 bytes of each to 16 bit lanes, then multiplies and adds neighbouring
+    /// pairs into 32 bit lanes, which can't overflow for any embedding size in use.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn dot(a: &[i8], b: &[i8]) -> i32 {
+        let len = a.len().min(b.len());
+        let mut sum = _mm256_setzero_si256();
+        let mut i = 0;
+        while i + 16 <= len {
+            let x = _mm256_cvtepi8_epi16(_mm_loadu_si128(a.as_ptr().add(i) as *const __m128i));
+            let y = _mm256_cvtepi8_epi16(_mm_loadu_si128(b.as_ptr().add(i) as *const __m128i));
+            sum = _mm256_add_epi32(sum, _mm256_madd_epi16(x, y));
+            i += 16;
+        }
+        let mut lanes = [0i32; 8];
+        _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, sum);
+        lanes.iter().sum::<i32>() + super::dot_scalar(&a[i..len], &b[i..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dot_product() {
+        let a: Vec<i8> = (0..100).map(|i| (i * 37 % 255 - 127) as i8).collect();
+        let b: Vec<i8> = (0..100).map(|i| (i * 91 % 255 - 127) as i8).collect();
+        let expected: i32 = a.iter().zip(&b).map(|(&x, &y)| x as i32 * y as i32).sum();
+        assert_eq!(dot_scalar(&a, &b), expected);
+        assert_eq!(dot_product()(&a, &b), expected);
+        assert_eq!(
+            dot_product()(&a[..7], &b[..7]),
+            dot_scalar(&a[..7], &b[..7])
+        );
+        assert_eq!(dot_product()(&[127; 32], &[-127; 32]), -127 * 127 * 32);
+    }
+
+    #[tokio::test]
+    async fn test_index() {
+        let db = Database::new(None).await.expect("failed to create db");
+        let conversation = db.find_conversation("test").await.unwrap();
+        let key = || ("ada".to_owned(), conversation);
+        let mut index = Index::default();
+        index.insert(key(), 1, &[1, 2]);
+        index.insert(key(), 2, &[3, 4]);
+        index.insert(key(), 3, &[5, 6]);
+        index.insert(key(), 4, &[7]);
+        index.insert(key(), 1, &[8, 9]);
+        let shard = &index.shards[&key()];
+        assert_eq!(shard.history_ids, [3, 2, 1]);
+        assert_eq!(shard.embeddings, [5, 6, 3, 4, 8, 9]);
+        assert_eq!(index.positions[&3], (key(), 0));
+
+        // moving to another model
+        index.insert(("other".to_owned(), conversation), 2, &[1]);
+        assert_eq!(index.shards[&key()].history_ids, [3, 1]);
+        assert_eq!(index.positions[&1], (key(), 1));
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let db = Arc::new(Database::new(None).await.expect("failed to create db"));
+        let conversation = db.find_conversation("test").await.unwrap();
+        let other = db.find_conversation("other").await.unwrap();
+        let hay = db.add_user_message(conversation, "hay").await.unwrap();
+        let oats = db.add_user_message(conversation, "oats").await.unwrap();
+        let elsewhere = db.add_user_message(other, "hay").await.unwrap();
+        let model = || "ada".to_owned();
+        db.set_message_embeddings(
+            model(),
+            vec![(hay, vec![1.0, 0.0]), (elsewhere, vec![1.0, 0.0])],
+        )
+        .await
+        .unwrap();
+
+        let store = SqliteStore::new(db.clone());
+        let ids = |hits: &[Hit]| hits.iter().map(|h| h.history_id).collect::<Vec<_>>();
+        let query = [0.0, 1.0];
+        let hits = store.search(conversation, "ada", &query, 5).await.unwrap();
+        assert_eq!(ids(&hits), vec![hay]);
+
+        // saved after the index was loaded
+        db.set_message_embeddings(model(), vec![(oats, vec![0.6, 0.8])])
+            .await
+            .unwrap();
+        let hits = store.search(conversation, "ada", &query, 5).await.unwrap();
+        assert_eq!(ids(&hits), vec![oats, hay]);
+        assert!((hits[0].score - 0.8).abs() < 0.01);
+        let hits = store.search(conversation, "ada", &query, 1).await.unwrap();
+        assert_eq!(ids(&hits), vec![oats]);
+        assert!(store
+            .search(conversation, "other", &query, 5)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_hooks() {
+        let db = Arc::new(Database::new(None).await.expect("failed to create db"));
+        let conversation = db.find_conversation("test").await.unwrap();
+        let other = db.find_conversation("other").await.unwrap();
+        let hay = db.add_user_message(conversation, "hay").await.unwrap();
+        let oats = db.add_user_message(conversation, "oats").await.unwrap();
+        let straw = db.add_user_message(conversation, "straw").await.unwrap();
+        db.set_message_embeddings(
+            "ada".to_owned(),
+            vec![(hay, vec![1.0, 0.0]), (oats, vec![0.6, 0.8])],
+        )
+        .await
+        .unwrap();
+
+        let store = SqliteStore::load(db.clone()).await.unwrap();
+        let query = [0.0, 1.0];
+        let search = |conversation| store.search(conversation, "ada", &query, 5);
+        let ids = |hits: Vec<Hit>| hits.iter().map(|h| h.history_id).collect::<Vec<_>>();
+        let entry = Entry {
+            history_id: straw,
+            conversation,
+            embedding: vec![0.0, 1.0],
+        };
+        store.index("ada", &[entry]).await.unwrap();
+        assert_eq!(
+            ids(search(conversation).await.unwrap()),
+            vec![straw, oats, hay]
+        );
+
+        assert!(db.redact_message(None, oats).await.unwrap());
+        store.remove(&[oats]).await.unwrap();
+        assert_eq!(ids(search(conversation).await.unwrap()), vec![straw, hay]);
+
+        db.merge_conversations(conversation, other).await.unwrap();
+        store.reassign(conversation, other).await.unwrap();
+        assert!(search(conversation).await.unwrap().is_empty());
+        assert_eq!(ids(search(other).await.unwrap()), vec![straw, hay]);
+
+        store.remove_conversation(other).await.unwrap();
+        assert!(search(other).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+//! A small read-only HTTP server, so operators can read conversations (including
+//! DMs) without shell access. Every request needs `Authorization: Bearer <HTTP_TOKEN>`.
+
+use crate::schema::{Database, Message, Role, TranscriptEntry};
+use axum::{
+    extract::{Path, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::Html,
+    routing::get,
+    Router,
+};
+use eyre::Result;
+use minijinja::context;
+use serde::Serialize;
+use std::{net::SocketAddr, sync::Arc};
+
+const TRANSCRIPT_TEMPLATE: &str = include_str!("transcript.html");
+
+struct WebState {
+    database: Arc<Database>,
+    token: String,
+}
+
+pub async fn serve(addr: SocketAddr, database: Arc<Database>, token: String) -> Result<()> {
+    let state = Arc::new(WebState { database, token });
+    let app = Router::new()
+        .route("/conversations/:name", get(conversation))
+        .with_state(state);
+
+    log::info!("Serving transcripts on http://{addr}");
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+
+    Ok(())
+}
+
+async fn conversation(
+    State(state): State<Arc<WebState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<Html<String>, StatusCode> {
+    if !authorized(&headers, &state.token) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let conversation = state
+        .database
+        .lookup_conversation(&name)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let entries = state
+        .database
+        .transcript(conversation)
+        .await
+        .map_err(internal_error)?;
+    let description = state
+        .database
+        .description(conversation)
+        .await
+        .map_err(internal_error)?;
+
+    render_transcript(&name, description.as_deref(), &entries)
+        .map(Html)
+        .map_err(internal_error)
+}
+
+fn authorized(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map_or(false, |given| {
+            same_secret(given.trim().as_bytes(), token.as_bytes())
+        })
+}
+
+/// Compare without stopping at the first difference, so how long a wrong guess takes
+/// to reject doesn't give away how much of it was right.
+fn same_secret(given: &[u8], secret: &[u8]) -> bool {
+    given.len() == secret.len()
+        && std::hint::black_box(
+            given
+                .iter()
+                .zip(secret)
+                .fold(0u8, |diff, (a, b)| diff | (a ^ b)),
+        ) == 0
+}
+
+fn internal_error<E: std::fmt::Display>(e: E) -> StatusCode {
+    log::error!("Failed to serve transcript: {}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+#[derive(Serialize)]
+struct Entry {
+    class: &'static str,
+    label: String,
+    id: i64,
+    created_at: Option<String>,
+    content: String,
+}
+
+fn render_transcript(
+    name: &str,
+    description: Option<&str>,
+    entries: &[TranscriptEntry],
+) -> Result<String> {
+    let entries = entries
+        .iter()
+        .map(|entry| {
+            let (class, label) = match &entry.message {
+                Message::Content { role, .. } => match role {
+                    Role::System => ("system", "system".to_owned()),
+                    Role::User => ("user", "user".to_owned()),
+                    Role::Assistant => ("assistant", "horse".to_owned()),
+                    Role::Function => ("function", "function".to_owned()),
+                },
+                Message::Function { fn_name, .. } => ("function", format!("called {fn_name}")),
+                Message::FunctionResult { fn_name, .. } => {
+                    ("function", format!("{fn_name} returned"))
+                }
+            };
+            Entry {
+                class,
+                label,
+                id: entry.id,
+                created_at: entry.created_at.clone(),
+                content: entry.message.content(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // the .html name turns on autoescaping
+    let mut env = minijinja::Environment::new();
+    env.add_template("transcript.html", TRANSCRIPT_TEMPLATE)?;
+    let html = env
+        .get_template("transcript.html")?
+        .render(context! { name, description, entries })?;
+    Ok(html)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorized() {
+        let mut headers = HeaderMap::new();
+        assert!(!authorized(&headers, "secret"));
+        headers.insert(AUTHORIZATION, "Bearer wrong".parse().unwrap());
+        assert!(!authorized(&headers, "secret"));
+        headers.insert(AUTHORIZATION, "Bearer secret".parse().unwrap());
+        assert!(authorized(&headers, "secret"));
+    }
+
+    #[test]
+    fn test_same_secret() {
+        assert!(same_secret(b"secret", b"secret"));
+        assert!(!same_secret(b"secreT", b"secret"));
+        assert!(!same_secret(b"secret!", b"secret"));
+        assert!(!same_secret(b"", b"secret"));
+    }
+
+    #[test]
+    fn test_render_transcript() {
+        let entries = vec![
+            TranscriptEntry {
+                id: 1,
+                created_at: Some("2023-08-01 12:00:00".to_owned()),
+                message: Message::new(Role::User, "<b>hi</b> horse"),
+            },
+            TranscriptEntry {
+                id: 2,
+                created_at: None,
+                message: Message::function_result("roll_dice", "4"),
+            },
+        ];
+        let html = render_transcript("#general", Some("Dice & banter"), &entries).unwrap();
+        assert!(html.contains("&lt;b&gt;hi&lt;/b&gt; horse"));
+        assert!(html.contains("roll_dice returned"));
+        assert!(html.contains("2023-08-01 12:00:00"));
+        assert!(html.contains("Dice &amp; banter"));
+    }
+}